@@ -0,0 +1,275 @@
+//! Operations for managing a running node's tables, similar to Cassandra's nodetool. There is no
+//!  network server yet; once there is, it exposes these to operators.
+
+use std::path::{Path, PathBuf};
+
+use crate::engine::Table;
+use crate::export::{export_to_folder, ExportedComponent};
+use crate::keycache::KeyCacheStats;
+use crate::prelude::*;
+use crate::quarantine::QuarantinedRow;
+use crate::sstable::SsTableName;
+use crate::tasks::BackgroundTask;
+use crate::time::MergeTimestamp;
+
+/// A summary of a table's storage
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TableStats {
+    pub ss_table_count: usize,
+    /// the size of all SsTables' data files in bytes
+    pub data_size: u64,
+    /// the sum of all SsTables' row counts, i.e. rows stored in several SsTables are counted
+    ///  several times
+    pub row_count: u64,
+    pub tombstone_count: u64,
+    /// estimated number of distinct partitions in all SsTables
+    pub partition_count_estimate: u64,
+    /// the size of the MemTable receiving writes in bytes
+    pub mem_table_size: usize,
+    /// the number of MemTables that are being flushed
+    pub flushing_mem_tables: usize,
+    /// the number of reads that exceeded TableConfig::tombstone_warn_threshold
+    pub tombstone_warnings: u64,
+    /// the number of corrupt SsTable rows that reads skipped, see Quarantine
+    pub quarantined_rows: usize,
+    /// the number of disk space checks that found the data folders above
+    ///  TableConfig::disk_warn_watermark
+    pub disk_space_warnings: u64,
+    /// point reads of the live SsTables that were served from their KeyCaches...
+    pub key_cache_hits: u64,
+    /// ... and those that were not. These are reset when compaction replaces an SsTable.
+    pub key_cache_misses: u64,
+}
+
+/// A summary of a single SsTable
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SsTableInfo {
+    pub name: SsTableName,
+    pub folder: PathBuf,
+    pub data_size: u64,
+    pub row_count: u64,
+    /// estimated number of distinct partitions, see SsTableStats::partitions
+    pub partition_count_estimate: u64,
+    pub min_timestamp: MergeTimestamp,
+    pub max_timestamp: MergeTimestamp,
+}
+
+/// writes the table's MemTable to a new SsTable
+pub fn flush(table: &Table) -> HtResult<()> {
+    table.flush()
+}
+
+/// merges all of the table's SsTables into one ('major compaction')
+pub fn compact(table: &Table) -> HtResult<()> {
+    table.compact_all()
+}
+
+/// rewrites each SsTable on its own, discarding expired data and tombstones past their grace
+///  period without merging SsTables
+pub fn cleanup(table: &Table) -> HtResult<()> {
+    for ss_table in table.ss_tables() {
+        table.cleanup(ss_table.name())?;
+    }
+    Ok(())
+}
+
+pub fn stats(table: &Table) -> TableStats {
+    let view = table.view();
+
+    let mut result = TableStats {
+        ss_table_count: view.ss_tables.len(),
+        data_size: 0,
+        row_count: 0,
+        tombstone_count: 0,
+        partition_count_estimate: table.partition_count_estimate(),
+        mem_table_size: view.mem_table.read().unwrap().size(),
+        flushing_mem_tables: view.flushing.len(),
+        tombstone_warnings: table.tombstone_warnings(),
+        quarantined_rows: table.quarantine().count(),
+        disk_space_warnings: table.disk_space_warnings(),
+        key_cache_hits: 0,
+        key_cache_misses: 0,
+    };
+    let mut key_cache = KeyCacheStats::default();
+    for ss_table in &view.ss_tables {
+        key_cache.add(&ss_table.key_cache().stats());
+        result.data_size += ss_table.data_size();
+        result.row_count += ss_table.stats().row_count;
+        result.tombstone_count += ss_table.stats().tombstone_count;
+    }
+    result.key_cache_hits = key_cache.hits;
+    result.key_cache_misses = key_cache.misses;
+    result
+}
+
+/// the table's live SsTables, oldest first
+pub fn list_sstables(table: &Table) -> Vec<SsTableInfo> {
+    let mut result = table.ss_tables().iter()
+        .map(|ss_table| SsTableInfo {
+            name: ss_table.name().clone(),
+            folder: ss_table.folder().to_path_buf(),
+            data_size: ss_table.data_size(),
+            row_count: ss_table.stats().row_count,
+            partition_count_estimate: ss_table.stats().partition_count_estimate(),
+            min_timestamp: ss_table.stats().min_timestamp,
+            max_timestamp: ss_table.stats().max_timestamp,
+        })
+        .collect::<Vec<_>>();
+    result.sort_by_key(|info| info.name.generation);
+    result
+}
+
+/// the corrupt SsTable rows the table's reads skipped, see Quarantine
+pub fn quarantine(table: &Table) -> Vec<QuarantinedRow> {
+    table.quarantine().rows()
+}
+
+/// the running flushes and compactions of all given tables
+pub fn background_tasks(tables: &[&Table]) -> Vec<BackgroundTask> {
+    tables.iter()
+        .flat_map(|table| table.background_tasks())
+        .collect()
+}
+
+/// changes a setting of an open table, see Table::set_setting
+pub fn set_setting(table: &Table, name: &str, value: &str) -> HtResult<()> {
+    table.set_setting(name, value)
+}
+
+/// see Table::split
+pub fn split_sstable(table: &Table, name: &SsTableName, num_parts: usize) -> HtResult<()> {
+    table.split(name, num_parts)
+}
+
+/// copies one of the table's live SsTables to a folder, see export::export_to_folder
+pub fn export_sstable(table: &Table, name: &SsTableName, folder: &Path) -> HtResult<Vec<ExportedComponent>> {
+    match table.ss_tables().iter().find(|t| t.name() == name) {
+        Some(ss_table) => export_to_folder(ss_table, folder),
+        None => Err(HtError::misc("no SsTable with this name")),
+    }
+}
+
+/// see Table::snapshot
+pub fn snapshot(table: &Table, name: &str) -> HtResult<()> {
+    table.snapshot(name)
+}
+
+/// a Prometheus metric: its name, type, help text and how to take its value from TableStats
+type Metric = (&'static str, &'static str, &'static str, fn(&TableStats) -> u64);
+
+/// Renders the tables' stats in Prometheus' text exposition format, with the table name as a
+///  label, e.g. for serving them on a '/metrics' endpoint.
+pub fn prometheus_metrics(tables: &[&Table]) -> String {
+    let stats = tables.iter()
+        .map(|table| (table.schema().name.clone(), stats(table)))
+        .collect::<Vec<_>>();
+
+    let metrics: &[Metric] = &[
+        ("ht_sstables", "gauge", "number of live SsTables", |s| s.ss_table_count as u64),
+        ("ht_sstable_data_bytes", "gauge", "size of the SsTables' data files", |s| s.data_size),
+        ("ht_sstable_rows", "gauge", "rows in SsTables, counting rows in several SsTables several times", |s| s.row_count),
+        ("ht_sstable_tombstones", "gauge", "NULL column values and row tombstones in SsTables", |s| s.tombstone_count),
+        ("ht_partitions_estimate", "gauge", "estimated number of distinct partitions in SsTables", |s| s.partition_count_estimate),
+        ("ht_memtable_bytes", "gauge", "size of the MemTable receiving writes", |s| s.mem_table_size as u64),
+        ("ht_flushing_memtables", "gauge", "number of MemTables being flushed", |s| s.flushing_mem_tables as u64),
+        ("ht_tombstone_warnings", "counter", "reads exceeding the tombstone warn threshold", |s| s.tombstone_warnings),
+        ("ht_quarantined_rows", "gauge", "corrupt SsTable rows skipped by reads", |s| s.quarantined_rows as u64),
+        ("ht_disk_space_warnings", "counter", "disk space checks finding data folders above the warn watermark", |s| s.disk_space_warnings),
+        ("ht_key_cache_hits", "gauge", "point reads of live SsTables served from their key caches", |s| s.key_cache_hits),
+        ("ht_key_cache_misses", "gauge", "point reads of live SsTables not served from their key caches", |s| s.key_cache_misses),
+    ];
+
+    let mut result = String::new();
+    for (name, tpe, help, value) in metrics {
+        result.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, tpe));
+        for (table, stats) in &stats {
+            result.push_str(&format!("{}{{table=\"{}\"}} {}\n", name, escape_label_value(table), value(stats)));
+        }
+    }
+    result
+}
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::admin;
+    use crate::engine::Table;
+    use crate::sstable::SsTableComponent;
+    use crate::testutils::{SimpleTableTestSetup, test_table_config};
+    use crate::time::HtClock;
+
+    #[test]
+    pub fn test_admin() {
+        let setup = SimpleTableTestSetup::new();
+        let config = test_table_config();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::new(&config, &setup.schema, &clock).unwrap();
+
+        for pk in 0..3 {
+            table.put(setup.full_row(pk, Some("a"), Some(pk))).unwrap();
+            admin::flush(&table).unwrap();
+        }
+        table.put(setup.full_row(0, None, None)).unwrap();
+
+        let stats = admin::stats(&table);
+        assert_eq!(stats.ss_table_count, 3);
+        assert_eq!(stats.row_count, 3);
+        assert_eq!(stats.partition_count_estimate, 3);
+        assert!(stats.mem_table_size > 0);
+        assert_eq!(stats.flushing_mem_tables, 0);
+
+        let ss_tables = admin::list_sstables(&table);
+        assert_eq!(ss_tables.len(), 3);
+        assert!(ss_tables[0].name.generation < ss_tables[1].name.generation);
+        assert_eq!(ss_tables.iter().map(|t| t.data_size).sum::<u64>(), stats.data_size);
+        assert!(ss_tables.iter().all(|t| t.partition_count_estimate == 1));
+
+        // the snapshot keeps its files after compaction deleted the SsTables it was taken of
+        admin::snapshot(&table, "snap").unwrap();
+        assert!(admin::snapshot(&table, "snap").is_err());
+        assert!(admin::snapshot(&table, "../snap").is_err());
+        admin::compact(&table).unwrap();
+        admin::cleanup(&table).unwrap();
+        assert!(admin::background_tasks(&[&table]).is_empty());
+
+        let snapshot_folder = ss_tables[0].folder.join("snapshots").join("snap");
+        for info in &ss_tables {
+            // nothing is indexed, so there is no column index to snapshot
+            for &component in SsTableComponent::ALL.iter().filter(|&&c| c != SsTableComponent::ColumnIndex) {
+                assert!(snapshot_folder.join(info.name.file_name(component)).exists());
+                assert!(!info.folder.join(info.name.file_name(component)).exists());
+            }
+        }
+        assert!(config.table_folder(&setup.schema).join("snapshots").join("snap").join("manifest").exists());
+
+        let stats = admin::stats(&table);
+        assert_eq!(stats.ss_table_count, 1);
+        assert_eq!(stats.row_count, 3);
+        assert_eq!(stats.mem_table_size, 0);
+
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_prometheus_metrics() {
+        let setup = SimpleTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::new(&test_table_config(), &setup.schema, &clock).unwrap();
+        table.put(setup.full_row(1, Some("a"), None)).unwrap();
+        table.flush().unwrap();
+
+        let metrics = admin::prometheus_metrics(&[&table]);
+        assert!(metrics.contains("# TYPE ht_sstables gauge\nht_sstables{table=\"test_table\"} 1\n"));
+        assert!(metrics.contains("ht_sstable_rows{table=\"test_table\"} 1\n"));
+        assert!(metrics.contains("ht_memtable_bytes{table=\"test_table\"} 0\n"));
+
+        assert_eq!(admin::escape_label_value("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+
+        table.drop_table().unwrap();
+    }
+}
@@ -0,0 +1,239 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::prelude::*;
+use crate::table::{decode_column_value, decode_column_value_of_kind, ColumnId, ColumnType, ColumnValue, DetachedRowData, PrimaryKeySpec, RowData, TableSchema};
+
+/// An aggregate function computed per group, see Table::aggregate. NULL columns and columns
+///  that were never written are ignored by all functions except Count, which counts rows.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Aggregate {
+    Count,
+    Min(ColumnId),
+    Max(ColumnId),
+    /// for Int and BigInt columns
+    Sum(ColumnId),
+}
+
+impl Aggregate {
+    /// checks that the column exists and has a type the function supports
+    pub fn validate(&self, schema: &TableSchema) -> HtResult<()> {
+        match *self {
+            Aggregate::Count => Ok(()),
+            Aggregate::Min(col_id) | Aggregate::Max(col_id) => match schema.column(col_id)?.tpe {
+                ColumnType::Duration => Err(HtError::misc("Duration columns have no minimum or maximum")),
+                _ => Ok(()),
+            },
+            Aggregate::Sum(col_id) => match schema.column(col_id)?.tpe {
+                ColumnType::Int | ColumnType::BigInt => Ok(()),
+                _ => Err(HtError::misc("only Int and BigInt columns can be summed")),
+            },
+        }
+    }
+}
+
+/// A column value that outlives the row it was read from, encoded like primary key values (see
+///  DetachedRowData::encode_column_value)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AggregatedValue {
+    /// the value kind, see ColumnFlags
+    kind: u8,
+    buf: Bytes,
+}
+
+impl AggregatedValue {
+    fn new(value: &ColumnValue) -> AggregatedValue {
+        let mut buf = Vec::new();
+        DetachedRowData::encode_column_value(&mut buf, value);
+        AggregatedValue { kind: value.kind(), buf: Bytes::from(buf) }
+    }
+
+    pub fn value(&self) -> ColumnValue<'_> {
+        decode_column_value_of_kind(&self.buf, self.kind, &mut 0)
+    }
+}
+
+/// The result of an Aggregate for one group. Min, Max and Sum are None if the group has no
+///  values for the column.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AggregateResult {
+    Count(u64),
+    Min(Option<AggregatedValue>),
+    Max(Option<AggregatedValue>),
+    Sum(Option<i128>),
+}
+
+/// The rows sharing a partition key and the values of some leading cluster key columns
+pub struct AggregateGroup {
+    schema: Arc<TableSchema>,
+    /// the group's primary key values, encoded like a PartialClusterKey
+    key: Bytes,
+    /// one per Aggregate, in the order they were given
+    pub results: Vec<AggregateResult>,
+}
+
+impl AggregateGroup {
+    /// the values of the partition key columns, followed by the grouped cluster key columns
+    pub fn key_values(&self) -> Vec<ColumnValue<'_>> {
+        let mut offs = 0;
+        let mut result = Vec::new();
+        for col in &self.schema.pk_columns {
+            if offs >= self.key.len() {
+                break;
+            }
+            result.push(decode_column_value(&self.key, &col.tpe, &mut offs));
+        }
+        result
+    }
+}
+
+/// Computes aggregates per group over rows in primary key order. Each group's rows arrive
+///  consecutively, so groups are finished one at a time without holding the input rows.
+pub struct GroupAggregator<'a> {
+    schema: Arc<TableSchema>,
+    /// the number of primary key columns in the group key
+    key_len: usize,
+    aggregates: &'a [Aggregate],
+    groups: Vec<AggregateGroup>,
+}
+
+impl <'a> GroupAggregator<'a> {
+    /// cluster_key_len is the number of leading cluster key columns that are part of the group
+    ///  key, 0 for one group per partition
+    pub fn new(schema: &Arc<TableSchema>, cluster_key_len: usize, aggregates: &'a [Aggregate]) -> HtResult<GroupAggregator<'a>> {
+        if cluster_key_len > schema.cluster_key_len() {
+            return Err(HtError::misc("grouping by more cluster key columns than the table has"));
+        }
+        for aggregate in aggregates {
+            aggregate.validate(schema)?;
+        }
+
+        let num_partition_key_columns = schema.pk_columns.iter()
+            .filter(|c| c.pk_spec == PrimaryKeySpec::PartitionKey)
+            .count();
+        Ok(GroupAggregator {
+            schema: schema.clone(),
+            key_len: num_partition_key_columns + cluster_key_len,
+            aggregates,
+            groups: Vec::new(),
+        })
+    }
+
+    pub fn add(&mut self, row: &RowData) {
+        let mut key = Vec::new();
+        for col in row.columns().take(self.key_len) {
+            DetachedRowData::encode_column_value(&mut key, &col.value.expect("primary key columns must not be null"));
+        }
+
+        if self.groups.last().map(|g| g.key != key).unwrap_or(true) {
+            let results = self.aggregates.iter()
+                .map(|aggregate| match aggregate {
+                    Aggregate::Count => AggregateResult::Count(0),
+                    Aggregate::Min(_) => AggregateResult::Min(None),
+                    Aggregate::Max(_) => AggregateResult::Max(None),
+                    Aggregate::Sum(_) => AggregateResult::Sum(None),
+                })
+                .collect();
+            self.groups.push(AggregateGroup { schema: self.schema.clone(), key: Bytes::from(key), results });
+        }
+
+        let group = self.groups.last_mut().unwrap();
+        for (aggregate, result) in self.aggregates.iter().zip(group.results.iter_mut()) {
+            let value = |col_id| row.read_col_by_id(col_id).and_then(|c| c.value);
+            let compare = |col_id, v1: &ColumnValue, v2: &ColumnValue| match row.schema.column(col_id) {
+                Ok(col) => col.compare_values(v1, v2),
+                Err(_) => v1.cmp(v2),
+            };
+
+            match (aggregate, result) {
+                (Aggregate::Count, AggregateResult::Count(count)) => *count += 1,
+                (&Aggregate::Min(col_id), AggregateResult::Min(min)) => {
+                    if let Some(v) = value(col_id) {
+                        if min.as_ref().map(|m| compare(col_id, &v, &m.value()) == Ordering::Less).unwrap_or(true) {
+                            *min = Some(AggregatedValue::new(&v));
+                        }
+                    }
+                }
+                (&Aggregate::Max(col_id), AggregateResult::Max(max)) => {
+                    if let Some(v) = value(col_id) {
+                        if max.as_ref().map(|m| compare(col_id, &v, &m.value()) == Ordering::Greater).unwrap_or(true) {
+                            *max = Some(AggregatedValue::new(&v));
+                        }
+                    }
+                }
+                (&Aggregate::Sum(col_id), AggregateResult::Sum(sum)) => {
+                    let v = match value(col_id) {
+                        Some(ColumnValue::Int(v)) => v as i128,
+                        Some(ColumnValue::BigInt(v)) => v as i128,
+                        _ => continue,
+                    };
+                    *sum = Some(sum.unwrap_or(0) + v);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    pub fn finish(self) -> Vec<AggregateGroup> {
+        self.groups
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::aggregate::{Aggregate, AggregateResult, GroupAggregator};
+    use crate::table::{ColumnId, ColumnValue};
+    use crate::testutils::{ClusteredTableTestSetup, test_table_config};
+
+    #[test]
+    pub fn test_group_aggregator() {
+        let setup = ClusteredTableTestSetup::new();
+        let rows = vec!(
+            setup.row(1, 0, 1, Some("b")),
+            setup.row(1, 0, 0, None),
+            setup.row(1, 1, 5, Some("a")),
+            setup.row(2, 0, 0, Some("c")),
+        );
+        let aggregates = [Aggregate::Count, Aggregate::Min(ColumnId(3)), Aggregate::Max(ColumnId(3)), Aggregate::Sum(ColumnId(2))];
+
+        let aggregate = |cluster_key_len| {
+            let mut aggregator = GroupAggregator::new(&setup.schema, cluster_key_len, &aggregates).unwrap();
+            for row in &rows {
+                aggregator.add(&row.row_data_view());
+            }
+            aggregator.finish()
+        };
+
+        let groups = aggregate(0);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].key_values(), vec!(ColumnValue::BigInt(1)));
+        assert_eq!(groups[0].results[0], AggregateResult::Count(3));
+        match (&groups[0].results[1], &groups[0].results[2]) {
+            (AggregateResult::Min(Some(min)), AggregateResult::Max(Some(max))) => {
+                assert_eq!(min.value(), ColumnValue::Text("a"));
+                assert_eq!(max.value(), ColumnValue::Text("b"));
+            }
+            _ => panic!("no min / max"),
+        }
+        assert_eq!(groups[0].results[3], AggregateResult::Sum(Some(6)));
+        assert_eq!(groups[1].key_values(), vec!(ColumnValue::BigInt(2)));
+        assert_eq!(groups[1].results[0], AggregateResult::Count(1));
+
+        let groups = aggregate(1);
+        assert_eq!(groups.iter().map(|g| g.key_values()).collect::<Vec<_>>(), vec!(
+            vec!(ColumnValue::BigInt(1), ColumnValue::Int(0)),
+            vec!(ColumnValue::BigInt(1), ColumnValue::Int(1)),
+            vec!(ColumnValue::BigInt(2), ColumnValue::Int(0)),
+        ));
+        assert_eq!(groups[0].results[0], AggregateResult::Count(2));
+        assert_eq!(groups[0].results[3], AggregateResult::Sum(Some(1)));
+
+        assert!(GroupAggregator::new(&setup.schema, 3, &aggregates).is_err());
+        assert!(GroupAggregator::new(&setup.schema, 0, &[Aggregate::Sum(ColumnId(3))]).is_err());
+        assert!(GroupAggregator::new(&setup.schema, 0, &[Aggregate::Min(ColumnId(9))]).is_err());
+
+        test_table_config().remove_table_folder(&setup.schema).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::prelude::*;
+use crate::slowlog::describe_key;
+use crate::table::DetachedRowData;
+use crate::time::MergeTimestamp;
+
+/// Data modifying operations. Writes of NULL values or expiry are Puts, while Table::delete
+///  and Table::touch are reported as Deletes and Touches.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AuditOperation {
+    Put,
+    Delete,
+    Touch,
+    DropTable,
+}
+
+/// A data modifying operation, reported to a table's AuditCallback - writes after they were
+///  applied, DDL before it is applied. There are no users yet, so there is no 'who' - an
+///  embedding application that has them can record it in its callback.
+#[derive(Clone, Debug)]
+pub struct AuditEvent {
+    pub table: String,
+    pub operation: AuditOperation,
+    pub timestamp: MergeTimestamp,
+    /// a hash of the written row's primary key (see slowlog::describe_key), None for DDL
+    pub key_hash: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(table: &str, operation: AuditOperation, timestamp: MergeTimestamp, row: Option<&DetachedRowData>) -> AuditEvent {
+        AuditEvent {
+            table: table.to_string(),
+            operation,
+            timestamp,
+            key_hash: row.map(|row| describe_key(&row.row_data_view(), true)),
+        }
+    }
+}
+
+/// Receives a table's audit events. If it returns an error, the operation fails: DDL is not
+///  applied, and a write returns HtError::NotDelivered although it was applied. So every
+///  operation that succeeded is in the audit log, and only a failed write may be missing from it.
+pub trait AuditCallback: Send + Sync {
+    fn on_audit_event(&self, event: &AuditEvent) -> HtResult<()>;
+}
+
+/// Appends audit events to a file, one tab separated line per event:
+///  timestamp ticks, table, operation, key hash ('-' for DDL)
+pub struct AuditFile {
+    file: Mutex<File>,
+}
+
+impl AuditFile {
+    pub fn open(path: &Path) -> HtResult<AuditFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditFile { file: Mutex::new(file) })
+    }
+}
+
+impl AuditCallback for AuditFile {
+    fn on_audit_event(&self, event: &AuditEvent) -> HtResult<()> {
+        let line = format!("{}\t{}\t{:?}\t{}\n",
+                           event.timestamp.ticks,
+                           event.table,
+                           event.operation,
+                           event.key_hash.as_deref().unwrap_or("-"));
+        self.file.lock().unwrap().write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::audit::AuditFile;
+    use crate::engine::Table;
+    use crate::slowlog::describe_key;
+    use crate::testutils::{SimpleTableTestSetup, test_table_config};
+    use crate::time::HtClock;
+
+    #[test]
+    pub fn test_audit_file() {
+        let setup = SimpleTableTestSetup::new();
+        let config = test_table_config();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let mut table = Table::new(&config, &setup.schema, &clock).unwrap();
+
+        let path = config.base_folder.join("audit-test.log");
+        let _ = std::fs::remove_file(&path);
+        table.set_audit_callback(Some(Box::new(AuditFile::open(&path).unwrap())));
+
+        table.put(setup.full_row(1, Some("a"), None)).unwrap();
+        table.try_put(setup.full_row(2, None, None)).unwrap();
+        table.update(&setup.pk_row(1), |_| None).unwrap();
+        table.touch(&setup.pk_row(2), 60).unwrap();
+        table.delete(&setup.pk_row(1)).unwrap();
+        table.drop_table().unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines = content.lines()
+            .map(|line| line.split('\t').skip(1).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let key_hash = |pk| describe_key(&setup.pk_row(pk).row_data_view(), true);
+        assert_eq!(lines, vec!(
+            vec!("test_table", "Put", &key_hash(1)),
+            vec!("test_table", "Put", &key_hash(2)),
+            vec!("test_table", "Touch", &key_hash(2)),
+            vec!("test_table", "Delete", &key_hash(1)),
+            vec!("test_table", "DropTable", "-"),
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,304 @@
+//! Incremental backups: when TableConfig::incremental_backups is set, each SsTable written by a
+//!  flush is hard linked into a 'backups' folder next to it. Together with a snapshot, the
+//!  backed up SsTables allow restoring the table's state at the time of the last flush.
+//!
+//! The backup manifest in the table's 'backups' folder in the base folder lists the backed up
+//!  files with their checksums, so that a backup can be verified before it is restored.
+
+use std::hash::Hasher;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use seahash::SeaHasher;
+
+use crate::config::TableConfig;
+use crate::manifest::write_atomically;
+use crate::prelude::*;
+use crate::sstable::{SsTable, SsTableComponent, SsTableName};
+use crate::table::TableSchema;
+use crate::time::MergeTimestamp;
+
+/// the name of the folders containing backups
+pub const BACKUPS_FOLDER: &str = "backups";
+
+/// A single backed up file, i.e. a hard link to one of an SsTable's components
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BackupEntry {
+    /// when the file was backed up, in millis since the epoch
+    pub backed_up_at: u64,
+    pub name: SsTableName,
+    pub component: SsTableComponent,
+    pub checksum: u64,
+    /// the backups folder containing the file
+    pub folder: PathBuf,
+}
+
+impl BackupEntry {
+    pub fn path(&self) -> PathBuf {
+        self.folder.join(self.name.file_name(self.component))
+    }
+}
+
+/// backup manifest format: one line per file, containing the time it was backed up (epoch
+///  millis), table name, generation, component, checksum (hex) and the folder it is stored in,
+///  separated by single spaces
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BackupManifest {
+    pub entries: Vec<BackupEntry>,
+}
+
+impl BackupManifest {
+    const FILE_NAME: &'static str = "manifest";
+    const TMP_FILE_NAME: &'static str = "manifest.tmp";
+
+    fn folder(config: &TableConfig, schema: &TableSchema) -> PathBuf {
+        config.table_folder(schema).join(BACKUPS_FOLDER)
+    }
+
+    /// reads a table's backup manifest, returning an empty manifest if there is none yet
+    pub fn read(config: &TableConfig, schema: &TableSchema) -> HtResult<BackupManifest> {
+        let mut buf = String::new();
+        match config.new_file(&BackupManifest::folder(config, schema), BackupManifest::FILE_NAME, false) {
+            Ok(mut file) => file.read_to_string(&mut buf)?,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(BackupManifest { entries: Vec::new() }),
+            Err(e) => return Err(e.into()),
+        };
+
+        let invalid = || HtError::misc("invalid backup manifest entry");
+        let mut entries = Vec::new();
+        for line in buf.lines().filter(|l| !l.is_empty()) {
+            let mut parts = line.splitn(6, ' ');
+            let mut next = || parts.next().ok_or_else(invalid);
+
+            let backed_up_at = next()?.parse::<u64>().map_err(|_| invalid())?;
+            let table = next()?;
+            let generation = next()?.parse::<u64>().map_err(|_| invalid())?;
+            let component = SsTableComponent::parse(next()?).ok_or_else(invalid)?;
+            let checksum = u64::from_str_radix(next()?, 16).map_err(|_| invalid())?;
+            let folder = PathBuf::from(next()?);
+
+            entries.push(BackupEntry { backed_up_at, name: SsTableName::new(table, generation), component, checksum, folder });
+        }
+        Ok(BackupManifest { entries })
+    }
+
+    /// replaces the table's backup manifest atomically, see Manifest::write
+    pub fn write(&self, config: &TableConfig, schema: &TableSchema) -> HtResult<()> {
+        let mut buf = String::new();
+        for entry in &self.entries {
+            let folder = entry.folder.to_str()
+                .ok_or_else(|| HtError::misc("backup folder is not valid UTF-8"))?;
+            buf.push_str(&format!("{} {} {} {} {:016x} {}\n",
+                                  entry.backed_up_at, entry.name.table, entry.name.generation, entry.component.name(), entry.checksum, folder));
+        }
+
+        let folder = BackupManifest::folder(config, schema);
+        std::fs::create_dir_all(&folder)?;
+        write_atomically(&folder, BackupManifest::FILE_NAME, BackupManifest::TMP_FILE_NAME, buf.as_bytes())
+    }
+}
+
+/// Hard links the SsTables' files into the backups folder next to them and adds them to the
+///  backup manifest. A crash before the manifest is written leaves links that are not listed in
+///  it - they are harmless, but not pruned.
+pub fn backup_ss_tables(config: &TableConfig, schema: &TableSchema, ss_tables: &[Arc<SsTable>], now: MergeTimestamp) -> HtResult<()> {
+    let mut manifest = BackupManifest::read(config, schema)?;
+
+    for ss_table in ss_tables {
+        let folder = ss_table.folder().join(BACKUPS_FOLDER);
+        std::fs::create_dir_all(&folder)?;
+
+        for &component in SsTableComponent::ALL {
+            let file_name = ss_table.name().file_name(component);
+            if component.is_optional() && !ss_table.folder().join(&file_name).exists() {
+                continue;
+            }
+            std::fs::hard_link(ss_table.folder().join(&file_name), folder.join(&file_name))?;
+            manifest.entries.push(BackupEntry {
+                backed_up_at: now.epoch_millis(),
+                name: ss_table.name().clone(),
+                component,
+                checksum: file_checksum(&folder.join(&file_name))?,
+                folder: folder.clone(),
+            });
+        }
+    }
+
+    manifest.write(config, schema)
+}
+
+/// Removes backed up files that were backed up before a given point in time, returning the
+///  number of removed files. Files that are missing already are removed from the manifest.
+pub fn prune_backups(config: &TableConfig, schema: &TableSchema, before: MergeTimestamp) -> HtResult<usize> {
+    let manifest = BackupManifest::read(config, schema)?;
+    let (pruned, retained): (Vec<_>, Vec<_>) = manifest.entries.into_iter()
+        .partition(|e| e.backed_up_at < before.epoch_millis());
+
+    if pruned.is_empty() {
+        return Ok(0);
+    }
+
+    // the manifest is written first so that it never lists files that do not exist
+    BackupManifest { entries: retained }.write(config, schema)?;
+    for entry in &pruned {
+        match std::fs::remove_file(entry.path()) {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {},
+            other => other?,
+        }
+    }
+    Ok(pruned.len())
+}
+
+/// Checks the backed up files listed in the backup manifest, returning those that are missing
+///  or do not match their checksum.
+pub fn verify_backups(config: &TableConfig, schema: &TableSchema) -> HtResult<Vec<(PathBuf, String)>> {
+    let mut result = Vec::new();
+    for entry in BackupManifest::read(config, schema)?.entries {
+        let path = entry.path();
+        match file_checksum(&path) {
+            Ok(checksum) if checksum == entry.checksum => {},
+            Ok(_) => result.push((path, "checksum mismatch".to_string())),
+            Err(e) => result.push((path, format!("{:?}", e))),
+        }
+    }
+    Ok(result)
+}
+
+/// Checks that the SsTables in a snapshot or backups folder are restorable: each SsTable is
+///  opened from the folder and verified (see SsTable::verify), and files listed in the backup
+///  manifest must match their checksums. Returns the SsTables with problems, ordered by
+///  generation. SsTables have no checksums of their own yet, so corrupted files in snapshots
+///  are detected only if they break the row format.
+pub fn verify_backup(config: &Arc<TableConfig>, schema: &Arc<TableSchema>, path: &Path) -> HtResult<Vec<(SsTableName, String)>> {
+    let names = SsTableName::in_folder(config, schema, path)?;
+    let manifest = BackupManifest::read(config, schema)?;
+
+    let mut result = Vec::new();
+    for name in names {
+        if let Err(e) = SsTable::open(config, schema, path, &name).and_then(|ss_table| ss_table.verify()) {
+            result.push((name, format!("{:?}", e)));
+            continue;
+        }
+
+        let mismatch = manifest.entries.iter()
+            .filter(|e| e.name == name && e.folder == path)
+            .find(|e| !matches!(file_checksum(&e.path()), Ok(checksum) if checksum == e.checksum));
+        if let Some(entry) = mismatch {
+            result.push((name, format!("checksum mismatch for {:?}", entry.component)));
+        }
+    }
+    Ok(result)
+}
+
+/// the seahash of a file's contents
+pub fn file_checksum(path: &Path) -> HtResult<u64> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = SeaHasher::new();
+    let mut buf = vec!(0u8; 64*1024);
+    loop {
+        match file.read(&mut buf)? {
+            0 => return Ok(hasher.finish()),
+            n => hasher.write(&buf[..n]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::backup::{BACKUPS_FOLDER, BackupManifest, verify_backup, verify_backups};
+    use crate::config::TableConfig;
+    use crate::engine::Table;
+    use crate::sstable::SsTableComponent;
+    use crate::testutils::{SimpleTableTestSetup, test_table_config};
+    use crate::time::{HtClock, MergeTimestamp};
+
+    #[test]
+    pub fn test_incremental_backups() {
+        let setup = SimpleTableTestSetup::new();
+        setup.clock.set(MergeTimestamp::new(10_000, 0, 0, 0));
+        let config = Arc::new(TableConfig {
+            incremental_backups: true,
+            backup_retention: Some(Duration::from_secs(60)),
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::new(&config, &setup.schema, &clock).unwrap();
+
+        table.put(setup.full_row(1, Some("a"), None)).unwrap();
+        table.flush().unwrap();
+        setup.clock.set(MergeTimestamp::new(50_000, 0, 0, 0));
+        table.put(setup.full_row(2, Some("b"), None)).unwrap();
+        table.flush().unwrap();
+
+        // the backups survive compaction
+        let flushed = table.ss_tables().iter().map(|t| t.name().clone()).collect::<Vec<_>>();
+        table.compact_all().unwrap();
+
+        let manifest = BackupManifest::read(&config, &setup.schema).unwrap();
+        assert_eq!(manifest.entries.len(), 2 * (SsTableComponent::ALL.len() - 1), "no column index without indexed columns");
+        assert_eq!(manifest.entries[0].name, flushed[0]);
+        assert_eq!(manifest.entries[0].backed_up_at, 10_000);
+        assert!(manifest.entries.iter().all(|e| e.path().exists()));
+        assert!(verify_backups(&config, &setup.schema).unwrap().is_empty());
+
+        std::fs::write(manifest.entries[4].path(), b"corrupt").unwrap();
+        let corrupt = verify_backups(&config, &setup.schema).unwrap();
+        assert_eq!(corrupt.iter().map(|c| &c.0).collect::<Vec<_>>(), vec!(&manifest.entries[4].path()));
+
+        // the first flush's backup is older than the retention period
+        setup.clock.set(MergeTimestamp::new(80_000, 0, 0, 0));
+        assert_eq!(table.prune_backups().unwrap(), SsTableComponent::ALL.len() - 1);
+        let pruned = BackupManifest::read(&config, &setup.schema).unwrap();
+        assert_eq!(pruned.entries, manifest.entries[SsTableComponent::ALL.len() - 1..].to_vec());
+        assert!(!manifest.entries[0].path().exists());
+        assert!(manifest.entries[4].path().exists());
+
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_verify_backup() {
+        let setup = SimpleTableTestSetup::new();
+        let config = Arc::new(TableConfig {
+            incremental_backups: true,
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::new(&config, &setup.schema, &clock).unwrap();
+
+        for pk in 0..2 {
+            table.put(setup.full_row(pk, Some("a"), None)).unwrap();
+            table.flush().unwrap();
+        }
+        table.snapshot("s1").unwrap();
+        let names = table.ss_tables().iter().map(|t| t.name().clone()).collect::<Vec<_>>();
+
+        let data_folder = config.table_data_folders(&setup.schema)[0].clone();
+        let snapshot = data_folder.join("snapshots").join("s1");
+        let backups = data_folder.join(BACKUPS_FOLDER);
+        assert!(verify_backup(&config, &setup.schema, &snapshot).unwrap().is_empty());
+        assert!(verify_backup(&config, &setup.schema, &backups).unwrap().is_empty());
+
+        // all copies are hard links of the same files. Bytes appended to a data file do not break
+        //  the row format, but they change its checksum.
+        std::fs::OpenOptions::new().append(true).open(backups.join(names[1].file_name(SsTableComponent::Data))).unwrap()
+            .write_all(b"garbage").unwrap();
+        assert!(verify_backup(&config, &setup.schema, &snapshot).unwrap().is_empty());
+        let problems = verify_backup(&config, &setup.schema, &backups).unwrap();
+        assert_eq!(problems.iter().map(|p| &p.0).collect::<Vec<_>>(), vec!(&names[1]));
+
+        std::fs::OpenOptions::new().write(true).open(snapshot.join(names[0].file_name(SsTableComponent::Index))).unwrap()
+            .write_all(&u64::MAX.to_ne_bytes()).unwrap();
+        let problems = verify_backup(&config, &setup.schema, &snapshot).unwrap();
+        assert_eq!(problems.iter().map(|p| &p.0).collect::<Vec<_>>(), vec!(&names[0]));
+        let problems = verify_backup(&config, &setup.schema, &backups).unwrap();
+        assert_eq!(problems.iter().map(|p| &p.0).collect::<Vec<_>>(), vec!(&names[0], &names[1]));
+
+        table.drop_table().unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use bytes::Bytes;
+
+use crate::prelude::*;
+
+/// A bounded LRU cache of an SsTable's data file blocks for IoBackend::Pread. Each point read
+///  visits the same first steps of the index search, so their rows - and hot rows in general -
+///  are served from the cache rather than with a read from the file each time.
+///
+/// Blocks are aligned to BLOCK_SIZE in the data file, and a read that spans several blocks is
+///  assembled from them.
+pub struct BlockCache {
+    /// the maximum number of cached blocks
+    capacity: usize,
+    entries: Mutex<LruEntries>,
+}
+
+struct LruEntries {
+    /// block index -> (block data, last use)
+    by_block: HashMap<u64, (Bytes, u64)>,
+    /// last use -> block index, the least recently used block first
+    by_use: BTreeMap<u64, u64>,
+    next_use: u64,
+}
+
+impl BlockCache {
+    pub const BLOCK_SIZE: u64 = 4096;
+
+    /// a capacity of 0 disables the cache
+    pub fn new(capacity: usize) -> BlockCache {
+        BlockCache {
+            capacity,
+            entries: Mutex::new(LruEntries { by_block: HashMap::new(), by_use: BTreeMap::new(), next_use: 0 }),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Fills buf with the file's data starting at offs. Blocks that are not cached are read
+    ///  with read_at (taking a buffer and a file offset) and added to the cache, file_len
+    ///  being the length of the file.
+    pub fn read<F>(&self, buf: &mut [u8], offs: u64, file_len: u64, read_at: F) -> HtResult<()> where F: Fn(&mut [u8], u64) -> HtResult<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offs + done as u64;
+            let idx = pos / BlockCache::BLOCK_SIZE;
+            let block = match self.get(idx) {
+                Some(block) => block,
+                None => {
+                    let start = idx * BlockCache::BLOCK_SIZE;
+                    let mut block = vec![0u8; file_len.saturating_sub(start).min(BlockCache::BLOCK_SIZE) as usize];
+                    read_at(&mut block, start)?;
+                    let block = Bytes::from(block);
+                    self.put(idx, block.clone());
+                    block
+                }
+            };
+
+            let offs_in_block = (pos - idx * BlockCache::BLOCK_SIZE) as usize;
+            if offs_in_block >= block.len() {
+                return Err(HtError::misc("read beyond the end of the file"));
+            }
+            let len = (block.len() - offs_in_block).min(buf.len() - done);
+            buf[done..done + len].copy_from_slice(&block[offs_in_block..offs_in_block + len]);
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn get(&self, idx: u64) -> Option<Bytes> {
+        let mut entries = self.entries.lock().unwrap();
+        let use_tick = entries.next_use;
+
+        let (block, prev_use) = match entries.by_block.get_mut(&idx) {
+            None => return None,
+            Some((block, last_use)) => (block.clone(), std::mem::replace(last_use, use_tick)),
+        };
+        entries.next_use += 1;
+        entries.by_use.remove(&prev_use);
+        entries.by_use.insert(use_tick, idx);
+        Some(block)
+    }
+
+    /// adds a block, evicting the least recently used block if the cache is full
+    fn put(&self, idx: u64, block: Bytes) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let use_tick = entries.next_use;
+        entries.next_use += 1;
+
+        if let Some((_, prev_use)) = entries.by_block.insert(idx, (block, use_tick)) {
+            entries.by_use.remove(&prev_use);
+        }
+        entries.by_use.insert(use_tick, idx);
+
+        if entries.by_block.len() > self.capacity {
+            let (&oldest, _) = entries.by_use.iter().next().unwrap();
+            let evicted = entries.by_use.remove(&oldest).unwrap();
+            entries.by_block.remove(&evicted);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().by_block.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use crate::blockcache::BlockCache;
+    use crate::prelude::*;
+
+    #[test]
+    pub fn test_block_cache() {
+        let file = (0..3 * BlockCache::BLOCK_SIZE as usize + 100).map(|i| i as u8).collect::<Vec<_>>();
+        let reads = Cell::new(0);
+        let read_at = |buf: &mut [u8], offs: u64| -> HtResult<()> {
+            reads.set(reads.get() + 1);
+            buf.copy_from_slice(&file[offs as usize..offs as usize + buf.len()]);
+            Ok(())
+        };
+
+        let cache = BlockCache::new(2);
+        let read = |offs: usize, len: usize| {
+            let mut buf = vec![0u8; len];
+            cache.read(&mut buf, offs as u64, file.len() as u64, read_at).unwrap();
+            assert_eq!(buf, &file[offs..offs + len]);
+        };
+
+        // a read spanning two blocks, then served from the cache
+        read(4000, 200);
+        assert_eq!(reads.get(), 2);
+        read(4090, 10);
+        assert_eq!(reads.get(), 2);
+
+        // the last block is shorter; block 0 is the least recently used and evicted
+        read(3 * BlockCache::BLOCK_SIZE as usize + 50, 50);
+        assert_eq!((reads.get(), cache.len()), (3, 2));
+        read(10, 10);
+        assert_eq!(reads.get(), 4);
+
+        let mut buf = vec![0u8; 10];
+        assert!(cache.read(&mut buf, file.len() as u64 - 5, file.len() as u64, read_at).is_err());
+
+        let disabled = BlockCache::new(0);
+        let mut buf = vec![0u8; 10];
+        disabled.read(&mut buf, 0, file.len() as u64, read_at).unwrap();
+        assert_eq!(disabled.len(), 0);
+    }
+}
@@ -0,0 +1,166 @@
+use std::io::Write;
+
+use crate::prelude::*;
+use crate::primitives::*;
+
+/// A Bloom filter over partition tokens (see RowData::partition_token), allowing reads to skip
+///  SsTables that do not contain a partition without searching their index.
+///
+/// The filter is sized for the estimated number of distinct partitions in its SsTable (see
+///  SsTableStats::partition_count_estimate), so that the false positive rate is close to the
+///  configured one regardless of the SsTable's size. The k bit positions are derived from the
+///  64 bit token by double hashing, so no additional hashing of the partition key is needed.
+///
+/// filter format:
+///   fixed u32         number of hash functions (k); 0 for a filter that matches everything
+///   fixed u64         number of bit words
+///   fixed u64 *       bit words
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BloomFilter {
+    num_hashes: u32,
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    const MAX_HASHES: u32 = 30;
+
+    /// a filter that matches every partition, e.g. if filters are disabled
+    pub fn match_all() -> BloomFilter {
+        BloomFilter { num_hashes: 0, bits: Vec::new() }
+    }
+
+    /// Creates an empty filter sized for the given number of distinct partitions and the desired
+    ///  false positive rate. Adding more partitions than that raises the false positive rate.
+    pub fn with_capacity(num_keys: u64, false_positive_rate: f64) -> BloomFilter {
+        let num_keys = num_keys.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+
+        let num_bits = (-num_keys * false_positive_rate.ln() / (ln2 * ln2)).ceil().max(64.0);
+        let num_words = (num_bits / 64.0).ceil() as usize;
+        let num_hashes = ((num_words * 64) as f64 / num_keys * ln2).round() as u32;
+
+        BloomFilter {
+            num_hashes: num_hashes.clamp(1, BloomFilter::MAX_HASHES),
+            bits: vec!(0; num_words),
+        }
+    }
+
+    /// adds a partition token; adding a token more than once has no effect
+    pub fn add(&mut self, token: u64) {
+        for bit in self.bit_positions(token) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// false if the filter's SsTable definitely does not contain the partition
+    pub fn may_contain(&self, token: u64) -> bool {
+        self.bit_positions(token)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    fn bit_positions(&self, token: u64) -> impl Iterator<Item=usize> {
+        let num_bits = self.bits.len() as u64 * 64;
+        let h1 = token;
+        let h2 = token.rotate_left(32) | 1;
+        (0..self.num_hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+
+    /// the filter's size in memory
+    pub fn size_bytes(&self) -> usize {
+        self.bits.len() * 8
+    }
+
+    /// the false positive rate expected for the given number of distinct partitions
+    pub fn expected_false_positive_rate(&self, num_keys: u64) -> f64 {
+        if self.num_hashes == 0 {
+            return 1.0;
+        }
+        let num_bits = (self.bits.len() * 64) as f64;
+        let k = self.num_hashes as f64;
+        (1.0 - (-k * num_keys as f64 / num_bits).exp()).powf(k)
+    }
+
+    pub fn write_to<W>(&self, w: &mut W) -> std::io::Result<()> where W: Write {
+        w.encode_fixed_u32(self.num_hashes)?;
+        w.encode_fixed_u64(self.bits.len() as u64)?;
+        for &word in &self.bits {
+            w.encode_fixed_u64(word)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(buf: &[u8]) -> HtResult<BloomFilter> {
+        const HEADER_LEN: usize = 12;
+        if buf.len() < HEADER_LEN {
+            return Err(HtError::misc("truncated filter file"));
+        }
+        let mut offs = 0;
+        let num_hashes = buf.decode_fixed_u32(&mut offs);
+        let num_words = buf.decode_fixed_u64(&mut offs);
+        if num_words.checked_mul(8) != Some((buf.len() - HEADER_LEN) as u64) {
+            return Err(HtError::misc("filter file length does not match its number of bit words"));
+        }
+        if num_hashes > BloomFilter::MAX_HASHES || (num_hashes > 0 && num_words == 0) {
+            return Err(HtError::misc("corrupt filter file"));
+        }
+
+        let bits = (0..num_words)
+            .map(|_| buf.decode_fixed_u64(&mut offs))
+            .collect();
+        Ok(BloomFilter { num_hashes, bits })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bloom::BloomFilter;
+    use crate::table::partition_token;
+
+    #[test]
+    pub fn test_bloom_filter() {
+        let tokens = (0..10_000u64).map(|i| partition_token(&i.to_be_bytes())).collect::<Vec<_>>();
+
+        let for_tokens = |tokens: &[u64], rate| {
+            let mut filter = BloomFilter::with_capacity(tokens.len() as u64, rate);
+            for &token in tokens {
+                filter.add(token);
+            }
+            filter
+        };
+
+        for &rate in &[0.1, 0.01, 0.001] {
+            let filter = for_tokens(&tokens, rate);
+            assert!(tokens.iter().all(|&t| filter.may_contain(t)));
+            assert!((filter.expected_false_positive_rate(tokens.len() as u64) - rate).abs() < rate * 0.2);
+
+            let false_positives = (10_000..110_000u64)
+                .filter(|i| filter.may_contain(partition_token(&i.to_be_bytes())))
+                .count();
+            let measured = false_positives as f64 / 100_000.0;
+            assert!(measured < rate * 1.3, "rate {}: measured {}", rate, measured);
+            assert!(measured > rate * 0.7, "rate {}: measured {}", rate, measured);
+        }
+
+        // sized for the number of keys
+        assert!(BloomFilter::with_capacity(100, 0.01).size_bytes() * 50 < BloomFilter::with_capacity(10_000, 0.01).size_bytes());
+
+        let filter = for_tokens(&tokens[..100], 0.01);
+        let mut buf = Vec::new();
+        filter.write_to(&mut buf).unwrap();
+        assert_eq!(BloomFilter::read_from(&buf).unwrap(), filter);
+
+        // corrupt files are rejected
+        assert!(BloomFilter::read_from(&buf[..buf.len() - 1]).is_err());
+        assert!(BloomFilter::read_from(&buf[..5]).is_err());
+        let mut garbled = buf.clone();
+        garbled[4..12].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(BloomFilter::read_from(&garbled).is_err());
+        let mut garbled = buf.clone();
+        garbled[0..4].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(BloomFilter::read_from(&garbled).is_err());
+
+        assert!(BloomFilter::match_all().may_contain(17));
+        assert!(BloomFilter::with_capacity(0, 0.01).size_bytes() > 0);
+    }
+}
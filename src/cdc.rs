@@ -0,0 +1,183 @@
+//! Change data capture: a table's ChangeSink receives every written row after it was applied,
+//!  so every delivered change was applied. If the sink fails, the write returns
+//!  HtError::NotDelivered although it was applied - retrying the write (which is idempotent
+//!  since the row carries its timestamps) delivers it, so every applied write is delivered at
+//!  least once if callers retry failed writes.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::prelude::*;
+use crate::table::{ColumnValue, DetachedRowData};
+use crate::time::MergeTimestamp;
+
+/// A written row. The timestamp is taken when the write is captured, so a write that is
+///  delivered again (e.g. when it is retried) has a different timestamp - it does not identify
+///  duplicates.
+#[derive(Clone, Debug)]
+pub struct ChangeEvent {
+    pub table: String,
+    pub timestamp: MergeTimestamp,
+    pub row: DetachedRowData,
+}
+
+pub trait ChangeSink: Send + Sync {
+    fn on_change(&self, event: &ChangeEvent) -> HtResult<()>;
+}
+
+/// Appends changes to a file as newline delimited JSON, one object per change, e.g.
+///  `{"table":"t","timestamp":123,"deleted":false,"columns":{"pk":1,"text":null}}`. Columns
+///  that are not part of the written row are missing, while deleted columns are null.
+pub struct JsonLinesSink {
+    file: Mutex<File>,
+}
+
+impl JsonLinesSink {
+    pub fn open(path: &Path) -> HtResult<JsonLinesSink> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(JsonLinesSink { file: Mutex::new(file) })
+    }
+
+    pub fn to_json(event: &ChangeEvent) -> String {
+        let row = event.row.row_data_view();
+        let columns = row.columns()
+            .map(|col| {
+                let name = row.schema().column(col.col_id).map(|c| c.name.as_str()).unwrap_or("?");
+                let value = col.value.as_ref().map(json_value).unwrap_or_else(|| "null".to_string());
+                format!("{}:{}", json_string(name), value)
+            })
+            .collect::<Vec<_>>();
+
+        format!("{{\"table\":{},\"timestamp\":{},\"deleted\":{},\"columns\":{{{}}}}}",
+                json_string(&event.table),
+                event.timestamp.ticks,
+                row.row_tombstone().is_some(),
+                columns.join(","))
+    }
+}
+
+impl ChangeSink for JsonLinesSink {
+    fn on_change(&self, event: &ChangeEvent) -> HtResult<()> {
+        let mut line = JsonLinesSink::to_json(event);
+        line.push('\n');
+        self.file.lock().unwrap().write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Date and Time are numbers of days / nanoseconds, Varints that do not fit into an i128 are
+///  strings of their hex magnitude, and values of reserved kinds are strings of their hex data
+fn json_value(value: &ColumnValue) -> String {
+    match value {
+        ColumnValue::Boolean(v) => v.to_string(),
+        ColumnValue::Int(v) => v.to_string(),
+        ColumnValue::BigInt(v) => v.to_string(),
+        ColumnValue::Text(v) => json_string(v),
+        ColumnValue::Date(v) => v.to_string(),
+        ColumnValue::Time(v) => v.to_string(),
+        ColumnValue::Duration { months, days, nanos } => format!("{{\"months\":{},\"days\":{},\"nanos\":{}}}", months, days, nanos),
+        ColumnValue::Varint(v) => match v.as_i128() {
+            Some(v) => v.to_string(),
+            None => format!("\"{}0x{}\"",
+                            if v.negative { "-" } else { "" },
+                            v.magnitude.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+        },
+        ColumnValue::Unknown { data, .. } => format!("\"0x{}\"", data.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use crate::cdc::{ChangeEvent, ChangeSink, json_string, json_value, JsonLinesSink};
+    use crate::config::{StorageMode, TableConfig};
+    use crate::engine::Table;
+    use crate::prelude::*;
+    use crate::table::{ColumnValue, DetachedRowData, Varint};
+    use crate::testutils::{SimpleTableTestSetup, test_table_config};
+    use crate::time::HtClock;
+
+    #[test]
+    pub fn test_json_lines_sink() {
+        let setup = SimpleTableTestSetup::new();
+        let config = test_table_config();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let mut table = Table::new(&config, &setup.schema, &clock).unwrap();
+
+        let path = config.base_folder.join("cdc-test.jsonl");
+        let _ = std::fs::remove_file(&path);
+        table.set_change_sink(Some(Box::new(JsonLinesSink::open(&path).unwrap())));
+
+        table.put(setup.full_row(1, Some("a\"b"), None)).unwrap();
+        table.try_put(setup.partial_row(2, None)).unwrap();
+        table.delete(&setup.pk_row(1)).unwrap();
+
+        let ts = setup.clock.now().ticks;
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().collect::<Vec<_>>(), vec!(
+            format!("{{\"table\":\"test_table\",\"timestamp\":{},\"deleted\":false,\"columns\":{{\"pk\":1,\"text\":\"a\\\"b\",\"int\":null}}}}", ts),
+            format!("{{\"table\":\"test_table\",\"timestamp\":{},\"deleted\":false,\"columns\":{{\"pk\":2,\"text\":null}}}}", ts),
+            format!("{{\"table\":\"test_table\",\"timestamp\":{},\"deleted\":true,\"columns\":{{\"pk\":1}}}}", ts),
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_failed_write() {
+        struct CollectChanges(Arc<Mutex<Vec<DetachedRowData>>>);
+        impl ChangeSink for CollectChanges {
+            fn on_change(&self, event: &ChangeEvent) -> HtResult<()> {
+                self.0.lock().unwrap().push(event.row.clone());
+                Ok(())
+            }
+        }
+
+        let setup = SimpleTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let row_size = setup.full_row(0, Some("a"), None).encoded_len();
+        let config = Arc::new(TableConfig {
+            storage_mode: StorageMode::InMemory { max_size: Some(row_size), evict: false },
+            ..TableConfig::new(test_table_config().base_folder.join("cdc-in-memory"))
+        });
+        let mut table = Table::new(&config, &setup.schema, &clock).unwrap();
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        table.set_change_sink(Some(Box::new(CollectChanges(changes.clone()))));
+
+        // a write that is not applied is not delivered
+        table.put(setup.full_row(1, Some("a"), None)).unwrap();
+        assert!(table.put(setup.full_row(2, Some("a"), None)).is_err());
+        let changes = changes.lock().unwrap();
+        assert_eq!(changes.iter().map(|row| setup.pk(&row.row_data_view())).collect::<Vec<_>>(), vec!(1));
+    }
+
+    #[test]
+    pub fn test_json_value() {
+        assert_eq!(json_string("a\n\u{1}ä"), "\"a\\n\\u0001ä\"");
+        assert_eq!(json_value(&ColumnValue::Duration { months: 1, days: -2, nanos: 3 }), "{\"months\":1,\"days\":-2,\"nanos\":3}");
+        assert_eq!(json_value(&ColumnValue::Varint(Varint::new(true, &[1, 0]))), "-256");
+        assert_eq!(json_value(&ColumnValue::Varint(Varint::new(false, &[1; 17]))), format!("\"0x{}\"", "01".repeat(17)));
+    }
+}
@@ -0,0 +1,155 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::config::TableConfig;
+use crate::manifest::Manifest;
+use crate::prelude::*;
+use crate::sstable::{SsTable, SsTableName};
+use crate::table::TableSchema;
+
+/// The result of checking a table's files, see check_table
+#[derive(Debug)]
+pub struct TableHealth {
+    /// the SsTables listed in the manifest
+    pub live: Vec<SsTableName>,
+    /// SsTables listed in the manifest that can not be opened, e.g. because files are missing
+    pub unreadable: Vec<(SsTableName, String)>,
+    /// SsTables that can be opened but fail SsTable::verify
+    pub corrupt: Vec<(SsTableName, String)>,
+    /// SsTables that are not listed in the manifest, i.e. leftovers of an interrupted flush or
+    ///  compaction. They do not affect the table's data.
+    pub orphaned: Vec<(SsTableName, PathBuf)>,
+}
+
+impl TableHealth {
+    /// true if all live SsTables are readable and consistent
+    pub fn is_healthy(&self) -> bool {
+        self.unreadable.is_empty() && self.corrupt.is_empty()
+    }
+}
+
+/// Checks a table's manifest against its data folders and verifies all live SsTables, e.g. before
+///  a node starts serving. This does not require the table to be opened, and it fails only if the
+///  manifest or the data folders can not be read - problems with SsTables are reported in the
+///  TableHealth.
+///
+/// There is no catalog of tables yet, so this checks a single table. SsTables have no checksums
+///  yet, so corrupted values are detected only if they break the row format.
+pub fn check_table(config: &Arc<TableConfig>, schema: &Arc<TableSchema>) -> HtResult<TableHealth> {
+    let manifest = Manifest::read(config, schema)?;
+
+    let mut health = TableHealth {
+        live: manifest.entries.iter().map(|e| e.name.clone()).collect(),
+        unreadable: Vec::new(),
+        corrupt: Vec::new(),
+        orphaned: Vec::new(),
+    };
+
+    for entry in &manifest.entries {
+        match SsTable::open(config, schema, &entry.folder, &entry.name) {
+            Err(e) => health.unreadable.push((entry.name.clone(), format!("{:?}", e))),
+            Ok(ss_table) => if let Err(e) = ss_table.verify() {
+                health.corrupt.push((entry.name.clone(), format!("{:?}", e)));
+            },
+        }
+    }
+
+    for (name, folder) in SsTableName::discover(config, schema)? {
+        if !health.live.contains(&name) {
+            health.orphaned.push((name, folder));
+        }
+    }
+    Ok(health)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Seek, SeekFrom, Write};
+    use std::sync::Arc;
+
+    use crate::check::check_table;
+    use crate::engine::Table;
+    use crate::sstable::{SsTableComponent, SsTableName};
+    use crate::testutils::{SimpleTableTestSetup, test_table_config};
+    use crate::time::HtClock;
+
+    #[test]
+    pub fn test_check_table() {
+        let setup = SimpleTableTestSetup::new();
+        let config = test_table_config();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::new(&config, &setup.schema, &clock).unwrap();
+
+        for pk in 0..3 {
+            table.put(setup.full_row(pk, Some("a"), Some(pk))).unwrap();
+            table.flush().unwrap();
+        }
+
+        let health = check_table(&config, &setup.schema).unwrap();
+        assert!(health.is_healthy());
+        assert_eq!(health.live.len(), 3);
+        assert!(health.orphaned.is_empty());
+
+        let folder = config.table_data_folders(&setup.schema)[0].clone();
+        let file = |name: &SsTableName, component| folder.join(name.file_name(component));
+
+        // a leftover of an interrupted flush
+        let leftover = SsTableName::new(&setup.schema.name, 99);
+        std::fs::write(file(&leftover, SsTableComponent::Data), b"").unwrap();
+
+        // an SsTable with a missing file, and one with an index pointing beyond its data
+        std::fs::remove_file(file(&health.live[0], SsTableComponent::Stats)).unwrap();
+        std::fs::OpenOptions::new().write(true).open(file(&health.live[1], SsTableComponent::Index)).unwrap()
+            .write_all(&u64::MAX.to_ne_bytes()).unwrap();
+
+        let health = check_table(&config, &setup.schema).unwrap();
+        assert!(!health.is_healthy());
+        assert_eq!(health.unreadable.iter().map(|e| &e.0).collect::<Vec<_>>(), vec!(&health.live[0]));
+        assert_eq!(health.corrupt.iter().map(|e| &e.0).collect::<Vec<_>>(), vec!(&health.live[1]));
+        assert_eq!(health.orphaned, vec!((leftover, folder)));
+
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_check_table_garbled() {
+        let setup = SimpleTableTestSetup::new();
+        let config = test_table_config();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::new(&config, &setup.schema, &clock).unwrap();
+
+        for pk in 0..6 {
+            table.put(setup.full_row(pk, Some("abcdefghij"), Some(pk))).unwrap();
+            table.put(setup.full_row(pk + 100, Some("abcdefghij"), Some(pk))).unwrap();
+            table.flush().unwrap();
+        }
+
+        let health = check_table(&config, &setup.schema).unwrap();
+        assert!(health.is_healthy());
+
+        let folder = config.table_data_folders(&setup.schema)[0].clone();
+        let file = |name: &SsTableName, component| folder.join(name.file_name(component));
+        let open = |name: &SsTableName, component| std::fs::OpenOptions::new().write(true).open(file(name, component)).unwrap();
+        let garble = |name: &SsTableName, component, offs: u64, len: usize| {
+            let mut f = open(name, component);
+            f.seek(SeekFrom::Start(offs)).unwrap();
+            f.write_all(&vec!(0xffu8; len)).unwrap();
+        };
+
+        // truncated data and index files
+        open(&health.live[0], SsTableComponent::Data).set_len(12).unwrap();
+        open(&health.live[1], SsTableComponent::Index).set_len(12).unwrap();
+        // a garbled row length, and garbled row contents
+        garble(&health.live[2], SsTableComponent::Data, 8, 12);
+        garble(&health.live[3], SsTableComponent::Data, 9, 20);
+        // a truncated data file header and stats file
+        open(&health.live[4], SsTableComponent::Data).set_len(4).unwrap();
+        open(&health.live[5], SsTableComponent::Stats).set_len(4).unwrap();
+
+        let health = check_table(&config, &setup.schema).unwrap();
+        assert_eq!(health.corrupt.iter().map(|e| &e.0).collect::<Vec<_>>(), health.live[0..4].iter().collect::<Vec<_>>());
+        assert_eq!(health.unreadable.iter().map(|e| &e.0).collect::<Vec<_>>(), health.live[4..6].iter().collect::<Vec<_>>());
+
+        table.drop_table().unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use log::warn;
+
+use crate::time::{MergeTimestamp, WallClock};
+
+/// What a skew was measured against, see ClockSkewMonitor
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ClockSkewSource {
+    /// the wall clock moved differently from the monotonic clock since the previous check, e.g.
+    ///  because it was set manually or by NTP
+    Monotonic,
+    /// a timestamp received from another node differed from the local wall clock
+    Remote,
+}
+
+pub trait ClockSkewCallback: Send + Sync {
+    /// skew_millis is positive if the wall clock (or the remote node) is ahead
+    fn on_clock_skew(&self, source: ClockSkewSource, skew_millis: i64);
+}
+
+struct NoClockSkewCallback {}
+
+impl ClockSkewCallback for NoClockSkewCallback {
+    fn on_clock_skew(&self, _source: ClockSkewSource, _skew_millis: i64) {}
+}
+
+/// Detects skew of a node's wall clock, which MergeTimestamps are based on. Last-write-wins
+///  silently drops writes from a node whose clock lags behind, so skew beyond a threshold is
+///  logged, counted and passed to a callback.
+///
+/// The wall clock is compared with the monotonic clock at each check, so a step of the wall
+///  clock between two checks is reported once. Drift that is too slow to exceed the threshold
+///  between checks, and a wall clock that was wrong from the start, can only be detected by
+///  comparing with other nodes' timestamps. There is no networking yet; once there is,
+///  timestamps received from other nodes are passed to observe_remote.
+pub struct ClockSkewMonitor {
+    max_skew_millis: u64,
+    callback: Box<dyn ClockSkewCallback>,
+    /// (wall clock in HT epoch millis, monotonic clock) at the previous check
+    baseline: Mutex<Option<(u64, Instant)>>,
+    skew_events: AtomicU64,
+    /// the largest absolute skew seen so far, from either source
+    max_observed_skew_millis: AtomicU64,
+}
+
+impl ClockSkewMonitor {
+    pub fn new(max_skew_millis: u64, callback: Box<dyn ClockSkewCallback>) -> ClockSkewMonitor {
+        ClockSkewMonitor {
+            max_skew_millis,
+            callback,
+            baseline: Mutex::new(None),
+            skew_events: AtomicU64::new(0),
+            max_observed_skew_millis: AtomicU64::new(0),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn new_without_callback(max_skew_millis: u64) -> ClockSkewMonitor {
+        ClockSkewMonitor::new(max_skew_millis, Box::new(NoClockSkewCallback {}))
+    }
+
+    /// compares the wall clock with the monotonic clock now, e.g. from a periodic task
+    pub fn check(&self) -> i64 {
+        self.check_at(WallClock::ht_epoch_millis(), Instant::now())
+    }
+
+    /// Returns the difference between the time that passed on the wall clock and on the
+    ///  monotonic clock since the previous check, which is 0 for the first check
+    pub fn check_at(&self, wall_millis: u64, monotonic: Instant) -> i64 {
+        let mut baseline = self.baseline.lock().unwrap();
+        let skew_millis = match *baseline {
+            None => 0,
+            Some((prev_wall_millis, prev_monotonic)) => {
+                let wall_elapsed = wall_millis as i64 - prev_wall_millis as i64;
+                let monotonic_elapsed = monotonic.saturating_duration_since(prev_monotonic).as_millis() as i64;
+                wall_elapsed - monotonic_elapsed
+            }
+        };
+        *baseline = Some((wall_millis, monotonic));
+
+        self.record(ClockSkewSource::Monotonic, skew_millis);
+        skew_millis
+    }
+
+    /// Returns the difference between a timestamp received from another node and the local
+    ///  wall clock. This includes the time the timestamp took to arrive, so the threshold
+    ///  must be well above the network latency.
+    pub fn observe_remote(&self, remote: MergeTimestamp, local: MergeTimestamp) -> i64 {
+        let skew_millis = remote.epoch_millis() as i64 - local.epoch_millis() as i64;
+        self.record(ClockSkewSource::Remote, skew_millis);
+        skew_millis
+    }
+
+    fn record(&self, source: ClockSkewSource, skew_millis: i64) {
+        self.max_observed_skew_millis.fetch_max(skew_millis.unsigned_abs(), Ordering::Relaxed);
+        if skew_millis.unsigned_abs() > self.max_skew_millis {
+            self.skew_events.fetch_add(1, Ordering::Relaxed);
+            warn!("clock skew of {} ms ({:?}) exceeds the threshold of {} ms", skew_millis, source, self.max_skew_millis);
+            self.callback.on_clock_skew(source, skew_millis);
+        }
+    }
+
+    /// the number of checks and observations that exceeded the threshold
+    pub fn skew_events(&self) -> u64 {
+        self.skew_events.load(Ordering::Relaxed)
+    }
+
+    pub fn max_observed_skew_millis(&self) -> u64 {
+        self.max_observed_skew_millis.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use crate::clockskew::{ClockSkewCallback, ClockSkewMonitor, ClockSkewSource};
+    use crate::time::MergeTimestamp;
+
+    struct RecordingCallback {
+        events: Arc<Mutex<Vec<(ClockSkewSource, i64)>>>,
+    }
+
+    impl ClockSkewCallback for RecordingCallback {
+        fn on_clock_skew(&self, source: ClockSkewSource, skew_millis: i64) {
+            self.events.lock().unwrap().push((source, skew_millis));
+        }
+    }
+
+    #[test]
+    pub fn test_clock_skew() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let monitor = ClockSkewMonitor::new(500, Box::new(RecordingCallback { events: events.clone() }));
+        let start = Instant::now();
+
+        assert_eq!(monitor.check_at(100_000, start), 0);
+        assert_eq!(monitor.check_at(101_100, start + Duration::from_millis(1000)), 100);
+
+        // the wall clock was set back by 2 seconds, which is reported once
+        assert_eq!(monitor.check_at(100_100, start + Duration::from_millis(2000)), -2000);
+        assert_eq!(monitor.check_at(101_100, start + Duration::from_millis(3000)), 0);
+
+        let local = MergeTimestamp::new(200_000, 0, 1, 0);
+        assert_eq!(monitor.observe_remote(MergeTimestamp::new(200_300, 5, 2, 0), local), 300);
+        assert_eq!(monitor.observe_remote(MergeTimestamp::new(201_000, 0, 2, 0), local), 1000);
+
+        assert_eq!(*events.lock().unwrap(), vec!((ClockSkewSource::Monotonic, -2000), (ClockSkewSource::Remote, 1000)));
+        assert_eq!(monitor.skew_events(), 2);
+        assert_eq!(monitor.max_observed_skew_millis(), 2000);
+
+        let monitor = ClockSkewMonitor::new_without_callback(500);
+        monitor.check();
+        assert!(monitor.check().abs() < 100);
+    }
+}
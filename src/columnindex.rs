@@ -0,0 +1,325 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem::size_of;
+use std::path::PathBuf;
+
+use memmap::{Mmap, MmapOptions};
+
+use crate::prelude::*;
+use crate::primitives::*;
+use crate::table::{ColumnId, ColumnValue, DetachedRowData, RowData};
+
+/// An SsTable's index of the values of regular columns (see TableConfig::indexed_columns),
+///  mapping each value to the data file offsets of the rows that have it. This is attached to
+///  the SsTable like its BloomFilter and written along with it during flush and compaction, so
+///  indexing causes no additional writes. A query by value looks up every SsTable's index
+///  and scans the MemTables, see Table::find_by_index.
+///
+/// An SsTable's index only reflects the SsTable's own rows: a row found by value may have been
+///  changed or deleted in a newer SsTable, so the merged rows must be checked again.
+///
+/// The index is memory mapped and binary searched like the SsTable's primary key index. It
+///  holds a fixed size entry per (value, row), sorted by a hash of the value, so the index
+///  supports lookups by equality only. Rows of other values with the same hash are returned
+///  as well, which the check of the merged rows filters out.
+///
+/// An SsTable without an index file (or with an empty one) has no indexed columns.
+///
+/// index format:
+///   fixed u32         number of indexed columns
+///   per column:
+///     u8              column id
+///     fixed u64       number of entries
+///   entries of each column, in the order above, ascending by (hash, offset):
+///     fixed u64       hash of the value, encoded like primary key values (see value_hash)
+///     fixed u64       the row's offset in the data file
+pub struct ColumnIndex {
+    mmap: Option<Mmap>,
+    /// (column id, offset of its first entry, number of entries)
+    columns: Vec<(ColumnId, usize, usize)>,
+}
+
+const ENTRY_LEN: usize = 2 * size_of::<u64>();
+const COLUMN_HEADER_LEN: usize = 1 + size_of::<u64>();
+
+fn value_hash(value: &ColumnValue) -> u64 {
+    let mut buf = Vec::new();
+    DetachedRowData::encode_column_value(&mut buf, value);
+    seahash::hash(&buf)
+}
+
+impl ColumnIndex {
+    /// the index of an SsTable that has no indexed columns
+    pub fn empty() -> ColumnIndex {
+        ColumnIndex { mmap: None, columns: Vec::new() }
+    }
+
+    /// Maps an index file, checking that its header matches its length
+    pub fn open(file: &File) -> HtResult<ColumnIndex> {
+        if file.metadata()?.len() == 0 {
+            return Ok(ColumnIndex::empty());
+        }
+        let mmap = unsafe { MmapOptions::new().map(file) }?;
+
+        let corrupt = || HtError::misc("corrupt column index file");
+        if mmap.len() < size_of::<u32>() {
+            return Err(corrupt());
+        }
+        let mut offs = 0;
+        let num_columns = mmap.decode_fixed_u32(&mut offs) as usize;
+        let mut entries_offs = num_columns.checked_mul(COLUMN_HEADER_LEN)
+            .and_then(|len| len.checked_add(size_of::<u32>()))
+            .filter(|&len| len <= mmap.len())
+            .ok_or_else(corrupt)?;
+
+        let mut columns = Vec::new();
+        for _ in 0..num_columns {
+            let col_id = ColumnId(mmap.decode_u8(&mut offs));
+            let num_entries = usize::try_from(mmap.decode_fixed_u64(&mut offs)).map_err(|_| corrupt())?;
+            columns.push((col_id, entries_offs, num_entries));
+            entries_offs = num_entries.checked_mul(ENTRY_LEN)
+                .and_then(|len| len.checked_add(entries_offs))
+                .ok_or_else(corrupt)?;
+        }
+        if entries_offs != mmap.len() {
+            return Err(corrupt());
+        }
+        Ok(ColumnIndex { mmap: Some(mmap), columns })
+    }
+
+    fn column(&self, col_id: ColumnId) -> Option<(usize, usize)> {
+        self.columns.iter()
+            .find(|c| c.0 == col_id)
+            .map(|&(_, offs, num_entries)| (offs, num_entries))
+    }
+
+    /// the (value hash, row offset) entry at a column's idx'th position
+    fn entry(&self, column_offs: usize, idx: usize) -> (u64, u64) {
+        let buf = self.mmap.as_ref().expect("columns without a mapped file");
+        let mut offs = column_offs + idx * ENTRY_LEN;
+        (buf.decode_fixed_u64(&mut offs), buf.decode_fixed_u64(&mut offs))
+    }
+
+    /// true if the SsTable was written while the column was indexed. SsTables written before
+    ///  that have no entries for it until they are compacted.
+    pub fn is_indexed(&self, col_id: ColumnId) -> bool {
+        self.column(col_id).is_some()
+    }
+
+    /// the data file offsets of the rows with the given value (and possibly of rows with values
+    ///  of the same hash) in ascending order, or None if the column is not indexed
+    pub fn lookup(&self, col_id: ColumnId, value: &ColumnValue) -> Option<Vec<u64>> {
+        let (column_offs, num_entries) = self.column(col_id)?;
+        let hash = value_hash(value);
+
+        let mut lo = 0;
+        let mut hi = num_entries;
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.entry(column_offs, mid).0 < hash {
+                lo = mid + 1;
+            }
+            else {
+                hi = mid;
+            }
+        }
+
+        Some((lo..num_entries)
+            .map(|idx| self.entry(column_offs, idx))
+            .take_while(|&(h, _)| h == hash)
+            .map(|(_, offs)| offs)
+            .collect())
+    }
+
+    /// the number of (value, row) entries for a column
+    pub fn num_entries(&self, col_id: ColumnId) -> usize {
+        self.column(col_id)
+            .map(|(_, num_entries)| num_entries)
+            .unwrap_or(0)
+    }
+}
+
+/// Collects a ColumnIndex while an SsTable is written. Entries are buffered in memory up to a
+///  limit, and then sorted and spilled to a temporary file as a sorted run. Writing the index
+///  merges the runs, so memory use does not grow with the SsTable's size.
+pub struct ColumnIndexCollector {
+    col_ids: Vec<ColumnId>,
+    max_buffered_entries: usize,
+    /// per column, the (value hash, row offset) entries that were not spilled yet
+    buffered: Vec<Vec<(u64, u64)>>,
+    spill_path: PathBuf,
+    spill_file: Option<BufWriter<File>>,
+    spill_len: usize,
+    /// per column, the spilled runs as (offset in the spill file, number of entries)
+    runs: Vec<Vec<(usize, usize)>>,
+}
+
+impl ColumnIndexCollector {
+    /// the number of entries collected in memory before they are spilled
+    pub const DEFAULT_MAX_BUFFERED_ENTRIES: usize = 1 << 20;
+
+    /// spill_path is the temporary file that is used if more than max_buffered_entries
+    ///  entries are collected. It is removed when the index is written.
+    pub fn new(col_ids: &[ColumnId], spill_path: PathBuf, max_buffered_entries: usize) -> ColumnIndexCollector {
+        ColumnIndexCollector {
+            col_ids: col_ids.to_vec(),
+            max_buffered_entries,
+            buffered: col_ids.iter().map(|_| Vec::new()).collect(),
+            spill_path,
+            spill_file: None,
+            spill_len: 0,
+            runs: col_ids.iter().map(|_| Vec::new()).collect(),
+        }
+    }
+
+    /// offs is the row's offset in the data file. NULL columns and columns that were not
+    ///  written are not indexed.
+    pub fn add_row(&mut self, row: &RowData, offs: u64) -> HtResult<()> {
+        for (i, &col_id) in self.col_ids.iter().enumerate() {
+            if let Some(value) = row.read_col_by_id(col_id).and_then(|col| col.value) {
+                self.buffered[i].push((value_hash(&value), offs));
+            }
+        }
+        if self.buffered.iter().map(|b| b.len()).sum::<usize>() >= self.max_buffered_entries {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> HtResult<()> {
+        if self.spill_file.is_none() {
+            self.spill_file = Some(BufWriter::new(File::create(&self.spill_path)?));
+        }
+        let spill_file = self.spill_file.as_mut().unwrap();
+
+        for (buffered, runs) in self.buffered.iter_mut().zip(self.runs.iter_mut()) {
+            buffered.sort_unstable();
+            runs.push((self.spill_len, buffered.len()));
+            for &(hash, offs) in buffered.iter() {
+                spill_file.encode_fixed_u64(hash)?;
+                spill_file.encode_fixed_u64(offs)?;
+            }
+            self.spill_len += buffered.len() * ENTRY_LEN;
+            buffered.clear();
+        }
+        Ok(())
+    }
+
+    /// writes the index, merging the spilled runs with the entries buffered in memory
+    pub fn write_to<W>(mut self, w: &mut W) -> HtResult<()> where W: Write {
+        let spilled = match self.spill_file.take() {
+            None => None,
+            Some(spill_file) => {
+                spill_file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+                let file = File::open(&self.spill_path)?;
+                Some(unsafe { MmapOptions::new().map(&file) }?)
+            }
+        };
+
+        let result = self.write_merged(w, spilled.as_deref());
+        if spilled.is_some() {
+            std::fs::remove_file(&self.spill_path)?;
+        }
+        result
+    }
+
+    fn write_merged<W>(&mut self, w: &mut W, spilled: Option<&[u8]>) -> HtResult<()> where W: Write {
+        w.encode_fixed_u32(self.col_ids.len() as u32)?;
+        for (i, &col_id) in self.col_ids.iter().enumerate() {
+            let num_entries = self.buffered[i].len() + self.runs[i].iter().map(|r| r.1).sum::<usize>();
+            w.encode_u8(col_id.0)?;
+            w.encode_fixed_u64(num_entries as u64)?;
+        }
+
+        for (buffered, runs) in self.buffered.iter_mut().zip(self.runs.iter()) {
+            buffered.sort_unstable();
+            let mut sources: Vec<Box<dyn Iterator<Item=(u64, u64)> + '_>> = vec!(Box::new(buffered.iter().copied()));
+            if let Some(spilled) = spilled {
+                for &(run_offs, num_entries) in runs {
+                    sources.push(Box::new((0..num_entries).map(move |idx| {
+                        let mut offs = run_offs + idx * ENTRY_LEN;
+                        (spilled.decode_fixed_u64(&mut offs), spilled.decode_fixed_u64(&mut offs))
+                    })));
+                }
+            }
+
+            let mut heap = BinaryHeap::new();
+            for (source_idx, source) in sources.iter_mut().enumerate() {
+                if let Some(entry) = source.next() {
+                    heap.push(Reverse((entry, source_idx)));
+                }
+            }
+            while let Some(Reverse(((hash, offs), source_idx))) = heap.pop() {
+                w.encode_fixed_u64(hash)?;
+                w.encode_fixed_u64(offs)?;
+                if let Some(entry) = sources[source_idx].next() {
+                    heap.push(Reverse((entry, source_idx)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+
+    use crate::columnindex::{ColumnIndex, ColumnIndexCollector};
+    use crate::table::{ColumnId, ColumnValue};
+    use crate::testutils::{SimpleTableTestSetup, test_table_config};
+
+    #[test]
+    pub fn test_column_index() {
+        let setup = SimpleTableTestSetup::new();
+        let folder = test_table_config().table_folder(&setup.schema);
+        std::fs::create_dir_all(&folder).unwrap();
+        let index_path = folder.join("test-columnindex");
+        let spill_path = folder.join("test-columnindex.spill");
+
+        let rows = [
+            setup.full_row(1, Some("a"), Some(10)),
+            setup.full_row(2, Some("b"), None),
+            setup.partial_row(3, Some("a")),
+        ];
+
+        // max_buffered_entries of 2 spills after every row
+        for &max_buffered_entries in &[ColumnIndexCollector::DEFAULT_MAX_BUFFERED_ENTRIES, 2] {
+            let mut collector = ColumnIndexCollector::new(&[ColumnId(1), ColumnId(2)], spill_path.clone(), max_buffered_entries);
+            for (i, row) in rows.iter().enumerate() {
+                collector.add_row(&row.row_data_view(), 100 * i as u64).unwrap();
+            }
+            let mut file = File::create(&index_path).unwrap();
+            collector.write_to(&mut file).unwrap();
+            assert!(!spill_path.exists());
+
+            let index = ColumnIndex::open(&File::open(&index_path).unwrap()).unwrap();
+            assert_eq!(index.lookup(ColumnId(1), &ColumnValue::Text("a")), Some(vec!(0, 200)));
+            assert_eq!(index.lookup(ColumnId(1), &ColumnValue::Text("b")), Some(vec!(100)));
+            assert_eq!(index.lookup(ColumnId(1), &ColumnValue::Text("c")), Some(vec!()));
+            assert_eq!(index.lookup(ColumnId(2), &ColumnValue::Int(10)), Some(vec!(0)));
+            assert_eq!(index.lookup(ColumnId(0), &ColumnValue::BigInt(1)), None);
+            assert!(index.is_indexed(ColumnId(2)));
+            assert!(!index.is_indexed(ColumnId(0)));
+            assert_eq!(index.num_entries(ColumnId(1)), 3);
+            assert_eq!(index.num_entries(ColumnId(2)), 1);
+        }
+
+        let mut file = File::create(&index_path).unwrap();
+        ColumnIndexCollector::new(&[], spill_path.clone(), 2).write_to(&mut file).unwrap();
+        assert!(!ColumnIndex::open(&File::open(&index_path).unwrap()).unwrap().is_indexed(ColumnId(1)));
+
+        // an empty file has no indexed columns, corrupt files are rejected
+        std::fs::write(&index_path, b"").unwrap();
+        assert!(!ColumnIndex::open(&File::open(&index_path).unwrap()).unwrap().is_indexed(ColumnId(1)));
+        for garbled in [&b"ab"[..], &[1, 0, 0, 0, 1][..], &[1, 0, 0, 0, 1, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff][..]] {
+            std::fs::write(&index_path, garbled).unwrap();
+            assert!(ColumnIndex::open(&File::open(&index_path).unwrap()).is_err());
+        }
+
+        test_table_config().remove_table_folder(&setup.schema).unwrap();
+    }
+}
@@ -0,0 +1,996 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use crate::config::{AccessAdvice, CompactionStrategyConfig, TableConfig};
+use crate::hll::HyperLogLog;
+use crate::prelude::*;
+use crate::sstable::{SsTable, SsTableGenerations, SsTableName, SsTableWriter};
+use crate::table::{partition_token, DetachedRowData, RowColumnIter, RowData, SeekableRows, TableSchema};
+use crate::tasks::TaskProgress;
+use crate::time::{HtClock, MergeTimestamp, TtlTimestamp};
+
+/// Merges several row sources - each of which is sorted by primary key - into a single sorted
+///  sequence of rows, merging rows with the same primary key.
+pub struct MergeIterator<'a> {
+    sources: Vec<Box<dyn SeekableRows<'a> + 'a>>,
+    heads: Vec<Option<RowData<'a>>>,
+}
+
+impl <'a> MergeIterator<'a> {
+    pub fn new(mut sources: Vec<Box<dyn SeekableRows<'a> + 'a>>) -> HtResult<MergeIterator<'a>> {
+        let mut heads = Vec::new();
+        for source in sources.iter_mut() {
+            heads.push(source.next().transpose()?);
+        }
+
+        Ok(MergeIterator { sources, heads })
+    }
+
+    pub fn from_ss_tables(ss_tables: &[&'a SsTable]) -> HtResult<MergeIterator<'a>> {
+        let sources = ss_tables.iter()
+            .map(|t| Box::new(t.rows()) as Box<dyn SeekableRows<'a> + 'a>)
+            .collect();
+        MergeIterator::new(sources)
+    }
+
+    /// Repositions all sources so that the next merged row is the first one with a primary key
+    ///  greater than or equal to that of pks. This is a binary search in each source, so
+    ///  seeking forward to skip large ranges is much cheaper than iterating over them.
+    pub fn seek(&mut self, pks: &RowData) -> HtResult<()> {
+        for (source, head) in self.sources.iter_mut().zip(self.heads.iter_mut()) {
+            source.seek(pks)?;
+            *head = source.next().transpose()?;
+        }
+        Ok(())
+    }
+
+    /// indices of all sources whose current row has the smallest primary key
+    fn min_indices(&self) -> Vec<usize> {
+        let mut result: Vec<usize> = Vec::new();
+
+        for (idx, head) in self.heads.iter().enumerate() {
+            if let Some(row) = head {
+                let cmp = match result.first() {
+                    None => Ordering::Less,
+                    Some(&min_idx) => row.compare_by_pk(self.heads[min_idx].as_ref().unwrap()),
+                };
+
+                match cmp {
+                    Ordering::Less => {
+                        result.clear();
+                        result.push(idx);
+                    },
+                    Ordering::Equal => result.push(idx),
+                    Ordering::Greater => {},
+                }
+            }
+        }
+        result
+    }
+
+    fn advance(&mut self, idx: usize) -> HtResult<RowData<'a>> {
+        let next = self.sources[idx].next().transpose()?;
+        Ok(std::mem::replace(&mut self.heads[idx], next).unwrap())
+    }
+}
+
+impl <'a> Iterator for MergeIterator<'a> {
+    type Item = HtResult<DetachedRowData>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let indices = self.min_indices();
+        if indices.is_empty() {
+            return None;
+        }
+
+        let mut merged: Option<DetachedRowData> = None;
+        for idx in indices {
+            let row = match self.advance(idx) {
+                Ok(row) => row,
+                Err(e) => return Some(Err(e)),
+            };
+
+            merged = Some(match merged {
+                None => row.detach(),
+                Some(prev) => prev.row_data_view().merge(&row),
+            });
+        }
+        merged.map(Ok)
+    }
+}
+
+/// The thresholds for discarding data during compaction. Expired columns and tombstones are kept
+///  for TableConfig::gc_grace_seconds to ensure they shadow older data they replaced in other
+///  SsTables (or on other replicas), and are discarded afterwards.
+#[derive(Copy, Clone, Debug)]
+pub struct GcThresholds {
+    /// tombstones (i.e. NULL columns) written before this timestamp are discarded
+    pub gc_before: MergeTimestamp,
+    /// columns that expired before this are discarded
+    pub expired_before: TtlTimestamp,
+}
+
+impl GcThresholds {
+    pub fn new(clock: &dyn HtClock, gc_grace_seconds: u32) -> GcThresholds {
+        let now = clock.now();
+        let now_ttl = clock.ttl_timestamp(0);
+
+        GcThresholds {
+            gc_before: now.minus_seconds(gc_grace_seconds as u64),
+            expired_before: TtlTimestamp::new(now_ttl.epoch_seconds.saturating_sub(gc_grace_seconds)),
+        }
+    }
+
+    /// Discards GC-able tombstones (including row tombstones), expired columns and expired
+    ///  touches from a row. Returns None if neither regular columns nor a row tombstone or touch
+    ///  are left, i.e. if the entire row can be discarded.
+    ///
+    /// shadowed_since is the oldest timestamp of SsTables outside the compaction that may hold
+    ///  data of the row's partition (see oldest_overlapping). Tombstones and expired columns that
+    ///  are not older than that are retained regardless of gc grace, since discarding them would
+    ///  resurrect the data they replaced.
+    ///
+    /// touched_until is the latest expiry of touches in SsTables outside the compaction that may
+    ///  hold the row's partition (see latest_overlapping_touch). Such a touch may extend the
+    ///  expiry of the row's columns when it is merged with them, so expired columns are
+    ///  retained while it has not expired beyond gc grace.
+    pub fn purge(&self, row: &RowData, shadowed_since: Option<MergeTimestamp>, touched_until: Option<TtlTimestamp>) -> Option<DetachedRowData> {
+        let shadows_nothing = |timestamp: MergeTimestamp| match shadowed_since {
+            Some(since) => timestamp < since,
+            None => true,
+        };
+
+        let may_be_touched = touched_until.is_some_and(|expiry| expiry >= self.expired_before);
+
+        let mut has_regular_columns = false;
+        let row_tombstone = row.row_tombstone().filter(|&ts| ts >= self.gc_before || !shadows_nothing(ts));
+        // once a touch has expired, so have all columns it applies to
+        let touch = row.touch().filter(|touch| touch.expiry >= self.expired_before || !shadows_nothing(touch.timestamp));
+
+        // columns the schema does not know, e.g. from a newer schema version, are kept
+        let columns = RowColumnIter::new(row)
+            .including_unknown()
+            .filter(|col| {
+                if row.schema.is_primary_key(col.col_id) {
+                    return true;
+                }
+
+                let discard = match (&col.value, col.expiry) {
+                    (_, Some(expiry)) if expiry < self.expired_before => shadows_nothing(col.timestamp) && !may_be_touched,
+                    (None, _) => col.timestamp < self.gc_before && shadows_nothing(col.timestamp),
+                    _ => false,
+                };
+                if !discard {
+                    has_regular_columns = true;
+                }
+                !discard
+            })
+            .collect::<Vec<_>>();
+
+        if has_regular_columns || row_tombstone.is_some() || touch.is_some() {
+            Some(DetachedRowData::assemble_unchecked(&row.schema, &columns, row_tombstone, touch))
+        }
+        else {
+            None
+        }
+    }
+}
+
+/// The oldest timestamp of the SsTables whose partition key range contains a given partition,
+///  i.e. the oldest data in them that tombstones for the partition may shadow. This is based on
+///  the SsTables' min / max partition keys, so it may report SsTables that do not actually
+///  contain the partition.
+pub fn oldest_overlapping(schema: &TableSchema, ss_tables: &[&SsTable], partition_key: &[u8]) -> Option<MergeTimestamp> {
+    ss_tables.iter()
+        .map(|t| t.stats())
+        .filter(|stats| stats.row_count > 0)
+        .filter(|stats| schema.compare_partition_keys(&stats.min_partition_key, partition_key) != Ordering::Greater)
+        .filter(|stats| schema.compare_partition_keys(&stats.max_partition_key, partition_key) != Ordering::Less)
+        .map(|stats| stats.min_timestamp)
+        .min()
+}
+
+/// The latest expiry of touches in the SsTables whose partition key range contains a given
+///  partition, see oldest_overlapping and SsTableStats::max_touch_expiry
+pub fn latest_overlapping_touch(schema: &TableSchema, ss_tables: &[&SsTable], partition_key: &[u8]) -> Option<TtlTimestamp> {
+    ss_tables.iter()
+        .map(|t| t.stats())
+        .filter(|stats| stats.row_count > 0)
+        .filter(|stats| schema.compare_partition_keys(&stats.min_partition_key, partition_key) != Ordering::Greater)
+        .filter(|stats| schema.compare_partition_keys(&stats.max_partition_key, partition_key) != Ordering::Less)
+        .filter_map(|stats| stats.max_touch_expiry)
+        .max()
+}
+
+/// What a compaction needs to know about the table it compacts, and where it reports its
+///  progress
+pub struct CompactionContext<'a> {
+    pub config: Arc<TableConfig>,
+    pub schema: Arc<TableSchema>,
+    pub generations: Arc<SsTableGenerations>,
+    pub gc: GcThresholds,
+    pub progress: &'a TaskProgress,
+}
+
+/// Merges the rows of several SsTables into new SsTables, purging GC-able data along the way.
+///  The caller is responsible for replacing the original SsTables with the result.
+///
+/// others are the table's SsTables that do not take part in the compaction: tombstones that may
+///  shadow data in them are retained.
+pub fn compact(config: &Arc<TableConfig>,
+               schema: &Arc<TableSchema>,
+               generations: &Arc<SsTableGenerations>,
+               ss_tables: &[&SsTable],
+               others: &[&SsTable],
+               gc: &GcThresholds)
+               -> HtResult<Vec<SsTable>> {
+    let progress = TaskProgress::default();
+    let ctx = CompactionContext { config: config.clone(), schema: schema.clone(), generations: generations.clone(), gc: *gc, progress: &progress };
+    compact_parallel(&ctx, ss_tables, others, 1)
+}
+
+/// Same as compact, but splitting the token range into sub-ranges that are compacted on separate
+///  threads, each writing SsTables of its own. Rows are stored in key rather than token order,
+///  so every thread scans all of the input - this reduces wall-clock time if merging and
+///  writing rather than reading is the bottleneck, at the price of several passes over the
+///  input, and the resulting SsTables' key ranges overlap.
+///
+/// The progress counts the bytes of merged rows each thread reads, against a total of the
+///  inputs' data sizes times the number of threads.
+pub fn compact_parallel(ctx: &CompactionContext, ss_tables: &[&SsTable], others: &[&SsTable], num_threads: usize) -> HtResult<Vec<SsTable>> {
+    let config = &ctx.config;
+    ctx.progress.set_total(ss_tables.iter().map(|t| t.data_size()).sum::<u64>() * num_threads.max(1) as u64);
+
+    if config.compaction_advice {
+        for ss_table in ss_tables {
+            ss_table.advise(AccessAdvice::Sequential)?;
+        }
+    }
+
+    let results = if num_threads <= 1 {
+        vec!(compact_token_range(ctx, ss_tables, others, &(0..=u64::MAX)))
+    }
+    else {
+        std::thread::scope(|scope| {
+            let threads = token_ranges(num_threads).into_iter()
+                .map(|tokens| scope.spawn(move || compact_token_range(ctx, ss_tables, others, &tokens)))
+                .collect::<Vec<_>>();
+            threads.into_iter()
+                .map(|t| t.join().unwrap_or_else(|_| Err(HtError::misc("compaction thread panicked"))))
+                .collect::<Vec<_>>()
+        })
+    };
+
+    let mut result = Vec::new();
+    let mut error = None;
+    for r in results {
+        match r {
+            Ok(created) => result.extend(created),
+            Err(e) => error = error.or(Some(e)),
+        }
+    }
+    if let Some(e) = error {
+        // the SsTables written by the other threads are not used
+        for ss_table in result {
+            ss_table.delete_files()?;
+        }
+        return Err(e);
+    }
+
+    if config.compaction_advice {
+        for ss_table in ss_tables {
+            ss_table.advise(AccessAdvice::DontNeed)?;
+            ss_table.advise(config.read_advice)?;
+        }
+    }
+    Ok(result)
+}
+
+/// splits the range of partition tokens into num_ranges ranges of equal size
+fn token_ranges(num_ranges: usize) -> Vec<RangeInclusive<u64>> {
+    let width = u64::MAX / num_ranges as u64;
+    (0..num_ranges as u64)
+        .map(|i| if i + 1 == num_ranges as u64 {
+            i * width..=u64::MAX
+        }
+        else {
+            i * width..=(i + 1) * width - 1
+        })
+        .collect()
+}
+
+/// what compact_token_range needs to know about the partition it is currently compacting
+struct CompactedPartition {
+    key: Vec<u8>,
+    in_range: bool,
+    shadowed_since: Option<MergeTimestamp>,
+    touched_until: Option<TtlTimestamp>,
+}
+
+/// compacts the partitions whose token lies in the given range
+fn compact_token_range(ctx: &CompactionContext, ss_tables: &[&SsTable], others: &[&SsTable], tokens: &RangeInclusive<u64>) -> HtResult<Vec<SsTable>> {
+    let schema = &ctx.schema;
+    let mut writer = SsTableWriter::new(&ctx.config, schema, &ctx.generations);
+
+    let mut partition: Option<CompactedPartition> = None;
+    for row in MergeIterator::from_ss_tables(ss_tables)? {
+        let row = row?;
+        let row = row.row_data_view();
+        ctx.progress.add_processed(row.encoded_len() as u64);
+
+        let partition_key = row.partition_key_bytes();
+        let current = match partition {
+            Some(p) if p.key == partition_key => p,
+            _ => CompactedPartition {
+                in_range: tokens.contains(&partition_token(&partition_key)),
+                shadowed_since: oldest_overlapping(schema, others, &partition_key),
+                touched_until: latest_overlapping_touch(schema, others, &partition_key),
+                key: partition_key,
+            },
+        };
+        let (in_range, shadowed_since, touched_until) = (current.in_range, current.shadowed_since, current.touched_until);
+        partition = Some(current);
+        if !in_range {
+            continue;
+        }
+
+        if let Some(purged) = ctx.gc.purge(&row, shadowed_since, touched_until) {
+            writer.write_row(&purged.row_data_view())?;
+        }
+    }
+    writer.finish()
+}
+
+/// A compaction that a CompactionStrategy proposes, with estimates of its effect. Creating a
+///  plan has no side effects, so it can also be used as a 'dry run' to see what a compaction
+///  would do.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompactionPlan {
+    /// names of the SsTables to be compacted
+    pub inputs: Vec<SsTableName>,
+    /// combined data file size of the input SsTables
+    pub input_size: u64,
+    pub estimated_output_size: u64,
+    pub estimated_reclaimed_bytes: u64,
+    /// the input SsTables contain only GC-able data, and they are deleted without being read
+    pub drop_only: bool,
+}
+
+impl CompactionPlan {
+    /// Estimates output size based on the partition key sketches of the inputs: partitions
+    ///  present in several inputs are assumed to shrink to the size of a single copy. This
+    ///  ignores purged tombstones and expired data, so it tends to overestimate the output.
+    pub fn new(inputs: &[&SsTable]) -> CompactionPlan {
+        let input_size: u64 = inputs.iter().map(|t| t.data_size()).sum();
+
+        let mut merged_partitions = HyperLogLog::new();
+        let mut sum_partitions = 0;
+        for ss_table in inputs {
+            merged_partitions.merge(&ss_table.stats().partitions);
+            sum_partitions += ss_table.stats().partition_count_estimate();
+        }
+
+        let estimated_output_size = match sum_partitions {
+            0 => input_size,
+            _ => {
+                let ratio = (merged_partitions.estimate() as f64 / sum_partitions as f64).min(1.0);
+                (input_size as f64 * ratio) as u64
+            }
+        };
+
+        CompactionPlan {
+            inputs: inputs.iter().map(|t| t.name().clone()).collect(),
+            input_size,
+            estimated_output_size,
+            estimated_reclaimed_bytes: input_size - estimated_output_size,
+            drop_only: false,
+        }
+    }
+
+    pub fn drop(inputs: &[&SsTable]) -> CompactionPlan {
+        let input_size: u64 = inputs.iter().map(|t| t.data_size()).sum();
+
+        CompactionPlan {
+            inputs: inputs.iter().map(|t| t.name().clone()).collect(),
+            input_size,
+            estimated_output_size: 0,
+            estimated_reclaimed_bytes: input_size,
+            drop_only: true,
+        }
+    }
+}
+
+pub trait CompactionStrategy {
+    /// Selects the SsTables that should be compacted next, returning None if no compaction is
+    ///  necessary.
+    fn plan(&self, ss_tables: &[Arc<SsTable>], gc: &GcThresholds) -> Option<CompactionPlan>;
+}
+
+pub fn compaction_strategy(config: &CompactionStrategyConfig) -> Box<dyn CompactionStrategy> {
+    match *config {
+        CompactionStrategyConfig::SizeTiered { min_threshold, max_threshold, bucket_low, bucket_high, min_sstable_size } =>
+            Box::new(SizeTieredCompactionStrategy { min_threshold, max_threshold, bucket_low, bucket_high, min_sstable_size }),
+        CompactionStrategyConfig::TimeWindow { window_seconds, min_threshold, max_threshold } =>
+            Box::new(TimeWindowCompactionStrategy { window_seconds, min_threshold, max_threshold }),
+    }
+}
+
+/// Returns the SsTables that contain only GC-able data and can be deleted without being read.
+///  Their tombstones may however still shadow data in other SsTables, so an SsTable qualifies
+///  only if all other SsTables' data is newer than its own. Touches in other SsTables may
+///  extend the expiry of its columns, so it does not qualify while another SsTable with
+///  overlapping partition tokens has touches that have not expired beyond gc grace.
+pub fn fully_expired_ss_tables<'a>(ss_tables: &'a [Arc<SsTable>], gc: &GcThresholds) -> Vec<&'a SsTable> {
+    let is_expired = |t: &SsTable| match t.stats().max_expiry {
+        Some(expiry) => expiry < gc.expired_before,
+        None => false,
+    };
+
+    let min_live_timestamp = ss_tables.iter()
+        .filter(|t| !is_expired(t))
+        .map(|t| t.stats().min_timestamp)
+        .min();
+
+    ss_tables.iter()
+        .map(|t| t.as_ref())
+        .filter(|t| is_expired(t))
+        .filter(|t| match min_live_timestamp {
+            Some(ts) => t.stats().max_timestamp < ts,
+            None => true,
+        })
+        .filter(|t| !ss_tables.iter()
+            .filter(|o| o.name() != t.name())
+            .filter(|o| o.stats().max_touch_expiry.is_some_and(|expiry| expiry >= gc.expired_before))
+            .any(|o| o.stats().overlaps_tokens(&(t.stats().min_token..=t.stats().max_token))))
+        .collect()
+}
+
+/// Proposes compacting the SsTable with the highest ratio of tombstones to rows on its own, if
+///  that ratio is at least threshold and all of its tombstones can be purged, see
+///  TableConfig::tombstone_compaction_threshold.
+pub fn tombstone_compaction(schema: &TableSchema, ss_tables: &[Arc<SsTable>], gc: &GcThresholds, threshold: f64) -> Option<CompactionPlan> {
+    let tombstone_ratio = |t: &SsTable| match t.stats().row_count {
+        0 => 0.0,
+        rows => t.stats().tombstone_count as f64 / rows as f64,
+    };
+
+    ss_tables.iter()
+        .map(|t| t.as_ref())
+        .filter(|t| t.stats().tombstone_count > 0 && tombstone_ratio(t) >= threshold)
+        .filter(|t| t.stats().max_timestamp < gc.gc_before)
+        .filter(|t| {
+            let (min_key, max_key) = (&t.stats().min_partition_key, &t.stats().max_partition_key);
+            ss_tables.iter()
+                .filter(|o| o.name() != t.name())
+                .map(|o| o.stats())
+                .filter(|o| o.row_count > 0 && o.min_timestamp <= t.stats().max_timestamp)
+                .all(|o| schema.compare_partition_keys(&o.max_partition_key, min_key) == Ordering::Less
+                    || schema.compare_partition_keys(&o.min_partition_key, max_key) == Ordering::Greater)
+        })
+        .max_by(|t1, t2| tombstone_ratio(t1).partial_cmp(&tombstone_ratio(t2)).unwrap_or(Ordering::Equal))
+        .map(|t| CompactionPlan::new(&[t]))
+}
+
+/// see CompactionStrategyConfig::SizeTiered
+pub struct SizeTieredCompactionStrategy {
+    min_threshold: usize,
+    max_threshold: usize,
+    bucket_low: f64,
+    bucket_high: f64,
+    min_sstable_size: u64,
+}
+
+impl SizeTieredCompactionStrategy {
+    fn buckets<'a>(&self, ss_tables: &'a [Arc<SsTable>]) -> Vec<Vec<&'a SsTable>> {
+        let mut sorted = ss_tables.iter().map(|t| t.as_ref()).collect::<Vec<_>>();
+        sorted.sort_by_key(|t| t.data_size());
+
+        let mut buckets: Vec<Vec<&SsTable>> = Vec::new();
+        for ss_table in sorted {
+            let size = ss_table.data_size();
+
+            let fits = match buckets.last() {
+                None => false,
+                Some(bucket) => {
+                    let avg = bucket.iter().map(|t| t.data_size()).sum::<u64>() as f64 / bucket.len() as f64;
+                    (size < self.min_sstable_size && avg < self.min_sstable_size as f64) ||
+                        (size as f64 >= avg * self.bucket_low && size as f64 <= avg * self.bucket_high)
+                }
+            };
+
+            if fits {
+                buckets.last_mut().unwrap().push(ss_table);
+            }
+            else {
+                buckets.push(vec!(ss_table));
+            }
+        }
+        buckets
+    }
+}
+
+impl CompactionStrategy for SizeTieredCompactionStrategy {
+    fn plan(&self, ss_tables: &[Arc<SsTable>], _gc: &GcThresholds) -> Option<CompactionPlan> {
+        // buckets are sorted by size, and ties are resolved in favor of small SsTables since
+        //  compacting them is cheap
+        let bucket = self.buckets(ss_tables)
+            .into_iter()
+            .filter(|b| b.len() >= self.min_threshold)
+            .rev()
+            .max_by_key(|b| b.len())?;
+
+        let inputs = bucket.into_iter()
+            .take(self.max_threshold)
+            .collect::<Vec<_>>();
+        Some(CompactionPlan::new(&inputs))
+    }
+}
+
+/// see CompactionStrategyConfig::TimeWindow
+pub struct TimeWindowCompactionStrategy {
+    window_seconds: u64,
+    min_threshold: usize,
+    max_threshold: usize,
+}
+
+impl CompactionStrategy for TimeWindowCompactionStrategy {
+    fn plan(&self, ss_tables: &[Arc<SsTable>], gc: &GcThresholds) -> Option<CompactionPlan> {
+        let expired = fully_expired_ss_tables(ss_tables, gc);
+        if !expired.is_empty() {
+            return Some(CompactionPlan::drop(&expired));
+        }
+
+        let mut windows: BTreeMap<u64, Vec<&SsTable>> = BTreeMap::new();
+        for ss_table in ss_tables.iter().map(|t| t.as_ref()) {
+            let window = ss_table.stats().max_timestamp.epoch_millis() / (self.window_seconds * 1000);
+            windows.entry(window).or_default().push(ss_table);
+        }
+
+        let newest_window = *windows.keys().next_back()?;
+
+        for (window, mut candidates) in windows.into_iter().rev() {
+            let threshold = if window == newest_window { self.min_threshold } else { 2 };
+            if candidates.len() >= threshold {
+                candidates.sort_by_key(|t| t.data_size());
+                candidates.truncate(self.max_threshold);
+                return Some(CompactionPlan::new(&candidates));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::compaction::{compact, compact_parallel, compaction_strategy, CompactionContext, fully_expired_ss_tables, oldest_overlapping, token_ranges, tombstone_compaction, CompactionPlan, GcThresholds, MergeIterator};
+    use crate::config::{CompactionStrategyConfig, TableConfig};
+    use crate::memtable::MemTable;
+    use crate::sstable::{SsTable, SsTableGenerations};
+    use crate::table::{ColumnData, ColumnId, ColumnValue, DetachedRowData};
+    use crate::tasks::TaskProgress;
+    use crate::testutils::{SimpleTableTestSetup, test_table_config};
+    use crate::time::{HtClock, MergeTimestamp, TtlTimestamp};
+
+    #[test]
+    pub fn test_merge_iterator() {
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+
+        let rows1 = [
+            setup.full_row(1, Some("a"), None),
+            setup.full_row(3, Some("b"), None),
+        ];
+        setup.clock.set(MergeTimestamp::from_ticks(99999));
+        let rows2 = [
+            setup.partial_row(2, Some("x")),
+            setup.partial_row(3, Some("y")),
+            setup.partial_row(4, Some("z")),
+        ];
+
+        let t1 = SsTable::create(&config, &setup.schema, rows1.iter().map(|r| r.row_data_view())).unwrap();
+        let t2 = SsTable::create(&config, &setup.schema, rows2.iter().map(|r| r.row_data_view())).unwrap();
+
+        let merged = MergeIterator::from_ss_tables(&[&t1, &t2]).unwrap()
+            .map(|r| r.unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(merged.iter().map(|r| setup.pk(&r.row_data_view())).collect::<Vec<_>>(), vec!(1, 2, 3, 4));
+        assert_eq!(merged.iter().map(|r| setup.value(&r.row_data_view()).to_string()).collect::<Vec<_>>(),
+                   vec!("a", "x", "y", "z"));
+    }
+
+    #[test]
+    pub fn test_merge_iterator_seek() {
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+
+        let rows = (0..20).filter(|pk| pk % 2 == 0).map(|pk| setup.full_row(pk, Some("t"), None)).collect::<Vec<_>>();
+        let ss_table = SsTable::create(&config, &setup.schema, rows.iter().map(|r| r.row_data_view())).unwrap();
+
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let mut mem_table = MemTable::new(&config, &setup.schema, &clock);
+        for pk in (0..20).filter(|pk| pk % 3 == 0) {
+            mem_table.add(setup.full_row(pk, Some("m"), None));
+        }
+
+        let mut merged = MergeIterator::new(vec!(Box::new(ss_table.rows()), Box::new(mem_table.rows()))).unwrap();
+        let next_pk = |merged: &mut MergeIterator| merged.next().map(|r| setup.pk(&r.unwrap().row_data_view()));
+
+        assert_eq!(next_pk(&mut merged), Some(0));
+        merged.seek(&setup.pk_row(5).row_data_view()).unwrap();
+        assert_eq!(next_pk(&mut merged), Some(6));
+        assert_eq!(next_pk(&mut merged), Some(8));
+        assert_eq!(next_pk(&mut merged), Some(9));
+
+        // seeking backwards works as well
+        merged.seek(&setup.pk_row(2).row_data_view()).unwrap();
+        assert_eq!(next_pk(&mut merged), Some(2));
+        assert_eq!(next_pk(&mut merged), Some(3));
+
+        merged.seek(&setup.pk_row(18).row_data_view()).unwrap();
+        assert_eq!(next_pk(&mut merged), Some(18));
+        assert_eq!(next_pk(&mut merged), None);
+        merged.seek(&setup.pk_row(19).row_data_view()).unwrap();
+        assert_eq!(next_pk(&mut merged), None);
+    }
+
+    #[test]
+    pub fn test_purge() {
+        let setup = SimpleTableTestSetup::new();
+        setup.clock.set(MergeTimestamp::new(100_000, 0, 0, 0));
+        let old = MergeTimestamp::new(10_000, 0, 0, 0);
+        let recent = MergeTimestamp::new(95_000, 0, 0, 0);
+
+        let gc = GcThresholds::new(setup.clock.as_ref(), 10);
+        assert_eq!(gc.expired_before, TtlTimestamp::new(90));
+
+        let row = |text: (MergeTimestamp, Option<TtlTimestamp>, Option<&'static str>)| {
+            DetachedRowData::assemble(&setup.schema, &vec!(
+                ColumnData::new(ColumnId(0), setup.clock.now(), None, Some(ColumnValue::BigInt(1))),
+                ColumnData::new(ColumnId(1), text.0, text.1, text.2.map(ColumnValue::Text)),
+            )).unwrap()
+        };
+
+        // live data is retained
+        let purged = gc.purge(&row((old, None, Some("a"))).row_data_view(), None, None).unwrap();
+        assert_eq!(setup.value(&purged.row_data_view()), "a");
+        let purged = gc.purge(&row((old, Some(TtlTimestamp::new(200)), Some("a"))).row_data_view(), None, None).unwrap();
+        assert_eq!(setup.value(&purged.row_data_view()), "a");
+
+        // expired, but still within gc grace
+        let purged = gc.purge(&row((old, Some(TtlTimestamp::new(95)), Some("a"))).row_data_view(), None, None).unwrap();
+        assert_eq!(purged.row_data_view().read_col_by_id(ColumnId(1)).unwrap().expiry, Some(TtlTimestamp::new(95)));
+
+        // expired beyond gc grace
+        assert!(gc.purge(&row((old, Some(TtlTimestamp::new(80)), Some("a"))).row_data_view(), None, None).is_none());
+
+        // tombstones
+        assert!(gc.purge(&row((recent, None, None)).row_data_view(), None, None).is_some());
+        assert!(gc.purge(&row((old, None, None)).row_data_view(), None, None).is_none());
+
+        // tombstones and expired columns that may shadow data in other SsTables are retained
+        let shadowed_since = Some(MergeTimestamp::new(5_000, 0, 0, 0));
+        assert!(gc.purge(&row((old, None, None)).row_data_view(), shadowed_since, None).is_some());
+        assert!(gc.purge(&row((old, Some(TtlTimestamp::new(80)), Some("a"))).row_data_view(), shadowed_since, None).is_some());
+        let shadowed_since = Some(MergeTimestamp::new(20_000, 0, 0, 0));
+        assert!(gc.purge(&row((old, None, None)).row_data_view(), shadowed_since, None).is_none());
+
+        // expired columns that a touch in another SsTable may extend are retained
+        let expired = row((old, Some(TtlTimestamp::new(80)), Some("a")));
+        assert!(gc.purge(&expired.row_data_view(), None, Some(TtlTimestamp::new(95))).is_some());
+        assert!(gc.purge(&expired.row_data_view(), None, Some(TtlTimestamp::new(85))).is_none());
+
+        // touches are retained until they expired beyond gc grace
+        let touch = |expiry| DetachedRowData::touch(&setup.schema, &vec!(ColumnData::new(ColumnId(0), old, None, Some(ColumnValue::BigInt(1)))), old, TtlTimestamp::new(expiry)).unwrap();
+        assert_eq!(gc.purge(&touch(95).row_data_view(), None, None).unwrap().row_data_view().touch(), touch(95).row_data_view().touch());
+        assert!(gc.purge(&touch(80).row_data_view(), None, None).is_none());
+        assert!(gc.purge(&touch(80).row_data_view(), Some(MergeTimestamp::new(5_000, 0, 0, 0)), None).is_some());
+    }
+
+    #[test]
+    pub fn test_compact_parallel() {
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+
+        let rows1 = (0..50).map(|pk| setup.full_row(pk, Some("a"), None)).collect::<Vec<_>>();
+        setup.clock.set(MergeTimestamp::from_ticks(99999));
+        let rows2 = (25..75).map(|pk| setup.partial_row(pk, Some("b"))).collect::<Vec<_>>();
+        let t1 = SsTable::create(&config, &setup.schema, rows1.iter().map(|r| r.row_data_view())).unwrap();
+        let t2 = SsTable::create(&config, &setup.schema, rows2.iter().map(|r| r.row_data_view())).unwrap();
+
+        let gc = GcThresholds::new(setup.clock.as_ref(), 10);
+        let generations = Arc::new(SsTableGenerations::seed(&config, &setup.schema).unwrap());
+        let progress = TaskProgress::default();
+        let ctx = CompactionContext { config: config.clone(), schema: setup.schema.clone(), generations: generations.clone(), gc, progress: &progress };
+        let compacted = compact_parallel(&ctx, &[&t1, &t2], &[], 4).unwrap();
+        assert_eq!(compacted.len(), 4);
+
+        // the threads' SsTables have distinct generations, following the inputs'
+        let mut generations = compacted.iter().map(|t| t.name().generation).collect::<Vec<_>>();
+        generations.sort_unstable();
+        generations.dedup();
+        assert_eq!(generations.len(), 4);
+        assert!(generations[0] > t2.name().generation);
+        assert_eq!(compacted.iter().map(|t| t.stats().row_count).sum::<u64>(), 75);
+
+        // each partition is in exactly one output, the one for its token range
+        for pk in 0..75 {
+            let found = compacted.iter()
+                .filter_map(|t| t.find_by_full_pk(&setup.pk_row(pk).row_data_view()).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(found.len(), 1);
+            assert_eq!(setup.value(&found[0]), if pk < 25 { "a" } else { "b" });
+        }
+    }
+
+    #[test]
+    pub fn test_token_ranges() {
+        assert_eq!(token_ranges(1), vec!(0..=u64::MAX));
+        let ranges = token_ranges(3);
+        assert_eq!(*ranges[0].start(), 0);
+        assert_eq!(*ranges[2].end(), u64::MAX);
+        for i in 1..3 {
+            assert_eq!(*ranges[i].start(), ranges[i-1].end() + 1);
+        }
+    }
+
+    #[test]
+    pub fn test_compact_overlapping() {
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+
+        setup.clock.set(MergeTimestamp::new(10_000, 0, 0, 0));
+        let older = SsTable::create(&config, &setup.schema, [setup.full_row(5, Some("a"), None)].iter().map(|r| r.row_data_view())).unwrap();
+        setup.clock.set(MergeTimestamp::new(20_000, 0, 0, 0));
+        let rows = [setup.partial_row(1, None), setup.partial_row(5, None), setup.partial_row(9, None)];
+        let tombstones = SsTable::create(&config, &setup.schema, rows.iter().map(|r| r.row_data_view())).unwrap();
+        setup.clock.set(MergeTimestamp::new(30_000, 0, 0, 0));
+        let newer = SsTable::create(&config, &setup.schema, [setup.full_row(9, Some("b"), Some(9))].iter().map(|r| r.row_data_view())).unwrap();
+
+        // only the tombstone for pk 5 shadows data outside the compaction
+        setup.clock.set(MergeTimestamp::new(100_000, 0, 0, 0));
+        let gc = GcThresholds::new(setup.clock.as_ref(), 10);
+        assert_eq!(oldest_overlapping(&setup.schema, &[&older, &newer], &setup.pk_row(5).row_data_view().partition_key_bytes()), Some(MergeTimestamp::new(10_000, 0, 0, 0)));
+        assert_eq!(oldest_overlapping(&setup.schema, &[&older, &newer], &setup.pk_row(1).row_data_view().partition_key_bytes()), None);
+
+        let generations = Arc::new(SsTableGenerations::seed(&config, &setup.schema).unwrap());
+        let compacted = compact(&config, &setup.schema, &generations, &[&tombstones], &[&older, &newer], &gc).unwrap();
+        assert_eq!(compacted[0].stats().row_count, 1);
+        assert!(compacted[0].find_by_full_pk(&setup.pk_row(5).row_data_view()).unwrap().is_some());
+    }
+
+    #[test]
+    pub fn test_tombstone_compaction() {
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+
+        setup.clock.set(MergeTimestamp::new(10_000, 0, 0, 0));
+        let older = Arc::new(SsTable::create(&config, &setup.schema, [setup.full_row(5, Some("a"), Some(5))].iter().map(|r| r.row_data_view())).unwrap());
+        setup.clock.set(MergeTimestamp::new(20_000, 0, 0, 0));
+        let rows = [setup.full_row(1, Some("a"), Some(1)), setup.partial_row(2, None), setup.partial_row(3, None)];
+        let tombstones = Arc::new(SsTable::create(&config, &setup.schema, rows.iter().map(|r| r.row_data_view())).unwrap());
+
+        setup.clock.set(MergeTimestamp::new(100_000, 0, 0, 0));
+        let gc = GcThresholds::new(setup.clock.as_ref(), 10);
+        let plan = tombstone_compaction(&setup.schema, &[older.clone(), tombstones.clone()], &gc, 0.5).unwrap();
+        assert_eq!(plan.inputs, vec!(tombstones.name().clone()));
+        assert!(tombstone_compaction(&setup.schema, &[older.clone(), tombstones.clone()], &gc, 0.9).is_none());
+
+        // within gc grace
+        let recent_gc = GcThresholds::new(setup.clock.as_ref(), 90);
+        assert!(tombstone_compaction(&setup.schema, &[older.clone(), tombstones.clone()], &recent_gc, 0.5).is_none());
+
+        // an older SsTable overlapping the key range
+        setup.clock.set(MergeTimestamp::new(20_000, 0, 0, 0));
+        let rows = [setup.full_row(1, Some("a"), Some(1)), setup.partial_row(7, None), setup.partial_row(8, None)];
+        let overlapping = Arc::new(SsTable::create(&config, &setup.schema, rows.iter().map(|r| r.row_data_view())).unwrap());
+        assert!(tombstone_compaction(&setup.schema, &[older, overlapping], &gc, 0.5).is_none());
+    }
+
+    #[test]
+    pub fn test_compact() {
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+        setup.clock.set(MergeTimestamp::new(10_000, 0, 0, 0));
+
+        let rows1 = [
+            setup.full_row(1, Some("a"), None),
+            setup.full_row(2, Some("b"), None),
+        ];
+        setup.clock.set(MergeTimestamp::new(20_000, 0, 0, 0));
+        let rows2 = [
+            setup.partial_row(1, None),
+            setup.partial_row(2, Some("c")),
+        ];
+
+        let t1 = SsTable::create(&config, &setup.schema, rows1.iter().map(|r| r.row_data_view())).unwrap();
+        let t2 = SsTable::create(&config, &setup.schema, rows2.iter().map(|r| r.row_data_view())).unwrap();
+
+        let generations = Arc::new(SsTableGenerations::seed(&config, &setup.schema).unwrap());
+
+        // within gc grace: the tombstone is retained
+        setup.clock.set(MergeTimestamp::new(25_000, 0, 0, 0));
+        let compacted = compact(&config, &setup.schema, &generations, &[&t1, &t2], &[], &GcThresholds::new(setup.clock.as_ref(), 10)).unwrap();
+        assert_eq!(compacted.len(), 1);
+        assert_eq!(compacted[0].stats().row_count, 2);
+        assert_eq!(compacted[0].stats().tombstone_count, 1);
+
+        // the tombstone is discarded, and row 1 along with it
+        setup.clock.set(MergeTimestamp::new(35_000, 0, 0, 0));
+        let compacted = compact(&config, &setup.schema, &generations, &[&t1, &t2], &[], &GcThresholds::new(setup.clock.as_ref(), 10)).unwrap();
+        assert_eq!(compacted.len(), 1);
+        assert_eq!(compacted[0].stats().row_count, 1);
+
+        let row = compacted[0].find_by_full_pk(&setup.pk_row(2).row_data_view()).unwrap().unwrap();
+        assert_eq!(setup.value(&row), "c");
+        assert!(compacted[0].find_by_full_pk(&setup.pk_row(1).row_data_view()).unwrap().is_none());
+    }
+
+    #[test]
+    pub fn test_compact_unknown_columns() {
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+
+        // columns of a newer schema version, one of them with a value of a reserved kind
+        let added = |pk: i64| DetachedRowData::assemble(&setup.schema, &vec!(
+            ColumnData::new(ColumnId(0), setup.clock.now(), None, Some(ColumnValue::BigInt(pk))),
+            ColumnData::new(ColumnId(7), setup.clock.now(), None, Some(ColumnValue::Text("added"))),
+            ColumnData::new(ColumnId(8), setup.clock.now(), None, Some(ColumnValue::Unknown { kind: 9, data: &[1, 2, 3] })),
+        )).unwrap();
+        let t1 = SsTable::create(&config, &setup.schema, [added(1), added(2)].iter().map(|r| r.row_data_view())).unwrap();
+        let t2 = SsTable::create(&config, &setup.schema, [setup.full_row(1, Some("a"), None)].iter().map(|r| r.row_data_view())).unwrap();
+
+        let generations = Arc::new(SsTableGenerations::seed(&config, &setup.schema).unwrap());
+        let compacted = compact(&config, &setup.schema, &generations, &[&t1, &t2], &[], &GcThresholds::new(setup.clock.as_ref(), 10)).unwrap();
+        for pk in 1..=2 {
+            let row = compacted[0].find_by_full_pk(&setup.pk_row(pk).row_data_view()).unwrap().unwrap();
+            assert_eq!(row.read_col_by_id(ColumnId(7)).unwrap().value, Some(ColumnValue::Text("added")));
+            assert_eq!(row.read_col_by_id(ColumnId(8)).unwrap().value, Some(ColumnValue::Unknown { kind: 9, data: &[1, 2, 3] }));
+        }
+        let row = compacted[0].find_by_full_pk(&setup.pk_row(1).row_data_view()).unwrap().unwrap();
+        assert_eq!(setup.value(&row), "a");
+    }
+
+    #[test]
+    pub fn test_size_tiered_plan() {
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+
+        let strategy = compaction_strategy(&CompactionStrategyConfig::SizeTiered {
+            min_threshold: 3,
+            max_threshold: 4,
+            bucket_low: 0.5,
+            bucket_high: 1.5,
+            min_sstable_size: 0,
+        });
+
+        let create = |pks: std::ops::Range<i64>| {
+            let rows = pks.map(|pk| setup.full_row(pk, Some("abc"), None)).collect::<Vec<_>>();
+            Arc::new(SsTable::create(&config, &setup.schema, rows.iter().map(|r| r.row_data_view())).unwrap())
+        };
+
+        let gc = GcThresholds::new(setup.clock.as_ref(), 0);
+
+        let mut ss_tables = vec!(create(0..10), create(0..100), create(5..15));
+        assert!(strategy.plan(&ss_tables, &gc).is_none());
+
+        ss_tables.push(create(0..10));
+        ss_tables.push(create(0..10));
+        ss_tables.push(create(50..140));
+        ss_tables.push(create(100..190));
+
+        // the smaller of two eligible buckets, limited to max_threshold
+        let plan = strategy.plan(&ss_tables, &gc).unwrap();
+        assert_eq!(plan.inputs.len(), 4);
+        for name in &plan.inputs {
+            let ss_table = ss_tables.iter().find(|t| t.name() == name).unwrap();
+            assert!(ss_table.stats().row_count == 10);
+        }
+
+        let small_size = ss_tables[0].data_size();
+        assert_eq!(plan.input_size, 4 * small_size);
+        assert!(plan.estimated_output_size >= small_size);
+        assert!(plan.estimated_output_size < 2 * small_size);
+        assert_eq!(plan.estimated_reclaimed_bytes, plan.input_size - plan.estimated_output_size);
+    }
+
+    #[test]
+    pub fn test_plan_without_overlap() {
+        let config = Arc::new(TableConfig::new(test_table_config().base_folder.clone()));
+        let setup = SimpleTableTestSetup::new();
+
+        let rows1 = (0..10).map(|pk| setup.full_row(pk, Some("abc"), None)).collect::<Vec<_>>();
+        let rows2 = (10..20).map(|pk| setup.full_row(pk, Some("abc"), None)).collect::<Vec<_>>();
+        let t1 = SsTable::create(&config, &setup.schema, rows1.iter().map(|r| r.row_data_view())).unwrap();
+        let t2 = SsTable::create(&config, &setup.schema, rows2.iter().map(|r| r.row_data_view())).unwrap();
+
+        let plan = CompactionPlan::new(&[&t1, &t2]);
+        assert_eq!(plan.estimated_output_size, plan.input_size);
+        assert_eq!(plan.estimated_reclaimed_bytes, 0);
+        assert!(!plan.drop_only);
+    }
+
+    #[test]
+    pub fn test_time_window_plan() {
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+
+        let strategy = compaction_strategy(&CompactionStrategyConfig::TimeWindow {
+            window_seconds: 100,
+            min_threshold: 3,
+            max_threshold: 32,
+        });
+
+        let create = |seconds: u64, pk: i64, ttl: u32| {
+            setup.clock.set(MergeTimestamp::new(seconds * 1000, 0, 0, 0));
+            let row = DetachedRowData::assemble(&setup.schema, &vec!(
+                ColumnData::new(ColumnId(0), setup.clock.now(), None, Some(ColumnValue::BigInt(pk))),
+                ColumnData::new(ColumnId(1), setup.clock.now(), Some(setup.clock.ttl_timestamp(ttl)), Some(ColumnValue::Text("abc"))),
+            )).unwrap();
+            Arc::new(SsTable::create(&config, &setup.schema, [row].iter().map(|r| r.row_data_view())).unwrap())
+        };
+
+        let ss_tables = vec!(
+            create(1010, 1, 1000),
+            create(1020, 2, 1000),
+            create(1110, 3, 1000),
+            create(1120, 4, 1000),
+        );
+        let gc = GcThresholds::new(setup.clock.as_ref(), 0);
+
+        // the current window has less than min_threshold SsTables, the older window is compacted
+        let plan = strategy.plan(&ss_tables, &gc).unwrap();
+        assert!(!plan.drop_only);
+        assert_eq!(plan.inputs, vec!(ss_tables[0].name().clone(), ss_tables[1].name().clone()));
+
+        let ss_tables = vec!(
+            create(1010, 1, 1000),
+            create(1110, 3, 1000),
+            create(1120, 4, 1000),
+            create(1130, 5, 1000),
+        );
+        let plan = strategy.plan(&ss_tables, &gc).unwrap();
+        assert_eq!(plan.inputs.len(), 3);
+        assert!(!plan.inputs.contains(ss_tables[0].name()));
+
+        // SsTables with only expired data are dropped
+        let ss_tables = vec!(
+            create(1010, 1, 10),
+            create(1020, 2, 10),
+            create(1110, 3, 1000),
+        );
+        let plan = strategy.plan(&ss_tables, &GcThresholds::new(setup.clock.as_ref(), 0)).unwrap();
+        assert!(plan.drop_only);
+        assert_eq!(plan.inputs.len(), 2);
+        assert_eq!(plan.estimated_reclaimed_bytes, plan.input_size);
+
+        // ... but only if no older data could be shadowed by them
+        let ss_tables = vec!(
+            create(1000, 1, 1000),
+            create(1010, 1, 10),
+        );
+        setup.clock.set(MergeTimestamp::new(1100 * 1000, 0, 0, 0));
+        let gc = GcThresholds::new(setup.clock.as_ref(), 0);
+        assert!(fully_expired_ss_tables(&ss_tables, &gc).is_empty());
+        assert!(strategy.plan(&ss_tables, &gc).is_none());
+
+        // ... and not while a touch in another SsTable may extend their columns' expiry
+        let touch = |seconds: u64, pk: i64| {
+            setup.clock.set(MergeTimestamp::new(seconds * 1000, 0, 0, 0));
+            let pk_columns = vec!(ColumnData::new(ColumnId(0), setup.clock.now(), None, Some(ColumnValue::BigInt(pk))));
+            let row = DetachedRowData::touch(&setup.schema, &pk_columns, setup.clock.now(), setup.clock.ttl_timestamp(1000)).unwrap();
+            Arc::new(SsTable::create(&config, &setup.schema, [row].iter().map(|r| r.row_data_view())).unwrap())
+        };
+        let ss_tables = vec!(create(1010, 1, 10), touch(1015, 1));
+        setup.clock.set(MergeTimestamp::new(1100 * 1000, 0, 0, 0));
+        assert!(fully_expired_ss_tables(&ss_tables, &GcThresholds::new(setup.clock.as_ref(), 0)).is_empty());
+
+        let ss_tables = vec!(create(1010, 1, 10), touch(1015, 2));
+        setup.clock.set(MergeTimestamp::new(1100 * 1000, 0, 0, 0));
+        assert_eq!(fully_expired_ss_tables(&ss_tables, &GcThresholds::new(setup.clock.as_ref(), 0)).len(), 1);
+    }
+}
@@ -1,19 +1,391 @@
 use std::fs::{OpenOptions, File};
-use std::path::PathBuf;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use crate::prelude::*;
+use crate::table::{ColumnId, TableSchema};
+
+#[derive(Clone)]
 pub struct TableConfig {
+    /// holds each table's manifest
     pub base_folder: PathBuf,
+    /// SsTables are stored in these folders (e.g. one per disk), each new SsTable going to the
+    ///  folder with the most free space
+    pub data_folders: Vec<PathBuf>,
+    /// Table::flush_if_needed flushes the MemTable once its rows use this many bytes of memory,
+    ///  including rows replaced by later writes to the same key (see MemTable::arena_size)...
+    pub mem_table_flush_size: usize,
+    /// ... or once its oldest write is this old, bounding the time data is held in memory only
+    pub mem_table_flush_age: Option<Duration>,
+    /// SsTableWriter starts a new SsTable once the data file of the current one reaches this size
+    pub max_sstable_data_size: u64,
+    /// partitions exceeding this number of bytes in an SsTable are logged and counted in the
+    ///  SsTable's stats when the SsTable is written
+    pub large_partition_warn_threshold: u64,
+    /// tombstones and expired columns are retained for this long before compaction may discard
+    ///  them, so that they reliably shadow the data they replace
+    pub gc_grace_seconds: u32,
+    /// Writes with columns expiring more than this many seconds in the future fail. TTLs are
+    ///  stored as expiry timestamps, which can not lie beyond TtlTimestamp::MAX.
+    pub max_ttl_seconds: u32,
+    /// Columns that a put sets to a value without an expiry of their own expire this many
+    ///  seconds after the write, None meaning that they do not expire
+    pub default_ttl_seconds: Option<u32>,
+    pub compaction_strategy: CompactionStrategyConfig,
+    pub storage_mode: StorageMode,
+    /// access pattern hint for the memory maps of SsTables while they serve reads, e.g. Random
+    ///  for tables that are used for point reads only
+    pub read_advice: AccessAdvice,
+    /// how point reads (e.g. Table::get) read SsTable rows, see IoBackend
+    pub io_backend: IoBackend,
+    /// the number of data file blocks each SsTable caches for IoBackend::Pread, see
+    ///  BlockCache. 0 disables the cache.
+    pub block_cache_size: usize,
+    /// Hints the OS to read ahead while compaction scans its input SsTables, and to drop them
+    ///  from the page cache afterwards, so compaction does not evict data used by reads.
+    pub compaction_advice: bool,
+    /// Limits the rate of writes to the table, None meaning unlimited. Short bursts of up to
+    ///  one second's worth of writes are allowed.
+    pub max_writes_per_second: Option<u64>,
+    /// limits the rate of written row data in bytes, see max_writes_per_second
+    pub max_write_bytes_per_second: Option<u64>,
+    /// Reads and writes taking at least this long are reported to the table's
+    ///  SlowQueryCallback, which logs them by default. None disables this.
+    pub slow_query_threshold: Option<Duration>,
+    /// report slow queries with a hash of their key rather than the key's values
+    pub slow_query_hash_keys: bool,
+    /// Reads encountering more than this many tombstones (NULL columns and deleted rows) are
+    ///  logged and counted in TableStats, typically pointing to a table that is used as a queue...
+    pub tombstone_warn_threshold: u64,
+    /// ... and reads encountering more than this many fail with HtError::TombstoneOverwhelm
+    ///  rather than keeping the node busy
+    pub tombstone_failure_threshold: u64,
+    /// If the compaction strategy has nothing to do, an SsTable with at least this ratio of
+    ///  tombstones to rows is compacted on its own - provided its tombstones are all past
+    ///  gc_grace_seconds and no other SsTable holds older data they might shadow, so that all of
+    ///  them are purged. None disables this.
+    pub tombstone_compaction_threshold: Option<f64>,
+    /// Major compactions (Table::compact_all) are split into this many token ranges that are
+    ///  compacted on separate threads, see compaction::compact_parallel
+    pub major_compaction_threads: usize,
+    /// hard link each flushed SsTable into a 'backups' folder, see the backup module
+    pub incremental_backups: bool,
+    /// backed up files are removed after this time, None keeping them until they are removed
+    ///  manually
+    pub backup_retention: Option<Duration>,
+    /// how long Table::write_once remembers idempotency ids, see IdempotencyWindow
+    pub idempotency_window: Duration,
+    /// validate SsTable rows when they are read, skipping corrupt rows and adding them to the
+    ///  table's Quarantine rather than failing the read
+    pub validate_reads: bool,
+    /// the fraction of a data folder's file system above which checks log warnings, None
+    ///  (the default) disabling them
+    pub disk_warn_watermark: Option<f64>,
+    /// the fraction of a data folder's file system above which writes fail with
+    ///  HtError::InsufficientDiskSpace, and compactions that would exceed it are skipped. None
+    ///  (the default) disables this, since the file system may be shared with other data.
+    pub disk_critical_watermark: Option<f64>,
+    /// The false positive rate of each SsTable's BloomFilter, which is sized for the number of
+    ///  partitions in the SsTable, between 0 and 1 exclusive. Lower rates need more memory.
+    ///  None writes filters that match every partition, e.g. for tables that are only scanned.
+    pub bloom_fp_chance: Option<f64>,
+    /// the number of recently read primary keys whose row offsets each SsTable caches, see
+    ///  KeyCache. 0 disables the cache.
+    pub key_cache_size: usize,
+    /// Touches all pages of an SsTable's index when the SsTable is opened, so that the first
+    ///  reads after a restart do not fault them in one at a time. The BloomFilter is always read
+    ///  into memory, and there is no separate index summary.
+    pub preload_index: bool,
+    /// Locks the pages of SsTable indexes in memory (mlock) when the SsTables are opened,
+    ///  which also loads them. This is subject to the process' RLIMIT_MEMLOCK; SsTables whose
+    ///  index can not be locked are logged and opened anyway.
+    pub lock_index: bool,
+    /// Regular columns whose values are indexed in each SsTable, for queries by value (see
+    ///  ColumnIndex). Changing this affects only SsTables written afterwards; queries fall
+    ///  back to scanning older SsTables until compaction has rewritten them.
+    pub indexed_columns: Vec<ColumnId>,
 }
 
 impl TableConfig {
-    pub fn new_file(&self, name_base: &str, extension: &str, writeable: bool) -> std::io::Result<File> {
-        let mut path = self.base_folder.clone();
-        path.push(format!("{}.{}", name_base, extension));
+    pub fn new(base_folder: PathBuf) -> TableConfig {
+        TableConfig {
+            data_folders: vec!(base_folder.clone()),
+            base_folder,
+            mem_table_flush_size: 64 * 1024 * 1024,
+            mem_table_flush_age: None,
+            max_sstable_data_size: 160 * 1024 * 1024,
+            large_partition_warn_threshold: 100 * 1024 * 1024,
+            gc_grace_seconds: 10 * 24 * 60 * 60,
+            max_ttl_seconds: 20 * 365 * 24 * 60 * 60,
+            default_ttl_seconds: None,
+            compaction_strategy: CompactionStrategyConfig::size_tiered(),
+            storage_mode: StorageMode::Persistent,
+            read_advice: AccessAdvice::Normal,
+            io_backend: IoBackend::Mmap,
+            block_cache_size: 256,
+            compaction_advice: true,
+            max_writes_per_second: None,
+            max_write_bytes_per_second: None,
+            slow_query_threshold: None,
+            slow_query_hash_keys: false,
+            tombstone_warn_threshold: 1000,
+            tombstone_failure_threshold: 100_000,
+            tombstone_compaction_threshold: Some(0.2),
+            major_compaction_threads: 1,
+            incremental_backups: false,
+            backup_retention: None,
+            idempotency_window: Duration::from_secs(600),
+            validate_reads: true,
+            disk_warn_watermark: None,
+            disk_critical_watermark: None,
+            bloom_fp_chance: Some(0.01),
+            key_cache_size: 1024,
+            preload_index: false,
+            lock_index: false,
+            indexed_columns: Vec::new(),
+        }
+    }
+
+    /// checks settings whose values can not be used, before a table with this config is set up
+    pub fn validate(&self, schema: &TableSchema) -> HtResult<()> {
+        if let Some(fp_chance) = self.bloom_fp_chance {
+            if !(fp_chance > 0.0 && fp_chance < 1.0) {
+                return Err(HtError::misc("bloom_fp_chance must be between 0 and 1"));
+            }
+        }
+        if self.default_ttl_seconds.is_some_and(|ttl| ttl == 0 || ttl > self.max_ttl_seconds) {
+            return Err(HtError::misc("default_ttl_seconds must be positive and at most max_ttl_seconds"));
+        }
+        match self.compaction_strategy {
+            CompactionStrategyConfig::TimeWindow { window_seconds: 0, .. } =>
+                return Err(HtError::misc("window_seconds must be positive")),
+            CompactionStrategyConfig::SizeTiered { min_threshold, max_threshold, .. } |
+            CompactionStrategyConfig::TimeWindow { min_threshold, max_threshold, .. } =>
+                if min_threshold == 0 || min_threshold > max_threshold {
+                    return Err(HtError::misc("min_threshold must be positive and at most max_threshold"));
+                },
+        }
+        if self.major_compaction_threads == 0 {
+            return Err(HtError::misc("major_compaction_threads must be positive"));
+        }
+        for watermark in self.disk_warn_watermark.iter().chain(self.disk_critical_watermark.iter()) {
+            if !(0.0..=1.0).contains(watermark) {
+                return Err(HtError::misc("disk watermarks must be between 0 and 1"));
+            }
+        }
+        if let (Some(warn), Some(critical)) = (self.disk_warn_watermark, self.disk_critical_watermark) {
+            if warn > critical {
+                return Err(HtError::misc("disk_warn_watermark must be at most disk_critical_watermark"));
+            }
+        }
+        for &col_id in &self.indexed_columns {
+            schema.column(col_id)?;
+            if schema.is_primary_key(col_id) {
+                return Err(HtError::misc("primary key columns can not be indexed"));
+            }
+        }
+        Ok(())
+    }
+
+    /// the settings' names and values in a human readable form, e.g. for system tables
+    pub fn settings(&self) -> Vec<(&'static str, String)> {
+        vec!(
+            ("base_folder", format!("{:?}", self.base_folder)),
+            ("data_folders", format!("{:?}", self.data_folders)),
+            ("mem_table_flush_size", self.mem_table_flush_size.to_string()),
+            ("mem_table_flush_age", format!("{:?}", self.mem_table_flush_age)),
+            ("max_sstable_data_size", self.max_sstable_data_size.to_string()),
+            ("large_partition_warn_threshold", self.large_partition_warn_threshold.to_string()),
+            ("gc_grace_seconds", self.gc_grace_seconds.to_string()),
+            ("max_ttl_seconds", self.max_ttl_seconds.to_string()),
+            ("default_ttl_seconds", format!("{:?}", self.default_ttl_seconds)),
+            ("compaction_strategy", format!("{:?}", self.compaction_strategy)),
+            ("storage_mode", format!("{:?}", self.storage_mode)),
+            ("read_advice", format!("{:?}", self.read_advice)),
+            ("io_backend", format!("{:?}", self.io_backend)),
+            ("block_cache_size", self.block_cache_size.to_string()),
+            ("compaction_advice", self.compaction_advice.to_string()),
+            ("max_writes_per_second", format!("{:?}", self.max_writes_per_second)),
+            ("max_write_bytes_per_second", format!("{:?}", self.max_write_bytes_per_second)),
+            ("slow_query_threshold", format!("{:?}", self.slow_query_threshold)),
+            ("slow_query_hash_keys", self.slow_query_hash_keys.to_string()),
+            ("tombstone_warn_threshold", self.tombstone_warn_threshold.to_string()),
+            ("tombstone_failure_threshold", self.tombstone_failure_threshold.to_string()),
+            ("tombstone_compaction_threshold", format!("{:?}", self.tombstone_compaction_threshold)),
+            ("major_compaction_threads", self.major_compaction_threads.to_string()),
+            ("incremental_backups", self.incremental_backups.to_string()),
+            ("backup_retention", format!("{:?}", self.backup_retention)),
+            ("idempotency_window", format!("{:?}", self.idempotency_window)),
+            ("validate_reads", self.validate_reads.to_string()),
+            ("disk_warn_watermark", format!("{:?}", self.disk_warn_watermark)),
+            ("disk_critical_watermark", format!("{:?}", self.disk_critical_watermark)),
+            ("bloom_fp_chance", format!("{:?}", self.bloom_fp_chance)),
+            ("key_cache_size", self.key_cache_size.to_string()),
+            ("preload_index", self.preload_index.to_string()),
+            ("lock_index", self.lock_index.to_string()),
+            ("indexed_columns", format!("{:?}", self.indexed_columns.iter().map(|c| c.0).collect::<Vec<_>>())),
+        )
+    }
+
+    fn table_folder_name(schema: &TableSchema) -> String {
+        format!("{}-{}", schema.name, schema.table_id)
+    }
+
+    /// Each table has a folder of its own in the base folder and in each data folder, named
+    ///  after the table's name and id. This is the one in the base folder.
+    pub fn table_folder(&self, schema: &TableSchema) -> PathBuf {
+        self.base_folder.join(TableConfig::table_folder_name(schema))
+    }
+
+    /// the table's folders in all data folders
+    pub fn table_data_folders(&self, schema: &TableSchema) -> Vec<PathBuf> {
+        self.data_folders.iter()
+            .map(|f| f.join(TableConfig::table_folder_name(schema)))
+            .collect()
+    }
+
+    /// creates the table's folders if they do not exist yet
+    pub fn create_table_folder(&self, schema: &TableSchema) -> std::io::Result<()> {
+        std::fs::create_dir_all(self.table_folder(schema))?;
+        for folder in self.table_data_folders(schema) {
+            std::fs::create_dir_all(folder)?;
+        }
+        Ok(())
+    }
+
+    /// removes the table's folders with all files in them
+    pub fn remove_table_folder(&self, schema: &TableSchema) -> std::io::Result<()> {
+        for folder in self.table_data_folders(schema).into_iter().chain(Some(self.table_folder(schema))) {
+            match std::fs::remove_dir_all(folder) {
+                Err(ref e) if e.kind() == ErrorKind::NotFound => {} // data folder and base folder can coincide
+                r => r?,
+            }
+        }
+        Ok(())
+    }
+
+    /// the table's data folder where a new SsTable should be stored, i.e. the one on the disk
+    ///  with the most free space
+    pub fn select_data_folder(&self, schema: &TableSchema) -> std::io::Result<PathBuf> {
+        let mut result = None;
+        for folder in self.table_data_folders(schema) {
+            let available = fs2::available_space(&folder)?;
+            match result {
+                Some((_, a)) if a >= available => {}
+                _ => result = Some((folder, available)),
+            }
+        }
+
+        match result {
+            Some((folder, _)) => Ok(folder),
+            None => Err(std::io::Error::new(ErrorKind::NotFound, "no data folders configured")),
+        }
+    }
 
+    pub fn new_file(&self, folder: &Path, file_name: &str, writeable: bool) -> std::io::Result<File> {
         OpenOptions::new()
             .create(writeable)
             .write(writeable)
             .read(true)
-            .open(&path)
+            .open(folder.join(file_name))
+    }
+
+    /// creates a new file, failing with ErrorKind::AlreadyExists if the file exists
+    pub fn create_new_file(&self, folder: &Path, file_name: &str) -> std::io::Result<File> {
+        OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .read(true)
+            .open(folder.join(file_name))
+    }
+
+    /// names of all files in a folder
+    pub fn file_names(&self, folder: &Path) -> std::io::Result<Vec<String>> {
+        let mut result = Vec::new();
+        for entry in std::fs::read_dir(folder)? {
+            if let Some(name) = entry?.file_name().to_str() {
+                result.push(name.to_string());
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StorageMode {
+    /// the MemTable is flushed to SsTables in the table's data folders
+    Persistent,
+    /// Data is held in the MemTable only and never touches the disk, e.g. for caches or tests.
+    ///  If max_size (bytes of row data) is set, writes exceeding it either evict the rows that
+    ///  were written least recently or fail, depending on evict.
+    InMemory {
+        max_size: Option<usize>,
+        evict: bool,
+    },
+}
+
+/// Access pattern hints for SsTable files, passed to the OS via madvise(2) (and fadvise(2) for
+///  DontNeed). They affect only performance, and they are ignored on platforms without them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AccessAdvice {
+    Normal,
+    /// disables read ahead
+    Random,
+    /// aggressive read ahead, and pages are freed soon after they were read
+    Sequential,
+    /// starts reading the entire file in the background
+    WillNeed,
+    /// drops the file's pages from memory and from the page cache
+    DontNeed,
+}
+
+/// How point reads read SsTable rows. Scans and compactions always read through the SsTables'
+///  memory maps.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IoBackend {
+    /// through the data file's memory map
+    Mmap,
+    /// With positioned reads (pread(2)) of the rows on the index search path, through a bounded
+    ///  cache of data file blocks (see TableConfig::block_cache_size). The data file's pages are
+    ///  then held by the page cache only rather than mapped into the process, which can behave
+    ///  better for tables that are much larger than memory. The index is still read through its
+    ///  memory map. On platforms without pread, blocks are copied from the memory map.
+    Pread,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompactionStrategyConfig {
+    /// Compacts SsTables of similar size: SsTables are grouped into buckets whose sizes lie
+    ///  within [bucket_low, bucket_high] times the bucket's average size (SsTables smaller than
+    ///  min_sstable_size all share a bucket), and a bucket is compacted once it holds at least
+    ///  min_threshold SsTables, taking at most max_threshold of them.
+    SizeTiered {
+        min_threshold: usize,
+        max_threshold: usize,
+        bucket_low: f64,
+        bucket_high: f64,
+        min_sstable_size: u64,
+    },
+    /// For time series data with TTLs: SsTables are grouped into time windows of window_seconds
+    ///  based on their newest data. The current window is compacted once it holds min_threshold
+    ///  SsTables, older windows are compacted into a single SsTable, and SsTables containing
+    ///  only expired data are dropped without reading them.
+    TimeWindow {
+        window_seconds: u64,
+        min_threshold: usize,
+        max_threshold: usize,
+    },
+}
+
+impl CompactionStrategyConfig {
+    pub fn size_tiered() -> CompactionStrategyConfig {
+        CompactionStrategyConfig::SizeTiered {
+            min_threshold: 4,
+            max_threshold: 32,
+            bucket_low: 0.5,
+            bucket_high: 1.5,
+            min_sstable_size: 50 * 1024 * 1024,
+        }
     }
 }
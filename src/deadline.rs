@@ -0,0 +1,88 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::prelude::*;
+
+/// Cancels reads from another thread, e.g. when the client that issued them disconnects.
+///  Clones share their state.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+}
+
+/// Bounds a read by a point in time and / or a cancellation token. Reads check it between
+///  MemTables and SsTables, so a read that touches many SsTables fails with HtError::Timeout
+///  or HtError::Cancelled instead of blocking the calling thread indefinitely. A single
+///  SsTable lookup is not interrupted.
+#[derive(Clone, Debug, Default)]
+pub struct Deadline {
+    at: Option<Instant>,
+    token: Option<CancellationToken>,
+}
+
+impl Deadline {
+    /// never expires
+    pub fn none() -> Deadline {
+        Deadline::default()
+    }
+
+    pub fn after(timeout: Duration) -> Deadline {
+        Deadline { at: Some(Instant::now() + timeout), token: None }
+    }
+
+    pub fn with_token(self, token: &CancellationToken) -> Deadline {
+        Deadline { token: Some(token.clone()), ..self }
+    }
+
+    pub fn check(&self) -> HtResult<()> {
+        if self.token.as_ref().map(|t| t.is_cancelled()).unwrap_or(false) {
+            return Err(HtError::Cancelled);
+        }
+        match self.at {
+            Some(at) if Instant::now() >= at => Err(HtError::Timeout),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::deadline::{CancellationToken, Deadline};
+    use crate::prelude::*;
+
+    #[test]
+    pub fn test_deadline() {
+        assert!(Deadline::none().check().is_ok());
+        assert!(Deadline::after(Duration::from_secs(3600)).check().is_ok());
+        match Deadline::after(Duration::from_secs(0)).check() {
+            Err(HtError::Timeout) => {}
+            _ => panic!("expected timeout"),
+        }
+
+        let token = CancellationToken::new();
+        let deadline = Deadline::after(Duration::from_secs(3600)).with_token(&token);
+        assert!(deadline.check().is_ok());
+        token.clone().cancel();
+        match deadline.check() {
+            Err(HtError::Cancelled) => {}
+            _ => panic!("expected cancellation"),
+        }
+    }
+}
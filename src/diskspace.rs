@@ -0,0 +1,173 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use log::warn;
+
+use crate::config::TableConfig;
+use crate::prelude::*;
+use crate::table::TableSchema;
+use crate::time::MergeTimestamp;
+
+/// writes use the result of a disk space check for this long before checking again
+const CHECK_INTERVAL_MILLIS: u64 = 1000;
+
+/// How full a table's data folders are, relative to TableConfig::disk_warn_watermark and
+///  TableConfig::disk_critical_watermark
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum DiskSpaceLevel {
+    Normal,
+    Warn,
+    Critical,
+}
+
+impl DiskSpaceLevel {
+    fn to_bits(self) -> u64 {
+        match self {
+            DiskSpaceLevel::Normal => 1,
+            DiskSpaceLevel::Warn => 2,
+            DiskSpaceLevel::Critical => 3,
+        }
+    }
+
+    fn from_bits(bits: u64) -> Option<DiskSpaceLevel> {
+        match bits {
+            1 => Some(DiskSpaceLevel::Normal),
+            2 => Some(DiskSpaceLevel::Warn),
+            3 => Some(DiskSpaceLevel::Critical),
+            _ => None,
+        }
+    }
+}
+
+/// The space on the file system containing a table data folder
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DiskSpace {
+    pub folder: PathBuf,
+    /// bytes available to unprivileged users
+    pub available: u64,
+    pub total: u64,
+}
+
+impl DiskSpace {
+    pub fn of_table(config: &TableConfig, schema: &TableSchema) -> HtResult<Vec<DiskSpace>> {
+        config.table_data_folders(schema).into_iter()
+            .map(|folder| {
+                let stats = fs2::statvfs(&folder)?;
+                Ok(DiskSpace { folder, available: stats.available_space(), total: stats.total_space() })
+            })
+            .collect()
+    }
+
+    /// the fraction of the file system that is not available, between 0 and 1
+    pub fn used_ratio(&self) -> f64 {
+        match self.total {
+            0 => 1.0,
+            total => 1.0 - self.available as f64 / total as f64,
+        }
+    }
+
+    /// the level of the fullest data folder
+    pub fn level(config: &TableConfig, disk_space: &[DiskSpace]) -> DiskSpaceLevel {
+        let used = disk_space.iter().map(|d| d.used_ratio()).fold(0.0, f64::max);
+        match (config.disk_warn_watermark, config.disk_critical_watermark) {
+            (_, Some(critical)) if used >= critical => DiskSpaceLevel::Critical,
+            (Some(warn), _) if used >= warn => DiskSpaceLevel::Warn,
+            _ => DiskSpaceLevel::Normal,
+        }
+    }
+
+    /// Checks if writing a given number of bytes keeps all data folders below the critical
+    ///  watermark. SsTables are written to a single data folder, but it is not known in advance
+    ///  which one, so this requires the space in every data folder.
+    pub fn has_room_for(config: &TableConfig, disk_space: &[DiskSpace], bytes: u64) -> bool {
+        let critical = match config.disk_critical_watermark {
+            Some(critical) => critical,
+            None => return true,
+        };
+        disk_space.iter().all(|d| {
+            let after = DiskSpace { folder: d.folder.clone(), available: d.available.saturating_sub(bytes), total: d.total };
+            after.used_ratio() < critical
+        })
+    }
+}
+
+/// Caches a table's DiskSpaceLevel for writes, counting and logging checks that find the data
+///  folders above the warn watermark.
+///
+/// The cached level is read by every write, so it is kept in an atomic rather than behind a
+///  lock. Writes that find it outdated at the same time may each check the data folders, which
+///  is harmless.
+#[derive(Default)]
+pub struct DiskSpaceMonitor {
+    /// the epoch millis of the last check, shifted left by two bits, with the level found in the
+    ///  lower two bits (see DiskSpaceLevel::to_bits); 0 if there was no check yet
+    last_check: AtomicU64,
+    warnings: AtomicU64,
+}
+
+impl DiskSpaceMonitor {
+    /// the level found by the last check, checking again if it is older than the check interval
+    pub fn level(&self, config: &TableConfig, schema: &TableSchema, now: MergeTimestamp) -> HtResult<DiskSpaceLevel> {
+        let last_check = self.last_check.load(Ordering::Acquire);
+        match DiskSpaceLevel::from_bits(last_check & 3) {
+            Some(level) if (last_check >> 2) + CHECK_INTERVAL_MILLIS > now.epoch_millis() => Ok(level),
+            _ => {
+                let disk_space = DiskSpace::of_table(config, schema)?;
+                Ok(self.record(config, schema, now, &disk_space))
+            }
+        }
+    }
+
+    /// checks the data folders now, e.g. before a compaction
+    pub fn check(&self, config: &TableConfig, schema: &TableSchema, now: MergeTimestamp) -> HtResult<Vec<DiskSpace>> {
+        let disk_space = DiskSpace::of_table(config, schema)?;
+        self.record(config, schema, now, &disk_space);
+        Ok(disk_space)
+    }
+
+    fn record(&self, config: &TableConfig, schema: &TableSchema, now: MergeTimestamp, disk_space: &[DiskSpace]) -> DiskSpaceLevel {
+        let level = DiskSpace::level(config, disk_space);
+        self.last_check.store(now.epoch_millis() << 2 | level.to_bits(), Ordering::Release);
+
+        if level != DiskSpaceLevel::Normal {
+            self.warnings.fetch_add(1, Ordering::Relaxed);
+            let used = disk_space.iter()
+                .map(|d| format!("{:?} {:.1}%", d.folder, d.used_ratio() * 100.0))
+                .collect::<Vec<_>>();
+            warn!("data folders of table {} are filled above the {:?} watermark: {}", schema.name, level, used.join(", "));
+        }
+        level
+    }
+
+    /// the number of checks that found the data folders above the warn watermark
+    pub fn warnings(&self) -> u64 {
+        self.warnings.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::config::TableConfig;
+    use crate::diskspace::{DiskSpace, DiskSpaceLevel};
+
+    #[test]
+    pub fn test_disk_space_level() {
+        let config = |critical: Option<f64>| TableConfig {
+            disk_warn_watermark: Some(0.8),
+            disk_critical_watermark: critical,
+            ..TableConfig::new(PathBuf::new())
+        };
+        let space = |available: u64| DiskSpace { folder: PathBuf::from("a"), available, total: 100 };
+
+        assert_eq!(DiskSpace::level(&config(Some(0.9)), &[space(50)]), DiskSpaceLevel::Normal);
+        assert_eq!(DiskSpace::level(&config(Some(0.9)), &[space(50), space(20)]), DiskSpaceLevel::Warn);
+        assert_eq!(DiskSpace::level(&config(Some(0.9)), &[space(10), space(50)]), DiskSpaceLevel::Critical);
+        assert_eq!(DiskSpace::level(&config(None), &[space(0)]), DiskSpaceLevel::Warn);
+
+        assert!(DiskSpace::has_room_for(&config(Some(0.9)), &[space(50), space(20)], 9));
+        assert!(!DiskSpace::has_room_for(&config(Some(0.9)), &[space(50), space(20)], 10));
+        assert!(DiskSpace::has_room_for(&config(None), &[space(0)], 10));
+    }
+}
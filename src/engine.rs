@@ -0,0 +1,2975 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+use arc_swap::ArcSwap;
+use fs2::FileExt;
+use log::warn;
+
+use crate::aggregate::{Aggregate, AggregateGroup, GroupAggregator};
+use crate::audit::{AuditCallback, AuditEvent, AuditOperation};
+use crate::backup;
+use crate::cdc::{ChangeEvent, ChangeSink};
+use crate::compaction::{compact_parallel, compaction_strategy, tombstone_compaction, CompactionContext, CompactionPlan, GcThresholds, MergeIterator};
+use crate::config::{IoBackend, StorageMode, TableConfig};
+use crate::deadline::Deadline;
+use crate::diskspace::{DiskSpace, DiskSpaceLevel, DiskSpaceMonitor};
+use crate::hll::HyperLogLog;
+use crate::idempotency::IdempotencyWindow;
+use crate::locks::PartitionLocks;
+use crate::manifest::Manifest;
+use crate::memtable::MemTable;
+use crate::predicate::{ColumnPredicate, PredicateOp};
+use crate::prelude::*;
+use crate::quarantine::Quarantine;
+use crate::ratelimit::WriteRateLimiter;
+use crate::readfilter::{apply_read_filter, ReadFilter};
+use crate::resultset::{PagingState, ResultSet};
+use crate::settings::{apply_setting, SettingsOverrides};
+use crate::slice::{ClusteringBound, ClusteringBounds, PartitionSlice, SlicePosition};
+use crate::slowlog::{describe_key, LogSlowQueries, QueryKind, QueryTimer, SlowQueryCallback};
+use crate::sstable::{SsTable, SsTableComponent, SsTableGenerations, SsTableName, SsTableRowIter, SsTableWriter};
+use crate::table::{ColumnData, ColumnId, ColumnValue, DetachedRowData, PrimaryKey, PrimaryKeySpec, RowData, SeekableRows, TableSchema};
+use crate::tasks::{BackgroundTask, BackgroundTasks, TaskKind, TaskProgress};
+use crate::time::{HtClock, MergeTimestamp, TtlTimestamp};
+
+const LOCK_FILE_NAME: &str = "lock";
+const PARTITION_LOCK_STRIPES: usize = 256;
+
+/// the name of the folders containing snapshots, see Table::snapshot
+const SNAPSHOTS_FOLDER: &str = "snapshots";
+
+/// A table's storage on a single node: writes go to a MemTable which is flushed to SsTables,
+///  and reads merge the MemTable's and all SsTables' data for a given primary key.
+///
+/// The MemTables and SsTables are held in an immutable TableView which flushes and compactions
+///  replace atomically. Reads work on a consistent snapshot without locking the table, and
+///  writes only contend on the active MemTable.
+pub struct Table {
+    /// replaced when settings are changed at runtime, see Table::set_setting
+    config: ArcSwap<TableConfig>,
+    schema: Arc<TableSchema>,
+    clock: Arc<dyn HtClock>,
+    view: ArcSwap<TableView>,
+    /// serializes flushes and compactions, i.e. all changes to the view
+    view_update: Mutex<()>,
+    /// shared by everything that writes SsTables, so that generations are unique across all
+    ///  data folders
+    generations: Arc<SsTableGenerations>,
+    /// held for the Table's lifetime, releasing the lock when the Table is dropped; None for
+    ///  read-only and in-memory tables
+    _lock_file: Option<File>,
+    read_only: bool,
+    write_limiter: Option<WriteRateLimiter>,
+    partition_locks: PartitionLocks,
+    idempotency: IdempotencyWindow,
+    slow_query_callback: Box<dyn SlowQueryCallback>,
+    audit_callback: Option<Box<dyn AuditCallback>>,
+    change_sink: Option<Box<dyn ChangeSink>>,
+    read_filter: Option<Box<dyn ReadFilter>>,
+    /// the number of reads that exceeded TableConfig::tombstone_warn_threshold
+    tombstone_warnings: AtomicU64,
+    quarantine: Quarantine,
+    background_tasks: BackgroundTasks,
+    disk_space: DiskSpaceMonitor,
+}
+
+/// The result of reading a single row, see Table::lookup
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReadResult {
+    Live(DetachedRowData),
+    /// The row was deleted by a row tombstone with the given timestamp, and nothing was
+    ///  written after it. Once compaction discarded the tombstone after its GC grace period,
+    ///  the row is NotFound.
+    Deleted { at: MergeTimestamp },
+    /// there is no data for the primary key, or the table's ReadFilter hides the row
+    NotFound,
+}
+
+impl ReadResult {
+    /// the row if it is live, which is what Table::get returns
+    pub fn into_live(self) -> Option<DetachedRowData> {
+        match self {
+            ReadResult::Live(row) => Some(row),
+            _ => None,
+        }
+    }
+}
+
+/// Limits for Table::scan_limited. Scans stop reading as soon as the limit is reached, so
+///  that a limited scan of a large table is cheap. There are no reverse scans yet.
+#[derive(Clone, Default)]
+pub struct ScanLimits {
+    /// the maximum number of rows returned, which must be positive
+    pub limit: Option<usize>,
+    /// The maximum number of rows returned per partition, which must be positive. Further rows
+    ///  of a partition are skipped without passing them to the ReadFilter, but they are still
+    ///  read.
+    pub per_partition_limit: Option<usize>,
+    /// Continues a scan after a row, typically the paging_state of the previous page. Rows of
+    ///  its partition count towards per_partition_limit with those returned before.
+    pub start_after: Option<PagingState>,
+}
+
+/// A snapshot of the data a table's reads are based on
+pub struct TableView {
+    /// the MemTable that receives writes
+    pub mem_table: Arc<RwLock<MemTable>>,
+    /// MemTables that no longer receive writes and are being flushed, newest first
+    pub flushing: Vec<Arc<RwLock<MemTable>>>,
+    pub ss_tables: Vec<Arc<SsTable>>,
+    /// incremented by every change of the view. A read works on a single view, so it sees all
+    ///  writes that completed before that view's version was loaded.
+    pub version: u64,
+}
+
+impl TableView {
+    /// The active MemTable followed by the flushing ones, i.e. newest first. A flush adds a
+    ///  MemTable's SsTables and removes it from 'flushing' in a single view update, so each
+    ///  row is in a view's MemTables or in its SsTables, and reads that work on a single view
+    ///  never miss rows that are being flushed.
+    pub fn mem_tables(&self) -> impl Iterator<Item=&Arc<RwLock<MemTable>>> {
+        Some(&self.mem_table).into_iter().chain(self.flushing.iter())
+    }
+}
+
+impl Table {
+    /// Sets up a new table, creating its folder. This fails with HtError::Locked if the table's
+    ///  folder is in use by another process or Table instance. In-memory tables have no folder.
+    pub fn new(config: &Arc<TableConfig>, schema: &Arc<TableSchema>, clock: &Arc<dyn HtClock>) -> HtResult<Table> {
+        schema.validate()?;
+        config.validate(schema)?;
+        if config.storage_mode != StorageMode::Persistent {
+            return Ok(Table::create(config, schema, clock, SsTableGenerations::default(), None, false));
+        }
+
+        config.create_table_folder(schema)?;
+        let lock_file = Table::lock_table_folder(config, schema)?;
+        let config = SettingsOverrides::with_stored(config, schema)?;
+        let generations = SsTableGenerations::seed(&config, schema)?;
+        Ok(Table::create(&config, schema, clock, generations, Some(lock_file), false))
+    }
+
+    fn create(config: &Arc<TableConfig>, schema: &Arc<TableSchema>, clock: &Arc<dyn HtClock>, generations: SsTableGenerations, lock_file: Option<File>, read_only: bool) -> Table {
+        let view = TableView {
+            mem_table: Arc::new(RwLock::new(MemTable::new(config, schema, clock))),
+            flushing: Vec::new(),
+            ss_tables: Vec::new(),
+            version: 0,
+        };
+
+        Table {
+            config: ArcSwap::new(config.clone()),
+            schema: schema.clone(),
+            clock: clock.clone(),
+            view: ArcSwap::from_pointee(view),
+            view_update: Mutex::new(()),
+            generations: Arc::new(generations),
+            _lock_file: lock_file,
+            read_only,
+            write_limiter: WriteRateLimiter::new(config),
+            partition_locks: PartitionLocks::new(PARTITION_LOCK_STRIPES),
+            idempotency: IdempotencyWindow::new(config.idempotency_window),
+            slow_query_callback: Box::new(LogSlowQueries {}),
+            audit_callback: None,
+            change_sink: None,
+            read_filter: None,
+            tombstone_warnings: AtomicU64::new(0),
+            quarantine: Quarantine::new(clock),
+            background_tasks: BackgroundTasks::default(),
+            disk_space: DiskSpaceMonitor::default(),
+        }
+    }
+
+    /// replaces the default callback for slow queries (see TableConfig::slow_query_threshold),
+    ///  which logs them
+    pub fn set_slow_query_callback(&mut self, callback: Box<dyn SlowQueryCallback>) {
+        self.slow_query_callback = callback;
+    }
+
+    /// Sets a callback receiving all writes after they were applied and DDL operations before
+    ///  they are applied, see AuditCallback. There is none by default.
+    pub fn set_audit_callback(&mut self, callback: Option<Box<dyn AuditCallback>>) {
+        self.audit_callback = callback;
+    }
+
+    fn audit(&self, operation: AuditOperation, row: Option<&DetachedRowData>) -> HtResult<()> {
+        match &self.audit_callback {
+            Some(callback) => callback.on_audit_event(&AuditEvent::new(&self.schema.name, operation, self.clock.now(), row)),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets a sink receiving all written rows after they were applied, see the cdc module.
+    ///  There is none by default.
+    pub fn set_change_sink(&mut self, sink: Option<Box<dyn ChangeSink>>) {
+        self.change_sink = sink;
+    }
+
+    fn capture_change(&self, row: &DetachedRowData) -> HtResult<()> {
+        match &self.change_sink {
+            Some(sink) => sink.on_change(&ChangeEvent { table: self.schema.name.clone(), timestamp: self.clock.now(), row: row.clone() }),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets a filter that can suppress or mask rows returned by reads, see the readfilter module.
+    ///  There is none by default.
+    pub fn set_read_filter(&mut self, filter: Option<Box<dyn ReadFilter>>) {
+        self.read_filter = filter;
+    }
+
+    fn filter_row(&self, caller: Option<&str>, row: DetachedRowData) -> HtResult<Option<DetachedRowData>> {
+        match &self.read_filter {
+            Some(filter) => apply_read_filter(filter.as_ref(), caller, row),
+            None => Ok(Some(row)),
+        }
+    }
+
+    /// Acquires an advisory lock on a lock file in the table's folder. The lock is released when
+    ///  the returned file is closed, including when the process dies.
+    fn lock_table_folder(config: &TableConfig, schema: &TableSchema) -> HtResult<File> {
+        let lock_file = config.new_file(&config.table_folder(schema), LOCK_FILE_NAME, true)?;
+        match lock_file.try_lock_exclusive() {
+            Ok(()) => Ok(lock_file),
+            Err(ref e) if e.kind() == fs2::lock_contended_error().kind() =>
+                Err(HtError::Locked(config.table_folder(schema).join(LOCK_FILE_NAME))),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Opens an existing table with the SsTables listed in its manifest. This also works for a
+    ///  table that was never written to.
+    pub fn open(config: &Arc<TableConfig>, schema: &Arc<TableSchema>, clock: &Arc<dyn HtClock>) -> HtResult<Table> {
+        let table = Table::new(config, schema, clock)?;
+        table.open_ss_tables()?;
+        Ok(table)
+    }
+
+    /// Opens an existing table without modifying anything on disk, e.g. for analytics or for
+    ///  verifying a backup. The table's lock is not acquired, so this works even while another
+    ///  process has the table open, reading the SsTables that are live at the time of opening.
+    ///  All writes, including flushes and compactions, are rejected.
+    pub fn open_read_only(config: &Arc<TableConfig>, schema: &Arc<TableSchema>, clock: &Arc<dyn HtClock>) -> HtResult<Table> {
+        schema.validate()?;
+        config.validate(schema)?;
+        let config = SettingsOverrides::with_stored(config, schema)?;
+        let table = Table::create(&config, schema, clock, SsTableGenerations::default(), None, true);
+        table.open_ss_tables()?;
+        Ok(table)
+    }
+
+    fn open_ss_tables(&self) -> HtResult<()> {
+        if !self.is_persistent() {
+            return Ok(());
+        }
+
+        let mut ss_tables = Vec::new();
+        for entry in Manifest::read(&self.config.load(), &self.schema)?.entries {
+            ss_tables.push(Arc::new(SsTable::open(&self.config.load(), &self.schema, &entry.folder, &entry.name)?));
+        }
+        self.update_ss_tables(|_| ss_tables);
+        Ok(())
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn is_persistent(&self) -> bool {
+        self.config.load().storage_mode == StorageMode::Persistent
+    }
+
+    fn check_writable(&self) -> HtResult<()> {
+        if self.is_read_only() {
+            return Err(HtError::misc("table is opened read-only"));
+        }
+        Ok(())
+    }
+
+    /// Fails writes of rows with neither regular columns nor a row tombstone or touch. Such a row
+    ///  does not exist (see RowData), so writing it would have no effect.
+    fn check_has_content(&self, row: &DetachedRowData) -> HtResult<()> {
+        let row = row.row_data_view();
+        if row.row_tombstone().is_none() && row.touch().is_none() && row.columns().all(|c| self.schema.is_primary_key(c.col_id)) {
+            return Err(HtError::misc("a row must have at least one regular column"));
+        }
+        Ok(())
+    }
+
+    /// fails writes with columns expiring beyond TableConfig::max_ttl_seconds from now
+    fn check_expiry(&self, row: &DetachedRowData) -> HtResult<()> {
+        let max_expiry = self.clock.ttl_timestamp(self.config.load().max_ttl_seconds);
+        let row = row.row_data_view();
+        let exceeds = |expiry: Option<TtlTimestamp>| matches!(expiry, Some(expiry) if expiry > max_expiry);
+        if row.columns().any(|col| exceeds(col.expiry)) || exceeds(row.touch().map(|touch| touch.expiry)) {
+            return Err(HtError::misc("TTL exceeds TableConfig::max_ttl_seconds"));
+        }
+        Ok(())
+    }
+
+    /// fails writes while the data folders are filled beyond TableConfig::disk_critical_watermark
+    fn check_disk_space(&self) -> HtResult<()> {
+        let config = self.config.load();
+        if config.storage_mode != StorageMode::Persistent || config.disk_critical_watermark.is_none() {
+            return Ok(());
+        }
+        match self.disk_space.level(&config, &self.schema, self.clock.now())? {
+            DiskSpaceLevel::Critical => Err(HtError::InsufficientDiskSpace),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the space in the table's data folders now, updating the level writes use
+    pub fn disk_space(&self) -> HtResult<Vec<DiskSpace>> {
+        self.disk_space.check(&self.config.load(), &self.schema, self.clock.now())
+    }
+
+    /// the number of disk space checks that found the data folders above
+    ///  TableConfig::disk_warn_watermark
+    pub fn disk_space_warnings(&self) -> u64 {
+        self.disk_space.warnings()
+    }
+
+    /// Checks if compacting the given SsTables keeps the data folders below the critical
+    ///  watermark, assuming that the compaction's output is as large as its input. Both exist
+    ///  until the compaction finishes.
+    fn has_room_for_compaction(&self, inputs: &[Arc<SsTable>]) -> HtResult<bool> {
+        let disk_space = self.disk_space()?;
+        let size = inputs.iter().map(|t| t.data_size()).sum();
+        if DiskSpace::has_room_for(&self.config.load(), &disk_space, size) {
+            return Ok(true);
+        }
+        warn!("skipping compaction of {} bytes of table {}: not enough disk space", size, self.schema.name);
+        Ok(false)
+    }
+
+    /// Shuts the table down cleanly: flushes the MemTable, writes the manifest and then
+    ///  releases the SsTables' memory maps and the table's lock. Compaction runs synchronously,
+    ///  so there is none in progress at this point.
+    pub fn shutdown(self) -> HtResult<()> {
+        if !self.is_read_only() && self.is_persistent() {
+            self.flush()?;
+            self.write_manifest(&self.view.load().ss_tables)?;
+        }
+        drop(self);
+        Ok(())
+    }
+
+    /// Imports the table's SsTables from a folder, e.g. SsTables written by an external tool for
+    ///  a bulk load, or copied from another node. The SsTables are copied into the table's data
+    ///  folders with new generations and are readable once this returns; the folder is not
+    ///  modified.
+    ///
+    /// All SsTables are opened and verified (see SsTable::verify) before any of them is imported,
+    ///  so an SsTable with missing or malformed files fails the import as a whole. SsTables do
+    ///  not record the schema they were written with, so rows written with an incompatible
+    ///  schema are detected only if they break the row format.
+    pub fn import_sstables(&self, folder: &Path) -> HtResult<Vec<SsTableName>> {
+        self.check_writable()?;
+        if !self.is_persistent() {
+            return Err(HtError::misc("in-memory tables can not import SsTables"));
+        }
+
+        let config = self.config.load_full();
+        let names = SsTableName::in_folder(&config, &self.schema, folder)?;
+        for name in &names {
+            SsTable::open(&config, &self.schema, folder, name)
+                .and_then(|ss_table| ss_table.verify())
+                .map_err(|e| HtError::misc(&format!("SsTable {} can not be imported: {:?}", name, e)))?;
+        }
+
+        let _guard = self.view_update.lock().unwrap();
+        let mut imported = Vec::new();
+        for name in &names {
+            imported.push(Arc::new(SsTable::import(&config, &self.schema, &self.generations, folder, name)?));
+        }
+
+        let ss_tables = self.update_ss_tables(|ss_tables| ss_tables.iter().chain(imported.iter()).cloned().collect());
+        self.write_manifest(&ss_tables)?;
+        Ok(imported.iter().map(|ss_table| ss_table.name().clone()).collect())
+    }
+
+    /// drops the table, removing its folder with all of its files
+    pub fn drop_table(self) -> HtResult<()> {
+        self.check_writable()?;
+        self.audit(AuditOperation::DropTable, None)?;
+        if !self.is_persistent() {
+            return Ok(());
+        }
+
+        let config = self.config.load_full();
+        let schema = self.schema.clone();
+
+        // SsTables must be unmapped before their files are removed
+        drop(self);
+        config.remove_table_folder(&schema)?;
+        Ok(())
+    }
+
+    pub fn schema(&self) -> &Arc<TableSchema> {
+        &self.schema
+    }
+
+    /// the table's current config, including settings changed at runtime
+    pub fn config(&self) -> Arc<TableConfig> {
+        self.config.load_full()
+    }
+
+    /// Changes one of settings::RUNTIME_SETTINGS, persisting the new value in the table's folder
+    ///  so that it overrides the TableConfig the table is opened with from now on. Flushes and
+    ///  compactions that start afterwards use the new value; a new key_cache_size applies to
+    ///  SsTables that are written afterwards.
+    pub fn set_setting(&self, name: &str, value: &str) -> HtResult<()> {
+        self.check_writable()?;
+        let _update = self.view_update.lock().unwrap();
+
+        let mut config = TableConfig::clone(&self.config.load());
+        apply_setting(&mut config, name, value)?;
+        config.validate(&self.schema)?;
+
+        if self.is_persistent() {
+            let mut overrides = SettingsOverrides::read(&config, &self.schema)?;
+            overrides.entries.insert(name.to_string(), value.to_string());
+            overrides.write(&config, &self.schema)?;
+        }
+        self.config.store(Arc::new(config));
+        Ok(())
+    }
+
+    /// the current snapshot of the table's MemTables and SsTables
+    pub fn view(&self) -> Arc<TableView> {
+        self.view.load_full()
+    }
+
+    /// the number of reads that exceeded TableConfig::tombstone_warn_threshold, including
+    ///  those that failed
+    pub fn tombstone_warnings(&self) -> u64 {
+        self.tombstone_warnings.load(Ordering::Relaxed)
+    }
+
+    /// Estimates the number of distinct partitions in the live SsTables by merging their
+    ///  HyperLogLog sketches, so partitions stored in several SsTables are counted once. Data
+    ///  that was not flushed yet is not included.
+    pub fn partition_count_estimate(&self) -> u64 {
+        let mut partitions = HyperLogLog::new();
+        for ss_table in &self.view.load().ss_tables {
+            partitions.merge(&ss_table.stats().partitions);
+        }
+        partitions.estimate()
+    }
+
+    /// the corrupt SsTable rows reads encountered, see TableConfig::validate_reads
+    pub fn quarantine(&self) -> &Quarantine {
+        &self.quarantine
+    }
+
+    /// the table's running flushes and compactions, see BackgroundTasks
+    pub fn background_tasks(&self) -> Vec<BackgroundTask> {
+        self.background_tasks.snapshot()
+    }
+
+    pub fn ss_tables(&self) -> Vec<Arc<SsTable>> {
+        self.view.load().ss_tables.clone()
+    }
+
+    /// Writes a row, waiting if this exceeds the table's write rate limit. If the row was
+    ///  written but reporting it failed, this returns HtError::NotDelivered.
+    pub fn put(&self, row: DetachedRowData) -> HtResult<()> {
+        self.write(row, AuditOperation::Put)
+    }
+
+    /// put, reporting the write as the given operation
+    fn write(&self, row: DetachedRowData, operation: AuditOperation) -> HtResult<()> {
+        let row = self.check_write(row)?;
+        let mut timer = QueryTimer::start(&self.config.load());
+        if let Some(limiter) = &self.write_limiter {
+            limiter.acquire(row.encoded_len());
+            timer.phase("rate limit");
+        }
+        self.put_unlimited(row.clone())?;
+        timer.finish(&self.config.load(), self.slow_query_callback.as_ref(), QueryKind::Put, &row);
+        self.report_write(operation, &row)
+    }
+
+    /// Deletes the row for a primary key by writing a row tombstone with the current timestamp
+    ///  (see DetachedRowData::row_tombstone). pk_data is a row containing (at least) the full
+    ///  primary key.
+    pub fn delete(&self, pk_data: &DetachedRowData) -> HtResult<()> {
+        let pk_data = pk_data.row_data_view();
+        let pk_columns = pk_data.columns()
+            .filter(|c| self.schema.is_primary_key(c.col_id))
+            .collect();
+        self.write(DetachedRowData::row_tombstone(&self.schema, &pk_columns, self.clock.now())?, AuditOperation::Delete)
+    }
+
+    /// Refreshes the expiry of a row's columns to ttl_seconds from now without rewriting their
+    ///  values, by writing a touch (see DetachedRowData::touch) - e.g. for session and cache
+    ///  tables. The touch is applied when it is merged with the columns, so this needs no read.
+    ///  It does not create a row that does not exist.
+    pub fn touch(&self, pk_data: &DetachedRowData, ttl_seconds: u32) -> HtResult<()> {
+        let pk_data = pk_data.row_data_view();
+        let pk_columns = pk_data.columns()
+            .filter(|c| self.schema.is_primary_key(c.col_id))
+            .collect();
+        self.write(DetachedRowData::touch(&self.schema, &pk_columns, self.clock.now(), self.clock.ttl_timestamp(ttl_seconds))?, AuditOperation::Touch)
+    }
+
+    /// Writes a row, failing with HtError::RateLimited instead of waiting if this exceeds the
+    ///  table's write rate limit
+    pub fn try_put(&self, row: DetachedRowData) -> HtResult<()> {
+        let row = self.check_write(row)?;
+        if let Some(limiter) = &self.write_limiter {
+            if !limiter.try_acquire(row.encoded_len()) {
+                return Err(HtError::RateLimited);
+            }
+        }
+        let timer = QueryTimer::start(&self.config.load());
+        self.put_unlimited(row.clone())?;
+        timer.finish(&self.config.load(), self.slow_query_callback.as_ref(), QueryKind::Put, &row);
+        self.report_write(AuditOperation::Put, &row)
+    }
+
+    /// the checks a write must pass before it is applied, returning the row with the default
+    ///  TTL applied
+    fn check_write(&self, row: DetachedRowData) -> HtResult<DetachedRowData> {
+        self.check_writable()?;
+        self.check_has_content(&row)?;
+        let row = self.with_default_ttl(row);
+        self.check_expiry(&row)?;
+        self.check_disk_space()?;
+        Ok(row)
+    }
+
+    /// reports a write that was applied to the audit callback and the change sink, see
+    ///  HtError::NotDelivered
+    fn report_write(&self, operation: AuditOperation, row: &DetachedRowData) -> HtResult<()> {
+        self.audit(operation, Some(row))
+            .and_then(|_| self.capture_change(row))
+            .map_err(|e| HtError::NotDelivered(Box::new(e)))
+    }
+
+    /// the row with TableConfig::default_ttl_seconds applied to its regular columns that have a
+    ///  value but no expiry
+    fn with_default_ttl(&self, row: DetachedRowData) -> DetachedRowData {
+        let default_ttl = match self.config.load().default_ttl_seconds {
+            Some(ttl) => ttl,
+            None => return row,
+        };
+
+        let view = row.row_data_view();
+        let needs_ttl = |col: &ColumnData| col.value.is_some() && col.expiry.is_none() && !self.schema.is_primary_key(col.col_id);
+        if !view.columns().any(|col| needs_ttl(&col)) {
+            return row;
+        }
+
+        let expiry = self.clock.ttl_timestamp(default_ttl);
+        let columns = view.columns()
+            .map(|col| match needs_ttl(&col) {
+                true => ColumnData::new(col.col_id, col.timestamp, Some(expiry), col.value),
+                false => col,
+            })
+            .collect();
+        DetachedRowData::assemble_unchecked(&self.schema, &columns, view.row_tombstone(), view.touch())
+    }
+
+    fn put_unlimited(&self, row: DetachedRowData) -> HtResult<()> {
+        loop {
+            let view = self.view.load();
+            let mut mem_table = view.mem_table.write().unwrap();
+
+            // a flush may have replaced the MemTable while we were waiting for the lock
+            if !Arc::ptr_eq(&view.mem_table, &self.view.load().mem_table) {
+                continue;
+            }
+
+            match self.config.load().storage_mode {
+                StorageMode::InMemory { max_size: Some(max_size), evict: false } => {
+                    if mem_table.size() + row.encoded_len() > max_size {
+                        return Err(HtError::misc("in-memory table is full"));
+                    }
+                    mem_table.add(row);
+                }
+                StorageMode::InMemory { max_size: Some(max_size), evict: true } => {
+                    mem_table.add(row);
+                    while mem_table.size() > max_size {
+                        mem_table.evict_oldest();
+                    }
+                }
+                _ => mem_table.add(row),
+            }
+            return Ok(());
+        }
+    }
+
+    /// Returns the merged data for a given primary key. pk_data is a row containing (at least)
+    ///  the full primary key.
+    pub fn get(&self, pk_data: &DetachedRowData) -> HtResult<Option<DetachedRowData>> {
+        self.get_with_deadline(pk_data, &Deadline::none())
+    }
+
+    /// Same as get, identifying the row by its PrimaryKey. SsTables are searched by the encoded
+    ///  key; MemTables are keyed by rows and are searched with PrimaryKey::lookup_row.
+    pub fn get_by_pk(&self, pk: &PrimaryKey) -> HtResult<Option<DetachedRowData>> {
+        let pk_data = pk.lookup_row();
+        let mut tombstones = TombstoneCounter::new(self, QueryKind::Get, Some(&pk_data));
+        let result = self.read_by_pk(pk, &pk_data, None, &Deadline::none(), None, &mut tombstones)?;
+        tombstones.finish();
+        Ok(result.into_live())
+    }
+
+    /// same as get, but failing with HtError::Timeout / HtError::Cancelled if the deadline
+    ///  expires before all MemTables and SsTables were read
+    pub fn get_with_deadline(&self, pk_data: &DetachedRowData, deadline: &Deadline) -> HtResult<Option<DetachedRowData>> {
+        Ok(self.read(pk_data, None, deadline, None)?.into_live())
+    }
+
+    /// same as get, passing the caller's identity to the table's ReadFilter
+    pub fn get_as(&self, pk_data: &DetachedRowData, caller: &str) -> HtResult<Option<DetachedRowData>> {
+        Ok(self.read(pk_data, None, &Deadline::none(), Some(caller))?.into_live())
+    }
+
+    /// Same as get, but distinguishing a row that was explicitly deleted from one that never
+    ///  existed, e.g. for read repair which must not resurrect deleted rows from other replicas
+    pub fn lookup(&self, pk_data: &DetachedRowData) -> HtResult<ReadResult> {
+        self.read(pk_data, None, &Deadline::none(), None)
+    }
+
+    /// Same as get, but the result contains only the primary key columns and the given
+    ///  columns. The other columns are skipped without decoding their values, which saves CPU
+    ///  for reads of a few columns of wide rows.
+    pub fn get_columns(&self, pk_data: &DetachedRowData, col_ids: &[ColumnId]) -> HtResult<Option<DetachedRowData>> {
+        Ok(self.read(pk_data, Some(col_ids), &Deadline::none(), None)?.into_live())
+    }
+
+    fn read(&self, pk_data: &DetachedRowData, col_ids: Option<&[ColumnId]>, deadline: &Deadline, caller: Option<&str>) -> HtResult<ReadResult> {
+        let mut tombstones = TombstoneCounter::new(self, QueryKind::Get, Some(pk_data));
+        let result = self.read_by_pk(&pk_data.row_data_view().encode_pk(), pk_data, col_ids, deadline, caller, &mut tombstones)?;
+        tombstones.finish();
+        Ok(result)
+    }
+
+    /// pk_data is a row holding pk's columns, for MemTable lookups and for reporting. The merged
+    ///  row's tombstones are added to the caller's TombstoneCounter.
+    fn read_by_pk(&self, pk: &PrimaryKey, pk_data: &DetachedRowData, col_ids: Option<&[ColumnId]>, deadline: &Deadline, caller: Option<&str>, tombstones: &mut TombstoneCounter) -> HtResult<ReadResult> {
+        let mut timer = QueryTimer::start(&self.config.load());
+        let view = self.view.load();
+
+        let mut result: Option<DetachedRowData> = None;
+        let mut merge = |row: &RowData| {
+            let row = match col_ids {
+                Some(col_ids) => row.project(col_ids),
+                None => row.detach(),
+            };
+            result = Some(match result.take() {
+                None => row,
+                Some(prev) => prev.row_data_view().merge(&row.row_data_view()),
+            });
+        };
+
+        for mem_table in view.mem_tables() {
+            deadline.check()?;
+            if let Some(row) = mem_table.read().unwrap().get(pk_data) {
+                merge(&row.row_data_view());
+            }
+        }
+        timer.phase("memtables");
+
+        let config = self.config.load();
+        let quarantine = Some(&self.quarantine).filter(|_| config.validate_reads);
+        for ss_table in &view.ss_tables {
+            deadline.check()?;
+            match config.io_backend {
+                IoBackend::Mmap => {
+                    let row = match quarantine {
+                        Some(quarantine) => ss_table.find_validated_by_pk(pk, quarantine)?,
+                        None => ss_table.find_by_pk(pk)?,
+                    };
+                    if let Some(row) = row {
+                        merge(&row);
+                    }
+                }
+                IoBackend::Pread => if let Some(row) = ss_table.read_by_pk(pk, quarantine)? {
+                    merge(&row.row_data_view());
+                },
+            }
+            timer.ss_table(ss_table.name());
+        }
+
+        timer.finish(&self.config.load(), self.slow_query_callback.as_ref(), QueryKind::Get, pk_data);
+
+        if let Some(row) = &result {
+            tombstones.add(&row.row_data_view())?;
+        }
+        match result {
+            None => Ok(ReadResult::NotFound),
+            Some(row) if row.row_data_view().is_deleted() => Ok(ReadResult::Deleted { at: row.row_data_view().timestamp() }),
+            Some(row) if row.row_data_view().is_touch_only() => Ok(ReadResult::NotFound),
+            Some(row) => match self.filter_row(caller, row)? {
+                Some(row) => Ok(ReadResult::Live(row)),
+                None => Ok(ReadResult::NotFound),
+            },
+        }
+    }
+
+    /// Returns the merged rows of a partition that lie within the given bounds, in clustering
+    ///  order. pk_data is a row containing (at least) the partition key.
+    ///
+    /// All sources are positioned at the slice's start by a binary search, so this reads only
+    ///  the slice's rows rather than the entire partition.
+    pub fn get_partition(&self, pk_data: &DetachedRowData, bounds: &ClusteringBounds) -> HtResult<Vec<DetachedRowData>> {
+        Ok(self.read_slices(pk_data, &[bounds], None, None)?.into_rows())
+    }
+
+    /// same as get_partition, passing the caller's identity to the table's ReadFilter
+    pub fn get_partition_as(&self, pk_data: &DetachedRowData, bounds: &ClusteringBounds, caller: &str) -> HtResult<Vec<DetachedRowData>> {
+        Ok(self.read_slices(pk_data, &[bounds], None, Some(caller))?.into_rows())
+    }
+
+    /// Same as get_partition, returning at most limit rows and stopping the read there. If the
+    ///  limit is reached, the result's paging_state holds the last row's key, and the next page
+    ///  can be read with an exclusive lower bound of its cluster key values. A limit of 0 is
+    ///  rejected.
+    pub fn get_partition_limited(&self, pk_data: &DetachedRowData, bounds: &ClusteringBounds, limit: usize) -> HtResult<ResultSet> {
+        self.read_slices(pk_data, &[bounds], Some(limit), None)
+    }
+
+    /// Returns the merged rows of a partition whose last cluster key column has one of the
+    ///  given values, in clustering order. prefix holds the values of all other cluster key
+    ///  columns. pk_data is a row containing (at least) the partition key.
+    ///
+    /// Each value is read as a separate slice, so this does not scan the rest of the partition.
+    pub fn get_partition_in(&self, pk_data: &DetachedRowData, prefix: &[ColumnValue], values: &[ColumnValue]) -> HtResult<Vec<DetachedRowData>> {
+        let last_col = match self.schema.pk_columns.last() {
+            Some(col) if col.pk_spec != PrimaryKeySpec::PartitionKey => col,
+            _ => return Err(HtError::misc("the table has no cluster key")),
+        };
+        if prefix.len() + 1 != self.schema.cluster_key_len() {
+            return Err(HtError::misc("IN restrictions require values for all other cluster key columns"));
+        }
+
+        let mut values = values.to_vec();
+        values.sort_by(|v1, v2| last_col.compare_pk_values(v1, v2));
+        values.dedup();
+
+        let bounds = values.iter()
+            .map(|value| {
+                let mut key = prefix.to_vec();
+                key.push(*value);
+                ClusteringBounds::new(Some(ClusteringBound::inclusive(key.clone())), Some(ClusteringBound::inclusive(key)))
+            })
+            .collect::<Vec<_>>();
+        Ok(self.read_slices(pk_data, &bounds.iter().collect::<Vec<_>>(), None, None)?.into_rows())
+    }
+
+    /// reads several slices of a partition, which must be given in clustering order
+    fn read_slices(&self, pk_data: &DetachedRowData, bounds: &[&ClusteringBounds], limit: Option<usize>, caller: Option<&str>) -> HtResult<ResultSet> {
+        if limit == Some(0) {
+            return Err(HtError::misc("the limit must be positive"));
+        }
+        let slices = bounds.iter()
+            .map(|bounds| PartitionSlice::new(&self.schema, &pk_data.row_data_view(), bounds))
+            .collect::<HtResult<Vec<_>>>()?;
+        let mut timer = QueryTimer::start(&self.config.load());
+        let view = self.view.load();
+
+        let token = pk_data.row_data_view().partition_token();
+
+        let mem_tables = view.mem_tables()
+            .map(|mem_table| mem_table.read().unwrap())
+            .collect::<Vec<_>>();
+        let sources = mem_tables.iter()
+            .map(|mem_table| Box::new(mem_table.rows()) as Box<dyn SeekableRows>)
+            .chain(view.ss_tables.iter()
+                .filter(|ss_table| ss_table.may_contain_partition(token))
+                .map(|ss_table| Box::new(self.ss_table_rows(ss_table)) as Box<dyn SeekableRows>))
+            .collect();
+
+        let mut rows = MergeIterator::new(sources)?;
+        timer.phase("open");
+
+        let mut tombstones = TombstoneCounter::new(self, QueryKind::GetPartition, Some(pk_data));
+        let mut result = Vec::new();
+        let mut paging_state = None;
+        'slices: for slice in &slices {
+            rows.seek(&slice.seek_row().row_data_view())?;
+            timer.phase("seek");
+            for row in &mut rows {
+                let row = row?;
+                match slice.position(&row.row_data_view()) {
+                    SlicePosition::Before => {}
+                    SlicePosition::Inside => {
+                        tombstones.add(&row.row_data_view())?;
+                        if row.row_data_view().is_deleted() || row.row_data_view().is_touch_only() {
+                            continue;
+                        }
+                        result.extend(self.filter_row(caller, row)?);
+                        if limit.is_some_and(|limit| result.len() >= limit) {
+                            paging_state = result.last().map(|row: &DetachedRowData| PagingState { last_key: row.row_data_view().encode_pk(), partition_rows: result.len() });
+                            timer.phase("merge");
+                            break 'slices;
+                        }
+                    },
+                    SlicePosition::After => break,
+                }
+            }
+            timer.phase("merge");
+        }
+
+        for ss_table in &view.ss_tables {
+            timer.ss_table(ss_table.name());
+        }
+        timer.finish(&self.config.load(), self.slow_query_callback.as_ref(), QueryKind::GetPartition, pk_data);
+        tombstones.finish();
+        let mut result = ResultSet::new(&self.schema, result);
+        result.paging_state = paging_state;
+        Ok(result)
+    }
+
+    /// Returns the merged rows of all partitions whose token (see RowData::partition_token) lies
+    ///  in the given range, in primary key order, e.g. for splitting a full table scan into
+    ///  parts. SsTables whose token range does not overlap are skipped, but since rows are
+    ///  stored in key rather than token order, the others are read in their entirety.
+    pub fn scan_token_range(&self, tokens: RangeInclusive<u64>) -> HtResult<Vec<DetachedRowData>> {
+        Ok(self.scan(tokens, &[], &ScanLimits::default(), None)?.into_rows())
+    }
+
+    /// same as scan_token_range, passing the caller's identity to the table's ReadFilter
+    pub fn scan_token_range_as(&self, tokens: RangeInclusive<u64>, caller: &str) -> HtResult<Vec<DetachedRowData>> {
+        Ok(self.scan(tokens, &[], &ScanLimits::default(), Some(caller))?.into_rows())
+    }
+
+    /// Same as scan_token_range, returning only rows that match all given predicates - like
+    ///  CQL's ALLOW FILTERING. This is expensive: every row in the token range is read and
+    ///  merged, and rows are only skipped afterwards, so it is meant for ad-hoc queries on
+    ///  small tables rather than for regular access paths.
+    ///
+    /// Predicates are checked after the table's ReadFilter, so they can not reveal the values
+    ///  of masked columns.
+    pub fn scan_filtered(&self, tokens: RangeInclusive<u64>, predicates: &[ColumnPredicate]) -> HtResult<Vec<DetachedRowData>> {
+        Ok(self.scan_limited(tokens, predicates, &ScanLimits::default())?.into_rows())
+    }
+
+    /// Same as scan_filtered, applying limits. If the limit is reached, the result's
+    ///  paging_state holds the last row's key, which the next page's ScanLimits::start_after
+    ///  continues from.
+    pub fn scan_limited(&self, tokens: RangeInclusive<u64>, predicates: &[ColumnPredicate], limits: &ScanLimits) -> HtResult<ResultSet> {
+        for predicate in predicates {
+            predicate.validate(&self.schema)?;
+        }
+        self.scan(tokens, predicates, limits, None)
+    }
+
+    /// Computes aggregates per group of rows matching the given predicates in a token range,
+    ///  with one group per partition and combination of the first cluster_key_len cluster key
+    ///  columns - like GROUP BY in CQL. Rows are merged in primary key order, so each group is
+    ///  finished before the next one starts, without collecting the rows. Groups are returned
+    ///  in primary key order.
+    pub fn aggregate(&self, tokens: RangeInclusive<u64>, predicates: &[ColumnPredicate], cluster_key_len: usize, aggregates: &[Aggregate]) -> HtResult<Vec<AggregateGroup>> {
+        for predicate in predicates {
+            predicate.validate(&self.schema)?;
+        }
+        let mut aggregator = GroupAggregator::new(&self.schema, cluster_key_len, aggregates)?;
+        self.scan_rows(tokens, predicates, &ScanLimits::default(), None, |row| aggregator.add(&row.row_data_view()))?;
+        Ok(aggregator.finish())
+    }
+
+    /// Returns the rows whose value of an indexed column (see TableConfig::indexed_columns)
+    ///  equals a value, in primary key order. The predicate must be Eq or In.
+    ///
+    /// Candidates are collected from the ColumnIndex of each SsTable and from the MemTables,
+    ///  which are scanned. SsTables written before the column was indexed are scanned as well.
+    ///  The candidates' rows are then read and merged like in get, and checked again, since a
+    ///  newer version of a row may have a different value or may be deleted.
+    pub fn find_by_index(&self, predicate: &ColumnPredicate) -> HtResult<ResultSet> {
+        predicate.validate(&self.schema)?;
+        if !self.config.load().indexed_columns.contains(&predicate.col_id) {
+            return Err(HtError::misc("the column is not indexed"));
+        }
+        let values = match &predicate.op {
+            PredicateOp::Eq(value) => vec!(value),
+            PredicateOp::In(values) => values.iter().collect(),
+            _ => return Err(HtError::misc("indexed lookups are supported for Eq and In only")),
+        };
+
+        let view = self.view.load();
+        let mut candidates = BTreeSet::new();
+        for mem_table in view.mem_tables() {
+            for row in mem_table.read().unwrap().iter() {
+                if predicate.matches(&row.row_data_view()) {
+                    candidates.insert(row.row_data_view().encode_pk());
+                }
+            }
+        }
+        for ss_table in &view.ss_tables {
+            if ss_table.column_index().is_indexed(predicate.col_id) {
+                for value in &values {
+                    for row in ss_table.find_by_column_value(predicate.col_id, value)?.unwrap_or_default() {
+                        candidates.insert(row.encode_pk());
+                    }
+                }
+            }
+            else {
+                for row in self.ss_table_rows(ss_table) {
+                    let row = row?;
+                    if predicate.matches(&row) {
+                        candidates.insert(row.encode_pk());
+                    }
+                }
+            }
+        }
+
+        let mut rows = Vec::new();
+        let mut tombstones = TombstoneCounter::new(self, QueryKind::IndexLookup, None);
+        for pk in candidates {
+            if let Some(row) = self.read_by_pk(&pk, &pk.lookup_row(), None, &Deadline::none(), None, &mut tombstones)?.into_live() {
+                if predicate.matches(&row.row_data_view()) {
+                    rows.push(row);
+                }
+            }
+        }
+        tombstones.finish();
+        Ok(ResultSet::new(&self.schema, rows))
+    }
+
+    fn scan(&self, tokens: RangeInclusive<u64>, predicates: &[ColumnPredicate], limits: &ScanLimits, caller: Option<&str>) -> HtResult<ResultSet> {
+        let mut rows = Vec::new();
+        let paging_state = self.scan_rows(tokens, predicates, limits, caller, |row| rows.push(row))?;
+        let mut result = ResultSet::new(&self.schema, rows);
+        result.paging_state = paging_state;
+        Ok(result)
+    }
+
+    /// passes the matching rows to f, returning the paging state if the limit was reached
+    fn scan_rows<F>(&self, tokens: RangeInclusive<u64>, predicates: &[ColumnPredicate], limits: &ScanLimits, caller: Option<&str>, mut f: F) -> HtResult<Option<PagingState>>
+        where F: FnMut(DetachedRowData) {
+        if limits.limit == Some(0) || limits.per_partition_limit == Some(0) {
+            return Err(HtError::misc("limits must be positive"));
+        }
+        let view = self.view.load();
+
+        let mem_tables = view.mem_tables()
+            .map(|mem_table| mem_table.read().unwrap())
+            .collect::<Vec<_>>();
+        let sources = mem_tables.iter()
+            .map(|mem_table| Box::new(mem_table.rows()) as Box<dyn SeekableRows>)
+            .chain(view.ss_tables.iter()
+                .filter(|ss_table| ss_table.stats().overlaps_tokens(&tokens))
+                .map(|ss_table| Box::new(self.ss_table_rows(ss_table)) as Box<dyn SeekableRows>))
+            .collect();
+
+        let mut rows = MergeIterator::new(sources)?;
+        // the current partition's key and the number of its rows in the result, starting with
+        //  those of the previous page
+        let mut partition: Option<(Vec<u8>, usize)> = None;
+        if let Some(start_after) = &limits.start_after {
+            let lookup_row = start_after.last_key.lookup_row();
+            rows.seek(&lookup_row.row_data_view())?;
+            partition = Some((lookup_row.row_data_view().partition_key_bytes(), start_after.partition_rows));
+        }
+
+        let mut tombstones = TombstoneCounter::new(self, QueryKind::Scan, None);
+        let mut num_rows = 0;
+        // after seeking, only the first row can be the one to start after
+        let mut start_after = limits.start_after.as_ref();
+        for row in rows {
+            let row = row?;
+            let view = row.row_data_view();
+            if let Some(start_after) = start_after.take() {
+                if view.encode_pk() == start_after.last_key {
+                    continue;
+                }
+            }
+            if !tokens.contains(&view.partition_token()) {
+                continue;
+            }
+            tombstones.add(&view)?;
+            if view.is_deleted() || view.is_touch_only() {
+                continue;
+            }
+
+            let partition_key = view.partition_key_bytes();
+            match &partition {
+                Some((key, count)) if *key == partition_key => {
+                    if limits.per_partition_limit.is_some_and(|l| *count >= l) {
+                        continue;
+                    }
+                }
+                _ => partition = Some((partition_key, 0)),
+            }
+
+            if let Some(row) = self.filter_row(caller, row)? {
+                if predicates.iter().all(|p| p.matches(&row.row_data_view())) {
+                    partition.as_mut().unwrap().1 += 1;
+                    num_rows += 1;
+                    if limits.limit.is_some_and(|limit| num_rows >= limit) {
+                        let paging_state = PagingState { last_key: row.row_data_view().encode_pk(), partition_rows: partition.unwrap().1 };
+                        f(row);
+                        tombstones.finish();
+                        return Ok(Some(paging_state));
+                    }
+                    f(row);
+                }
+            }
+        }
+        tombstones.finish();
+        Ok(None)
+    }
+
+    fn ss_table_rows<'a>(&'a self, ss_table: &'a SsTable) -> SsTableRowIter<'a> {
+        if self.config.load().validate_reads {
+            ss_table.validated_rows(&self.quarantine)
+        }
+        else {
+            ss_table.rows()
+        }
+    }
+
+    /// Reads the row for a primary key, passes it to f and writes the row f returns (if any),
+    ///  with no other update of the same partition in between. Plain puts are not serialized
+    ///  against this.
+    ///
+    /// f is called while holding the partition's lock, which is not reentrant and is shared with
+    ///  other partitions (see PartitionLocks). So f must not call update or lock_partition on
+    ///  this table, even for a different partition - that may deadlock.
+    pub fn update<F>(&self, pk_data: &DetachedRowData, f: F) -> HtResult<()>
+        where F: FnOnce(Option<DetachedRowData>) -> Option<DetachedRowData> {
+        let _guard = self.lock_partition(pk_data);
+        if let Some(row) = f(self.get(pk_data)?) {
+            self.put(row)?;
+        }
+        Ok(())
+    }
+
+    /// Performs a write unless a write with the same client supplied id was performed within
+    ///  TableConfig::idempotency_window, returning whether it was performed. If f fails, the id
+    ///  is forgotten so that the write can be retried - unless it failed with
+    ///  HtError::NotDelivered, since the write was applied then. A retry that arrives while the
+    ///  first attempt is still running counts as a duplicate.
+    pub fn write_once<F>(&self, idempotency_id: &str, f: F) -> HtResult<bool> where F: FnOnce(&Table) -> HtResult<()> {
+        if !self.idempotency.record(idempotency_id, self.clock.now()) {
+            return Ok(false);
+        }
+        match f(self) {
+            Ok(()) => Ok(true),
+            Err(e @ HtError::NotDelivered(_)) => Err(e),
+            Err(e) => {
+                self.idempotency.forget(idempotency_id);
+                Err(e)
+            }
+        }
+    }
+
+    /// Acquires the lock that serializes read-modify-write operations on a partition, see
+    ///  PartitionLocks. pk_data is a row containing (at least) the partition key. The lock is
+    ///  not reentrant, so the caller must not lock another partition or call update while
+    ///  holding it.
+    pub fn lock_partition(&self, pk_data: &DetachedRowData) -> MutexGuard<'_, ()> {
+        self.partition_locks.lock(pk_data.row_data_view().partition_token())
+    }
+
+    /// Replaces an in-memory table's contents with the given rows by swapping in a new
+    ///  MemTable, rather than deleting the rows that are not in it - which would leave their
+    ///  tombstones behind, since in-memory tables are never compacted. This is meant for tables
+    ///  that are rebuilt as a whole, e.g. the system tables: the rows are not checked, audited
+    ///  or captured like writes, and writes that run concurrently may be lost.
+    pub fn replace_contents(&self, rows: Vec<DetachedRowData>) -> HtResult<()> {
+        if self.is_persistent() {
+            return Err(HtError::misc("only the contents of in-memory tables can be replaced"));
+        }
+
+        let mut mem_table = MemTable::new(&self.config.load(), &self.schema, &self.clock);
+        for row in rows {
+            mem_table.add(row);
+        }
+
+        let _guard = self.view_update.lock().unwrap();
+        self.update_view(|view| TableView {
+            mem_table: Arc::new(RwLock::new(mem_table)),
+            flushing: view.flushing.clone(),
+            ss_tables: view.ss_tables.clone(),
+            version: view.version + 1,
+        });
+        Ok(())
+    }
+
+    /// Writes the MemTable's contents to SsTables and starts a new, empty MemTable. Writes
+    ///  continue to go to the new MemTable while the old one is flushed. This does nothing for
+    ///  in-memory tables.
+    ///
+    /// MemTables that are left over from a failed flush are flushed as well.
+    pub fn flush(&self) -> HtResult<()> {
+        self.check_writable()?;
+        if !self.is_persistent() {
+            return Ok(());
+        }
+
+        let _guard = self.view_update.lock().unwrap();
+
+        let view = self.view.load_full();
+        if !view.mem_table.read().unwrap().is_empty() {
+            let mut flushing = vec!(view.mem_table.clone());
+            flushing.extend(view.flushing.iter().cloned());
+            self.update_view(|view| TableView {
+                mem_table: Arc::new(RwLock::new(MemTable::new(&self.config.load(), &self.schema, &self.clock))),
+                flushing,
+                ss_tables: view.ss_tables.clone(),
+                version: view.version + 1,
+            });
+
+            // wait for writes that started before the MemTable was replaced
+            drop(view.mem_table.write().unwrap());
+        }
+
+        while let Some(mem_table) = self.view.load().flushing.last().cloned() {
+            let task = self.background_tasks.start(TaskKind::Flush, &self.schema.name, Vec::new());
+            let mut writer = SsTableWriter::new(&self.config.load(), &self.schema, &self.generations);
+            {
+                let rows = mem_table.read().unwrap();
+                task.progress().set_total(rows.size() as u64);
+                for row in rows.iter() {
+                    writer.write_row(&row.row_data_view())?;
+                    task.progress().add_processed(row.encoded_len() as u64);
+                }
+            }
+            let flushed = writer.finish()?.into_iter().map(Arc::new).collect::<Vec<_>>();
+
+            let ss_tables = self.update_view(|view| {
+                let mut ss_tables = view.ss_tables.clone();
+                ss_tables.extend(flushed.iter().cloned());
+
+                TableView {
+                    mem_table: view.mem_table.clone(),
+                    flushing: view.flushing.iter()
+                        .filter(|m| !Arc::ptr_eq(m, &mem_table))
+                        .cloned()
+                        .collect(),
+                    ss_tables,
+                    version: view.version + 1,
+                }
+            }).ss_tables.clone();
+            self.write_manifest(&ss_tables)?;
+
+            if self.config.load().incremental_backups {
+                backup::backup_ss_tables(&self.config.load(), &self.schema, &flushed, self.clock.now())?;
+                self.prune_backups_unlocked()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes backed up files that are older than TableConfig::backup_retention (see the backup
+    ///  module), returning the number of removed files. Flushes do this after each backup.
+    pub fn prune_backups(&self) -> HtResult<usize> {
+        self.check_writable()?;
+        let _guard = self.view_update.lock().unwrap();
+        self.prune_backups_unlocked()
+    }
+
+    /// the caller must hold view_update, which serializes changes to the backup manifest
+    fn prune_backups_unlocked(&self) -> HtResult<usize> {
+        match self.config.load().backup_retention {
+            Some(retention) => {
+                let before = MergeTimestamp::new(self.clock.now().epoch_millis().saturating_sub(retention.as_millis() as u64), 0, 0, 0);
+                backup::prune_backups(&self.config.load(), &self.schema, before)
+            },
+            None => Ok(0),
+        }
+    }
+
+    /// Flushes the MemTable if it exceeds TableConfig::mem_table_flush_size or
+    ///  mem_table_flush_age, returning whether it did. This is meant to be called regularly,
+    ///  e.g. after writes or from a timer.
+    pub fn flush_if_needed(&self) -> HtResult<bool> {
+        if self.is_read_only() || !self.is_persistent() {
+            return Ok(false);
+        }
+
+        let needs_flush = {
+            let view = self.view.load();
+            let mem_table = view.mem_table.read().unwrap();
+            let too_old = match (mem_table.first_write(), self.config.load().mem_table_flush_age) {
+                (Some(first_write), Some(max_age)) =>
+                    self.clock.now().epoch_millis().saturating_sub(first_write.epoch_millis()) >= max_age.as_millis() as u64,
+                _ => false,
+            };
+            too_old || mem_table.arena_size() >= self.config.load().mem_table_flush_size
+        };
+
+        if needs_flush {
+            self.flush()?;
+        }
+        Ok(needs_flush)
+    }
+
+    /// Atomically replaces the view, returning the new view. The caller must hold view_update.
+    fn update_view<F>(&self, f: F) -> Arc<TableView> where F: FnOnce(&TableView) -> TableView {
+        let new_view = Arc::new(f(&self.view.load()));
+        self.view.store(new_view.clone());
+        new_view
+    }
+
+    fn update_ss_tables<F>(&self, f: F) -> Vec<Arc<SsTable>> where F: FnOnce(&[Arc<SsTable>]) -> Vec<Arc<SsTable>> {
+        self.update_view(|view| TableView {
+            mem_table: view.mem_table.clone(),
+            flushing: view.flushing.clone(),
+            ss_tables: f(&view.ss_tables),
+            version: view.version + 1,
+        }).ss_tables.clone()
+    }
+
+    fn write_manifest(&self, ss_tables: &[Arc<SsTable>]) -> HtResult<()> {
+        Manifest::new(ss_tables).write(&self.config.load(), &self.schema)
+    }
+
+    fn gc_thresholds(&self) -> GcThresholds {
+        GcThresholds::new(self.clock.as_ref(), self.config.load().gc_grace_seconds)
+    }
+
+    fn compaction_context<'a>(&self, progress: &'a TaskProgress) -> CompactionContext<'a> {
+        CompactionContext {
+            config: self.config.load_full(),
+            schema: self.schema.clone(),
+            generations: self.generations.clone(),
+            gc: self.gc_thresholds(),
+            progress,
+        }
+    }
+
+    /// Returns the compaction the table's compaction strategy would run next, without actually
+    ///  running it. If the strategy has nothing to do, this falls back to a tombstone compaction
+    ///  (see TableConfig::tombstone_compaction_threshold).
+    pub fn plan_compaction(&self) -> Option<CompactionPlan> {
+        let ss_tables = &self.view.load().ss_tables;
+        let gc = self.gc_thresholds();
+        compaction_strategy(&self.config.load().compaction_strategy).plan(ss_tables, &gc)
+            .or_else(|| self.config.load().tombstone_compaction_threshold
+                .and_then(|threshold| tombstone_compaction(&self.schema, ss_tables, &gc, threshold)))
+    }
+
+    /// the table's SsTables that are not among the given compaction inputs
+    fn compaction_others(ss_tables: &[Arc<SsTable>], inputs: &[Arc<SsTable>]) -> Vec<Arc<SsTable>> {
+        ss_tables.iter()
+            .filter(|t| !inputs.iter().any(|i| Arc::ptr_eq(i, t)))
+            .cloned()
+            .collect()
+    }
+
+    /// Runs the next compaction proposed by the table's compaction strategy, if any, and
+    ///  returns its plan. Compactions that would fill the data folders beyond
+    ///  TableConfig::disk_critical_watermark are skipped.
+    pub fn compact(&self) -> HtResult<Option<CompactionPlan>> {
+        self.check_writable()?;
+        let _guard = self.view_update.lock().unwrap();
+
+        let plan = match self.plan_compaction() {
+            Some(plan) => plan,
+            None => return Ok(None),
+        };
+
+        let ss_tables = self.view.load().ss_tables.clone();
+        let inputs = ss_tables.iter()
+            .filter(|t| plan.inputs.contains(t.name()))
+            .cloned()
+            .collect::<Vec<_>>();
+        let others = Table::compaction_others(&ss_tables, &inputs);
+
+        if !plan.drop_only && !self.has_room_for_compaction(&inputs)? {
+            return Ok(None);
+        }
+
+        let compacted = if plan.drop_only {
+            Vec::new()
+        }
+        else {
+            let task = self.background_tasks.start(TaskKind::Compaction, &self.schema.name, plan.inputs.clone());
+            compact_parallel(&self.compaction_context(task.progress()),
+                             &inputs.iter().map(|t| t.as_ref()).collect::<Vec<_>>(),
+                             &others.iter().map(|t| t.as_ref()).collect::<Vec<_>>(),
+                             1)?
+        };
+
+        self.replace_ss_tables(&inputs, compacted)?;
+        Ok(Some(plan))
+    }
+
+    /// Replaces compaction inputs by their output in the view and the manifest, and then deletes
+    ///  the inputs' files. Reads that still use the inputs are unaffected since their memory
+    ///  maps remain valid.
+    fn replace_ss_tables(&self, inputs: &[Arc<SsTable>], outputs: Vec<SsTable>) -> HtResult<()> {
+        let ss_tables = self.update_ss_tables(|ss_tables| {
+            let mut result = Vec::new();
+            let mut outputs = Some(outputs);
+            for ss_table in ss_tables {
+                if inputs.iter().any(|i| Arc::ptr_eq(i, ss_table)) {
+                    // the output takes the place of the first input
+                    if let Some(outputs) = outputs.take() {
+                        result.extend(outputs.into_iter().map(Arc::new));
+                    }
+                }
+                else {
+                    result.push(ss_table.clone());
+                }
+            }
+            result
+        });
+
+        self.write_manifest(&ss_tables)?;
+        for ss_table in inputs {
+            ss_table.delete_files()?;
+        }
+        Ok(())
+    }
+
+    /// Major compaction: merges all SsTables, leaving one SsTable per key range (as limited by
+    ///  TableConfig::max_sstable_data_size), or per token range if it runs on several threads
+    ///  (see TableConfig::major_compaction_threads).
+    pub fn compact_all(&self) -> HtResult<()> {
+        self.check_writable()?;
+        let _guard = self.view_update.lock().unwrap();
+
+        let inputs = self.view.load().ss_tables.clone();
+        if inputs.is_empty() {
+            return Ok(());
+        }
+        if !self.has_room_for_compaction(&inputs)? {
+            return Err(HtError::InsufficientDiskSpace);
+        }
+
+        let task = self.background_tasks.start(TaskKind::Compaction, &self.schema.name, inputs.iter().map(|t| t.name().clone()).collect());
+        let compacted = compact_parallel(&self.compaction_context(task.progress()),
+                                         &inputs.iter().map(|t| t.as_ref()).collect::<Vec<_>>(),
+                                         &[],
+                                         self.config.load().major_compaction_threads)?;
+        drop(task);
+        self.replace_ss_tables(&inputs, compacted)
+    }
+
+    /// Cleanup compaction: rewrites a single SsTable to purge expired columns and GC-able
+    ///  tombstones, without merging it with other SsTables.
+    pub fn cleanup(&self, name: &SsTableName) -> HtResult<()> {
+        self.check_writable()?;
+        let _guard = self.view_update.lock().unwrap();
+
+        let ss_tables = self.view.load().ss_tables.clone();
+        let input = match ss_tables.iter().find(|t| t.name() == name) {
+            Some(t) => t.clone(),
+            None => return Err(HtError::misc("no SsTable with this name")),
+        };
+        let others = Table::compaction_others(&ss_tables, std::slice::from_ref(&input));
+        if !self.has_room_for_compaction(std::slice::from_ref(&input))? {
+            return Err(HtError::InsufficientDiskSpace);
+        }
+
+        let task = self.background_tasks.start(TaskKind::Cleanup, &self.schema.name, vec!(name.clone()));
+        let compacted = compact_parallel(&self.compaction_context(task.progress()), &[input.as_ref()], &others.iter().map(|t| t.as_ref()).collect::<Vec<_>>(), 1)?;
+        drop(task);
+        self.replace_ss_tables(&[input], compacted)
+    }
+
+    /// Rewrites a single SsTable as num_parts SsTables, each holding the partitions of an equal
+    ///  share of the token range, e.g. to break up the result of a major compaction. The parts
+    ///  are written on separate threads, see compaction::compact_parallel, and they are purged
+    ///  like in a cleanup. Rows are stored in key order, so the parts' key ranges overlap while
+    ///  their token ranges (see SsTableStats::min_token) do not. A part can be split further
+    ///  when it reaches TableConfig::max_sstable_data_size, and empty parts are not written.
+    pub fn split(&self, name: &SsTableName, num_parts: usize) -> HtResult<()> {
+        self.check_writable()?;
+        if num_parts < 2 {
+            return Err(HtError::misc("an SsTable must be split into at least two parts"));
+        }
+        let _guard = self.view_update.lock().unwrap();
+
+        let ss_tables = self.view.load().ss_tables.clone();
+        let input = match ss_tables.iter().find(|t| t.name() == name) {
+            Some(t) => t.clone(),
+            None => return Err(HtError::misc("no SsTable with this name")),
+        };
+        let others = Table::compaction_others(&ss_tables, std::slice::from_ref(&input));
+        if !self.has_room_for_compaction(std::slice::from_ref(&input))? {
+            return Err(HtError::InsufficientDiskSpace);
+        }
+
+        let task = self.background_tasks.start(TaskKind::Split, &self.schema.name, vec!(name.clone()));
+        let parts = compact_parallel(&self.compaction_context(task.progress()), &[input.as_ref()], &others.iter().map(|t| t.as_ref()).collect::<Vec<_>>(), num_parts)?;
+        drop(task);
+        self.replace_ss_tables(&[input], parts)
+    }
+
+    /// Flushes the table and creates a named snapshot of its SsTables, hard linking their files
+    ///  into a 'snapshots/<name>' folder in the table's folder in each data folder. The
+    ///  snapshot's manifest is stored in 'snapshots/<name>' in the table's base folder.
+    ///  Snapshots are unaffected by later compactions, and their files take up no additional
+    ///  space until the SsTables they link to are deleted.
+    ///
+    /// A snapshot is restored by copying its files back into the data folders and its manifest
+    ///  into the table's folder while the table is not open.
+    pub fn snapshot(&self, name: &str) -> HtResult<()> {
+        if name.is_empty() || name.contains(std::path::is_separator) || name.starts_with('.') {
+            return Err(HtError::misc("invalid snapshot name"));
+        }
+        if !self.is_persistent() {
+            return Err(HtError::misc("in-memory tables have no files to snapshot"));
+        }
+        self.flush()?;
+
+        let _guard = self.view_update.lock().unwrap();
+        let ss_tables = self.view.load().ss_tables.clone();
+
+        let manifest_folder = self.config.load().table_folder(&self.schema).join(SNAPSHOTS_FOLDER);
+        std::fs::create_dir_all(&manifest_folder)?;
+        std::fs::create_dir(manifest_folder.join(name))?;
+
+        for ss_table in &ss_tables {
+            let folder = ss_table.folder().join(SNAPSHOTS_FOLDER).join(name);
+            std::fs::create_dir_all(&folder)?;
+            for &component in SsTableComponent::ALL {
+                let file_name = ss_table.name().file_name(component);
+                if component.is_optional() && !ss_table.folder().join(&file_name).exists() {
+                    continue;
+                }
+                std::fs::hard_link(ss_table.folder().join(&file_name), folder.join(&file_name))?;
+            }
+        }
+        Manifest::new(&ss_tables).write_to_folder(&manifest_folder.join(name))
+    }
+}
+
+/// Counts the tombstones a read encounters, warning about the read if there are more than
+///  TableConfig::tombstone_warn_threshold and failing it once there are more than
+///  TableConfig::tombstone_failure_threshold
+struct TombstoneCounter<'a> {
+    table: &'a Table,
+    kind: QueryKind,
+    /// a row containing (at least) the read's key, None for reads that are not by key
+    key_row: Option<&'a DetachedRowData>,
+    count: u64,
+}
+
+impl <'a> TombstoneCounter<'a> {
+    fn new(table: &'a Table, kind: QueryKind, key_row: Option<&'a DetachedRowData>) -> TombstoneCounter<'a> {
+        TombstoneCounter { table, kind, key_row, count: 0 }
+    }
+
+    fn add(&mut self, row: &RowData) -> HtResult<()> {
+        self.count += row.tombstone_count();
+        if self.count > self.table.config.load().tombstone_failure_threshold {
+            self.warn("aborted");
+            return Err(HtError::TombstoneOverwhelm);
+        }
+        Ok(())
+    }
+
+    fn finish(self) {
+        if self.count > self.table.config.load().tombstone_warn_threshold {
+            self.warn("read");
+        }
+    }
+
+    fn warn(&self, outcome: &str) {
+        self.table.tombstone_warnings.fetch_add(1, Ordering::Relaxed);
+        warn!("{:?} on {} {} {} tombstones: {}",
+              self.kind,
+              self.table.schema.name,
+              outcome,
+              self.count,
+              self.key_row
+                  .map(|key_row| describe_key(&key_row.row_data_view(), self.table.config.load().slow_query_hash_keys))
+                  .unwrap_or_else(|| "-".to_string()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use crate::aggregate::{Aggregate, AggregateResult};
+    use crate::cdc::{ChangeEvent, ChangeSink};
+    use crate::config::{CompactionStrategyConfig, IoBackend, StorageMode, TableConfig};
+    use crate::deadline::{CancellationToken, Deadline};
+    use crate::engine::{ReadResult, ScanLimits, Table};
+    use crate::predicate::{ColumnPredicate, PredicateOp};
+    use crate::export;
+    use crate::prelude::*;
+    use crate::slice::{ClusteringBound, ClusteringBounds};
+    use crate::slowlog::QueryKind;
+    use crate::sstable::{SsTableComponent, SsTableName};
+    use crate::table::{ColumnData, ColumnId, ColumnValue, DetachedRowData, PrimaryKey};
+    use crate::testutils::{ClusteredTableTestSetup, CollectSlowQueries, SimpleTableTestSetup, test_table_config};
+    use crate::time::{HtClock, MergeTimestamp, TtlTimestamp};
+
+    fn table(setup: &SimpleTableTestSetup, gc_grace_seconds: u32) -> Table {
+        let config = Arc::new(TableConfig {
+            gc_grace_seconds,
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        Table::new(&config, &setup.schema, &clock).unwrap()
+    }
+
+    #[test]
+    pub fn test_put_get_flush() {
+        let setup = SimpleTableTestSetup::new();
+        let table = table(&setup, 0);
+
+        table.put(setup.full_row(1, Some("a"), Some(1))).unwrap();
+        table.put(setup.full_row(2, Some("b"), Some(2))).unwrap();
+        assert_eq!(setup.value(&table.get(&setup.pk_row(1)).unwrap().unwrap().row_data_view()), "a");
+
+        table.flush().unwrap();
+        assert_eq!(table.ss_tables().len(), 1);
+        assert_eq!(setup.value(&table.get(&setup.pk_row(1)).unwrap().unwrap().row_data_view()), "a");
+
+        setup.clock.set(MergeTimestamp::from_ticks(99999));
+        table.put(setup.partial_row(1, Some("x"))).unwrap();
+        assert_eq!(setup.value(&table.get(&setup.pk_row(1)).unwrap().unwrap().row_data_view()), "x");
+        assert_eq!(setup.value(&table.get(&setup.pk_row(2)).unwrap().unwrap().row_data_view()), "b");
+        assert!(table.get(&setup.pk_row(3)).unwrap().is_none());
+
+        let pk = PrimaryKey::new(&setup.schema, &[ColumnValue::BigInt(1)]).unwrap();
+        assert_eq!(setup.value(&table.get_by_pk(&pk).unwrap().unwrap().row_data_view()), "x");
+    }
+
+    #[test]
+    pub fn test_bloom_fp_chance() {
+        let setup = SimpleTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let config = |bloom_fp_chance: Option<f64>| Arc::new(TableConfig {
+            bloom_fp_chance,
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+
+        let mut filter_sizes = Vec::new();
+        for &fp_chance in &[Some(0.1), Some(0.001), None] {
+            let table = Table::new(&config(fp_chance), &setup.schema, &clock).unwrap();
+            for pk in 0..100 {
+                table.put(setup.full_row(pk, Some("a"), None)).unwrap();
+            }
+            table.flush().unwrap();
+            table.put(setup.full_row(100, Some("a"), None)).unwrap();
+            table.flush().unwrap();
+            table.compact_all().unwrap();
+
+            let ss_tables = table.ss_tables();
+            assert_eq!(ss_tables.len(), 1);
+            filter_sizes.push(ss_tables[0].filter().size_bytes());
+            assert_eq!(setup.value(&table.get(&setup.pk_row(100)).unwrap().unwrap().row_data_view()), "a");
+            assert_eq!(table.get_partition(&setup.pk_row(7), &ClusteringBounds::all()).unwrap().len(), 1);
+            table.drop_table().unwrap();
+        }
+        assert!(filter_sizes[0] < filter_sizes[1]);
+        assert_eq!(filter_sizes[2], 0);
+
+        for &fp_chance in &[0.0, 1.0, f64::NAN] {
+            assert!(Table::new(&config(Some(fp_chance)), &setup.schema, &clock).is_err());
+        }
+    }
+
+    #[test]
+    pub fn test_get_columns() {
+        let setup = SimpleTableTestSetup::new();
+        let table = table(&setup, 0);
+
+        table.put(setup.full_row(1, Some("a"), Some(1))).unwrap();
+        table.flush().unwrap();
+        setup.clock.set(MergeTimestamp::from_ticks(99999));
+        table.put(setup.partial_row(1, Some("x"))).unwrap();
+
+        let row = table.get_columns(&setup.pk_row(1), &[ColumnId(2)]).unwrap().unwrap();
+        assert_eq!(setup.int_value(&row.row_data_view()), 1);
+        assert!(row.row_data_view().read_col_by_id(ColumnId(1)).is_none());
+
+        let row = table.get_columns(&setup.pk_row(1), &[ColumnId(1)]).unwrap().unwrap();
+        assert_eq!(setup.value(&row.row_data_view()), "x");
+        assert!(row.row_data_view().read_col_by_id(ColumnId(2)).is_none());
+
+        assert!(table.get_columns(&setup.pk_row(2), &[ColumnId(1)]).unwrap().is_none());
+    }
+
+    #[test]
+    pub fn test_get_partition() {
+        let setup = ClusteredTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::new(&test_table_config(), &setup.schema, &clock).unwrap();
+
+        // rows are spread over an SsTable and the MemTable
+        for pk in 1..=3 {
+            for ts in 0..5 {
+                for seq in 0..2 {
+                    table.put(setup.row(pk, ts, seq, Some("a"))).unwrap();
+                }
+            }
+            table.flush().unwrap();
+        }
+        setup.clock.set(MergeTimestamp::from_ticks(99999));
+        table.put(setup.row(2, 3, 0, Some("b"))).unwrap();
+        table.put(setup.row(2, 5, 9, Some("b"))).unwrap();
+
+        let slice = |lower: Option<ClusteringBound>, upper: Option<ClusteringBound>| {
+            table.get_partition(&setup.partition_row(2), &ClusteringBounds::new(lower, upper)).unwrap().iter()
+                .map(|row| setup.cluster_key(&row.row_data_view()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(slice(None, None), vec!((0, 1), (0, 0), (1, 1), (1, 0), (2, 1), (2, 0), (3, 1), (3, 0), (4, 1), (4, 0), (5, 9)));
+        assert_eq!(slice(Some(ClusteringBound::inclusive(vec!(ColumnValue::Int(2)))), Some(ClusteringBound::exclusive(vec!(ColumnValue::Int(4))))),
+                   vec!((2, 1), (2, 0), (3, 1), (3, 0)));
+        assert_eq!(slice(Some(ClusteringBound::exclusive(vec!(ColumnValue::Int(2), ColumnValue::Int(1)))), Some(ClusteringBound::inclusive(vec!(ColumnValue::Int(3), ColumnValue::Int(1))))),
+                   vec!((2, 0), (3, 1)));
+        assert_eq!(slice(Some(ClusteringBound::exclusive(vec!(ColumnValue::Int(4)))), None), vec!((5, 9)));
+        assert!(slice(Some(ClusteringBound::inclusive(vec!(ColumnValue::Int(3)))), Some(ClusteringBound::inclusive(vec!(ColumnValue::Int(2))))).is_empty());
+
+        // rows are merged across MemTable and SsTables
+        let rows = table.get_partition(&setup.partition_row(2), &ClusteringBounds::new(
+            Some(ClusteringBound::inclusive(vec!(ColumnValue::Int(3), ColumnValue::Int(0)))),
+            Some(ClusteringBound::inclusive(vec!(ColumnValue::Int(3), ColumnValue::Int(0))))),
+        ).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].row_data_view().read_col_by_id(ColumnId(3)).unwrap().value, Some(ColumnValue::Text("b")));
+
+        assert!(table.get_partition(&setup.partition_row(4), &ClusteringBounds::all()).unwrap().is_empty());
+        assert!(table.get_partition(&setup.partition_row(2), &ClusteringBounds::new(Some(ClusteringBound::inclusive(vec!(ColumnValue::Text("x")))), None)).is_err());
+
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_get_partition_in() {
+        let setup = ClusteredTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::new(&test_table_config(), &setup.schema, &clock).unwrap();
+
+        for pk in 1..=2 {
+            for ts in 0..3 {
+                for seq in 0..5 {
+                    table.put(setup.row(pk, ts, seq, Some("a"))).unwrap();
+                }
+            }
+        }
+        table.flush().unwrap();
+        table.put(setup.row(1, 1, 7, Some("b"))).unwrap();
+
+        let keys = |prefix: &[ColumnValue], values: &[ColumnValue]| {
+            table.get_partition_in(&setup.partition_row(1), prefix, values).unwrap().iter()
+                .map(|row| setup.cluster_key(&row.row_data_view()))
+                .collect::<Vec<_>>()
+        };
+
+        // seq is descending, and duplicates and missing values are skipped
+        assert_eq!(keys(&[ColumnValue::Int(1)], &[ColumnValue::Int(0), ColumnValue::Int(7), ColumnValue::Int(3), ColumnValue::Int(9), ColumnValue::Int(3)]),
+                   vec!((1, 7), (1, 3), (1, 0)));
+        assert!(keys(&[ColumnValue::Int(5)], &[ColumnValue::Int(0)]).is_empty());
+        assert!(keys(&[ColumnValue::Int(1)], &[]).is_empty());
+
+        assert!(table.get_partition_in(&setup.partition_row(1), &[], &[ColumnValue::Int(0)]).is_err());
+        assert!(table.get_partition_in(&setup.partition_row(1), &[ColumnValue::Int(1)], &[ColumnValue::Text("x")]).is_err());
+
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_slow_queries() {
+        let setup = SimpleTableTestSetup::new();
+        let config = Arc::new(TableConfig {
+            slow_query_threshold: Some(Duration::from_secs(0)),
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let mut table = Table::new(&config, &setup.schema, &clock).unwrap();
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        table.set_slow_query_callback(Box::new(CollectSlowQueries { queries: queries.clone() }));
+
+        table.put(setup.full_row(1, Some("a"), None)).unwrap();
+        table.flush().unwrap();
+        table.get(&setup.pk_row(1)).unwrap();
+        table.get_partition(&setup.pk_row(1), &ClusteringBounds::all()).unwrap();
+
+        let queries = queries.lock().unwrap();
+        assert_eq!(queries.iter().map(|q| q.kind).collect::<Vec<_>>(), vec!(QueryKind::Put, QueryKind::Get, QueryKind::GetPartition));
+        assert!(queries.iter().all(|q| q.table == "test_table" && q.key == "[BigInt(1)]"));
+        assert!(queries[0].ss_tables.is_empty());
+        assert_eq!(queries[1].ss_tables, table.ss_tables().iter().map(|t| t.name().clone()).collect::<Vec<_>>());
+        assert_eq!(queries[1].timings[0].0, "memtables");
+
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_read_during_flush() {
+        let setup = SimpleTableTestSetup::new();
+        let table = Arc::new(table(&setup, 0));
+        let num_written = Arc::new(AtomicUsize::new(0));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let writer = {
+            let table = table.clone();
+            let num_written = num_written.clone();
+            let done = done.clone();
+            let rows = (0..500).map(|pk| setup.full_row(pk, Some("a"), Some(pk))).collect::<Vec<_>>();
+            std::thread::spawn(move || {
+                for (idx, row) in rows.into_iter().enumerate() {
+                    table.put(row).unwrap();
+                    num_written.store(idx + 1, Ordering::Release);
+                    if idx % 10 == 9 {
+                        table.flush().unwrap();
+                    }
+                    if idx % 50 == 49 {
+                        table.compact().unwrap();
+                    }
+                }
+                done.store(true, Ordering::Release);
+            })
+        };
+
+        // every row that was written is visible to all later reads, regardless of concurrent
+        //  flushes and compactions
+        while !done.load(Ordering::Acquire) {
+            let n = num_written.load(Ordering::Acquire) as i64;
+            for pk in (0..n).rev().take(20) {
+                let row = table.get(&setup.pk_row(pk)).unwrap();
+                assert!(row.is_some(), "row {} of {} disappeared", pk, n);
+            }
+        }
+        writer.join().unwrap();
+
+        for pk in 0..500 {
+            assert_eq!(setup.int_value(&table.get(&setup.pk_row(pk)).unwrap().unwrap().row_data_view()), pk);
+        }
+        Arc::try_unwrap(table).ok().unwrap().drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_read_your_writes() {
+        let setup = Arc::new(SimpleTableTestSetup::new());
+        let table = Arc::new(table(&setup, 0));
+        let done = Arc::new(AtomicBool::new(false));
+
+        // flushes and compactions race with the writers' puts and gets
+        let maintenance = {
+            let table = table.clone();
+            let done = done.clone();
+            std::thread::spawn(move || {
+                let mut version = 0;
+                while !done.load(Ordering::Acquire) {
+                    table.flush().unwrap();
+                    table.compact().unwrap();
+                    assert!(table.view().version >= version);
+                    version = table.view().version;
+                }
+            })
+        };
+
+        let writers = (0..4).map(|thread| {
+            let setup = setup.clone();
+            let table = table.clone();
+            std::thread::spawn(move || {
+                for pk in thread * 1000..thread * 1000 + 200 {
+                    table.put(setup.full_row(pk, Some("a"), Some(pk))).unwrap();
+                    let row = table.get(&setup.pk_row(pk)).unwrap();
+                    assert!(row.is_some(), "row {} is not visible after its put", pk);
+
+                    table.delete(&setup.pk_row(pk - 1)).unwrap();
+                    assert!(table.get(&setup.pk_row(pk - 1)).unwrap().is_none(), "row {} is visible after its delete", pk - 1);
+                }
+            })
+        }).collect::<Vec<_>>();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        done.store(true, Ordering::Release);
+        maintenance.join().unwrap();
+
+        assert!(table.view().version > 0);
+        Arc::try_unwrap(table).ok().unwrap().drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_compact_all() {
+        let setup = SimpleTableTestSetup::new();
+        setup.clock.set(MergeTimestamp::new(10_000, 0, 0, 0));
+        let table = table(&setup, 60);
+
+        for pk in 0..10 {
+            table.put(setup.full_row(pk, Some("a"), Some(pk))).unwrap();
+            table.flush().unwrap();
+        }
+        setup.clock.set(MergeTimestamp::new(20_000, 0, 0, 0));
+        table.put(setup.partial_row(3, Some("b"))).unwrap();
+        table.flush().unwrap();
+        assert_eq!(table.ss_tables().len(), 11);
+
+        let old_names = table.ss_tables().iter().map(|t| t.name().clone()).collect::<Vec<_>>();
+        table.compact_all().unwrap();
+
+        assert_eq!(table.ss_tables().len(), 1);
+        assert_eq!(table.ss_tables()[0].stats().row_count, 10);
+        assert_eq!(setup.value(&table.get(&setup.pk_row(3)).unwrap().unwrap().row_data_view()), "b");
+        for name in old_names {
+            assert!(!table.config.load().table_data_folders(&setup.schema)[0].join(name.file_name(SsTableComponent::Data)).exists());
+        }
+    }
+
+    #[test]
+    pub fn test_delete() {
+        let setup = SimpleTableTestSetup::new();
+        setup.clock.set(MergeTimestamp::new(10_000, 0, 0, 0));
+        let table = table(&setup, 60);
+
+        for pk in 0..3 {
+            table.put(setup.full_row(pk, Some("a"), Some(pk))).unwrap();
+        }
+        table.flush().unwrap();
+
+        // a row with only the primary key does not exist, so it can not be written
+        assert!(table.put(setup.pk_row(3)).is_err());
+
+        setup.clock.set(MergeTimestamp::new(11_000, 0, 0, 0));
+        table.delete(&setup.pk_row(1)).unwrap();
+        assert!(table.get(&setup.pk_row(1)).unwrap().is_none());
+        assert!(table.get(&setup.pk_row(2)).unwrap().is_some());
+        assert_eq!(table.get_partition(&setup.pk_row(1), &ClusteringBounds::all()).unwrap().len(), 0);
+
+        // the row tombstone survives compaction within the GC grace period
+        table.flush().unwrap();
+        table.compact_all().unwrap();
+        assert!(table.get(&setup.pk_row(1)).unwrap().is_none());
+
+        // later writes are merged on top of the row tombstone
+        setup.clock.set(MergeTimestamp::new(12_000, 0, 0, 0));
+        table.put(setup.partial_row(1, Some("b"))).unwrap();
+        let row = table.get(&setup.pk_row(1)).unwrap().unwrap();
+        assert_eq!(setup.value(&row.row_data_view()), "b");
+        assert!(row.row_data_view().read_col_by_id(ColumnId(2)).is_none());
+
+        // ... and after the grace period, compaction discards it
+        table.delete(&setup.pk_row(2)).unwrap();
+        table.flush().unwrap();
+        setup.clock.set(MergeTimestamp::new(100_000, 0, 0, 0));
+        table.compact_all().unwrap();
+        assert_eq!(table.ss_tables()[0].stats().row_count, 2);
+        assert!(table.get(&setup.pk_row(2)).unwrap().is_none());
+    }
+
+    #[test]
+    pub fn test_max_ttl() {
+        let setup = SimpleTableTestSetup::new();
+        setup.clock.set(MergeTimestamp::new(10_000_000, 0, 0, 0));
+        let config = Arc::new(TableConfig {
+            max_ttl_seconds: 3600,
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::new(&config, &setup.schema, &clock).unwrap();
+
+        let row = |pk: i64, expiry: TtlTimestamp| DetachedRowData::assemble(&setup.schema, &vec!(
+            ColumnData::new(ColumnId(0), setup.clock.now(), None, Some(ColumnValue::BigInt(pk))),
+            ColumnData::new(ColumnId(1), setup.clock.now(), Some(expiry), Some(ColumnValue::Text("abc"))),
+        )).unwrap();
+
+        table.put(row(1, setup.clock.ttl_timestamp(3600))).unwrap();
+        assert!(table.put(row(2, setup.clock.ttl_timestamp(3601))).is_err());
+        assert!(table.try_put(row(2, setup.clock.ttl_timestamp(u32::MAX))).is_err());
+        assert!(table.put(row(2, TtlTimestamp::MAX)).is_err());
+        assert!(table.get(&setup.pk_row(1)).unwrap().is_some());
+        assert!(table.get(&setup.pk_row(2)).unwrap().is_none());
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_touch() {
+        let setup = SimpleTableTestSetup::new();
+        setup.clock.set(MergeTimestamp::new(10_000_000, 0, 0, 0));
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::new(&test_table_config(), &setup.schema, &clock).unwrap();
+        table.put(setup.full_row(1, Some("a"), Some(1))).unwrap();
+        table.flush().unwrap();
+
+        let expiry = |col_id| table.get(&setup.pk_row(1)).unwrap().unwrap().row_data_view().read_col_by_id(col_id).unwrap().expiry;
+        setup.clock.set(MergeTimestamp::new(10_001_000, 0, 0, 0));
+        table.touch(&setup.pk_row(1), 60).unwrap();
+        assert_eq!(expiry(ColumnId(1)), Some(TtlTimestamp::new(10_061)));
+        assert_eq!(expiry(ColumnId(2)), Some(TtlTimestamp::new(10_061)));
+
+        // touching does not create rows
+        table.touch(&setup.pk_row(2), 60).unwrap();
+        assert!(table.get(&setup.pk_row(2)).unwrap().is_none());
+        assert!(table.get_partition(&setup.pk_row(2), &ClusteringBounds::all()).unwrap().is_empty());
+        assert_eq!(table.scan_token_range(0..=u64::MAX).unwrap().len(), 1);
+
+        table.flush().unwrap();
+        table.compact_all().unwrap();
+        assert_eq!(expiry(ColumnId(1)), Some(TtlTimestamp::new(10_061)));
+        assert!(table.get(&setup.pk_row(2)).unwrap().is_none());
+
+        // columns written after the touch are not affected
+        setup.clock.set(MergeTimestamp::new(10_002_000, 0, 0, 0));
+        table.put(setup.partial_row(1, Some("b"))).unwrap();
+        assert_eq!(expiry(ColumnId(1)), None);
+        assert_eq!(expiry(ColumnId(2)), Some(TtlTimestamp::new(10_061)));
+
+        assert!(table.touch(&setup.pk_row(1), u32::MAX).is_err());
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_touch_cleanup() {
+        let setup = SimpleTableTestSetup::new();
+        setup.clock.set(MergeTimestamp::new(10_000_000, 0, 0, 0));
+        let config = Arc::new(TableConfig {
+            gc_grace_seconds: 10,
+            compaction_strategy: CompactionStrategyConfig::TimeWindow { window_seconds: 3600, min_threshold: 4, max_threshold: 32 },
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::new(&config, &setup.schema, &clock).unwrap();
+
+        table.put(DetachedRowData::assemble(&setup.schema, &vec!(
+            ColumnData::new(ColumnId(0), setup.clock.now(), None, Some(ColumnValue::BigInt(1))),
+            ColumnData::new(ColumnId(1), setup.clock.now(), Some(setup.clock.ttl_timestamp(10)), Some(ColumnValue::Text("a"))),
+        )).unwrap()).unwrap();
+        table.flush().unwrap();
+        let name = table.ss_tables()[0].name().clone();
+
+        setup.clock.set(MergeTimestamp::new(10_005_000, 0, 0, 0));
+        table.touch(&setup.pk_row(1), 100).unwrap();
+        table.flush().unwrap();
+
+        // the column expired beyond gc grace in its own SsTable, but the touch in the other
+        //  SsTable extends it
+        setup.clock.set(MergeTimestamp::new(10_050_000, 0, 0, 0));
+        table.cleanup(&name).unwrap();
+        assert!(table.compact().unwrap().is_none());
+        assert_eq!(table.ss_tables().len(), 2);
+
+        let row = table.get(&setup.pk_row(1)).unwrap().unwrap();
+        assert_eq!(setup.value(&row.row_data_view()), "a");
+        assert_eq!(row.row_data_view().read_col_by_id(ColumnId(1)).unwrap().expiry, Some(TtlTimestamp::new(10_105)));
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_scan_filtered() {
+        let setup = SimpleTableTestSetup::new();
+        let table = table(&setup, 0);
+        for pk in 0..20 {
+            table.put(setup.full_row(pk, Some(if pk % 2 == 0 { "even" } else { "odd" }), Some(pk))).unwrap();
+            if pk % 5 == 4 {
+                table.flush().unwrap();
+            }
+        }
+        setup.clock.set(MergeTimestamp::new(20_000, 0, 0, 0));
+        table.put(setup.full_row(3, Some("even"), None)).unwrap();
+
+        let pks = |predicates: &[ColumnPredicate]| table.scan_filtered(0..=u64::MAX, predicates).unwrap().iter()
+            .map(|row| match row.row_data_view().read_col_by_id(ColumnId(0)).unwrap().value {
+                Some(ColumnValue::BigInt(pk)) => pk,
+                _ => panic!("no pk"),
+            })
+            .collect::<BTreeSet<_>>();
+
+        assert_eq!(pks(&[]).len(), 20);
+        let even = ColumnPredicate::new(ColumnId(1), PredicateOp::Eq(ColumnValue::Text("even")));
+        assert_eq!(pks(std::slice::from_ref(&even)), (0..20).filter(|pk| pk % 2 == 0).chain(vec!(3)).collect());
+        assert_eq!(pks(&[even, ColumnPredicate::new(ColumnId(2), PredicateOp::Ge(ColumnValue::Int(15)))]), vec!(16, 18).into_iter().collect());
+        assert_eq!(pks(&[ColumnPredicate::new(ColumnId(2), PredicateOp::In(vec!(ColumnValue::Int(3), ColumnValue::Int(4))))]), vec!(4).into_iter().collect());
+
+        assert!(table.scan_filtered(0..=u64::MAX, &[ColumnPredicate::new(ColumnId(2), PredicateOp::Eq(ColumnValue::Text("x")))]).is_err());
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_scan_limited() {
+        let setup = ClusteredTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::new(&test_table_config(), &setup.schema, &clock).unwrap();
+        for pk in 1..=3 {
+            for ts in 0..4 {
+                table.put(setup.row(pk, ts, 0, Some("a"))).unwrap();
+            }
+            if pk == 2 {
+                table.flush().unwrap();
+            }
+        }
+
+        let key = |row: &DetachedRowData| {
+            let view = row.row_data_view();
+            match view.read_col_by_id(ColumnId(0)).unwrap().value {
+                Some(ColumnValue::BigInt(pk)) => (pk, setup.cluster_key(&view).0),
+                _ => panic!("no pk"),
+            }
+        };
+        let all = table.scan_token_range(0..=u64::MAX).unwrap().iter().map(key).collect::<Vec<_>>();
+        assert_eq!(all.len(), 12);
+
+        // paging through the table
+        let mut limits = ScanLimits { limit: Some(5), ..ScanLimits::default() };
+        let mut pages = Vec::new();
+        loop {
+            let page = table.scan_limited(0..=u64::MAX, &[], &limits).unwrap();
+            pages.push(page.len());
+            limits.start_after = page.paging_state.clone();
+            if limits.start_after.is_none() {
+                break;
+            }
+            assert_eq!(limits.start_after.as_ref().map(|s| s.last_key.clone()), Some(page.into_rows().last().unwrap().row_data_view().encode_pk()));
+        }
+        assert_eq!(pages, vec!(5, 5, 2));
+
+        // the per-partition limit applies across pages
+        let mut limits = ScanLimits { limit: Some(1), per_partition_limit: Some(3), ..ScanLimits::default() };
+        let mut paged = Vec::new();
+        loop {
+            let page = table.scan_limited(0..=u64::MAX, &[], &limits).unwrap();
+            limits.start_after = page.paging_state.clone();
+            paged.extend(page.into_rows().iter().map(key));
+            if limits.start_after.is_none() {
+                break;
+            }
+        }
+        assert_eq!(paged, all.iter().filter(|(_, ts)| *ts < 3).cloned().collect::<Vec<_>>());
+
+        let limits = ScanLimits { limit: Some(5), start_after: None, per_partition_limit: Some(2) };
+        let rows = table.scan_limited(0..=u64::MAX, &[], &limits).unwrap().into_rows();
+        let expected = all.iter().filter(|(_, ts)| *ts < 2).take(5).cloned().collect::<Vec<_>>();
+        assert_eq!(rows.iter().map(key).collect::<Vec<_>>(), expected);
+
+        let page = table.get_partition_limited(&setup.partition_row(2), &ClusteringBounds::all(), 3).unwrap();
+        assert_eq!(page.rows().map(|row| setup.cluster_key(row.row_data())).collect::<Vec<_>>(), vec!((0, 0), (1, 0), (2, 0)));
+        assert!(page.paging_state.is_some());
+        assert!(table.get_partition_limited(&setup.partition_row(2), &ClusteringBounds::all(), 4).unwrap().paging_state.is_some());
+        assert!(table.get_partition_limited(&setup.partition_row(2), &ClusteringBounds::all(), 5).unwrap().paging_state.is_none());
+
+        // deleted rows do not end the page
+        table.delete(&setup.row(2, 0, 0, None)).unwrap();
+        let page = table.get_partition_limited(&setup.partition_row(2), &ClusteringBounds::all(), 2).unwrap();
+        assert_eq!(page.rows().map(|row| setup.cluster_key(row.row_data())).collect::<Vec<_>>(), vec!((1, 0), (2, 0)));
+        assert!(page.paging_state.is_some());
+
+        assert!(table.get_partition_limited(&setup.partition_row(2), &ClusteringBounds::all(), 0).is_err());
+        assert!(table.scan_limited(0..=u64::MAX, &[], &ScanLimits { limit: Some(0), ..ScanLimits::default() }).is_err());
+        assert!(table.scan_limited(0..=u64::MAX, &[], &ScanLimits { per_partition_limit: Some(0), ..ScanLimits::default() }).is_err());
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_aggregate() {
+        let setup = ClusteredTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::new(&test_table_config(), &setup.schema, &clock).unwrap();
+        for pk in 1..=3 {
+            for ts in 0..4 {
+                table.put(setup.row(pk, ts, pk as i32, Some(if ts % 2 == 0 { "even" } else { "odd" }))).unwrap();
+            }
+            if pk == 2 {
+                table.flush().unwrap();
+            }
+        }
+        setup.clock.set(MergeTimestamp::from_ticks(99999));
+        table.delete(&setup.row(3, 0, 3, None)).unwrap();
+
+        let groups = table.aggregate(0..=u64::MAX, &[], 0, &[Aggregate::Count, Aggregate::Sum(ColumnId(2)), Aggregate::Max(ColumnId(1))]).unwrap();
+        let mut by_pk = groups.iter()
+            .map(|g| (g.key_values()[0], g.results.clone()))
+            .collect::<Vec<_>>();
+        by_pk.sort_by_key(|(pk, _)| *pk);
+        assert_eq!(by_pk.iter().map(|(pk, _)| *pk).collect::<Vec<_>>(), vec!(ColumnValue::BigInt(1), ColumnValue::BigInt(2), ColumnValue::BigInt(3)));
+        assert_eq!(by_pk[0].1[0], AggregateResult::Count(4));
+        assert_eq!(by_pk[1].1[1], AggregateResult::Sum(Some(8)));
+        assert_eq!(by_pk[2].1[0], AggregateResult::Count(3));
+        match &by_pk[2].1[2] {
+            AggregateResult::Max(Some(max)) => assert_eq!(max.value(), ColumnValue::Int(3)),
+            _ => panic!("no max"),
+        }
+
+        let odd = ColumnPredicate::new(ColumnId(3), PredicateOp::Eq(ColumnValue::Text("odd")));
+        let groups = table.aggregate(0..=u64::MAX, &[odd], 1, &[Aggregate::Count]).unwrap();
+        assert_eq!(groups.len(), 6);
+        assert!(groups.iter().all(|g| g.key_values().len() == 2 && g.results == vec!(AggregateResult::Count(1))));
+
+        assert!(table.aggregate(0..=u64::MAX, &[], 3, &[Aggregate::Count]).is_err());
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_find_by_index() {
+        let setup = SimpleTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+
+        // an SsTable written before the column was indexed
+        let table = Table::new(&test_table_config(), &setup.schema, &clock).unwrap();
+        table.put(setup.full_row(1, Some("a"), None)).unwrap();
+        table.put(setup.full_row(2, Some("b"), None)).unwrap();
+        table.flush().unwrap();
+        table.shutdown().unwrap();
+
+        let config = Arc::new(TableConfig {
+            indexed_columns: vec!(ColumnId(1)),
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+        let table = Table::open(&config, &setup.schema, &clock).unwrap();
+        assert!(!table.ss_tables()[0].column_index().is_indexed(ColumnId(1)));
+
+        let find = |op| table.find_by_index(&ColumnPredicate::new(ColumnId(1), op)).unwrap()
+            .rows()
+            .map(|row| row.get_bigint("pk").unwrap().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(find(PredicateOp::Eq(ColumnValue::Text("a"))), vec!(1));
+
+        table.put(setup.full_row(3, Some("a"), None)).unwrap();
+        table.put(setup.full_row(4, Some("a"), None)).unwrap();
+        table.flush().unwrap();
+        assert_eq!(table.ss_tables()[1].column_index().num_entries(ColumnId(1)), 2);
+        assert_eq!(find(PredicateOp::Eq(ColumnValue::Text("a"))), vec!(1, 3, 4));
+
+        // newer values and deletions in the MemTable take precedence over the SsTables' indexes
+        setup.clock.set(MergeTimestamp::from_ticks(99999));
+        table.put(setup.full_row(1, Some("c"), None)).unwrap();
+        table.put(setup.full_row(2, Some("a"), None)).unwrap();
+        table.delete(&setup.pk_row(4)).unwrap();
+        assert_eq!(find(PredicateOp::Eq(ColumnValue::Text("a"))), vec!(2, 3));
+        assert_eq!(find(PredicateOp::In(vec!(ColumnValue::Text("b"), ColumnValue::Text("c")))), vec!(1));
+
+        table.compact_all().unwrap();
+        let ss_tables = table.ss_tables();
+        assert_eq!(ss_tables.len(), 1);
+        assert!(ss_tables[0].column_index().is_indexed(ColumnId(1)));
+        assert_eq!(find(PredicateOp::Eq(ColumnValue::Text("a"))), vec!(2, 3));
+        assert_eq!(find(PredicateOp::Eq(ColumnValue::Text("x"))), Vec::<i64>::new());
+
+        assert!(table.find_by_index(&ColumnPredicate::new(ColumnId(2), PredicateOp::Eq(ColumnValue::Int(1)))).is_err());
+        assert!(table.find_by_index(&ColumnPredicate::new(ColumnId(1), PredicateOp::Gt(ColumnValue::Text("a")))).is_err());
+
+        // an SsTable without an index file, e.g. an older or imported one, is scanned
+        std::fs::remove_file(ss_tables[0].folder().join(ss_tables[0].name().file_name(SsTableComponent::ColumnIndex))).unwrap();
+        drop(ss_tables);
+        table.shutdown().unwrap();
+        let table = Table::open(&config, &setup.schema, &clock).unwrap();
+        assert!(!table.ss_tables()[0].column_index().is_indexed(ColumnId(1)));
+        assert_eq!(table.find_by_index(&ColumnPredicate::new(ColumnId(1), PredicateOp::Eq(ColumnValue::Text("a")))).unwrap().len(), 2);
+        table.drop_table().unwrap();
+
+        // only existing regular columns can be indexed
+        for col_id in [ColumnId(0), ColumnId(9)] {
+            let config = Arc::new(TableConfig {
+                indexed_columns: vec!(col_id),
+                ..TableConfig::new(test_table_config().base_folder.clone())
+            });
+            assert!(Table::new(&config, &setup.schema, &clock).is_err());
+        }
+    }
+
+    #[test]
+    pub fn test_invalid_config() {
+        let setup = SimpleTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+
+        let base_folder = test_table_config().base_folder.clone();
+        let invalid = vec!(
+            TableConfig { compaction_strategy: CompactionStrategyConfig::TimeWindow { window_seconds: 0, min_threshold: 4, max_threshold: 32 }, ..TableConfig::new(base_folder.clone()) },
+            TableConfig { compaction_strategy: CompactionStrategyConfig::TimeWindow { window_seconds: 60, min_threshold: 8, max_threshold: 4 }, ..TableConfig::new(base_folder.clone()) },
+            TableConfig { major_compaction_threads: 0, ..TableConfig::new(base_folder.clone()) },
+            TableConfig { disk_warn_watermark: Some(0.9), disk_critical_watermark: Some(0.8), ..TableConfig::new(base_folder.clone()) },
+            TableConfig { disk_critical_watermark: Some(1.5), ..TableConfig::new(base_folder.clone()) },
+        );
+        for config in invalid {
+            assert!(Table::new(&Arc::new(config), &setup.schema, &clock).is_err());
+        }
+    }
+
+    #[test]
+    pub fn test_pread_point_reads() {
+        let setup = SimpleTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let config = Arc::new(TableConfig {
+            io_backend: IoBackend::Pread,
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+        let table = Table::new(&config, &setup.schema, &clock).unwrap();
+
+        for pk in 0..10 {
+            table.put(setup.full_row(pk, Some("a"), Some(pk))).unwrap();
+        }
+        table.flush().unwrap();
+        setup.clock.set(MergeTimestamp::from_ticks(99999));
+        table.put(setup.partial_row(3, Some("b"))).unwrap();
+        table.flush().unwrap();
+        table.put(setup.partial_row(4, Some("c"))).unwrap();
+
+        let get = |pk| table.get(&setup.pk_row(pk)).unwrap().map(|row| (setup.value(&row.row_data_view()).to_string(), setup.int_value(&row.row_data_view())));
+        assert_eq!(get(2), Some(("a".to_string(), 2)));
+        assert_eq!(get(3), Some(("b".to_string(), 3)));
+        assert_eq!(get(4), Some(("c".to_string(), 4)));
+        assert_eq!(get(10), None);
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_lookup() {
+        let setup = SimpleTableTestSetup::new();
+        setup.clock.set(MergeTimestamp::new(10_000, 0, 0, 0));
+        let table = table(&setup, 60);
+
+        table.put(setup.full_row(1, Some("a"), Some(1))).unwrap();
+        table.put(setup.full_row(2, Some("a"), Some(2))).unwrap();
+        table.flush().unwrap();
+        setup.clock.set(MergeTimestamp::new(11_000, 0, 0, 0));
+        table.delete(&setup.pk_row(1)).unwrap();
+
+        assert_eq!(table.lookup(&setup.pk_row(1)).unwrap(), ReadResult::Deleted { at: MergeTimestamp::new(11_000, 0, 0, 0) });
+        assert_eq!(table.lookup(&setup.pk_row(2)).unwrap().into_live(), table.get(&setup.pk_row(2)).unwrap());
+        assert_eq!(table.lookup(&setup.pk_row(3)).unwrap(), ReadResult::NotFound);
+
+        // a write after the tombstone makes the row live again
+        setup.clock.set(MergeTimestamp::new(12_000, 0, 0, 0));
+        table.put(setup.partial_row(1, Some("b"))).unwrap();
+        assert!(matches!(table.lookup(&setup.pk_row(1)).unwrap(), ReadResult::Live(_)));
+
+        // once the tombstone is discarded, the deletion can no longer be told from absence
+        table.delete(&setup.pk_row(2)).unwrap();
+        table.flush().unwrap();
+        table.compact_all().unwrap();
+        assert!(matches!(table.lookup(&setup.pk_row(2)).unwrap(), ReadResult::Deleted { .. }));
+        setup.clock.set(MergeTimestamp::new(100_000, 0, 0, 0));
+        table.compact_all().unwrap();
+        assert_eq!(table.lookup(&setup.pk_row(2)).unwrap(), ReadResult::NotFound);
+    }
+
+    #[test]
+    pub fn test_scan_token_range() {
+        let setup = SimpleTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::new(&test_table_config(), &setup.schema, &clock).unwrap();
+
+        for pk in 0..20 {
+            table.put(setup.full_row(pk, Some("a"), None)).unwrap();
+            if pk % 5 == 4 {
+                table.flush().unwrap();
+            }
+        }
+        table.delete(&setup.pk_row(7)).unwrap();
+
+        let token = |pk| setup.pk_row(pk).row_data_view().partition_token();
+        let mut tokens = (0..20).filter(|&pk| pk != 7).map(token).collect::<Vec<_>>();
+        tokens.sort();
+
+        // splitting the token range in two yields every live row exactly once
+        let split = tokens[9];
+        let mut scanned = table.scan_token_range(0..=split).unwrap();
+        assert_eq!(scanned.len(), 10);
+        scanned.extend(table.scan_token_range(split + 1..=u64::MAX).unwrap());
+        let mut pks = scanned.iter().map(|row| setup.pk(&row.row_data_view())).collect::<Vec<_>>();
+        pks.sort();
+        assert_eq!(pks, (0..20).filter(|&pk| pk != 7).collect::<Vec<_>>());
+
+        assert_eq!(table.scan_token_range(token(3)..=token(3)).unwrap().len(), 1);
+        assert!(table.scan_token_range(token(7)..=token(7)).unwrap().is_empty());
+
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_quarantine_corrupt_rows() {
+        let setup = SimpleTableTestSetup::new();
+        let table = table(&setup, 0);
+        table.put(setup.full_row(1, Some("aaaa"), Some(1))).unwrap();
+        table.put(setup.full_row(2, Some("bbbb"), Some(2))).unwrap();
+        table.flush().unwrap();
+        setup.clock.set(MergeTimestamp::from_ticks(setup.clock.now().ticks + 1));
+        table.put(setup.full_row(1, Some("zzzz"), Some(3))).unwrap();
+        table.flush().unwrap();
+
+        // change the kind of the newer row's text value so that it no longer matches the schema
+        let newer = table.ss_tables()[1].clone();
+        let path = newer.folder().join(newer.name().file_name(SsTableComponent::Data));
+        let mut data = std::fs::read(&path).unwrap();
+        let pos = data.windows(4).position(|w| w == b"zzzz").unwrap();
+        data[pos - 2] ^= 0x10;
+        std::fs::write(&path, data).unwrap();
+        drop(newer);
+        let table = {
+            let config = table.config().clone();
+            table.shutdown().unwrap();
+            let clock: Arc<dyn HtClock> = setup.clock.clone();
+            Table::open(&config, &setup.schema, &clock).unwrap()
+        };
+
+        // reads fall back to the older SsTable's data
+        assert_eq!(setup.int_value(&table.get(&setup.pk_row(1)).unwrap().unwrap().row_data_view()), 1);
+        assert_eq!(table.get_partition(&setup.pk_row(2), &ClusteringBounds::all()).unwrap().len(), 1);
+        assert_eq!(table.scan_token_range(0..=u64::MAX).unwrap().len(), 2);
+
+        let quarantined = table.quarantine().rows();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(&quarantined[0].ss_table, table.ss_tables()[1].name());
+        assert!(quarantined[0].error.contains("column type"));
+
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_tombstone_thresholds() {
+        let setup = ClusteredTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let config = Arc::new(TableConfig {
+            tombstone_warn_threshold: 3,
+            tombstone_failure_threshold: 6,
+            indexed_columns: vec!(ColumnId(3)),
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+        let table = Table::new(&config, &setup.schema, &clock).unwrap();
+
+        // each row has a NULL column, and deleted rows count as one tombstone
+        for seq in 0..3 {
+            table.put(setup.row(1, 0, seq, None)).unwrap();
+        }
+        for seq in 0..4 {
+            table.put(setup.row(2, 0, seq, None)).unwrap();
+        }
+        table.flush().unwrap();
+        table.delete(&setup.row(2, 0, 9, None)).unwrap();
+        for seq in 0..7 {
+            table.put(setup.row(3, 0, seq, Some("a"))).unwrap();
+            table.delete(&setup.row(3, 0, seq, None)).unwrap();
+        }
+
+        assert_eq!(table.get_partition(&setup.partition_row(1), &ClusteringBounds::all()).unwrap().len(), 3);
+        assert_eq!(table.tombstone_warnings(), 0);
+
+        assert_eq!(table.get_partition(&setup.partition_row(2), &ClusteringBounds::all()).unwrap().len(), 4);
+        assert_eq!(table.tombstone_warnings(), 1);
+
+        match table.get_partition(&setup.partition_row(3), &ClusteringBounds::all()) {
+            Err(HtError::TombstoneOverwhelm) => {}
+            other => panic!("expected TombstoneOverwhelm, got {:?}", other.map(|rows| rows.len())),
+        }
+        assert_eq!(table.tombstone_warnings(), 2);
+
+        // slices that skip the tombstones do not count them
+        let bounds = ClusteringBounds::new(Some(ClusteringBound::inclusive(vec!(ColumnValue::Int(0), ColumnValue::Int(1)))), None);
+        assert!(table.get_partition(&setup.partition_row(3), &bounds).unwrap().is_empty());
+        assert!(table.get(&setup.row(3, 0, 0, None)).unwrap().is_none());
+        assert_eq!(table.tombstone_warnings(), 2);
+
+        // scans count the tombstones of all rows in their token range
+        let token = |pk| setup.partition_row(pk).row_data_view().partition_token();
+        assert_eq!(table.scan_token_range(token(1)..=token(1)).unwrap().len(), 3);
+        assert_eq!(table.tombstone_warnings(), 2);
+        assert_eq!(table.scan_token_range(token(2)..=token(2)).unwrap().len(), 4);
+        assert_eq!(table.tombstone_warnings(), 3);
+        match table.scan_token_range(0..=u64::MAX) {
+            Err(HtError::TombstoneOverwhelm) => {}
+            other => panic!("expected TombstoneOverwhelm, got {:?}", other.map(|rows| rows.len())),
+        }
+        assert_eq!(table.tombstone_warnings(), 4);
+
+        // index lookups count the tombstones of all candidate rows
+        for seq in 0..7 {
+            table.put(setup.row(4, 0, seq, Some("b"))).unwrap();
+        }
+        table.flush().unwrap();
+        for seq in 0..7 {
+            table.delete(&setup.row(4, 0, seq, None)).unwrap();
+        }
+        match table.find_by_index(&ColumnPredicate::new(ColumnId(3), PredicateOp::Eq(ColumnValue::Text("b")))) {
+            Err(HtError::TombstoneOverwhelm) => {}
+            other => panic!("expected TombstoneOverwhelm, got {:?}", other.map(|rows| rows.len())),
+        }
+        assert_eq!(table.tombstone_warnings(), 5);
+
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_cleanup() {
+        let setup = SimpleTableTestSetup::new();
+        setup.clock.set(MergeTimestamp::new(10_000, 0, 0, 0));
+        let table = table(&setup, 60);
+
+        table.put(setup.full_row(1, None, None)).unwrap();
+        table.put(setup.full_row(2, Some("a"), None)).unwrap();
+        table.flush().unwrap();
+        table.put(setup.full_row(3, Some("b"), None)).unwrap();
+        table.flush().unwrap();
+
+        let name = table.ss_tables()[0].name().clone();
+
+        // tombstones are still within gc grace
+        table.cleanup(&name).unwrap();
+        assert_eq!(table.ss_tables().len(), 2);
+        assert_eq!(table.ss_tables()[0].stats().row_count, 2);
+
+        setup.clock.set(MergeTimestamp::new(100_000, 0, 0, 0));
+        let name = table.ss_tables()[0].name().clone();
+        table.cleanup(&name).unwrap();
+        assert_eq!(table.ss_tables().len(), 2);
+        assert_eq!(table.ss_tables()[0].stats().row_count, 1);
+        assert_eq!(table.ss_tables()[0].stats().tombstone_count, 0);
+        assert_eq!(table.ss_tables()[1].stats().tombstone_count, 1);
+
+        assert!(table.get(&setup.pk_row(1)).unwrap().is_none());
+        assert_eq!(setup.value(&table.get(&setup.pk_row(2)).unwrap().unwrap().row_data_view()), "a");
+
+        assert!(table.cleanup(&SsTableName::new("no_such_table", 1)).is_err());
+    }
+
+    #[test]
+    pub fn test_compact_by_strategy() {
+        let setup = SimpleTableTestSetup::new();
+        let config = Arc::new(TableConfig {
+            compaction_strategy: CompactionStrategyConfig::SizeTiered {
+                min_threshold: 2,
+                max_threshold: 32,
+                bucket_low: 0.5,
+                bucket_high: 1.5,
+                min_sstable_size: 0,
+            },
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::new(&config, &setup.schema, &clock).unwrap();
+
+        assert!(table.plan_compaction().is_none());
+        assert!(table.compact().unwrap().is_none());
+
+        table.put(setup.full_row(1, Some("a"), None)).unwrap();
+        table.flush().unwrap();
+        assert!(table.plan_compaction().is_none());
+
+        table.put(setup.full_row(2, Some("b"), None)).unwrap();
+        table.flush().unwrap();
+
+        let plan = table.plan_compaction().unwrap();
+        assert_eq!(plan.inputs.len(), 2);
+        // planning has no side effects
+        assert_eq!(table.ss_tables().len(), 2);
+
+        assert_eq!(table.compact().unwrap(), Some(plan));
+        assert_eq!(table.ss_tables().len(), 1);
+        assert_eq!(setup.value(&table.get(&setup.pk_row(1)).unwrap().unwrap().row_data_view()), "a");
+        assert_eq!(setup.value(&table.get(&setup.pk_row(2)).unwrap().unwrap().row_data_view()), "b");
+    }
+
+    #[test]
+    pub fn test_disk_space_watermarks() {
+        let setup = SimpleTableTestSetup::new();
+        setup.clock.set(MergeTimestamp::new(10_000, 0, 0, 0));
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let config = |warn: Option<f64>, critical: Option<f64>| Arc::new(TableConfig {
+            compaction_strategy: CompactionStrategyConfig::SizeTiered {
+                min_threshold: 2,
+                max_threshold: 32,
+                bucket_low: 0.5,
+                bucket_high: 1.5,
+                min_sstable_size: 0,
+            },
+            disk_warn_watermark: warn,
+            disk_critical_watermark: critical,
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+
+        // without a critical watermark, writes do not check
+        let table = Table::new(&config(Some(0.0), None), &setup.schema, &clock).unwrap();
+        table.put(setup.full_row(0, Some("a"), None)).unwrap();
+        assert_eq!(table.disk_space_warnings(), 0);
+        table.drop_table().unwrap();
+
+        // every check is above the warn watermark, writes use the last check for a second
+        let table = Table::new(&config(Some(0.0), Some(1.0)), &setup.schema, &clock).unwrap();
+        for pk in 0..2 {
+            table.put(setup.full_row(pk, Some("a"), None)).unwrap();
+            table.flush().unwrap();
+        }
+        assert_eq!(table.disk_space_warnings(), 1);
+        setup.clock.set(MergeTimestamp::new(11_000, 0, 0, 0));
+        table.put(setup.full_row(3, Some("a"), None)).unwrap();
+        assert_eq!(table.disk_space_warnings(), 2);
+        assert!(table.disk_space().unwrap()[0].total > 0);
+        table.shutdown().unwrap();
+
+        // above the critical watermark, writes fail and compactions are skipped
+        let table = Table::open(&config(None, Some(0.0)), &setup.schema, &clock).unwrap();
+        assert!(matches!(table.put(setup.full_row(4, Some("a"), None)), Err(HtError::InsufficientDiskSpace)));
+        assert!(table.plan_compaction().is_some());
+        assert!(table.compact().unwrap().is_none());
+        assert!(matches!(table.compact_all(), Err(HtError::InsufficientDiskSpace)));
+        assert_eq!(table.ss_tables().len(), 3);
+
+        // reads and flushes still work
+        assert!(table.get(&setup.pk_row(3)).unwrap().is_some());
+        table.flush().unwrap();
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_table_folder() {
+        let setup = SimpleTableTestSetup::new();
+        let table = table(&setup, 0);
+        let folder = table.config.load().table_folder(&setup.schema);
+        assert!(folder.is_dir());
+        assert!(folder.ends_with(format!("test_table-{}", setup.schema.table_id)));
+
+        table.put(setup.full_row(1, Some("a"), None)).unwrap();
+        table.flush().unwrap();
+        let name = table.ss_tables()[0].name().clone();
+        assert!(table.config.load().table_data_folders(&setup.schema)[0].join(name.file_name(SsTableComponent::Data)).is_file());
+
+        table.drop_table().unwrap();
+        assert!(!folder.exists());
+    }
+
+    #[test]
+    pub fn test_open_multiple_data_folders() {
+        let setup = SimpleTableTestSetup::new();
+        let base_folder = test_table_config().base_folder.clone();
+        let config = Arc::new(TableConfig {
+            data_folders: vec!(base_folder.join("disk1"), base_folder.join("disk2")),
+            ..TableConfig::new(base_folder)
+        });
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+
+        let table = Table::new(&config, &setup.schema, &clock).unwrap();
+        for pk in 0..3 {
+            table.put(setup.full_row(pk, Some("a"), None)).unwrap();
+            table.flush().unwrap();
+        }
+        table.cleanup(&table.ss_tables()[1].name().clone()).unwrap();
+
+        let data_folders = config.table_data_folders(&setup.schema);
+        for ss_table in table.ss_tables() {
+            assert!(data_folders.iter().any(|f| f == ss_table.folder()));
+        }
+
+        let expected = table.ss_tables().iter().map(|t| (t.name().clone(), t.folder().to_path_buf())).collect::<Vec<_>>();
+        drop(table);
+
+        let table = Table::open(&config, &setup.schema, &clock).unwrap();
+        assert_eq!(table.ss_tables().iter().map(|t| (t.name().clone(), t.folder().to_path_buf())).collect::<Vec<_>>(), expected);
+        for pk in 0..3 {
+            assert_eq!(setup.value(&table.get(&setup.pk_row(pk)).unwrap().unwrap().row_data_view()), "a");
+        }
+
+        table.drop_table().unwrap();
+        for folder in data_folders {
+            assert!(!folder.exists());
+        }
+    }
+
+    #[test]
+    pub fn test_lock() {
+        let setup = SimpleTableTestSetup::new();
+        let table = table(&setup, 0);
+
+        let config = test_table_config();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        match Table::open(&config, &setup.schema, &clock) {
+            Err(HtError::Locked(path)) => assert!(path.starts_with(config.table_folder(&setup.schema))),
+            _ => panic!("table should be locked"),
+        }
+
+        drop(table);
+        assert!(Table::open(&config, &setup.schema, &clock).is_ok());
+    }
+
+    #[test]
+    pub fn test_import_sstables() {
+        let source_setup = SimpleTableTestSetup::new();
+        let source = table(&source_setup, 0);
+        for pk in 0..5 {
+            source.put(source_setup.full_row(pk, Some("a"), Some(pk))).unwrap();
+            source.flush().unwrap();
+        }
+        let folder = source.config().table_folder(&source_setup.schema).join("export");
+        for ss_table in &source.ss_tables()[..2] {
+            export::export_to_folder(ss_table, &folder).unwrap();
+        }
+
+        let setup = SimpleTableTestSetup::new();
+        let table = table(&setup, 0);
+        table.put(setup.full_row(10, Some("b"), None)).unwrap();
+        table.flush().unwrap();
+
+        let imported = table.import_sstables(&folder).unwrap();
+        assert_eq!(imported.iter().map(|name| name.generation).collect::<Vec<_>>(), vec!(2, 3));
+        assert_eq!(table.ss_tables().len(), 3);
+        assert_eq!(setup.int_value(&table.get(&setup.pk_row(1)).unwrap().unwrap().row_data_view()), 1);
+        assert!(table.get(&setup.pk_row(2)).unwrap().is_none());
+
+        // an SsTable with a missing file fails the entire import
+        let broken = source.ss_tables()[2].clone();
+        export::export_to_folder(&broken, &folder).unwrap();
+        std::fs::remove_file(folder.join(broken.name().file_name(SsTableComponent::Stats))).unwrap();
+        assert!(table.import_sstables(&folder).is_err());
+        assert_eq!(table.ss_tables().len(), 3);
+
+        drop(table);
+        let config = test_table_config();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::open(&config, &setup.schema, &clock).unwrap();
+        assert_eq!(setup.int_value(&table.get(&setup.pk_row(0)).unwrap().unwrap().row_data_view()), 0);
+
+        table.drop_table().unwrap();
+        source.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_split() {
+        let setup = SimpleTableTestSetup::new();
+        let table = table(&setup, 0);
+        for pk in 0..100 {
+            table.put(setup.full_row(pk, Some("a"), Some(pk))).unwrap();
+        }
+        table.flush().unwrap();
+        let name = table.ss_tables()[0].name().clone();
+
+        assert!(table.split(&name, 1).is_err());
+        assert!(table.split(&SsTableName::new("test_table", 999), 4).is_err());
+        table.split(&name, 4).unwrap();
+
+        let parts = table.ss_tables();
+        assert_eq!(parts.len(), 4);
+        assert_eq!(parts.iter().map(|t| t.stats().row_count).sum::<u64>(), 100);
+        assert!((95..=105).contains(&table.partition_count_estimate()));
+        let mut token_ranges = parts.iter().map(|t| (t.stats().min_token, t.stats().max_token)).collect::<Vec<_>>();
+        token_ranges.sort();
+        for (i, (min_token, max_token)) in token_ranges.iter().enumerate() {
+            assert_eq!(min_token / (u64::MAX / 4), i as u64);
+            assert_eq!(max_token / (u64::MAX / 4), i as u64);
+        }
+
+        for pk in 0..100 {
+            assert_eq!(setup.int_value(&table.get(&setup.pk_row(pk)).unwrap().unwrap().row_data_view()), pk);
+        }
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_set_setting() {
+        let setup = SimpleTableTestSetup::new();
+        let table = table(&setup, 0);
+
+        table.set_setting("gc_grace_seconds", "100").unwrap();
+        table.set_setting("key_cache_size", "0").unwrap();
+        table.set_setting("compaction_strategy", "time_window 60").unwrap();
+        table.set_setting("default_ttl_seconds", "60").unwrap();
+        assert!(table.set_setting("gc_grace_seconds", "x").is_err());
+        assert!(table.set_setting("default_ttl_seconds", "0").is_err());
+        assert!(table.set_setting("storage_mode", "InMemory").is_err());
+
+        assert_eq!(table.config().gc_grace_seconds, 100);
+        assert_eq!(table.config().compaction_strategy, CompactionStrategyConfig::TimeWindow { window_seconds: 60, min_threshold: 4, max_threshold: 32 });
+
+        table.put(setup.full_row(1, Some("a"), None)).unwrap();
+        table.put(DetachedRowData::assemble(&setup.schema, &vec!(
+            ColumnData::new(ColumnId(0), setup.clock.now(), None, Some(ColumnValue::BigInt(2))),
+            ColumnData::new(ColumnId(1), setup.clock.now(), Some(setup.clock.ttl_timestamp(5)), Some(ColumnValue::Text("b"))),
+        )).unwrap()).unwrap();
+        table.flush().unwrap();
+        assert!(!table.ss_tables()[0].key_cache().is_enabled());
+
+        // the default TTL applies to columns without an expiry of their own
+        let expiry = |pk| table.get(&setup.pk_row(pk)).unwrap().unwrap().row_data_view().read_col_by_id(ColumnId(1)).unwrap().expiry;
+        assert_eq!(expiry(1), Some(setup.clock.ttl_timestamp(60)));
+        assert_eq!(expiry(2), Some(setup.clock.ttl_timestamp(5)));
+        drop(table);
+
+        // the overrides take precedence over the config the table is opened with
+        let config = test_table_config();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::open_read_only(&config, &setup.schema, &clock).unwrap();
+        assert_eq!(table.config().gc_grace_seconds, 100);
+        assert!(table.set_setting("gc_grace_seconds", "5").is_err());
+        drop(table);
+
+        let table = Table::open(&config, &setup.schema, &clock).unwrap();
+        assert_eq!(table.config().gc_grace_seconds, 100);
+        assert_eq!(table.config().key_cache_size, 0);
+        assert_eq!(table.config().default_ttl_seconds, Some(60));
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_shutdown() {
+        let setup = SimpleTableTestSetup::new();
+        let table = table(&setup, 0);
+        table.put(setup.full_row(1, Some("a"), None)).unwrap();
+        table.shutdown().unwrap();
+
+        let config = test_table_config();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::open(&config, &setup.schema, &clock).unwrap();
+        assert_eq!(table.ss_tables().len(), 1);
+        assert_eq!(setup.value(&table.get(&setup.pk_row(1)).unwrap().unwrap().row_data_view()), "a");
+    }
+
+    #[test]
+    pub fn test_open_read_only() {
+        let setup = SimpleTableTestSetup::new();
+        let table = table(&setup, 0);
+        table.put(setup.full_row(1, Some("a"), None)).unwrap();
+        table.flush().unwrap();
+
+        let config = test_table_config();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let read_only = Table::open_read_only(&config, &setup.schema, &clock).unwrap();
+        assert!(read_only.is_read_only());
+        assert!(!table.is_read_only());
+        assert_eq!(setup.value(&read_only.get(&setup.pk_row(1)).unwrap().unwrap().row_data_view()), "a");
+
+        assert!(read_only.put(setup.full_row(2, Some("b"), None)).is_err());
+        assert!(read_only.flush().is_err());
+        assert!(read_only.compact_all().is_err());
+
+        // the read-only table keeps seeing the SsTables that were live when it was opened
+        table.put(setup.full_row(2, Some("b"), None)).unwrap();
+        table.flush().unwrap();
+        table.compact_all().unwrap();
+        assert!(read_only.get(&setup.pk_row(2)).unwrap().is_none());
+        assert_eq!(setup.value(&read_only.get(&setup.pk_row(1)).unwrap().unwrap().row_data_view()), "a");
+
+        read_only.shutdown().unwrap();
+    }
+
+    #[test]
+    pub fn test_in_memory() {
+        let setup = SimpleTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let in_memory = |max_size, evict| Arc::new(TableConfig {
+            storage_mode: StorageMode::InMemory { max_size, evict },
+            ..TableConfig::new(test_table_config().base_folder.join("in-memory"))
+        });
+
+        let config = in_memory(None, false);
+        let table = Table::new(&config, &setup.schema, &clock).unwrap();
+        for pk in 0..100 {
+            table.put(setup.full_row(pk, Some("a"), None)).unwrap();
+        }
+        table.flush().unwrap();
+        table.compact_all().unwrap();
+        assert!(table.ss_tables().is_empty());
+        assert_eq!(setup.value(&table.get(&setup.pk_row(1)).unwrap().unwrap().row_data_view()), "a");
+        assert!(!config.table_folder(&setup.schema).exists());
+
+        // a second instance does not see the first one's data
+        assert!(Table::open(&config, &setup.schema, &clock).unwrap().get(&setup.pk_row(1)).unwrap().is_none());
+        table.shutdown().unwrap();
+
+        let row_size = setup.full_row(0, Some("a"), None).encoded_len();
+
+        let table = Table::new(&in_memory(Some(2 * row_size), false), &setup.schema, &clock).unwrap();
+        table.put(setup.full_row(1, Some("a"), None)).unwrap();
+        table.put(setup.full_row(2, Some("a"), None)).unwrap();
+        assert!(table.put(setup.full_row(3, Some("a"), None)).is_err());
+        assert!(table.get(&setup.pk_row(3)).unwrap().is_none());
+
+        let table = Table::new(&in_memory(Some(2 * row_size), true), &setup.schema, &clock).unwrap();
+        for pk in 1..=3 {
+            setup.clock.set(MergeTimestamp::from_ticks(1000 + pk as u64));
+            table.put(setup.full_row(pk, Some("a"), None)).unwrap();
+        }
+        assert!(table.get(&setup.pk_row(1)).unwrap().is_none());
+        assert!(table.get(&setup.pk_row(2)).unwrap().is_some());
+        assert!(table.get(&setup.pk_row(3)).unwrap().is_some());
+    }
+
+    #[test]
+    pub fn test_update() {
+        let setup = Arc::new(SimpleTableTestSetup::new());
+        let table = Arc::new(table(&setup, 0));
+        table.put(setup.full_row(1, Some("a"), Some(0))).unwrap();
+
+        let threads = (0..4).map(|_| {
+            let setup = setup.clone();
+            let table = table.clone();
+            std::thread::spawn(move || {
+                for _ in 0..25 {
+                    table.update(&setup.pk_row(1), |row| {
+                        let count = setup.int_value(&row.unwrap().row_data_view());
+                        setup.clock.set(MergeTimestamp::from_ticks(setup.clock.now().ticks + 1));
+                        Some(setup.full_row(1, Some("a"), Some(count + 1)))
+                    }).unwrap();
+                }
+            })
+        }).collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert_eq!(setup.int_value(&table.get(&setup.pk_row(1)).unwrap().unwrap().row_data_view()), 100);
+
+        table.update(&setup.pk_row(2), |row| {
+            assert!(row.is_none());
+            None
+        }).unwrap();
+        assert!(table.get(&setup.pk_row(2)).unwrap().is_none());
+    }
+
+    #[test]
+    pub fn test_write_once() {
+        let setup = SimpleTableTestSetup::new();
+        let table = table(&setup, 0);
+        table.put(setup.full_row(1, Some("a"), Some(0))).unwrap();
+
+        let increment = |table: &Table| table.update(&setup.pk_row(1), |row| {
+            let count = setup.int_value(&row.unwrap().row_data_view());
+            setup.clock.set(MergeTimestamp::from_ticks(setup.clock.now().ticks + 1));
+            Some(setup.full_row(1, Some("a"), Some(count + 1)))
+        });
+
+        assert!(table.write_once("x", increment).unwrap());
+        assert!(!table.write_once("x", increment).unwrap());
+        assert_eq!(setup.int_value(&table.get(&setup.pk_row(1)).unwrap().unwrap().row_data_view()), 1);
+
+        // a failed write can be retried
+        assert!(table.write_once("y", |_| Err(HtError::misc("failed"))).is_err());
+        assert!(table.write_once("y", increment).unwrap());
+        assert_eq!(setup.int_value(&table.get(&setup.pk_row(1)).unwrap().unwrap().row_data_view()), 2);
+
+        // a write that was applied but not delivered is not retried
+        struct FailingSink;
+        impl ChangeSink for FailingSink {
+            fn on_change(&self, _event: &ChangeEvent) -> HtResult<()> {
+                Err(HtError::misc("sink unavailable"))
+            }
+        }
+        let mut table = table;
+        table.set_change_sink(Some(Box::new(FailingSink)));
+        match table.write_once("z", increment) {
+            Err(HtError::NotDelivered(_)) => {}
+            _ => panic!("expected NotDelivered"),
+        }
+        assert!(!table.write_once("z", increment).unwrap());
+        assert_eq!(setup.int_value(&table.get(&setup.pk_row(1)).unwrap().unwrap().row_data_view()), 3);
+
+        table.drop_table().unwrap();
+    }
+
+    #[test]
+    pub fn test_get_with_deadline() {
+        let setup = SimpleTableTestSetup::new();
+        let table = table(&setup, 0);
+        table.put(setup.full_row(1, Some("a"), None)).unwrap();
+        table.flush().unwrap();
+
+        let deadline = Deadline::after(Duration::from_secs(3600));
+        assert!(table.get_with_deadline(&setup.pk_row(1), &deadline).unwrap().is_some());
+
+        match table.get_with_deadline(&setup.pk_row(1), &Deadline::after(Duration::from_secs(0))) {
+            Err(HtError::Timeout) => {}
+            _ => panic!("expected timeout"),
+        }
+
+        let token = CancellationToken::new();
+        let deadline = deadline.with_token(&token);
+        token.cancel();
+        match table.get_with_deadline(&setup.pk_row(1), &deadline) {
+            Err(HtError::Cancelled) => {}
+            _ => panic!("expected cancellation"),
+        }
+    }
+
+    #[test]
+    pub fn test_flush_if_needed() {
+        let setup = SimpleTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let row_size = setup.full_row(0, Some("a"), None).encoded_len();
+
+        let config = Arc::new(TableConfig {
+            mem_table_flush_size: 3 * row_size,
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+        let table = Table::new(&config, &setup.schema, &clock).unwrap();
+        for pk in 0..2 {
+            table.put(setup.full_row(pk, Some("a"), None)).unwrap();
+            assert!(!table.flush_if_needed().unwrap());
+        }
+        table.put(setup.full_row(2, Some("a"), None)).unwrap();
+        assert!(table.flush_if_needed().unwrap());
+        assert_eq!(table.ss_tables().len(), 1);
+
+        // overwriting the same row uses memory as well
+        for _ in 0..2 {
+            table.put(setup.full_row(3, Some("a"), None)).unwrap();
+            assert!(!table.flush_if_needed().unwrap());
+        }
+        table.put(setup.full_row(3, Some("a"), None)).unwrap();
+        assert!(table.flush_if_needed().unwrap());
+        table.shutdown().unwrap();
+
+        let config = Arc::new(TableConfig {
+            mem_table_flush_age: Some(Duration::from_secs(10)),
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+        let table = Table::new(&config, &setup.schema, &clock).unwrap();
+        setup.clock.set(MergeTimestamp::new(100_000, 0, 0, 0));
+        assert!(!table.flush_if_needed().unwrap());
+        table.put(setup.full_row(3, Some("a"), None)).unwrap();
+        setup.clock.set(MergeTimestamp::new(109_999, 0, 0, 0));
+        assert!(!table.flush_if_needed().unwrap());
+        setup.clock.set(MergeTimestamp::new(110_000, 0, 0, 0));
+        assert!(table.flush_if_needed().unwrap());
+        assert!(!table.flush_if_needed().unwrap());
+    }
+
+    #[test]
+    pub fn test_write_rate_limit() {
+        let setup = SimpleTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let config = Arc::new(TableConfig {
+            max_writes_per_second: Some(2),
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+        let table = Table::new(&config, &setup.schema, &clock).unwrap();
+
+        table.try_put(setup.full_row(1, Some("a"), None)).unwrap();
+        table.try_put(setup.full_row(2, Some("a"), None)).unwrap();
+        match table.try_put(setup.full_row(3, Some("a"), None)) {
+            Err(HtError::RateLimited) => {}
+            _ => panic!("expected rate limiting"),
+        }
+        assert!(table.get(&setup.pk_row(3)).unwrap().is_none());
+
+        // put waits instead of failing
+        table.put(setup.full_row(3, Some("a"), None)).unwrap();
+        assert!(table.get(&setup.pk_row(3)).unwrap().is_some());
+    }
+
+    #[test]
+    pub fn test_concurrent_put_flush() {
+        let setup = SimpleTableTestSetup::new();
+        let table = Arc::new(table(&setup, 0));
+
+        let writer = {
+            let table = table.clone();
+            let rows = (0..1000).map(|pk| setup.full_row(pk, Some("a"), None)).collect::<Vec<_>>();
+            std::thread::spawn(move || {
+                for row in rows {
+                    table.put(row).unwrap();
+                }
+            })
+        };
+
+        for _ in 0..10 {
+            table.flush().unwrap();
+            table.compact().unwrap();
+        }
+        writer.join().unwrap();
+
+        // a snapshot is unaffected by later flushes
+        table.put(setup.full_row(1000, Some("a"), None)).unwrap();
+        let view = table.view();
+        table.flush().unwrap();
+        assert!(view.mem_table.read().unwrap().get(&setup.pk_row(1000)).is_some());
+        assert_eq!(table.view().ss_tables.len(), view.ss_tables.len() + 1);
+        assert!(table.view().mem_table.read().unwrap().is_empty());
+
+        for pk in 0..1000 {
+            assert_eq!(setup.value(&table.get(&setup.pk_row(pk)).unwrap().unwrap().row_data_view()), "a");
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Copying live SsTables file by file, e.g. for shipping backups to another machine or (once
+//!  there are several nodes) streaming SsTables between nodes. Components are copied as they
+//!  are, without decoding rows, and their checksums are verified after copying.
+
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use seahash::SeaHasher;
+
+use crate::backup::file_checksum;
+use crate::prelude::*;
+use crate::primitives::EncodePrimitives;
+use crate::sstable::{SsTable, SsTableComponent, SsTableName};
+
+/// A copied SsTable file
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExportedComponent {
+    pub component: SsTableComponent,
+    pub len: u64,
+    /// the seahash of the file's contents, as in the backup manifest
+    pub checksum: u64,
+}
+
+/// Opens all of an SsTable's files before copying any of them, so that an SsTable that is
+///  deleted by a concurrent compaction is still copied completely. Missing optional components
+///  are None.
+fn open_components(ss_table: &SsTable) -> HtResult<Vec<(SsTableComponent, Option<File>)>> {
+    SsTableComponent::ALL.iter()
+        .map(|&component| {
+            match File::open(ss_table.folder().join(ss_table.name().file_name(component))) {
+                Ok(file) => Ok((component, Some(file))),
+                Err(ref e) if e.kind() == ErrorKind::NotFound && component.is_optional() => Ok((component, None)),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .collect()
+}
+
+/// copies exactly len bytes, returning their checksum
+fn copy_with_checksum<R, W>(r: &mut R, w: &mut W, len: u64) -> HtResult<u64> where R: Read, W: Write {
+    let mut hasher = SeaHasher::new();
+    let mut buf = vec!(0u8; 64*1024);
+    let mut remaining = len;
+    while remaining > 0 {
+        let chunk = remaining.min(buf.len() as u64) as usize;
+        r.read_exact(&mut buf[..chunk])?;
+        hasher.write(&buf[..chunk]);
+        w.write_all(&buf[..chunk])?;
+        remaining -= chunk as u64;
+    }
+    Ok(hasher.finish())
+}
+
+/// Copies an SsTable's files to a folder, keeping their names. Existing files are not
+///  overwritten. The copies are synced and read back to verify their checksums. Missing
+///  optional components are skipped.
+pub fn export_to_folder(ss_table: &SsTable, folder: &Path) -> HtResult<Vec<ExportedComponent>> {
+    std::fs::create_dir_all(folder)?;
+
+    let mut result = Vec::new();
+    for (component, file) in open_components(ss_table)? {
+        let mut file = match file {
+            Some(file) => file,
+            None => continue,
+        };
+        let target = folder.join(ss_table.name().file_name(component));
+        let mut copy = std::fs::OpenOptions::new().create_new(true).write(true).open(&target)?;
+
+        let len = file.metadata()?.len();
+        let checksum = copy_with_checksum(&mut file, &mut copy, len)?;
+        copy.sync_all()?;
+
+        if file_checksum(&target)? != checksum {
+            return Err(HtError::misc("checksum mismatch after copying an SsTable file"));
+        }
+        result.push(ExportedComponent { component, len, checksum });
+    }
+    Ok(result)
+}
+
+/// Writes an SsTable's files to a stream, see receive_from_stream for the other end.
+///
+/// stream format, for each component in SsTableComponent::ALL order:
+///   fixed u64         length of the file
+///   bytes             the file's contents
+///   fixed u64         checksum of the contents (seahash)
+///
+/// Missing optional components are sent as empty files, which SsTable::open treats like
+///  missing ones.
+pub fn export_to_stream<W>(ss_table: &SsTable, w: &mut W) -> HtResult<Vec<ExportedComponent>> where W: Write {
+    let mut result = Vec::new();
+    for (component, file) in open_components(ss_table)? {
+        let (mut file, len): (Box<dyn Read>, u64) = match file {
+            Some(file) => {
+                let len = file.metadata()?.len();
+                (Box::new(file), len)
+            },
+            None => (Box::new(std::io::empty()), 0),
+        };
+        w.encode_fixed_u64(len)?;
+        let checksum = copy_with_checksum(&mut file, w, len)?;
+        w.encode_fixed_u64(checksum)?;
+        result.push(ExportedComponent { component, len, checksum });
+    }
+    w.flush()?;
+    Ok(result)
+}
+
+/// Reads an SsTable written by export_to_stream, storing its files in a folder under the given
+///  name. If a checksum does not match, the files written so far are removed. Files that
+///  existed before are left alone, e.g. if an SsTable of the same name is in the folder.
+pub fn receive_from_stream<R>(r: &mut R, folder: &Path, name: &SsTableName) -> HtResult<Vec<ExportedComponent>> where R: Read {
+    std::fs::create_dir_all(folder)?;
+
+    let mut created = Vec::new();
+    let result = receive_components(r, folder, name, &mut created);
+    if result.is_err() {
+        for path in created {
+            match std::fs::remove_file(path) {
+                Err(ref e) if e.kind() == ErrorKind::NotFound => {},
+                other => other?,
+            }
+        }
+    }
+    result
+}
+
+/// created collects the paths of the files this creates, for cleaning up after an error
+fn receive_components<R>(r: &mut R, folder: &Path, name: &SsTableName, created: &mut Vec<PathBuf>) -> HtResult<Vec<ExportedComponent>> where R: Read {
+    let read_u64 = |r: &mut R| -> HtResult<u64> {
+        let mut buf = [0u8; 8];
+        r.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    };
+
+    let mut result = Vec::new();
+    for &component in SsTableComponent::ALL {
+        let path = folder.join(name.file_name(component));
+        let mut file = std::fs::OpenOptions::new().create_new(true).write(true).open(&path)?;
+        created.push(path);
+
+        let len = read_u64(r)?;
+        let checksum = copy_with_checksum(r, &mut file, len)?;
+        if read_u64(r)? != checksum {
+            return Err(HtError::misc("checksum mismatch in SsTable stream"));
+        }
+        file.sync_all()?;
+        result.push(ExportedComponent { component, len, checksum });
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::engine::Table;
+    use crate::export::{export_to_folder, export_to_stream, receive_from_stream};
+    use crate::sstable::{SsTable, SsTableComponent, SsTableName};
+    use crate::testutils::{SimpleTableTestSetup, test_table_config};
+    use crate::time::HtClock;
+
+    #[test]
+    pub fn test_export() {
+        let setup = SimpleTableTestSetup::new();
+        let config = test_table_config();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::new(&config, &setup.schema, &clock).unwrap();
+        for pk in 0..10 {
+            table.put(setup.full_row(pk, Some("a"), Some(pk))).unwrap();
+        }
+        table.flush().unwrap();
+        let ss_table = table.ss_tables()[0].clone();
+        let folder = config.table_folder(&setup.schema).join("export");
+
+        let exported = export_to_folder(&ss_table, &folder).unwrap();
+        assert_eq!(exported.iter().map(|e| e.component).collect::<Vec<_>>(), &SsTableComponent::ALL[..4], "no column index without indexed columns");
+        assert_eq!(exported.iter().find(|e| e.component == SsTableComponent::Data).unwrap().len, ss_table.data_size());
+        let copy = SsTable::open(&config, &setup.schema, &folder, ss_table.name()).unwrap();
+        copy.verify().unwrap();
+        assert_eq!(copy.stats(), ss_table.stats());
+        assert!(export_to_folder(&ss_table, &folder).is_err());
+
+        let mut stream = Vec::new();
+        let streamed = export_to_stream(&ss_table, &mut stream).unwrap();
+        assert_eq!(&streamed[..4], &exported[..]);
+        assert_eq!(streamed[4].len, 0, "the missing column index is sent as an empty file");
+
+        let received = SsTableName::new(&setup.schema.name, 1000);
+        assert_eq!(receive_from_stream(&mut stream.as_slice(), &folder, &received).unwrap(), streamed);
+        let copy = SsTable::open(&config, &setup.schema, &folder, &received).unwrap();
+        assert_eq!(setup.int_value(&copy.find_by_full_pk(&setup.pk_row(7).row_data_view()).unwrap().unwrap()), 7);
+
+        // a corrupted stream leaves no files behind
+        let corrupted = SsTableName::new(&setup.schema.name, 1001);
+        let last = stream.len() - 20;
+        stream[last] ^= 1;
+        assert!(receive_from_stream(&mut stream.as_slice(), &folder, &corrupted).is_err());
+        assert!(receive_from_stream(&mut &stream[..100], &folder, &corrupted).is_err());
+        for &component in SsTableComponent::ALL {
+            assert!(!folder.join(corrupted.file_name(component)).exists());
+        }
+
+        // an existing SsTable of the same name is kept
+        assert!(receive_from_stream(&mut stream.as_slice(), &folder, &received).is_err());
+        SsTable::open(&config, &setup.schema, &folder, &received).unwrap().verify().unwrap();
+
+        table.drop_table().unwrap();
+    }
+}
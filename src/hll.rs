@@ -0,0 +1,140 @@
+use std::io::Write;
+
+use crate::prelude::*;
+use crate::primitives::DecodePrimitives;
+
+/// A HyperLogLog sketch for estimating the number of distinct values (e.g. partition keys) in a
+///  data set with constant memory.
+///
+/// The sketch uses 2^PRECISION registers of one byte each, giving a standard error of about
+///  1.04 / sqrt(2^PRECISION), i.e. roughly 1.6%. Sketches can be merged, so per-SsTable sketches
+///  can be combined into an estimate for an entire table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    const PRECISION: u32 = 12;
+    const NUM_REGISTERS: usize = 1 << HyperLogLog::PRECISION;
+    const MAX_RANK: u8 = (64 - HyperLogLog::PRECISION + 1) as u8;
+
+    pub fn new() -> HyperLogLog {
+        HyperLogLog {
+            registers: vec!(0; HyperLogLog::NUM_REGISTERS),
+        }
+    }
+
+    pub fn add(&mut self, value: &[u8]) {
+        self.add_hash(seahash::hash(value));
+    }
+
+    pub fn add_hash(&mut self, hash: u64) {
+        let idx = (hash >> (64 - HyperLogLog::PRECISION)) as usize;
+        let rest = hash << HyperLogLog::PRECISION;
+        let rank = (rest.leading_zeros() + 1).min(HyperLogLog::MAX_RANK as u32) as u8;
+
+        if self.registers[idx] < rank {
+            self.registers[idx] = rank;
+        }
+    }
+
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (r, o) in self.registers.iter_mut().zip(other.registers.iter()) {
+            if *r < *o {
+                *r = *o;
+            }
+        }
+    }
+
+    pub fn estimate(&self) -> u64 {
+        let m = HyperLogLog::NUM_REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+
+        let sum: f64 = self.registers.iter()
+            .map(|&r| 1.0 / (1u64 << r) as f64)
+            .sum();
+        let raw = alpha * m * m / sum;
+
+        let num_zero = self.registers.iter().filter(|&&r| r == 0).count();
+        if raw <= 2.5 * m && num_zero > 0 {
+            // small range correction: linear counting
+            (m * (m / num_zero as f64).ln()).round() as u64
+        }
+        else {
+            raw.round() as u64
+        }
+    }
+
+    pub fn write_to<W>(&self, w: &mut W) -> std::io::Result<()> where W: Write {
+        w.write_all(&self.registers)
+    }
+
+    pub fn read_from(buf: &[u8], offs: &mut usize) -> HtResult<HyperLogLog> {
+        let registers = buf.try_decode_slice(offs, HyperLogLog::NUM_REGISTERS)
+            .ok_or_else(|| HtError::misc("truncated HyperLogLog"))?
+            .to_vec();
+        if registers.iter().any(|&r| r > HyperLogLog::MAX_RANK) {
+            return Err(HtError::misc("corrupt HyperLogLog register"));
+        }
+        Ok(HyperLogLog { registers })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::hll::HyperLogLog;
+
+    #[test]
+    pub fn test_empty() {
+        assert_eq!(HyperLogLog::new().estimate(), 0);
+    }
+
+    #[test]
+    pub fn test_estimate() {
+        for &n in &[10u64, 1000, 100_000] {
+            let mut hll = HyperLogLog::new();
+            for i in 0..n {
+                hll.add(&i.to_le_bytes());
+                // duplicates must not change the estimate
+                hll.add(&i.to_le_bytes());
+            }
+
+            let estimate = hll.estimate() as f64;
+            assert!((estimate - n as f64).abs() <= n as f64 * 0.05, "n={}, estimate={}", n, estimate);
+        }
+    }
+
+    #[test]
+    pub fn test_merge() {
+        let mut hll1 = HyperLogLog::new();
+        let mut hll2 = HyperLogLog::new();
+        for i in 0..10_000u64 {
+            hll1.add(&i.to_le_bytes());
+            hll2.add(&(i + 5_000).to_le_bytes());
+        }
+
+        hll1.merge(&hll2);
+        let estimate = hll1.estimate() as f64;
+        assert!((estimate - 15_000.0).abs() <= 15_000.0 * 0.05);
+    }
+
+    #[test]
+    pub fn test_write_read() {
+        let mut hll = HyperLogLog::new();
+        for i in 0..500u64 {
+            hll.add(&i.to_le_bytes());
+        }
+
+        let mut buf = Vec::new();
+        hll.write_to(&mut buf).unwrap();
+
+        let mut offs = 0;
+        assert_eq!(HyperLogLog::read_from(&buf, &mut offs).unwrap(), hll);
+        assert_eq!(offs, buf.len());
+
+        assert!(HyperLogLog::read_from(&buf[..buf.len() - 1], &mut 0).is_err());
+        buf[0] = 255;
+        assert!(HyperLogLog::read_from(&buf, &mut 0).is_err());
+    }
+}
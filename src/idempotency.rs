@@ -0,0 +1,91 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::time::MergeTimestamp;
+
+/// Remembers client supplied idempotency ids for a sliding window of time, so that a write
+///  retried by a client (e.g. after a network timeout) is not applied twice. This matters for
+///  writes that are not idempotent by themselves, e.g. read-modify-write updates or deletes
+///  that take their timestamp when they are retried.
+///
+/// The ids are kept in memory only, so a restart forgets them.
+pub struct IdempotencyWindow {
+    window: Duration,
+    ids: Mutex<RecordedIds>,
+}
+
+struct RecordedIds {
+    /// ids in the order they were recorded, with the epoch millis of recording
+    by_age: VecDeque<(u64, String)>,
+    ids: HashSet<String>,
+}
+
+impl IdempotencyWindow {
+    pub fn new(window: Duration) -> IdempotencyWindow {
+        IdempotencyWindow {
+            window,
+            ids: Mutex::new(RecordedIds { by_age: VecDeque::new(), ids: HashSet::new() }),
+        }
+    }
+
+    /// Records an id, returning false if it was recorded within the window already. Ids that
+    ///  fell out of the window are forgotten.
+    pub fn record(&self, id: &str, now: MergeTimestamp) -> bool {
+        let mut recorded = self.ids.lock().unwrap();
+        let now = now.epoch_millis();
+        let window = self.window.as_millis() as u64;
+
+        while let Some((recorded_at, _)) = recorded.by_age.front() {
+            if recorded_at.saturating_add(window) > now {
+                break;
+            }
+            let (_, expired) = recorded.by_age.pop_front().unwrap();
+            recorded.ids.remove(&expired);
+        }
+
+        if !recorded.ids.insert(id.to_string()) {
+            return false;
+        }
+        recorded.by_age.push_back((now, id.to_string()));
+        true
+    }
+
+    /// forgets an id, e.g. because the write it was recorded for failed and may be retried
+    pub fn forget(&self, id: &str) {
+        let mut recorded = self.ids.lock().unwrap();
+        if recorded.ids.remove(id) {
+            recorded.by_age.retain(|(_, recorded_id)| recorded_id != id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::idempotency::IdempotencyWindow;
+    use crate::time::MergeTimestamp;
+
+    #[test]
+    pub fn test_idempotency_window() {
+        let window = IdempotencyWindow::new(Duration::from_secs(10));
+        let at = |secs: u64| MergeTimestamp::new(secs * 1000, 0, 0, 0);
+
+        assert!(window.record("a", at(1)));
+        assert!(window.record("b", at(5)));
+        assert!(!window.record("a", at(8)));
+
+        // 'a' expires, 'b' is still in the window
+        assert!(window.record("a", at(11)));
+        assert!(!window.record("b", at(14)));
+        assert_eq!(window.ids.lock().unwrap().by_age.len(), 2);
+
+        window.forget("b");
+        assert!(window.record("b", at(14)));
+        assert!(!window.record("b", at(14)));
+
+        assert!(window.record("c", at(100)));
+        assert_eq!(window.ids.lock().unwrap().ids.len(), 1);
+    }
+}
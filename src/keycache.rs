@@ -0,0 +1,164 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use bytes::Bytes;
+
+/// A small LRU cache of an SsTable's recent point reads, mapping encoded primary keys (see
+///  PrimaryKey::bytes) to the offset of their row in the data file. Hot keys are found without
+///  a binary search in the index, which touches a page of the index and the data file for each
+///  step.
+///
+/// Only keys that were found are cached; missing keys are mostly filtered by the SsTable's
+///  BloomFilter.
+pub struct KeyCache {
+    capacity: usize,
+    entries: Mutex<LruEntries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct LruEntries {
+    /// key -> (offset, last use)
+    by_key: HashMap<Bytes, (u64, u64)>,
+    /// last use -> key, the least recently used entry first
+    by_use: BTreeMap<u64, Bytes>,
+    next_use: u64,
+}
+
+/// hit and miss counts of one or several KeyCaches
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct KeyCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl KeyCacheStats {
+    /// the fraction of lookups that were served from the cache, None if there were none
+    pub fn hit_rate(&self) -> Option<f64> {
+        match self.hits + self.misses {
+            0 => None,
+            lookups => Some(self.hits as f64 / lookups as f64),
+        }
+    }
+
+    pub fn add(&mut self, other: &KeyCacheStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+    }
+}
+
+impl KeyCache {
+    /// a capacity of 0 disables the cache
+    pub fn new(capacity: usize) -> KeyCache {
+        KeyCache {
+            capacity,
+            entries: Mutex::new(LruEntries { by_key: HashMap::new(), by_use: BTreeMap::new(), next_use: 0 }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// the cached offset for a key, counting the lookup as a hit or miss
+    pub fn get(&self, key: &Bytes) -> Option<u64> {
+        let mut entries = self.entries.lock().unwrap();
+        let use_tick = entries.next_use;
+
+        let result = match entries.by_key.get_mut(key) {
+            None => None,
+            Some((offset, last_use)) => {
+                let prev_use = std::mem::replace(last_use, use_tick);
+                Some((*offset, prev_use))
+            }
+        };
+
+        match result {
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            Some((offset, prev_use)) => {
+                entries.next_use += 1;
+                let key = entries.by_use.remove(&prev_use).unwrap();
+                entries.by_use.insert(use_tick, key);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(offset)
+            }
+        }
+    }
+
+    /// adds a key, evicting the least recently used key if the cache is full
+    pub fn put(&self, key: Bytes, offset: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let use_tick = entries.next_use;
+        entries.next_use += 1;
+
+        if let Some((_, prev_use)) = entries.by_key.insert(key.clone(), (offset, use_tick)) {
+            entries.by_use.remove(&prev_use);
+        }
+        entries.by_use.insert(use_tick, key);
+
+        if entries.by_key.len() > self.capacity {
+            let (&oldest, _) = entries.by_use.iter().next().unwrap();
+            let evicted = entries.by_use.remove(&oldest).unwrap();
+            entries.by_key.remove(&evicted);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().by_key.len()
+    }
+
+    pub fn stats(&self) -> KeyCacheStats {
+        KeyCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use crate::keycache::{KeyCache, KeyCacheStats};
+
+    #[test]
+    pub fn test_key_cache() {
+        let cache = KeyCache::new(2);
+        let key = |k: &'static str| Bytes::from_static(k.as_bytes());
+
+        assert_eq!(cache.get(&key("a")), None);
+        cache.put(key("a"), 1);
+        cache.put(key("b"), 2);
+        assert_eq!(cache.get(&key("a")), Some(1));
+
+        // 'b' is the least recently used key
+        cache.put(key("c"), 3);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&key("b")), None);
+        assert_eq!(cache.get(&key("a")), Some(1));
+        assert_eq!(cache.get(&key("c")), Some(3));
+
+        cache.put(key("c"), 4);
+        cache.put(key("d"), 5);
+        assert_eq!(cache.get(&key("a")), None);
+        assert_eq!(cache.get(&key("c")), Some(4));
+
+        assert_eq!(cache.stats(), KeyCacheStats { hits: 4, misses: 3 });
+        assert_eq!(cache.stats().hit_rate(), Some(4.0 / 7.0));
+        assert_eq!(KeyCacheStats::default().hit_rate(), None);
+
+        let disabled = KeyCache::new(0);
+        disabled.put(key("a"), 1);
+        assert_eq!(disabled.len(), 0);
+    }
+}
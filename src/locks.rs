@@ -0,0 +1,48 @@
+use std::sync::{Mutex, MutexGuard};
+
+/// Striped locks keyed by partition token, serializing read-modify-write operations (e.g.
+///  compare-and-set or counters) on the same partition while operations on different
+///  partitions proceed in parallel. Partitions share a lock if their tokens fall into the same
+///  stripe, which costs some parallelism but keeps the memory footprint fixed.
+///
+/// These locks are independent of the MemTable's lock: plain writes do not acquire them. They
+///  are not reentrant, and since partitions share stripes, holding one lock while acquiring
+///  another may deadlock even for different partitions.
+pub struct PartitionLocks {
+    stripes: Vec<Mutex<()>>,
+}
+
+impl PartitionLocks {
+    pub fn new(num_stripes: usize) -> PartitionLocks {
+        assert!(num_stripes > 0);
+        PartitionLocks {
+            stripes: (0..num_stripes).map(|_| Mutex::new(())).collect(),
+        }
+    }
+
+    fn stripe(&self, partition_token: u64) -> usize {
+        (partition_token % self.stripes.len() as u64) as usize
+    }
+
+    /// blocks until the partition's lock is acquired, holding it until the guard is dropped
+    pub fn lock(&self, partition_token: u64) -> MutexGuard<'_, ()> {
+        self.stripes[self.stripe(partition_token)].lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::locks::PartitionLocks;
+
+    #[test]
+    pub fn test_stripes() {
+        let locks = PartitionLocks::new(16);
+        assert_eq!(locks.stripe(3), locks.stripe(19));
+        assert_ne!(locks.stripe(3), locks.stripe(4));
+        assert!(locks.stripe(u64::MAX) < 16);
+
+        let _guard = locks.lock(3);
+        assert!(locks.stripes[locks.stripe(19)].try_lock().is_err());
+        assert!(locks.stripes[locks.stripe(4)].try_lock().is_ok());
+    }
+}
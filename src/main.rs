@@ -1,14 +1,48 @@
 #[macro_use]
 mod prelude;
 
+mod admin;
+mod aggregate;
+mod audit;
+mod backup;
+mod blockcache;
+mod bloom;
+mod cdc;
+mod check;
+mod clockskew;
+mod columnindex;
+mod compaction;
 mod config;
+mod deadline;
+mod diskspace;
+mod engine;
+mod export;
+mod hll;
+mod idempotency;
+mod keycache;
+mod locks;
+mod manifest;
 mod memtable;
+mod predicate;
 mod primitives;
+mod quarantine;
+mod ratelimit;
+mod readfilter;
+mod resultset;
+mod rowbuilder;
+mod settings;
+mod slice;
+mod slowlog;
 mod sstable;
+mod stats;
+mod system;
 mod table;
+mod tasks;
 mod time;
 mod tombstones;
 
+#[cfg(test)]
+mod simulation;
 #[cfg(test)]
 mod testutils;
 
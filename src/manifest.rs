@@ -0,0 +1,137 @@
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::TableConfig;
+use crate::prelude::*;
+use crate::sstable::{SsTable, SsTableName};
+use crate::table::TableSchema;
+
+/// The manifest lists a table's live SsTables and the data folder each of them is stored in. It
+///  is stored in the table's folder in the base folder and is the source of truth when a table
+///  is opened: SsTable files not listed in it are leftovers of an interrupted flush or
+///  compaction.
+///
+/// manifest format: one line per SsTable, containing its generation and the table data folder
+///  it is stored in, separated by a single space
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ManifestEntry {
+    pub name: SsTableName,
+    pub folder: PathBuf,
+}
+
+impl Manifest {
+    const FILE_NAME: &'static str = "manifest";
+    const TMP_FILE_NAME: &'static str = "manifest.tmp";
+
+    pub fn new(ss_tables: &[Arc<SsTable>]) -> Manifest {
+        Manifest {
+            entries: ss_tables.iter()
+                .map(|t| ManifestEntry { name: t.name().clone(), folder: t.folder().to_path_buf() })
+                .collect(),
+        }
+    }
+
+    /// reads a table's manifest, returning an empty manifest if there is none yet
+    pub fn read(config: &TableConfig, schema: &TableSchema) -> HtResult<Manifest> {
+        let mut buf = String::new();
+        match config.new_file(&config.table_folder(schema), Manifest::FILE_NAME, false) {
+            Ok(mut file) => file.read_to_string(&mut buf)?,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(Manifest { entries: Vec::new() }),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut entries = Vec::new();
+        for line in buf.lines().filter(|l| !l.is_empty()) {
+            let mut parts = line.splitn(2, ' ');
+            let generation = parts.next()
+                .and_then(|g| g.parse::<u64>().ok())
+                .ok_or_else(|| HtError::misc("invalid generation in manifest"))?;
+            let folder = parts.next()
+                .ok_or_else(|| HtError::misc("missing folder in manifest"))?;
+
+            entries.push(ManifestEntry { name: SsTableName::new(&schema.name, generation), folder: PathBuf::from(folder) });
+        }
+        Ok(Manifest { entries })
+    }
+
+    /// Replaces the table's manifest. The new manifest is written to a temporary file which is
+    ///  then renamed, so a crash leaves either the old or the new manifest in place.
+    pub fn write(&self, config: &TableConfig, schema: &TableSchema) -> HtResult<()> {
+        self.write_to_folder(&config.table_folder(schema))
+    }
+
+    /// writes the manifest to a given folder, e.g. a snapshot's
+    pub fn write_to_folder(&self, folder: &Path) -> HtResult<()> {
+        let mut buf = String::new();
+        for entry in &self.entries {
+            let entry_folder = entry.folder.to_str()
+                .ok_or_else(|| HtError::misc("data folder is not valid UTF-8"))?;
+            buf.push_str(&format!("{} {}\n", entry.name.generation, entry_folder));
+        }
+
+        write_atomically(folder, Manifest::FILE_NAME, Manifest::TMP_FILE_NAME, buf.as_bytes())
+    }
+}
+
+/// Replaces a file in a folder: the content is written to a temporary file which is synced and
+///  then renamed, and the folder is synced so that the rename is durable. A crash leaves either
+///  the old or the new file in place. This is for the table's small metadata files, e.g. the
+///  manifest.
+pub fn write_atomically(folder: &Path, file_name: &str, tmp_file_name: &str, content: &[u8]) -> HtResult<()> {
+    let mut file = std::fs::OpenOptions::new().create(true).write(true).truncate(true).open(folder.join(tmp_file_name))?;
+    file.write_all(content)?;
+    file.sync_all()?;
+
+    std::fs::rename(folder.join(tmp_file_name), folder.join(file_name))?;
+    sync_folder(folder)
+}
+
+/// makes the creation, renaming and removal of files in a folder durable
+#[cfg(unix)]
+pub fn sync_folder(folder: &Path) -> HtResult<()> {
+    File::open(folder)?.sync_all()?;
+    Ok(())
+}
+
+/// folders can not be opened as files on other platforms
+#[cfg(not(unix))]
+pub fn sync_folder(_folder: &Path) -> HtResult<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::manifest::{Manifest, ManifestEntry};
+    use crate::sstable::SsTableName;
+    use crate::testutils::{SimpleTableTestSetup, test_table_config};
+
+    #[test]
+    pub fn test_write_read() {
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+
+        assert!(Manifest::read(&config, &setup.schema).unwrap().entries.is_empty());
+
+        let manifest = Manifest {
+            entries: vec!(
+                ManifestEntry { name: SsTableName::new(&setup.schema.name, 3), folder: PathBuf::from("a/b") },
+                ManifestEntry { name: SsTableName::new(&setup.schema.name, 7), folder: PathBuf::from("/x y/z") },
+            ),
+        };
+        manifest.write(&config, &setup.schema).unwrap();
+        assert_eq!(Manifest::read(&config, &setup.schema).unwrap(), manifest);
+
+        let manifest = Manifest { entries: vec!(manifest.entries[1].clone()) };
+        manifest.write(&config, &setup.schema).unwrap();
+        assert_eq!(Manifest::read(&config, &setup.schema).unwrap(), manifest);
+    }
+}
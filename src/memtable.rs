@@ -1,48 +1,165 @@
 use std::collections::BTreeSet;
+use std::collections::btree_set::Range;
 use std::sync::Arc;
 
-use crate::config::TableConfig;
-use crate::table::{DetachedRowData, TableSchema};
+use bytes::{Bytes, BytesMut};
+
+use crate::config::{StorageMode, TableConfig};
+use crate::prelude::*;
+use crate::table::{DetachedRowData, RowData, SeekableRows, TableSchema};
+use crate::time::{HtClock, MergeTimestamp};
 
 pub struct MemTable {
     config: Arc<TableConfig>,
     schema: Arc<TableSchema>,
+    clock: Arc<dyn HtClock>,
     data: BTreeSet<DetachedRowData>,
+    arena: RowArena,
     size: usize,
+    first_write: Option<MergeTimestamp>,
 }
 
 impl MemTable {
-    pub fn new(config: &Arc<TableConfig>, schema: &Arc<TableSchema>) -> MemTable {
+    pub fn new(config: &Arc<TableConfig>, schema: &Arc<TableSchema>, clock: &Arc<dyn HtClock>) -> MemTable {
         MemTable {
             config: config.clone(),
             schema: schema.clone(),
+            clock: clock.clone(),
             data: BTreeSet::new(),
-            size: 0
+            arena: RowArena::new(),
+            size: 0,
+            first_write: None,
         }
     }
 
     pub fn add(&mut self, row: DetachedRowData) {
+        if self.first_write.is_none() {
+            self.first_write = Some(self.clock.now());
+        }
+
         let to_be_added = match self.data.take(&row) {
             None => row,
             Some(prev) => {
-                self.size -= prev.row_data_view().buf.len();
+                self.size -= prev.encoded_len();
                 row.row_data_view().merge(&prev.row_data_view())
             },
         };
 
-        self.size += &to_be_added.row_data_view().buf.len();
+        // an in-memory table's MemTable is never flushed, so the arena would keep every replaced
+        //  or evicted row's space for good, out of reach of the table's max_size
+        let to_be_added = match self.config.storage_mode {
+            StorageMode::Persistent => DetachedRowData::from_bytes(&self.schema, self.arena.copy(to_be_added.bytes())),
+            StorageMode::InMemory { .. } => to_be_added,
+        };
+        self.size += &to_be_added.encoded_len();
         assert!(self.data.insert(to_be_added));
     }
 
     pub fn get(&self, pk_data: &DetachedRowData) -> Option<&DetachedRowData> {
         self.data.get(pk_data)
     }
+
+    /// the approximate number of bytes of row data held by this memtable
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The number of bytes of arena memory used by this memtable. This includes rows that were
+    ///  replaced by a merge or evicted, since their space is reclaimed only when the memtable is
+    ///  dropped, so it grows with every write. In-memory tables do not use the arena, so this
+    ///  is 0 for them.
+    pub fn arena_size(&self) -> usize {
+        self.arena.used
+    }
+
+    /// when the first row was added to this memtable, None if it was never written to
+    pub fn first_write(&self) -> Option<MergeTimestamp> {
+        self.first_write
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Removes and returns the row that was written least recently, i.e. the one with the
+    ///  oldest timestamp. This scans all rows, which is fine for occasional eviction.
+    pub fn evict_oldest(&mut self) -> Option<DetachedRowData> {
+        let oldest = self.data.iter()
+            .min_by_key(|row| row.row_data_view().timestamp())?
+            .clone();
+
+        let row = self.data.take(&oldest)?;
+        self.size -= row.encoded_len();
+        Some(row)
+    }
+
+    /// iterates over the rows in primary key order
+    pub fn iter(&self) -> impl Iterator<Item=&DetachedRowData> {
+        self.data.iter()
+    }
+
+    /// iterates over the rows in primary key order as a seekable row source
+    pub fn rows(&self) -> MemTableRowIter<'_> {
+        MemTableRowIter { mem_table: self, range: self.data.range(..) }
+    }
+}
+
+/// A bump allocator for a MemTable's row buffers: rows are copied into large shared chunks
+///  rather than each holding an allocation of its own, and a chunk is freed as a whole when the
+///  last row referring to it is dropped - normally when the MemTable is dropped after it was
+///  flushed. Space of rows that were replaced by a merge is reclaimed only then, and rows handed
+///  out to readers keep their entire chunk alive.
+struct RowArena {
+    chunk: BytesMut,
+    /// bytes copied into chunks, plus the unused ends of chunks that were replaced
+    used: usize,
+}
+
+impl RowArena {
+    const CHUNK_SIZE: usize = 64*1024;
+
+    fn new() -> RowArena {
+        RowArena { chunk: BytesMut::new(), used: 0 }
+    }
+
+    fn copy(&mut self, buf: &[u8]) -> Bytes {
+        if self.chunk.capacity() < buf.len() {
+            self.used += self.chunk.capacity();
+            self.chunk = BytesMut::with_capacity(RowArena::CHUNK_SIZE.max(buf.len()));
+        }
+        self.chunk.extend_from_slice(buf);
+        self.used += buf.len();
+        self.chunk.split().freeze()
+    }
+}
+
+pub struct MemTableRowIter<'a> {
+    mem_table: &'a MemTable,
+    range: Range<'a, DetachedRowData>,
+}
+
+impl <'a> Iterator for MemTableRowIter<'a> {
+    type Item = HtResult<RowData<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.range.next().map(|row| Ok(row.row_data_view()))
+    }
+}
+
+impl <'a> SeekableRows<'a> for MemTableRowIter<'a> {
+    fn seek(&mut self, pks: &RowData) -> HtResult<()> {
+        self.range = self.mem_table.data.range(pks.detach()..);
+        Ok(())
+    }
 }
 
 
 #[cfg(test)]
 mod test {
-    use crate::memtable::MemTable;
+    use std::sync::Arc;
+
+    use crate::config::{StorageMode, TableConfig};
+    use crate::memtable::{MemTable, RowArena};
     use crate::table::{ColumnId, ColumnValue};
     use crate::testutils::{SimpleTableTestSetup, test_table_config};
     use crate::time::{HtClock, MergeTimestamp};
@@ -52,7 +169,8 @@ mod test {
         let config = test_table_config();
         let setup = SimpleTableTestSetup::new();
 
-        let mut mem_table = MemTable::new(&config, &setup.schema);
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let mut mem_table = MemTable::new(&config, &setup.schema, &clock);
         assert_eq!(0, mem_table.size);
 
         let row = setup.full_row(1, Option::Some("abc"), Option::Some(123));
@@ -93,6 +211,78 @@ mod test {
         // second row
     }
 
+    #[test]
+    pub fn test_evict_oldest() {
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let mut mem_table = MemTable::new(&config, &setup.schema, &clock);
+        assert!(mem_table.evict_oldest().is_none());
+
+        setup.clock.set(MergeTimestamp::from_ticks(200));
+        mem_table.add(setup.full_row(1, Some("a"), None));
+        setup.clock.set(MergeTimestamp::from_ticks(100));
+        mem_table.add(setup.full_row(2, Some("b"), None));
+        setup.clock.set(MergeTimestamp::from_ticks(300));
+        mem_table.add(setup.full_row(3, Some("c"), None));
+
+        assert_eq!(setup.value(&mem_table.evict_oldest().unwrap().row_data_view()), "b");
+        assert_eq!(setup.value(&mem_table.evict_oldest().unwrap().row_data_view()), "a");
+        assert!(mem_table.get(&setup.pk_row(1)).is_none());
+        assert_eq!(setup.value(&mem_table.evict_oldest().unwrap().row_data_view()), "c");
+        assert!(mem_table.is_empty());
+        assert_eq!(mem_table.size(), 0);
+    }
+
+    #[test]
+    pub fn test_row_arena() {
+        let mut arena = RowArena::new();
+        let a = arena.copy(b"abc");
+        let b = arena.copy(b"de");
+        assert_eq!(&a[..], b"abc");
+        assert_eq!(&b[..], b"de");
+        assert_eq!(b.as_ptr(), a[3..].as_ptr());
+
+        // a full chunk is replaced, and oversized buffers get a chunk of their own
+        let big = vec!(1u8; RowArena::CHUNK_SIZE + 1);
+        assert_eq!(&arena.copy(&big)[..], &big[..]);
+        let c = arena.copy(b"f");
+        assert_eq!(&c[..], b"f");
+        assert_ne!(c.as_ptr(), b[2..].as_ptr());
+        // the rest of the replaced chunk counts as used
+        assert_eq!(arena.used, RowArena::CHUNK_SIZE + big.len() + 1);
+
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let mut mem_table = MemTable::new(&config, &setup.schema, &clock);
+        mem_table.add(setup.full_row(1, Some("a"), None));
+        mem_table.add(setup.full_row(2, Some("b"), None));
+        let first = mem_table.get(&setup.pk_row(1)).unwrap().bytes();
+        let second = mem_table.get(&setup.pk_row(2)).unwrap().bytes();
+        assert_eq!(second.as_ptr(), first[first.len()..].as_ptr());
+        assert_eq!(mem_table.arena_size(), mem_table.size());
+
+        // a merged row is copied, and the replaced row's space stays in use
+        let size = mem_table.size();
+        let arena_size = mem_table.arena_size();
+        mem_table.add(setup.full_row(1, Some("c"), None));
+        assert_eq!(mem_table.size(), size);
+        assert!(mem_table.arena_size() > arena_size);
+
+        // in-memory tables keep each row's own buffer, so only live rows take up memory
+        let config = Arc::new(TableConfig {
+            storage_mode: StorageMode::InMemory { max_size: None, evict: false },
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+        let mut mem_table = MemTable::new(&config, &setup.schema, &clock);
+        mem_table.add(setup.full_row(1, Some("a"), None));
+        mem_table.add(setup.full_row(1, Some("c"), None));
+        assert_eq!(mem_table.size(), size / 2);
+        assert_eq!(mem_table.arena_size(), 0);
+    }
+
     //TODO expiry
     //TODO with cluster key
     //TODO merging update
@@ -0,0 +1,117 @@
+use std::cmp::Ordering;
+
+use crate::prelude::*;
+use crate::table::{ColumnId, ColumnType, ColumnValue, RowData, TableSchema};
+
+/// How a ColumnPredicate compares a column's value. There are no collection types yet, so there
+///  is no CONTAINS.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PredicateOp<'a> {
+    Eq(ColumnValue<'a>),
+    Lt(ColumnValue<'a>),
+    Le(ColumnValue<'a>),
+    Gt(ColumnValue<'a>),
+    Ge(ColumnValue<'a>),
+    In(Vec<ColumnValue<'a>>),
+}
+
+/// A condition on a single column's value, for scans that filter rows by regular columns (see
+///  Table::scan_filtered). Like in SQL, a column that is NULL or was never written matches no
+///  predicate.
+///
+/// Values are compared in the column type's order, honoring the collation of Text primary key
+///  columns. Durations have no meaningful order, so they can only be compared with Eq and In.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ColumnPredicate<'a> {
+    pub col_id: ColumnId,
+    pub op: PredicateOp<'a>,
+}
+
+impl <'a> ColumnPredicate<'a> {
+    pub fn new(col_id: ColumnId, op: PredicateOp<'a>) -> ColumnPredicate<'a> {
+        ColumnPredicate { col_id, op }
+    }
+
+    /// checks that the column exists and the predicate's values match its type
+    pub fn validate(&self, schema: &TableSchema) -> HtResult<()> {
+        let col = schema.column(self.col_id)?;
+        let values = match &self.op {
+            PredicateOp::Eq(v) => vec!(v),
+            PredicateOp::Lt(v) | PredicateOp::Le(v) | PredicateOp::Gt(v) | PredicateOp::Ge(v) => {
+                if col.tpe == ColumnType::Duration {
+                    return Err(HtError::misc("Duration columns can only be compared for equality"));
+                }
+                vec!(v)
+            }
+            PredicateOp::In(values) => values.iter().collect(),
+        };
+        if values.iter().any(|v| !col.tpe.matches(v)) {
+            return Err(HtError::misc("predicate value does not match the column type"));
+        }
+        Ok(())
+    }
+
+    /// Checks a row, decoding the values of the columns before the predicate's column but not
+    ///  the values of those after it
+    pub fn matches(&self, row: &RowData) -> bool {
+        let value = match row.read_col_by_id(self.col_id).and_then(|col| col.value) {
+            Some(value) => value,
+            None => return false,
+        };
+        let compare = |other: &ColumnValue| match row.schema.column(self.col_id) {
+            Ok(col) => col.compare_values(&value, other),
+            Err(_) => value.cmp(other),
+        };
+
+        match &self.op {
+            PredicateOp::Eq(v) => compare(v) == Ordering::Equal,
+            PredicateOp::Lt(v) => compare(v) == Ordering::Less,
+            PredicateOp::Le(v) => compare(v) != Ordering::Greater,
+            PredicateOp::Gt(v) => compare(v) == Ordering::Greater,
+            PredicateOp::Ge(v) => compare(v) != Ordering::Less,
+            PredicateOp::In(values) => values.iter().any(|v| compare(v) == Ordering::Equal),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::predicate::{ColumnPredicate, PredicateOp};
+    use crate::table::{ColumnId, ColumnValue};
+    use crate::testutils::{SimpleTableTestSetup, test_table_config};
+
+    #[test]
+    pub fn test_column_predicate() {
+        let setup = SimpleTableTestSetup::new();
+        let row = setup.full_row(1, Some("b"), Some(10));
+        let row = row.row_data_view();
+        let null_row = setup.full_row(2, None, None);
+        let null_row = null_row.row_data_view();
+
+        let text = |op| ColumnPredicate::new(ColumnId(1), op);
+        assert!(text(PredicateOp::Eq(ColumnValue::Text("b"))).matches(&row));
+        assert!(!text(PredicateOp::Eq(ColumnValue::Text("B"))).matches(&row));
+        assert!(text(PredicateOp::Lt(ColumnValue::Text("c"))).matches(&row));
+        assert!(!text(PredicateOp::Lt(ColumnValue::Text("b"))).matches(&row));
+        assert!(text(PredicateOp::Le(ColumnValue::Text("b"))).matches(&row));
+        assert!(text(PredicateOp::Gt(ColumnValue::Text("a"))).matches(&row));
+        assert!(!text(PredicateOp::Gt(ColumnValue::Text("b"))).matches(&row));
+        assert!(text(PredicateOp::Ge(ColumnValue::Text("b"))).matches(&row));
+        assert!(text(PredicateOp::In(vec!(ColumnValue::Text("a"), ColumnValue::Text("b")))).matches(&row));
+        assert!(!text(PredicateOp::In(vec!())).matches(&row));
+
+        // NULL columns match nothing
+        assert!(!text(PredicateOp::Eq(ColumnValue::Text("b"))).matches(&null_row));
+        assert!(!text(PredicateOp::Ge(ColumnValue::Text(""))).matches(&null_row));
+
+        assert!(ColumnPredicate::new(ColumnId(0), PredicateOp::Gt(ColumnValue::BigInt(0))).matches(&row));
+        assert!(ColumnPredicate::new(ColumnId(2), PredicateOp::Eq(ColumnValue::Int(10))).matches(&row));
+
+        assert!(text(PredicateOp::Eq(ColumnValue::Text("b"))).validate(&setup.schema).is_ok());
+        assert!(text(PredicateOp::Eq(ColumnValue::Int(1))).validate(&setup.schema).is_err());
+        assert!(text(PredicateOp::In(vec!(ColumnValue::Text("a"), ColumnValue::Int(1)))).validate(&setup.schema).is_err());
+        assert!(ColumnPredicate::new(ColumnId(9), PredicateOp::Eq(ColumnValue::Int(1))).validate(&setup.schema).is_err());
+
+        test_table_config().remove_table_folder(&setup.schema).unwrap();
+    }
+}
@@ -1,10 +1,28 @@
 use std::io::Error;
+use std::path::PathBuf;
 
 pub type HtResult<T> = std::result::Result<T, HtError>;
 
 #[derive(Debug)]
 pub enum HtError {
     Io(std::io::Error),
+    /// the lock file at this path is held by another process or Table instance
+    Locked(PathBuf),
+    /// a write was rejected because it exceeds the table's write rate limit
+    RateLimited,
+    /// a read did not finish before its deadline
+    Timeout,
+    /// a read was cancelled via its CancellationToken
+    Cancelled,
+    /// a read was aborted because it encountered more tombstones than
+    ///  TableConfig::tombstone_failure_threshold
+    TombstoneOverwhelm,
+    /// a write was rejected because the table's data folders are filled beyond
+    ///  TableConfig::disk_critical_watermark
+    InsufficientDiskSpace,
+    /// a write was applied, but reporting it to the table's AuditCallback or ChangeSink failed
+    ///  with the wrapped error, so retrying it would apply it twice
+    NotDelivered(Box<HtError>),
     Misc(String),
 }
 impl HtError {
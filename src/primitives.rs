@@ -105,12 +105,134 @@ impl <W> EncodePrimitives for W where W: Write {
     }
 }
 
+/// Infallible counterpart to Encode<T> for in-memory buffers
+pub trait Put<T> {
+    fn put(&mut self, value: T);
+}
+
+/// Infallible counterpart to EncodePrimitives for in-memory buffers. Writing to a Vec<u8> can not
+///  fail, so this avoids the error handling (and the overhead of the Write based path) when e.g.
+///  assembling rows. Both produce identical bytes.
+pub trait EncodeBuf {
+    fn put_u8(&mut self, value: u8);
+
+    fn put_varint_u64(&mut self, value: u64);
+    fn put_varint_u32(&mut self, value: u32);
+    fn put_varint_usize(&mut self, value: usize);
+
+    fn put_varint_i64(&mut self, value: i64) {
+        if value > 0 {
+            self.put_varint_u64((value as u64) << 1)
+        }
+        else {
+            self.put_varint_u64(((-value as u64) << 1) + 1)
+        }
+    }
+    fn put_varint_i32(&mut self, value: i32) {
+        if value >= 0 {
+            self.put_varint_u32((value as u32) << 1)
+        }
+        else {
+            self.put_varint_u32(((-value as u32) << 1) + 1)
+        }
+    }
+
+    fn put_fixed_u64(&mut self, value: u64);
+    fn put_fixed_f64(&mut self, value: f64);
+    fn put_fixed_u32(&mut self, value: u32);
+    fn put_fixed_f32(&mut self, value: f32);
+
+    fn put_bool(&mut self, value: bool);
+    fn put_utf8(&mut self, value: &str);
+}
+
+impl EncodeBuf for Vec<u8> {
+    #[inline]
+    fn put_u8(&mut self, value: u8) {
+        self.push(value);
+    }
+
+    fn put_varint_u64(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.push(((value & 0x7F) | 0x80) as u8);
+            value >>= 7;
+        }
+        self.push(value as u8);
+    }
+
+    fn put_varint_u32(&mut self, value: u32) {
+        self.put_varint_u64(value as u64);
+    }
+
+    fn put_varint_usize(&mut self, value: usize) {
+        self.put_varint_u64(value as u64);
+    }
+
+    fn put_fixed_u64(&mut self, value: u64) {
+        self.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_fixed_f64(&mut self, value: f64) {
+        self.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_fixed_u32(&mut self, value: u32) {
+        self.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_fixed_f32(&mut self, value: f32) {
+        self.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_bool(&mut self, value: bool) {
+        self.push(if value {1} else {0});
+    }
+
+    fn put_utf8(&mut self, value: &str) {
+        self.put_varint_usize(value.len());
+        self.extend_from_slice(value.as_bytes());
+    }
+}
+
+/// the number of bytes put_varint_u64 writes for a value, e.g. for sizing buffers up front
+pub fn varint_len(value: u64) -> usize {
+    let bits = 64 - value.leading_zeros() as usize;
+    bits.div_ceil(7).max(1)
+}
+
+/// the number of bytes put_varint_i64 writes for a value
+pub fn varint_len_i64(value: i64) -> usize {
+    if value > 0 {
+        varint_len((value as u64) << 1)
+    }
+    else {
+        varint_len(((value.wrapping_neg() as u64) << 1) + 1)
+    }
+}
+
+/// the number of bytes put_varint_i32 writes for a value
+pub fn varint_len_i32(value: i32) -> usize {
+    if value >= 0 {
+        varint_len(((value as u32) << 1) as u64)
+    }
+    else {
+        varint_len(((value.wrapping_neg() as u32) << 1).wrapping_add(1) as u64)
+    }
+}
+
 pub trait DecodePrimitives {
     fn decode_u8(&self, offs: &mut usize) -> u8;
 
     fn decode_varint_u64(&self, offs: &mut usize) -> u64;
     fn decode_varint_u32(&self, offs: &mut usize) -> u32;
     fn decode_varint_usize(&self, offs: &mut usize) -> usize;
+    /// advances offs past a varint without decoding it
+    fn skip_varint(&self, offs: &mut usize);
+    /// like decode_varint_usize, but None instead of a panic if the varint extends beyond the
+    ///  end of the buffer or overflows, e.g. for checking files that may be corrupt
+    fn try_decode_varint_usize(&self, offs: &mut usize) -> Option<usize>;
+    /// like decode_varint_u64, with the checks of try_decode_varint_usize
+    fn try_decode_varint_u64(&self, offs: &mut usize) -> Option<u64>;
 
     fn decode_varint_i64(&self, offs: &mut usize) -> i64 {
         let raw = self.decode_varint_u64(offs);
@@ -132,13 +254,48 @@ pub trait DecodePrimitives {
         }
     }
 
+    /// like decode_varint_u32, with the checks of try_decode_varint_usize
+    fn try_decode_varint_u32(&self, offs: &mut usize) -> Option<u32> {
+        self.try_decode_varint_u64(offs)?.try_into().ok()
+    }
+
+    /// like decode_varint_i64, with the checks of try_decode_varint_usize
+    fn try_decode_varint_i64(&self, offs: &mut usize) -> Option<i64> {
+        let raw = self.try_decode_varint_u64(offs)?;
+        if (raw&1) == 0 {
+            Some((raw >> 1) as i64)
+        }
+        else {
+            Some(-((raw >> 1) as i64))
+        }
+    }
+
+    /// like decode_varint_i32, with the checks of try_decode_varint_usize
+    fn try_decode_varint_i32(&self, offs: &mut usize) -> Option<i32> {
+        let raw = self.try_decode_varint_u32(offs)?;
+        if (raw&1) == 0 {
+            Some((raw >> 1) as i32)
+        }
+        else {
+            Some(-((raw >> 1) as i32))
+        }
+    }
+
     fn decode_fixed_u64(&self, offs: &mut usize) -> u64;
     fn decode_fixed_f64(&self, offs: &mut usize) -> f64;
     fn decode_fixed_u32(&self, offs: &mut usize) -> u32;
     fn decode_fixed_f32(&self, offs: &mut usize) -> f32;
+    /// like decode_fixed_u64, but None if the value extends beyond the end of the buffer
+    fn try_decode_fixed_u64(&self, offs: &mut usize) -> Option<u64>;
+    /// like decode_fixed_u32, but None if the value extends beyond the end of the buffer
+    fn try_decode_fixed_u32(&self, offs: &mut usize) -> Option<u32>;
 
     fn decode_bool(&self, offs: &mut usize) -> bool;
     fn decode_utf8(&self, offs: &mut usize) -> &str;
+    /// the next len bytes, e.g. after a length that is encoded together with other data
+    fn decode_slice(&self, offs: &mut usize, len: usize) -> &[u8];
+    /// like decode_slice, but None if the slice extends beyond the end of the buffer
+    fn try_decode_slice(&self, offs: &mut usize, len: usize) -> Option<&[u8]>;
 }
 
 
@@ -191,6 +348,13 @@ impl <D> DecodePrimitives for D where D: Deref<Target=[u8]> {
         result
     }
 
+    fn skip_varint(&self, offs: &mut usize) {
+        while self[*offs] & 0x80 != 0 {
+            *offs += 1;
+        }
+        *offs += 1;
+    }
+
     fn decode_varint_usize(&self, offs: &mut usize) -> usize {
         let mut result = 0usize;
         let mut shift = 0usize;
@@ -211,6 +375,52 @@ impl <D> DecodePrimitives for D where D: Deref<Target=[u8]> {
         result
     }
 
+    fn try_decode_varint_usize(&self, offs: &mut usize) -> Option<usize> {
+        let mut result = 0usize;
+        let mut shift = 0u32;
+
+        loop {
+            let next = *self.get(*offs)? as usize;
+            *offs += 1;
+
+            let bits = next & 0x7F;
+            if shift >= usize::BITS || (bits << shift) >> shift != bits {
+                return None;
+            }
+            result |= bits << shift;
+            shift += 7;
+
+            if next & 0x80 == 0 {
+                break;
+            }
+        }
+
+        Some(result)
+    }
+
+    fn try_decode_varint_u64(&self, offs: &mut usize) -> Option<u64> {
+        let mut result = 0u64;
+        let mut shift = 0u32;
+
+        loop {
+            let next = *self.get(*offs)? as u64;
+            *offs += 1;
+
+            let bits = next & 0x7F;
+            if shift >= u64::BITS || (bits << shift) >> shift != bits {
+                return None;
+            }
+            result |= bits << shift;
+            shift += 7;
+
+            if next & 0x80 == 0 {
+                break;
+            }
+        }
+
+        Some(result)
+    }
+
     fn decode_fixed_u64(&self, offs: &mut usize) -> u64 {
         let (buf, _) = self[*offs..].split_at(size_of::<u64>());
         *offs += size_of::<u64>();
@@ -235,6 +445,16 @@ impl <D> DecodePrimitives for D where D: Deref<Target=[u8]> {
         f32::from_le_bytes(buf.try_into().unwrap())
     }
 
+    fn try_decode_fixed_u64(&self, offs: &mut usize) -> Option<u64> {
+        let buf = self.try_decode_slice(offs, size_of::<u64>())?;
+        Some(u64::from_le_bytes(buf.try_into().unwrap()))
+    }
+
+    fn try_decode_fixed_u32(&self, offs: &mut usize) -> Option<u32> {
+        let buf = self.try_decode_slice(offs, size_of::<u32>())?;
+        Some(u32::from_le_bytes(buf.try_into().unwrap()))
+    }
+
     fn decode_bool(&self, offs: &mut usize) -> bool {
         let result = self[*offs] != 0;
         *offs += 1;
@@ -249,11 +469,24 @@ impl <D> DecodePrimitives for D where D: Deref<Target=[u8]> {
         //TODO unchecked: unsafe { std::str::from_utf8_unchecked(str_buf) }
         std::str::from_utf8(str_buf).expect("invalid UTF-8 string")
     }
+
+    fn decode_slice(&self, offs: &mut usize, len: usize) -> &[u8] {
+        let result = &self[*offs .. *offs+len];
+        *offs += len;
+        result
+    }
+
+    fn try_decode_slice(&self, offs: &mut usize, len: usize) -> Option<&[u8]> {
+        let end = offs.checked_add(len)?;
+        let result = self.get(*offs .. end)?;
+        *offs = end;
+        Some(result)
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::primitives::{EncodePrimitives, DecodePrimitives};
+    use crate::primitives::{EncodePrimitives, DecodePrimitives, EncodeBuf, varint_len, varint_len_i64, varint_len_i32};
 
     #[test]
     pub fn test_u8() {
@@ -301,6 +534,17 @@ mod test {
         assert_eq!("", v.decode_utf8(&mut offs));
         assert_eq!("hey", v.decode_utf8(&mut offs));
     }
+
+    #[test]
+    pub fn test_slice() {
+        let v = vec!(1u8, 2, 3, 4);
+        let mut offs = 1usize;
+
+        assert_eq!(&[2u8, 3], v.decode_slice(&mut offs, 2));
+        assert_eq!(3, offs);
+        assert!(v.decode_slice(&mut offs, 0).is_empty());
+        assert_eq!(&[4u8], v.decode_slice(&mut offs, 1));
+    }
     
     #[test]
     pub fn test_fixed_u32() {
@@ -408,6 +652,26 @@ mod test {
         assert_eq!(1234567890, v.decode_varint_u32(&mut offs));
     }
 
+    #[test]
+    pub fn test_skip_varint() {
+        let mut v = Vec::new();
+
+        v.encode_varint_u64(0).unwrap();
+        v.encode_varint_u64(9988).unwrap();
+        v.encode_varint_u64(0xffffffffffffffff).unwrap();
+        v.encode_varint_u64(127).unwrap();
+
+        let v = v;
+        let mut offs = 0usize;
+
+        v.skip_varint(&mut offs);
+        assert_eq!(offs, 1);
+        v.skip_varint(&mut offs);
+        v.skip_varint(&mut offs);
+        assert_eq!(127, v.decode_varint_u64(&mut offs));
+        assert_eq!(offs, v.len());
+    }
+
     #[test]
     pub fn test_varint_u64() {
         let mut v = Vec::new();
@@ -458,6 +722,36 @@ mod test {
         assert_eq!(1234567890, v.decode_varint_usize(&mut offs));
         assert_eq!(0x1234565432101234, v.decode_varint_usize(&mut offs));
         assert_eq!(0xffffffffffffffff, v.decode_varint_usize(&mut offs));
+
+        let mut offs = 0usize;
+        for expected in [0, 1, 127, 128, 9988, 1234567890, 0x1234565432101234, 0xffffffffffffffff] {
+            assert_eq!(Some(expected), v.try_decode_varint_usize(&mut offs));
+        }
+        assert_eq!(None, v.try_decode_varint_usize(&mut offs));
+
+        // truncated
+        assert_eq!(None, vec!(0x80u8, 0x80).try_decode_varint_usize(&mut 0));
+        // overflow
+        assert_eq!(None, vec!(0xffu8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f).try_decode_varint_usize(&mut 0));
+    }
+
+    #[test]
+    pub fn test_varint_len() {
+        for &value in &[0u64, 1, 0x7f, 0x80, 0x3fff, 0x4000, 1234567890, u64::MAX] {
+            let mut v = Vec::new();
+            v.put_varint_u64(value);
+            assert_eq!(varint_len(value), v.len(), "{}", value);
+        }
+        for &value in &[0i64, 1, -1, 63, 64, -64, -65, 1234567890, -1234567890, i64::MAX, -i64::MAX] {
+            let mut v = Vec::new();
+            v.put_varint_i64(value);
+            assert_eq!(varint_len_i64(value), v.len(), "{}", value);
+        }
+        for &value in &[0i32, 1, -1, 63, 64, -64, -65, 1234567890, -1234567890] {
+            let mut v = Vec::new();
+            v.put_varint_i32(value);
+            assert_eq!(varint_len_i32(value), v.len(), "{}", value);
+        }
     }
 
     #[test]
@@ -507,5 +801,51 @@ mod test {
         assert_eq!(0x7fffffffffffffff, v.decode_varint_i64(&mut offs));
         assert_eq!(-0x7fffffffffffffff, v.decode_varint_i64(&mut offs));
     }
-}
 
+    #[test]
+    pub fn test_encode_buf() {
+        let mut encoded = Vec::new();
+        let mut put = Vec::new();
+
+        for &v in &[0u64, 1, 127, 128, 9988, 0x1234565432101234, 0xffffffffffffffff] {
+            encoded.encode_varint_u64(v).unwrap();
+            put.put_varint_u64(v);
+            encoded.encode_varint_usize(v as usize).unwrap();
+            put.put_varint_usize(v as usize);
+            encoded.encode_fixed_u64(v).unwrap();
+            put.put_fixed_u64(v);
+        }
+        for &v in &[0u32, 1, 127, 128, 1234567890, 0xffffffff] {
+            encoded.encode_varint_u32(v).unwrap();
+            put.put_varint_u32(v);
+            encoded.encode_fixed_u32(v).unwrap();
+            put.put_fixed_u32(v);
+        }
+        for &v in &[0i64, 1, -1, -1234567890, 0x7fffffffffffffff, -0x7fffffffffffffff] {
+            encoded.encode_varint_i64(v).unwrap();
+            put.put_varint_i64(v);
+        }
+        for &v in &[0i32, 1, -1, 1234567890, -1234567890] {
+            encoded.encode_varint_i32(v).unwrap();
+            put.put_varint_i32(v);
+        }
+        for &v in &[0., -2.34, 987.654e29] {
+            encoded.encode_fixed_f64(v).unwrap();
+            put.put_fixed_f64(v);
+            encoded.encode_fixed_f32(v as f32).unwrap();
+            put.put_fixed_f32(v as f32);
+        }
+        for &v in &[true, false] {
+            encoded.encode_bool(v).unwrap();
+            put.put_bool(v);
+        }
+        for &v in &["", "abc", "äöü 😀"] {
+            encoded.encode_utf8(v).unwrap();
+            put.put_utf8(v);
+        }
+        encoded.encode_u8(99).unwrap();
+        put.put_u8(99);
+
+        assert_eq!(put, encoded);
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::{Arc, Mutex};
+
+use log::error;
+
+use crate::prelude::*;
+use crate::sstable::SsTableName;
+use crate::time::{HtClock, MergeTimestamp};
+
+/// An SsTable row that failed validation when it was read, see Quarantine
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QuarantinedRow {
+    pub ss_table: SsTableName,
+    /// the row's offset in the SsTable's data file
+    pub offset: u64,
+    pub error: String,
+    /// when the row was first found to be corrupt
+    pub detected_at: MergeTimestamp,
+}
+
+/// The corrupt SsTable rows a table's reads encountered. If TableConfig::validate_reads is set,
+///  reads skip rows that fail RowData::validate and serve the data from the other MemTables and
+///  SsTables, adding the rows here so that operators can restore or remove the affected
+///  SsTables. Compactions copy corrupt rows as they are.
+///
+/// Only corruption that RowData::validate reports as an error is quarantined. SsTables have no
+///  checksums, so a row that was changed but is still well-formed is served as it is.
+pub struct Quarantine {
+    clock: Arc<dyn HtClock>,
+    rows: Mutex<Vec<QuarantinedRow>>,
+}
+
+impl Quarantine {
+    pub fn new(clock: &Arc<dyn HtClock>) -> Quarantine {
+        Quarantine {
+            clock: clock.clone(),
+            rows: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// records a corrupt row, logging it the first time it is encountered
+    pub fn add(&self, ss_table: &SsTableName, offset: u64, error: &HtError) {
+        let mut rows = self.rows.lock().unwrap();
+        if rows.iter().any(|row| &row.ss_table == ss_table && row.offset == offset) {
+            return;
+        }
+
+        error!("corrupt row at offset {} of SsTable {}, skipping it: {:?}", offset, ss_table, error);
+        rows.push(QuarantinedRow {
+            ss_table: ss_table.clone(),
+            offset,
+            error: format!("{:?}", error),
+            detected_at: self.clock.now(),
+        });
+    }
+
+    /// the quarantined rows in the order they were encountered
+    pub fn rows(&self) -> Vec<QuarantinedRow> {
+        self.rows.lock().unwrap().clone()
+    }
+
+    /// the number of quarantined rows
+    pub fn count(&self) -> usize {
+        self.rows.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::prelude::*;
+    use crate::quarantine::Quarantine;
+    use crate::sstable::SsTableName;
+    use crate::time::{HtClock, ManualClock, MergeTimestamp};
+
+    #[test]
+    pub fn test_quarantine() {
+        let clock: Arc<dyn HtClock> = Arc::new(ManualClock::new(MergeTimestamp::from_ticks(5)));
+        let quarantine = Quarantine::new(&clock);
+        let name = SsTableName::new("t", 1);
+
+        quarantine.add(&name, 10, &HtError::misc("a"));
+        quarantine.add(&name, 10, &HtError::misc("b"));
+        quarantine.add(&name, 20, &HtError::misc("c"));
+        quarantine.add(&SsTableName::new("t", 2), 10, &HtError::misc("d"));
+
+        let rows = quarantine.rows();
+        assert_eq!(quarantine.count(), 3);
+        assert_eq!(rows.iter().map(|r| (r.ss_table.generation, r.offset)).collect::<Vec<_>>(), vec!((1, 10), (1, 20), (2, 10)));
+        assert!(rows[0].error.contains("\"a\""));
+        assert_eq!(rows[0].detected_at, MergeTimestamp::from_ticks(5));
+    }
+}
@@ -0,0 +1,161 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::TableConfig;
+
+/// Limits the rate of writes to a table, in rows and / or bytes per second, so that flushes
+///  and compactions are not permanently outrun by writes.
+///
+/// This is a token bucket per limit, holding up to one second's worth of tokens so that short
+///  bursts pass unhindered. A row that is larger than the per-second byte limit is admitted
+///  once the bucket is full, taking its full cost and putting the bucket into debt, so that it
+///  is delayed rather than rejected forever.
+pub struct WriteRateLimiter {
+    state: Mutex<LimiterState>,
+}
+
+struct LimiterState {
+    rows: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+    last_refill: Instant,
+}
+
+struct TokenBucket {
+    per_second: f64,
+    available: f64,
+}
+
+impl TokenBucket {
+    fn new(per_second: u64) -> TokenBucket {
+        TokenBucket {
+            per_second: per_second as f64,
+            available: per_second as f64,
+        }
+    }
+
+    fn refill(&mut self, elapsed: Duration) {
+        self.available = (self.available + elapsed.as_secs_f64() * self.per_second).min(self.per_second);
+    }
+
+    /// time until the bucket holds enough tokens for a given cost
+    fn wait_time(&self, cost: f64) -> Duration {
+        let required = cost.min(self.per_second);
+        if self.available >= required {
+            Duration::from_secs(0)
+        }
+        else {
+            Duration::from_secs_f64((required - self.available) / self.per_second)
+        }
+    }
+}
+
+impl WriteRateLimiter {
+    /// returns None if the config has no write rate limits
+    pub fn new(config: &TableConfig) -> Option<WriteRateLimiter> {
+        WriteRateLimiter::with_limits(config.max_writes_per_second, config.max_write_bytes_per_second)
+    }
+
+    fn with_limits(rows_per_second: Option<u64>, bytes_per_second: Option<u64>) -> Option<WriteRateLimiter> {
+        if rows_per_second.is_none() && bytes_per_second.is_none() {
+            return None;
+        }
+
+        Some(WriteRateLimiter {
+            state: Mutex::new(LimiterState {
+                rows: rows_per_second.map(TokenBucket::new),
+                bytes: bytes_per_second.map(TokenBucket::new),
+                last_refill: Instant::now(),
+            })
+        })
+    }
+
+    /// waits until a write of the given number of bytes is admitted
+    pub fn acquire(&self, bytes: usize) {
+        while let Err(wait_time) = self.try_acquire_at(bytes, Instant::now()) {
+            std::thread::sleep(wait_time);
+        }
+    }
+
+    /// admits a write of the given number of bytes if that is possible without waiting
+    pub fn try_acquire(&self, bytes: usize) -> bool {
+        self.try_acquire_at(bytes, Instant::now()).is_ok()
+    }
+
+    /// returns the time to wait before trying again if the write is not admitted
+    fn try_acquire_at(&self, bytes: usize, now: Instant) -> Result<(), Duration> {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+
+        let elapsed = now.saturating_duration_since(state.last_refill);
+        state.last_refill = state.last_refill.max(now);
+
+        let mut wait_time = Duration::from_secs(0);
+        for (bucket, cost) in state.rows.iter_mut().map(|b| (b, 1.0))
+            .chain(state.bytes.iter_mut().map(|b| (b, bytes as f64))) {
+            bucket.refill(elapsed);
+            wait_time = wait_time.max(bucket.wait_time(cost));
+        }
+        if wait_time > Duration::from_secs(0) {
+            return Err(wait_time);
+        }
+
+        if let Some(bucket) = &mut state.rows {
+            bucket.available -= 1.0;
+        }
+        if let Some(bucket) = &mut state.bytes {
+            bucket.available -= bytes as f64;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::ratelimit::WriteRateLimiter;
+
+    #[test]
+    pub fn test_rows_per_second() {
+        assert!(WriteRateLimiter::with_limits(None, None).is_none());
+
+        let limiter = WriteRateLimiter::with_limits(Some(10), None).unwrap();
+        let start = limiter.state.lock().unwrap().last_refill;
+
+        // a full bucket allows a burst of one second's worth of writes
+        for _ in 0..10 {
+            assert!(limiter.try_acquire_at(1_000_000, start).is_ok());
+        }
+        assert_eq!(limiter.try_acquire_at(1, start), Err(Duration::from_millis(100)));
+
+        assert!(limiter.try_acquire_at(1, start + Duration::from_millis(100)).is_ok());
+        assert!(limiter.try_acquire_at(1, start + Duration::from_millis(100)).is_err());
+
+        // the bucket does not fill beyond one second's worth
+        let later = start + Duration::from_secs(60);
+        for _ in 0..10 {
+            assert!(limiter.try_acquire_at(1, later).is_ok());
+        }
+        assert!(limiter.try_acquire_at(1, later).is_err());
+    }
+
+    #[test]
+    pub fn test_bytes_per_second() {
+        let limiter = WriteRateLimiter::with_limits(Some(1000), Some(100)).unwrap();
+        let start = limiter.state.lock().unwrap().last_refill;
+
+        // a row exceeding the limit is admitted with a full bucket, putting the bucket into debt
+        assert!(limiter.try_acquire_at(250, start).is_ok());
+        assert_eq!(limiter.try_acquire_at(50, start), Err(Duration::from_secs(2)));
+        assert!(limiter.try_acquire_at(50, start + Duration::from_millis(1999)).is_err());
+        assert!(limiter.try_acquire_at(50, start + Duration::from_millis(2000)).is_ok());
+
+        let limiter = WriteRateLimiter::with_limits(None, Some(1_000_000)).unwrap();
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire(500_000);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        assert!(!limiter.try_acquire(500_000));
+    }
+}
@@ -0,0 +1,97 @@
+//! Read authorization: a table's ReadFilter sees every row a read returns and can suppress it
+//!  or mask some of its columns, based on the identity of the caller. There are no users yet,
+//!  so the caller is an opaque string passed to the Table's *_as read methods. The other read
+//!  methods pass None, so a filter that requires an identity must treat None as 'no access'.
+
+use crate::prelude::*;
+use crate::table::{ColumnId, DetachedRowData, PrimaryKeySpec, RowData};
+
+/// A ReadFilter's decision for a single row
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ReadDecision {
+    Allow,
+    /// the row is skipped as if it did not exist
+    Suppress,
+    /// the row is returned without the given columns. Primary key columns can not be masked.
+    Mask(Vec<ColumnId>),
+}
+
+pub trait ReadFilter: Send + Sync {
+    /// called with the merged row; returning an error fails the read
+    fn check(&self, caller: Option<&str>, row: &RowData) -> HtResult<ReadDecision>;
+}
+
+/// applies a filter's decision to a row, returning None if the row is suppressed
+pub fn apply_read_filter(filter: &dyn ReadFilter, caller: Option<&str>, row: DetachedRowData) -> HtResult<Option<DetachedRowData>> {
+    let view = row.row_data_view();
+    match filter.check(caller, &view)? {
+        ReadDecision::Allow => Ok(Some(row)),
+        ReadDecision::Suppress => Ok(None),
+        ReadDecision::Mask(masked) => {
+            let retained = view.schema().columns.iter()
+                .filter(|col| col.pk_spec == PrimaryKeySpec::Regular && !masked.contains(&col.col_id))
+                .map(|col| col.col_id)
+                .collect::<Vec<_>>();
+            Ok(Some(view.project(&retained)))
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::engine::Table;
+    use crate::prelude::*;
+    use crate::readfilter::{ReadDecision, ReadFilter};
+    use crate::slice::ClusteringBounds;
+    use crate::table::{ColumnId, ColumnValue, RowData};
+    use crate::testutils::{SimpleTableTestSetup, test_table_config};
+    use crate::time::HtClock;
+
+    /// callers see rows with even partition keys; only "admin" sees the 'text' column
+    struct EvenRowsFilter {}
+
+    impl ReadFilter for EvenRowsFilter {
+        fn check(&self, caller: Option<&str>, row: &RowData) -> HtResult<ReadDecision> {
+            match (caller, row.read_col_by_id(ColumnId(0)).and_then(|col| col.value)) {
+                (None, _) => Err(HtError::misc("no caller")),
+                (Some("admin"), _) => Ok(ReadDecision::Allow),
+                (_, Some(ColumnValue::BigInt(pk))) if pk % 2 == 0 => Ok(ReadDecision::Mask(vec!(ColumnId(0), ColumnId(1)))),
+                _ => Ok(ReadDecision::Suppress),
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_read_filter() {
+        let setup = SimpleTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let mut table = Table::new(&test_table_config(), &setup.schema, &clock).unwrap();
+        table.put(setup.full_row(1, Some("a"), Some(1))).unwrap();
+        table.put(setup.full_row(2, Some("b"), Some(2))).unwrap();
+        table.set_read_filter(Some(Box::new(EvenRowsFilter {})));
+
+        // rows compare by primary key only, so their bytes are compared
+        assert_eq!(table.get_as(&setup.pk_row(1), "admin").unwrap().unwrap().bytes(), setup.full_row(1, Some("a"), Some(1)).bytes());
+        assert!(table.get_as(&setup.pk_row(1), "user").unwrap().is_none());
+        // the primary key is not masked
+        let masked = setup.full_row(2, Some("b"), Some(2)).row_data_view().project(&[ColumnId(2)]);
+        let row = table.get_as(&setup.pk_row(2), "user").unwrap().unwrap();
+        assert_eq!(row.bytes(), masked.bytes());
+        assert!(row.row_data_view().read_col_by_id(ColumnId(1)).is_none());
+        assert_eq!(setup.int_value(&row.row_data_view()), 2);
+
+        assert!(table.get_partition_as(&setup.pk_row(1), &ClusteringBounds::all(), "user").unwrap().is_empty());
+        let rows = table.scan_token_range_as(0..=u64::MAX, "user").unwrap();
+        assert_eq!(rows.iter().map(|row| row.bytes()).collect::<Vec<_>>(), vec!(masked.bytes()));
+
+        // reads without a caller
+        assert!(table.get(&setup.pk_row(2)).is_err());
+
+        table.set_read_filter(None);
+        assert_eq!(table.get(&setup.pk_row(1)).unwrap().unwrap().bytes(), setup.full_row(1, Some("a"), Some(1)).bytes());
+
+        table.drop_table().unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::prelude::*;
+use crate::table::{ColumnType, ColumnValue, DetachedRowData, PrimaryKey, RowData, TableSchema, Varint};
+
+/// The rows returned by a query together with their schema, giving access to columns by name
+///  and with typed getters, so that callers need not work with RowData and column ids.
+pub struct ResultSet {
+    schema: Arc<TableSchema>,
+    rows: Vec<DetachedRowData>,
+    /// where the query stopped if it reached a limit and there may be more rows, to continue
+    ///  from in a subsequent query; None if the result is complete
+    pub paging_state: Option<PagingState>,
+    /// identifies the query's trace if it was traced. There is no query tracing yet.
+    pub tracing_id: Option<Uuid>,
+}
+
+/// Where a limited query stopped, see ResultSet::paging_state
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PagingState {
+    /// the primary key of the last returned row
+    pub last_key: PrimaryKey,
+    /// The number of rows returned for the last row's partition, including those on earlier
+    ///  pages of a scan, so that ScanLimits::per_partition_limit applies across pages
+    pub partition_rows: usize,
+}
+
+impl ResultSet {
+    pub fn new(schema: &Arc<TableSchema>, rows: Vec<DetachedRowData>) -> ResultSet {
+        ResultSet { schema: schema.clone(), rows, paging_state: None, tracing_id: None }
+    }
+
+    pub fn schema(&self) -> &Arc<TableSchema> {
+        &self.schema
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn row(&self, idx: usize) -> Option<Row<'_>> {
+        self.rows.get(idx).map(|row| Row { data: row.row_data_view() })
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item=Row<'_>> {
+        self.rows.iter().map(|row| Row { data: row.row_data_view() })
+    }
+
+    pub fn into_rows(self) -> Vec<DetachedRowData> {
+        self.rows
+    }
+}
+
+/// A row of a ResultSet. Getters fail for column names that are not part of the schema and
+///  for columns of a different type, and return None for NULL columns and columns that were
+///  never written.
+pub struct Row<'a> {
+    data: RowData<'a>,
+}
+
+impl <'a> Row<'a> {
+    pub fn row_data(&self) -> &RowData<'a> {
+        &self.data
+    }
+
+    pub fn get(&self, name: &str) -> HtResult<Option<ColumnValue<'_>>> {
+        let col = self.data.schema.column_by_name(name)?;
+        Ok(self.data.read_col_by_id(col.col_id).and_then(|c| c.value))
+    }
+
+    pub fn is_null(&self, name: &str) -> HtResult<bool> {
+        Ok(self.get(name)?.is_none())
+    }
+
+    fn typed<'s, T, F>(&'s self, name: &str, tpe: ColumnType, f: F) -> HtResult<Option<T>> where F: FnOnce(ColumnValue<'s>) -> Option<T> {
+        if self.data.schema.column_by_name(name)?.tpe != tpe {
+            return Err(HtError::misc("column has a different type"));
+        }
+        Ok(self.get(name)?.and_then(f))
+    }
+
+    pub fn get_boolean(&self, name: &str) -> HtResult<Option<bool>> {
+        self.typed(name, ColumnType::Boolean, |v| match v { ColumnValue::Boolean(b) => Some(b), _ => None })
+    }
+
+    pub fn get_int(&self, name: &str) -> HtResult<Option<i32>> {
+        self.typed(name, ColumnType::Int, |v| match v { ColumnValue::Int(i) => Some(i), _ => None })
+    }
+
+    pub fn get_bigint(&self, name: &str) -> HtResult<Option<i64>> {
+        self.typed(name, ColumnType::BigInt, |v| match v { ColumnValue::BigInt(i) => Some(i), _ => None })
+    }
+
+    pub fn get_text(&self, name: &str) -> HtResult<Option<&str>> {
+        self.typed(name, ColumnType::Text, |v| match v { ColumnValue::Text(s) => Some(s), _ => None })
+    }
+
+    /// days since Jan 1 1970, see ColumnValue::as_system_time
+    pub fn get_date(&self, name: &str) -> HtResult<Option<u32>> {
+        self.typed(name, ColumnType::Date, |v| match v { ColumnValue::Date(days) => Some(days), _ => None })
+    }
+
+    /// nanoseconds since midnight, see ColumnValue::as_duration
+    pub fn get_time(&self, name: &str) -> HtResult<Option<u64>> {
+        self.typed(name, ColumnType::Time, |v| match v { ColumnValue::Time(nanos) => Some(nanos), _ => None })
+    }
+
+    pub fn get_varint(&self, name: &str) -> HtResult<Option<Varint<'_>>> {
+        self.typed(name, ColumnType::Varint, |v| match v { ColumnValue::Varint(v) => Some(v), _ => None })
+    }
+
+    /// (months, days, nanos), see ColumnType::Duration
+    pub fn get_duration(&self, name: &str) -> HtResult<Option<(i32, i32, i64)>> {
+        self.typed(name, ColumnType::Duration, |v| match v { ColumnValue::Duration { months, days, nanos } => Some((months, days, nanos)), _ => None })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use uuid::Uuid;
+
+    use crate::resultset::ResultSet;
+    use crate::table::{Collation, ColumnData, ColumnId, ColumnSchema, ColumnType, ColumnValue, DetachedRowData, PrimaryKeySpec, TableSchema};
+    use crate::testutils::{SimpleTableTestSetup, test_table_config};
+    use crate::time::MergeTimestamp;
+
+    #[test]
+    pub fn test_result_set() {
+        let setup = SimpleTableTestSetup::new();
+        let rows = vec!(
+            setup.full_row(1, Some("a"), Some(10)),
+            setup.full_row(2, None, Some(20)),
+            setup.partial_row(3, Some("c")),
+        );
+        let result = ResultSet::new(&setup.schema, rows);
+        assert_eq!(result.len(), 3);
+        assert!(result.paging_state.is_none());
+
+        let row = result.row(0).unwrap();
+        assert_eq!(row.get_bigint("pk").unwrap(), Some(1));
+        assert_eq!(row.get_text("text").unwrap(), Some("a"));
+        assert_eq!(row.get_int("int").unwrap(), Some(10));
+        assert!(!row.is_null("text").unwrap());
+
+        // NULL and absent columns
+        assert_eq!(result.row(1).unwrap().get_text("text").unwrap(), None);
+        assert!(result.row(1).unwrap().is_null("text").unwrap());
+        assert_eq!(result.row(2).unwrap().get_int("int").unwrap(), None);
+
+        assert!(row.get("unknown").is_err());
+        assert!(row.get_bigint("text").is_err());
+        assert!(result.row(3).is_none());
+
+        assert_eq!(result.rows().map(|r| r.get_bigint("pk").unwrap().unwrap()).collect::<Vec<_>>(), vec!(1, 2, 3));
+        assert_eq!(result.into_rows().len(), 3);
+
+        test_table_config().remove_table_folder(&setup.schema).unwrap();
+    }
+
+    #[test]
+    pub fn test_get_duration() {
+        let column = |col_id: u8, name: &str, tpe: ColumnType, pk_spec: PrimaryKeySpec| ColumnSchema {
+            col_id: ColumnId(col_id),
+            name: name.to_string(),
+            tpe,
+            pk_spec,
+            collation: Collation::Binary,
+        };
+        let schema = Arc::new(TableSchema::new("durations", &Uuid::new_v4(), vec!(
+            column(0, "pk", ColumnType::BigInt, PrimaryKeySpec::PartitionKey),
+            column(1, "duration", ColumnType::Duration, PrimaryKeySpec::Regular),
+        )));
+
+        let ts = MergeTimestamp::from_ticks(1);
+        let row = |pk: i64, duration: Option<ColumnValue<'static>>| DetachedRowData::assemble(&schema, &vec!(
+            ColumnData::new(ColumnId(0), ts, None, Some(ColumnValue::BigInt(pk))),
+            ColumnData::new(ColumnId(1), ts, None, duration),
+        )).unwrap();
+        let result = ResultSet::new(&schema, vec!(
+            row(1, Some(ColumnValue::Duration { months: 1, days: -2, nanos: 3 })),
+            row(2, None),
+        ));
+
+        assert_eq!(result.row(0).unwrap().get_duration("duration").unwrap(), Some((1, -2, 3)));
+        assert_eq!(result.row(1).unwrap().get_duration("duration").unwrap(), None);
+        assert!(result.row(0).unwrap().get_duration("pk").is_err());
+    }
+}
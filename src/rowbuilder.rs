@@ -0,0 +1,130 @@
+use std::sync::Arc;
+
+use crate::prelude::*;
+use crate::table::{ColumnData, ColumnId, ColumnValue, DetachedRowData, TableSchema};
+use crate::time::{MergeTimestamp, TtlTimestamp};
+
+/// Builds a row for writing, distinguishing the two meanings of 'no value' for a regular column:
+/// * set_null stores a NULL, i.e. it deletes the column's previous value
+/// * a column that is neither set nor set to NULL is not part of the row, and writing the row
+///   leaves the column's previous value unchanged
+///
+/// All primary key columns must be set.
+pub struct RowBuilder<'a> {
+    schema: Arc<TableSchema>,
+    timestamp: MergeTimestamp,
+    expiry: Option<TtlTimestamp>,
+    columns: Vec<ColumnData<'a>>,
+}
+
+impl <'a> RowBuilder<'a> {
+    pub fn new(schema: &Arc<TableSchema>, timestamp: MergeTimestamp) -> RowBuilder<'a> {
+        RowBuilder {
+            schema: schema.clone(),
+            timestamp,
+            expiry: None,
+            columns: Vec::new(),
+        }
+    }
+
+    /// an expiry for all regular columns that are set to a value
+    pub fn with_expiry(mut self, expiry: TtlTimestamp) -> RowBuilder<'a> {
+        self.expiry = Some(expiry);
+        self
+    }
+
+    pub fn set(mut self, col_id: ColumnId, value: ColumnValue<'a>) -> RowBuilder<'a> {
+        self.columns.push(ColumnData::new(col_id, self.timestamp, None, Some(value)));
+        self
+    }
+
+    /// deletes the column's value when the row is written
+    pub fn set_null(mut self, col_id: ColumnId) -> RowBuilder<'a> {
+        self.columns.push(ColumnData::new(col_id, self.timestamp, None, None));
+        self
+    }
+
+    /// Checks the columns against the schema and encodes the row. This fails for unknown
+    ///  columns, values not matching the column type, columns that were set more than once,
+    ///  and missing or NULL primary key columns.
+    pub fn build(self) -> HtResult<DetachedRowData> {
+        let mut col_ids = self.columns.iter().map(|c| c.col_id).collect::<Vec<_>>();
+        col_ids.sort();
+        col_ids.dedup();
+        if col_ids.len() != self.columns.len() {
+            return Err(HtError::misc("column is set more than once"));
+        }
+
+        let mut columns = Vec::new();
+        for pk_col in &self.schema.pk_columns {
+            match self.columns.iter().find(|c| c.col_id == pk_col.col_id) {
+                Some(col) if col.value.is_some() => columns.push(ColumnData::new(col.col_id, col.timestamp, None, col.value)),
+                Some(_) => return Err(HtError::misc("primary key columns must not be NULL")),
+                None => return Err(HtError::misc("primary key is incomplete")),
+            }
+        }
+
+        let mut regular = self.columns.iter()
+            .filter(|c| !self.schema.is_primary_key(c.col_id))
+            .map(|c| ColumnData::new(c.col_id, c.timestamp, c.value.and(self.expiry), c.value))
+            .collect::<Vec<_>>();
+        regular.sort_by_key(|c| c.col_id);
+        columns.extend(regular);
+
+        for col in &columns {
+            let col_schema = self.schema.column(col.col_id)?;
+            if let Some(value) = &col.value {
+                if !col_schema.tpe.matches(value) {
+                    return Err(HtError::misc("column value does not match the column type"));
+                }
+                value.validate()?;
+            }
+        }
+
+        DetachedRowData::assemble(&self.schema, &columns)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rowbuilder::RowBuilder;
+    use crate::table::{ColumnId, ColumnValue};
+    use crate::testutils::SimpleTableTestSetup;
+    use crate::time::{HtClock, MergeTimestamp, TtlTimestamp};
+
+    #[test]
+    pub fn test_row_builder() {
+        let setup = SimpleTableTestSetup::new();
+        let stored = setup.full_row(1, Some("a"), Some(2));
+
+        // 'int' is left out, so it keeps its value, while 'text' is deleted
+        let update = RowBuilder::new(&setup.schema, MergeTimestamp::from_ticks(setup.clock.now().ticks + 1))
+            .set_null(ColumnId(1))
+            .set(ColumnId(0), ColumnValue::BigInt(1))
+            .build().unwrap();
+        let merged = stored.row_data_view().merge(&update.row_data_view());
+        let merged = merged.row_data_view();
+        assert_eq!(merged.read_col_by_id(ColumnId(1)).unwrap().value, None);
+        assert_eq!(merged.read_col_by_id(ColumnId(2)).unwrap().value, Some(ColumnValue::Int(2)));
+
+        let row = RowBuilder::new(&setup.schema, MergeTimestamp::from_ticks(5))
+            .with_expiry(TtlTimestamp::new(7))
+            .set(ColumnId(2), ColumnValue::Int(3))
+            .set(ColumnId(0), ColumnValue::BigInt(1))
+            .set_null(ColumnId(1))
+            .build().unwrap();
+        let row = row.row_data_view();
+        assert!(row.validate().is_ok());
+        assert_eq!(row.columns().map(|c| c.col_id).collect::<Vec<_>>(), vec!(ColumnId(0), ColumnId(1), ColumnId(2)));
+        assert_eq!(row.read_col_by_id(ColumnId(1)).unwrap().expiry, None);
+        assert_eq!(row.read_col_by_id(ColumnId(2)).unwrap().expiry, Some(TtlTimestamp::new(7)));
+
+        let builder = || RowBuilder::new(&setup.schema, setup.clock.now());
+        assert!(builder().set(ColumnId(1), ColumnValue::Text("a")).build().is_err());
+        assert!(builder().set_null(ColumnId(0)).build().is_err());
+        assert!(builder().set(ColumnId(0), ColumnValue::Int(1)).build().is_err());
+        assert!(builder().set(ColumnId(0), ColumnValue::BigInt(1)).set(ColumnId(9), ColumnValue::Int(1)).build().is_err());
+        assert!(builder().set(ColumnId(0), ColumnValue::BigInt(1)).set(ColumnId(2), ColumnValue::Int(1)).set_null(ColumnId(2)).build().is_err());
+        assert!(builder().set(ColumnId(0), ColumnValue::BigInt(1)).set(ColumnId(0), ColumnValue::BigInt(2)).build().is_err());
+    }
+}
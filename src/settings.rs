@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read};
+use std::sync::Arc;
+
+use crate::config::{CompactionStrategyConfig, StorageMode, TableConfig};
+use crate::manifest::write_atomically;
+use crate::prelude::*;
+use crate::table::TableSchema;
+
+/// the settings that can be changed while a table is open, see Table::set_setting
+pub const RUNTIME_SETTINGS: &[&str] = &["gc_grace_seconds", "compaction_strategy", "key_cache_size", "default_ttl_seconds"];
+
+/// Settings that were changed at runtime, overriding the TableConfig a table is opened with.
+///  They are stored in the table's folder in the base folder, so they survive a restart.
+///  In-memory tables have no folder, so their overrides last until the table is dropped.
+///
+/// The system settings table (see SystemTables::settings) is an in-memory description that is
+///  rebuilt from the tables' configs on refresh, so it can not hold the overrides. It shows
+///  them though, since a table's config includes its overrides.
+///
+/// Values are in the format accepted by Table::set_setting. compaction_strategy is either
+///  'size_tiered' or 'time_window <window_seconds>', each with the default thresholds.
+///  default_ttl_seconds is either a number of seconds or 'none'.
+///
+/// settings format: one line per overridden setting, containing its name and value separated
+///  by a single space
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SettingsOverrides {
+    pub entries: BTreeMap<String, String>,
+}
+
+impl SettingsOverrides {
+    const FILE_NAME: &'static str = "settings";
+    const TMP_FILE_NAME: &'static str = "settings.tmp";
+
+    /// reads a table's overrides, returning no overrides if there are none yet
+    pub fn read(config: &TableConfig, schema: &TableSchema) -> HtResult<SettingsOverrides> {
+        let mut buf = String::new();
+        match config.new_file(&config.table_folder(schema), SettingsOverrides::FILE_NAME, false) {
+            Ok(mut file) => file.read_to_string(&mut buf)?,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(SettingsOverrides::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut entries = BTreeMap::new();
+        for line in buf.lines().filter(|l| !l.is_empty()) {
+            let mut parts = line.splitn(2, ' ');
+            let name = parts.next().unwrap();
+            let value = parts.next()
+                .ok_or_else(|| HtError::misc("missing value in settings"))?;
+            entries.insert(name.to_string(), value.to_string());
+        }
+        Ok(SettingsOverrides { entries })
+    }
+
+    /// Replaces the table's overrides, writing to a temporary file which is then renamed like
+    ///  the manifest
+    pub fn write(&self, config: &TableConfig, schema: &TableSchema) -> HtResult<()> {
+        let folder = config.table_folder(schema);
+
+        let mut buf = String::new();
+        for (name, value) in &self.entries {
+            buf.push_str(&format!("{} {}\n", name, value));
+        }
+
+        write_atomically(&folder, SettingsOverrides::FILE_NAME, SettingsOverrides::TMP_FILE_NAME, buf.as_bytes())
+    }
+
+    /// the given config with the overrides applied
+    pub fn apply(&self, config: &TableConfig, schema: &TableSchema) -> HtResult<TableConfig> {
+        let mut result = config.clone();
+        for (name, value) in &self.entries {
+            apply_setting(&mut result, name, value)?;
+        }
+        result.validate(schema)?;
+        Ok(result)
+    }
+
+    /// a persistent table's config with the overrides stored in its folder applied
+    pub fn with_stored(config: &Arc<TableConfig>, schema: &TableSchema) -> HtResult<Arc<TableConfig>> {
+        if config.storage_mode != StorageMode::Persistent {
+            return Ok(config.clone());
+        }
+
+        let overrides = SettingsOverrides::read(config, schema)?;
+        if overrides.entries.is_empty() {
+            return Ok(config.clone());
+        }
+        Ok(Arc::new(overrides.apply(config, schema)?))
+    }
+}
+
+/// sets one of the RUNTIME_SETTINGS in a config, parsing its value
+pub fn apply_setting(config: &mut TableConfig, name: &str, value: &str) -> HtResult<()> {
+    let invalid = || HtError::misc("invalid setting value");
+
+    match name {
+        "gc_grace_seconds" => config.gc_grace_seconds = value.parse().map_err(|_| invalid())?,
+        "key_cache_size" => config.key_cache_size = value.parse().map_err(|_| invalid())?,
+        "default_ttl_seconds" => config.default_ttl_seconds = match value {
+            "none" => None,
+            seconds => Some(seconds.parse().map_err(|_| invalid())?),
+        },
+        "compaction_strategy" => {
+            let mut parts = value.split(' ');
+            config.compaction_strategy = match (parts.next(), parts.next(), parts.next()) {
+                (Some("size_tiered"), None, _) => CompactionStrategyConfig::size_tiered(),
+                (Some("time_window"), Some(window_seconds), None) => CompactionStrategyConfig::TimeWindow {
+                    window_seconds: window_seconds.parse().map_err(|_| invalid())?,
+                    min_threshold: 4,
+                    max_threshold: 32,
+                },
+                _ => return Err(invalid()),
+            };
+        },
+        _ => return Err(HtError::misc("setting can not be changed at runtime")),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::config::{CompactionStrategyConfig, TableConfig};
+    use crate::settings::{apply_setting, SettingsOverrides};
+    use crate::testutils::{SimpleTableTestSetup, test_table_config};
+
+    #[test]
+    pub fn test_apply_setting() {
+        let mut config = TableConfig::new(PathBuf::new());
+
+        apply_setting(&mut config, "gc_grace_seconds", "17").unwrap();
+        apply_setting(&mut config, "key_cache_size", "0").unwrap();
+        apply_setting(&mut config, "compaction_strategy", "time_window 3600").unwrap();
+        assert_eq!(config.gc_grace_seconds, 17);
+        assert_eq!(config.key_cache_size, 0);
+        assert_eq!(config.compaction_strategy, CompactionStrategyConfig::TimeWindow { window_seconds: 3600, min_threshold: 4, max_threshold: 32 });
+
+        apply_setting(&mut config, "compaction_strategy", "size_tiered").unwrap();
+        assert_eq!(config.compaction_strategy, CompactionStrategyConfig::size_tiered());
+
+        apply_setting(&mut config, "default_ttl_seconds", "3600").unwrap();
+        assert_eq!(config.default_ttl_seconds, Some(3600));
+        apply_setting(&mut config, "default_ttl_seconds", "none").unwrap();
+        assert_eq!(config.default_ttl_seconds, None);
+        assert!(apply_setting(&mut config, "default_ttl_seconds", "-5").is_err());
+
+        assert!(apply_setting(&mut config, "gc_grace_seconds", "-1").is_err());
+        assert!(apply_setting(&mut config, "compaction_strategy", "time_window").is_err());
+        assert!(apply_setting(&mut config, "compaction_strategy", "size_tiered 4").is_err());
+        assert!(apply_setting(&mut config, "storage_mode", "InMemory").is_err());
+        assert_eq!(config.gc_grace_seconds, 17);
+    }
+
+    #[test]
+    pub fn test_write_read() {
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+
+        assert_eq!(SettingsOverrides::read(&config, &setup.schema).unwrap(), SettingsOverrides::default());
+
+        let mut overrides = SettingsOverrides::default();
+        overrides.entries.insert("gc_grace_seconds".to_string(), "5".to_string());
+        overrides.entries.insert("compaction_strategy".to_string(), "time_window 60".to_string());
+        overrides.write(&config, &setup.schema).unwrap();
+
+        let read = SettingsOverrides::read(&config, &setup.schema).unwrap();
+        assert_eq!(read, overrides);
+        assert_eq!(read.apply(&config, &setup.schema).unwrap().gc_grace_seconds, 5);
+
+        config.remove_table_folder(&setup.schema).unwrap();
+    }
+}
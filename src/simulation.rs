@@ -0,0 +1,117 @@
+//! Deterministic simulation of table workloads: a seeded random sequence of writes, flushes,
+//!  compactions and reads runs against a Table with a ManualClock, and every read is checked
+//!  against a simple model of the expected data. The engine has no background threads, so a
+//!  given seed always produces the same run, and a failure can be reproduced from its seed.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::config::TableConfig;
+use crate::engine::Table;
+use crate::table::ColumnId;
+use crate::testutils::{SimpleTableTestSetup, test_table_config};
+use crate::time::{HtClock, MergeTimestamp};
+
+/// xorshift64*, good enough for generating workloads and fully determined by its seed
+pub struct SimRng {
+    state: u64,
+}
+
+impl SimRng {
+    pub fn new(seed: u64) -> SimRng {
+        SimRng { state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// a number in 0..n
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+}
+
+const VALUES: &[&str] = &["a", "b", "c", "d", "e"];
+const NUM_PKS: u64 = 20;
+
+/// Runs a workload for a given seed, panicking with the seed and the operation's index if a
+///  read returns unexpected data. The model holds the latest text value per primary key, None
+///  standing for a tombstone.
+pub fn simulate(seed: u64, num_ops: usize) {
+    let setup = SimpleTableTestSetup::new();
+    let clock: Arc<dyn HtClock> = setup.clock.clone();
+    let config = Arc::new(TableConfig {
+        max_sstable_data_size: 200,
+        ..TableConfig::new(test_table_config().base_folder.clone())
+    });
+    let table = Table::new(&config, &setup.schema, &clock).unwrap();
+
+    let mut rng = SimRng::new(seed);
+    let mut model: BTreeMap<i64, Option<&'static str>> = BTreeMap::new();
+
+    let check = |table: &Table, model: &BTreeMap<i64, Option<&'static str>>, pk: i64, op: usize| {
+        let actual = table.get(&setup.pk_row(pk)).unwrap();
+        let actual = actual.as_ref().map(|row| {
+            let view = row.row_data_view();
+            view.read_col_by_id(ColumnId(1)).and_then(|col| col.value).map(|_| setup.value(&view).to_string())
+        });
+        let expected = model.get(&pk).map(|v| v.map(|s| s.to_string()));
+        assert_eq!(actual, expected, "seed {}, op {}, pk {}", seed, op, pk);
+    };
+
+    for op in 0..num_ops {
+        setup.clock.set(MergeTimestamp::from_ticks(setup.clock.now().ticks + 1));
+
+        let pk = rng.below(NUM_PKS) as i64;
+        match rng.below(100) {
+            0..=49 => {
+                let value = match rng.below(4) {
+                    0 => None,
+                    _ => Some(VALUES[rng.below(VALUES.len() as u64) as usize]),
+                };
+                table.put(setup.full_row(pk, value, None)).unwrap();
+                model.insert(pk, value);
+            }
+            50..=59 => {
+                let value = VALUES[rng.below(VALUES.len() as u64) as usize];
+                table.put(setup.partial_row(pk, Some(value))).unwrap();
+                model.insert(pk, Some(value));
+            }
+            60..=69 => table.flush().unwrap(),
+            70..=74 => { table.compact().unwrap(); }
+            75..=77 => table.compact_all().unwrap(),
+            _ => check(&table, &model, pk, op),
+        }
+    }
+
+    for pk in 0..NUM_PKS as i64 {
+        check(&table, &model, pk, num_ops);
+    }
+    table.drop_table().unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use crate::simulation::{simulate, SimRng};
+
+    #[test]
+    pub fn test_rng_is_deterministic() {
+        let mut a = SimRng::new(17);
+        let mut b = SimRng::new(17);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(SimRng::new(17).next_u64(), SimRng::new(18).next_u64());
+    }
+
+    #[test]
+    pub fn test_simulate() {
+        for seed in 0..20 {
+            simulate(seed, 300);
+        }
+    }
+}
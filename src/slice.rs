@@ -0,0 +1,206 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use crate::prelude::*;
+use crate::table::{ColumnData, ColumnValue, DetachedRowData, PrimaryKeySpec, RowData, TableSchema};
+use crate::time::MergeTimestamp;
+use crate::tombstones::PartialClusterKey;
+
+/// One end of a range of rows in a partition: values for the leading cluster key columns
+pub struct ClusteringBound<'a> {
+    pub values: Vec<ColumnValue<'a>>,
+    pub inclusive: bool,
+}
+
+impl <'a> ClusteringBound<'a> {
+    pub fn inclusive(values: Vec<ColumnValue<'a>>) -> ClusteringBound<'a> {
+        ClusteringBound { values, inclusive: true }
+    }
+
+    pub fn exclusive(values: Vec<ColumnValue<'a>>) -> ClusteringBound<'a> {
+        ClusteringBound { values, inclusive: false }
+    }
+}
+
+/// Restricts a partition read to a range of rows, e.g. "rows with a timestamp between A and B".
+///
+/// Bounds are compared in clustering order, so for a descending cluster key column the lower
+///  bound holds the larger value. A bound can have fewer values than there are cluster key
+///  columns, and it then applies to all rows starting with these values: an inclusive upper
+///  bound (3) includes the row (3, "x"), an exclusive one does not.
+#[derive(Default)]
+pub struct ClusteringBounds<'a> {
+    pub lower: Option<ClusteringBound<'a>>,
+    pub upper: Option<ClusteringBound<'a>>,
+}
+
+impl <'a> ClusteringBounds<'a> {
+    /// the entire partition
+    pub fn all() -> ClusteringBounds<'a> {
+        ClusteringBounds::default()
+    }
+
+    pub fn new(lower: Option<ClusteringBound<'a>>, upper: Option<ClusteringBound<'a>>) -> ClusteringBounds<'a> {
+        ClusteringBounds { lower, upper }
+    }
+
+    /// checks that the bounds' values are a prefix of the schema's cluster key
+    pub fn validate(&self, schema: &TableSchema) -> HtResult<()> {
+        let cluster_columns = schema.pk_columns.iter()
+            .filter(|col| col.pk_spec != PrimaryKeySpec::PartitionKey)
+            .collect::<Vec<_>>();
+
+        for bound in self.lower.iter().chain(self.upper.iter()) {
+            if bound.values.len() > cluster_columns.len() {
+                return Err(HtError::misc("clustering bound has more values than there are cluster key columns"));
+            }
+            for (col, value) in cluster_columns.iter().zip(&bound.values) {
+                if !col.tpe.matches(value) {
+                    return Err(HtError::misc("clustering bound value does not match the column type"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// where a row is relative to a PartitionSlice
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SlicePosition {
+    Before,
+    Inside,
+    After,
+}
+
+/// ClusteringBounds applied to a specific partition, with the bounds encoded as
+///  PartialClusterKeys so that rows can be checked against them without detaching them
+pub struct PartitionSlice {
+    schema: Arc<TableSchema>,
+    partition_key: Vec<u8>,
+    lower: Option<(Vec<u8>, bool)>,
+    upper: Option<(Vec<u8>, bool)>,
+    seek_row: DetachedRowData,
+}
+
+impl PartitionSlice {
+    /// pk_data is a row containing (at least) the partition key
+    pub fn new(schema: &Arc<TableSchema>, pk_data: &RowData, bounds: &ClusteringBounds) -> HtResult<PartitionSlice> {
+        bounds.validate(schema)?;
+
+        let mut partition_columns = Vec::new();
+        for col in schema.pk_columns.iter().filter(|col| col.pk_spec == PrimaryKeySpec::PartitionKey) {
+            match pk_data.read_col_by_id(col.col_id) {
+                Some(col_data) if col_data.value.is_some() => partition_columns.push(col_data),
+                _ => return Err(HtError::misc("partition key is incomplete")),
+            }
+        }
+        let partition_key = pk_data.partition_key_bytes();
+
+        let encode = |bound: &ClusteringBound| {
+            let mut buf = partition_key.clone();
+            for value in &bound.values {
+                DetachedRowData::encode_column_value(&mut buf, value);
+            }
+            (buf, bound.inclusive)
+        };
+
+        // the partition key followed by the lower bound's values, which sorts before all rows
+        //  of the slice (see RowData::compare_by_pk)
+        let mut seek_columns = partition_columns;
+        if let Some(lower) = &bounds.lower {
+            let cluster_columns = schema.pk_columns.iter()
+                .filter(|col| col.pk_spec != PrimaryKeySpec::PartitionKey);
+            for (col, value) in cluster_columns.zip(&lower.values) {
+                seek_columns.push(ColumnData::new(col.col_id, MergeTimestamp::from_ticks(0), None, Some(*value)));
+            }
+        }
+
+        Ok(PartitionSlice {
+            schema: schema.clone(),
+            lower: bounds.lower.as_ref().map(encode),
+            upper: bounds.upper.as_ref().map(encode),
+            partition_key,
+            seek_row: DetachedRowData::assemble(schema, &seek_columns)?,
+        })
+    }
+
+    /// a row that sorts before all rows of the slice, for seeking to its start
+    pub fn seek_row(&self) -> &DetachedRowData {
+        &self.seek_row
+    }
+
+    /// Checks a row's position relative to the slice. This assumes the row does not sort
+    ///  before the partition's start, i.e. rows of other partitions are After.
+    pub fn position(&self, row: &RowData) -> SlicePosition {
+        if row.partition_key_bytes() != self.partition_key {
+            return SlicePosition::After;
+        }
+
+        if let Some((buf, inclusive)) = &self.lower {
+            match PartialClusterKey::new(&self.schema, buf).compare_to(row) {
+                Ordering::Greater => return SlicePosition::Before,
+                Ordering::Equal if !inclusive => return SlicePosition::Before,
+                _ => {}
+            }
+        }
+
+        if let Some((buf, inclusive)) = &self.upper {
+            match PartialClusterKey::new(&self.schema, buf).compare_to(row) {
+                Ordering::Less => return SlicePosition::After,
+                Ordering::Equal if !inclusive => return SlicePosition::After,
+                _ => {}
+            }
+        }
+
+        SlicePosition::Inside
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::slice::{ClusteringBound, ClusteringBounds, PartitionSlice, SlicePosition};
+    use crate::table::ColumnValue;
+    use crate::testutils::ClusteredTableTestSetup;
+
+    #[test]
+    pub fn test_validate() {
+        let setup = ClusteredTableTestSetup::new();
+
+        assert!(ClusteringBounds::all().validate(&setup.schema).is_ok());
+        assert!(ClusteringBounds::new(Some(ClusteringBound::inclusive(vec!(ColumnValue::Int(1), ColumnValue::Int(2)))), None)
+            .validate(&setup.schema).is_ok());
+        assert!(ClusteringBounds::new(None, Some(ClusteringBound::inclusive(vec!(ColumnValue::Int(1), ColumnValue::Int(2), ColumnValue::Int(3)))))
+            .validate(&setup.schema).is_err());
+        assert!(ClusteringBounds::new(Some(ClusteringBound::exclusive(vec!(ColumnValue::Text("a")))), None)
+            .validate(&setup.schema).is_err());
+    }
+
+    #[test]
+    pub fn test_position() {
+        let setup = ClusteredTableTestSetup::new();
+
+        // seq is descending, so (5, 3) comes before (5, 2)
+        let bounds = ClusteringBounds::new(
+            Some(ClusteringBound::exclusive(vec!(ColumnValue::Int(5), ColumnValue::Int(3)))),
+            Some(ClusteringBound::inclusive(vec!(ColumnValue::Int(7)))),
+        );
+        let slice = PartitionSlice::new(&setup.schema, &setup.partition_row(1).row_data_view(), &bounds).unwrap();
+
+        let position = |pk: i64, ts: i32, seq: i32| slice.position(&setup.row(pk, ts, seq, None).row_data_view());
+        assert_eq!(position(1, 4, 0), SlicePosition::Before);
+        assert_eq!(position(1, 5, 4), SlicePosition::Before);
+        assert_eq!(position(1, 5, 3), SlicePosition::Before);
+        assert_eq!(position(1, 5, 2), SlicePosition::Inside);
+        assert_eq!(position(1, 7, 100), SlicePosition::Inside);
+        assert_eq!(position(1, 7, -100), SlicePosition::Inside);
+        assert_eq!(position(1, 8, 0), SlicePosition::After);
+        assert_eq!(position(2, 6, 0), SlicePosition::After);
+
+        // the seek row sorts before all rows of the slice
+        let seek_row = slice.seek_row().row_data_view();
+        assert!(seek_row.compare_by_pk(&setup.row(1, 5, 3, None).row_data_view()).is_le());
+        assert!(seek_row.compare_by_pk(&setup.row(1, 5, 4, None).row_data_view()).is_gt());
+        assert!(PartitionSlice::new(&setup.schema, &setup.partition_row(1).row_data_view(), &ClusteringBounds::all()).unwrap()
+            .seek_row().row_data_view().compare_by_pk(&setup.row(1, -1000, 1000, None).row_data_view()).is_lt());
+    }
+}
@@ -0,0 +1,180 @@
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use crate::config::TableConfig;
+use crate::sstable::SsTableName;
+use crate::table::{DetachedRowData, RowData};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum QueryKind {
+    Get,
+    GetPartition,
+    Put,
+    Scan,
+    IndexLookup,
+}
+
+/// A read or write that took longer than TableConfig::slow_query_threshold
+#[derive(Clone, Debug)]
+pub struct SlowQuery {
+    pub table: String,
+    pub kind: QueryKind,
+    /// the primary key (or the partition key for partition reads), see describe_key
+    pub key: String,
+    pub duration: Duration,
+    /// the SsTables that were read
+    pub ss_tables: Vec<SsTableName>,
+    /// the time spent in the query's phases, e.g. reading MemTables or a specific SsTable
+    pub timings: Vec<(String, Duration)>,
+}
+
+pub trait SlowQueryCallback: Send + Sync {
+    fn on_slow_query(&self, query: &SlowQuery);
+}
+
+/// the default SlowQueryCallback, logging slow queries as warnings
+pub struct LogSlowQueries {}
+
+impl SlowQueryCallback for LogSlowQueries {
+    fn on_slow_query(&self, query: &SlowQuery) {
+        warn!("slow {:?} on {}: {} took {:?}, SsTables {:?}, timings {:?}",
+              query.kind, query.table, query.key, query.duration, query.ss_tables, query.timings);
+    }
+}
+
+/// Collects the timings of a single query. It does nothing if there is no slow query threshold,
+///  so queries do not pay for taking timings that are never reported.
+pub struct QueryTimer {
+    threshold: Option<Duration>,
+    start: Instant,
+    phase_start: Instant,
+    ss_tables: Vec<SsTableName>,
+    timings: Vec<(String, Duration)>,
+}
+
+impl QueryTimer {
+    pub fn start(config: &TableConfig) -> QueryTimer {
+        let now = Instant::now();
+        QueryTimer {
+            threshold: config.slow_query_threshold,
+            start: now,
+            phase_start: now,
+            ss_tables: Vec::new(),
+            timings: Vec::new(),
+        }
+    }
+
+    /// ends the current phase, recording the time since the previous phase ended
+    pub fn phase(&mut self, name: &str) {
+        if self.threshold.is_some() {
+            let now = Instant::now();
+            self.timings.push((name.to_string(), now - self.phase_start));
+            self.phase_start = now;
+        }
+    }
+
+    /// ends a phase that read an SsTable
+    pub fn ss_table(&mut self, name: &SsTableName) {
+        if self.threshold.is_some() {
+            self.ss_tables.push(name.clone());
+            self.phase(&name.to_string());
+        }
+    }
+
+    /// Reports the query if it exceeded the threshold. key_row is a row containing (at least)
+    ///  the query's key.
+    pub fn finish(self, config: &TableConfig, callback: &dyn SlowQueryCallback, kind: QueryKind, key_row: &DetachedRowData) {
+        let threshold = match self.threshold {
+            Some(threshold) => threshold,
+            None => return,
+        };
+
+        let duration = self.start.elapsed();
+        if duration >= threshold {
+            let key_row = key_row.row_data_view();
+            callback.on_slow_query(&SlowQuery {
+                table: key_row.schema().name.clone(),
+                kind,
+                key: describe_key(&key_row, config.slow_query_hash_keys),
+                duration,
+                ss_tables: self.ss_tables,
+                timings: self.timings,
+            });
+        }
+    }
+}
+
+/// The values of a row's primary key columns, e.g. '[BigInt(1), Text("a")]', or a hash of
+///  them so that slow query logs do not contain user data.
+pub fn describe_key(row: &RowData, hash: bool) -> String {
+    let values = row.columns()
+        .filter(|col| row.schema().is_primary_key(col.col_id))
+        .filter_map(|col| col.value)
+        .collect::<Vec<_>>();
+
+    if hash {
+        let mut buf = Vec::new();
+        for value in &values {
+            DetachedRowData::encode_column_value(&mut buf, value);
+        }
+        format!("#{:016x}", seahash::hash(&buf))
+    }
+    else {
+        format!("{:?}", values)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use crate::config::TableConfig;
+    use crate::slowlog::{describe_key, QueryKind, QueryTimer};
+    use crate::sstable::SsTableName;
+    use crate::testutils::{CollectSlowQueries, SimpleTableTestSetup, test_table_config};
+
+    #[test]
+    pub fn test_query_timer() {
+        let setup = SimpleTableTestSetup::new();
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        let callback = CollectSlowQueries { queries: queries.clone() };
+        let row = setup.full_row(1, Some("a"), None);
+
+        let config = TableConfig {
+            slow_query_threshold: Some(Duration::from_millis(10)),
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        };
+
+        QueryTimer::start(&config).finish(&config, &callback, QueryKind::Get, &row);
+        assert!(queries.lock().unwrap().is_empty());
+
+        let mut timer = QueryTimer::start(&config);
+        timer.phase("memtables");
+        std::thread::sleep(Duration::from_millis(10));
+        timer.ss_table(&SsTableName::new("test_table", 7));
+        timer.finish(&config, &callback, QueryKind::Get, &row);
+
+        let queries = queries.lock().unwrap();
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0].kind, QueryKind::Get);
+        assert_eq!(queries[0].key, "[BigInt(1)]");
+        assert!(queries[0].duration >= Duration::from_millis(10));
+        assert_eq!(queries[0].ss_tables, vec!(SsTableName::new("test_table", 7)));
+        assert_eq!(queries[0].timings.iter().map(|t| t.0.as_str()).collect::<Vec<_>>(), vec!("memtables", "test_table-7"));
+        assert!(queries[0].timings[1].1 >= Duration::from_millis(10));
+    }
+
+    #[test]
+    pub fn test_describe_key() {
+        let setup = SimpleTableTestSetup::new();
+        let row = setup.full_row(1, Some("a"), Some(2));
+        assert_eq!(describe_key(&row.row_data_view(), false), "[BigInt(1)]");
+
+        let hashed = describe_key(&row.row_data_view(), true);
+        assert_eq!(hashed.len(), 17);
+        assert_eq!(hashed, describe_key(&setup.pk_row(1).row_data_view(), true));
+        assert_ne!(hashed, describe_key(&setup.pk_row(2).row_data_view(), true));
+    }
+}
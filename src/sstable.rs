@@ -1,80 +1,461 @@
 use std::cmp::Ordering;
-use std::io::{Seek, SeekFrom, Write};
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind, Read, Write};
 use std::mem::size_of;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
 use std::slice::from_raw_parts;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
 
+use log::warn;
 use memmap::{Mmap, MmapOptions};
 
-use crate::config::TableConfig;
+use crate::blockcache::BlockCache;
+use crate::bloom::BloomFilter;
+use crate::columnindex::{ColumnIndex, ColumnIndexCollector};
+use crate::config::{AccessAdvice, TableConfig};
+use crate::keycache::KeyCache;
+use crate::manifest::sync_folder;
 use crate::prelude::*;
 use crate::primitives::*;
+use crate::quarantine::Quarantine;
+use crate::stats::{SsTableStats, StatsCollector};
 use crate::table::*;
+use crate::time::MergeTimestamp;
 
-struct SsTable {
+#[cfg(unix)]
+fn advise_mmap(mmap: &Mmap, advice: AccessAdvice) -> HtResult<()> {
+    if mmap.is_empty() {
+        return Ok(());
+    }
+
+    let advice = match advice {
+        AccessAdvice::Normal => libc::MADV_NORMAL,
+        AccessAdvice::Random => libc::MADV_RANDOM,
+        AccessAdvice::Sequential => libc::MADV_SEQUENTIAL,
+        AccessAdvice::WillNeed => libc::MADV_WILLNEED,
+        AccessAdvice::DontNeed => libc::MADV_DONTNEED,
+    };
+    if unsafe { libc::madvise(mmap.as_ptr() as *mut libc::c_void, mmap.len(), advice) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn advise_mmap(_mmap: &Mmap, _advice: AccessAdvice) -> HtResult<()> {
+    Ok(())
+}
+
+/// reads a byte of each page, so that the pages are mapped before queries access them
+fn prefault_mmap(mmap: &Mmap) {
+    const PAGE_SIZE: usize = 4096;
+
+    let mut sum = 0u8;
+    for offs in (0..mmap.len()).step_by(PAGE_SIZE) {
+        sum = sum.wrapping_add(unsafe { std::ptr::read_volatile(mmap.as_ptr().add(offs)) });
+    }
+    std::hint::black_box(sum);
+}
+
+/// Locks a memory map's pages in RAM. This is limited by RLIMIT_MEMLOCK, so a failure is
+///  logged rather than failing the SsTable. The lock is released when the map is dropped.
+#[cfg(unix)]
+fn lock_mmap(mmap: &Mmap, name: &SsTableName) {
+    if mmap.is_empty() {
+        return;
+    }
+    if unsafe { libc::mlock(mmap.as_ptr() as *const libc::c_void, mmap.len()) } != 0 {
+        warn!("failed to lock the index of SsTable {} in memory: {}", name, std::io::Error::last_os_error());
+    }
+}
+
+#[cfg(not(unix))]
+fn lock_mmap(_mmap: &Mmap, _name: &SsTableName) {
+}
+
+/// An immutable, sorted file based set of rows.
+///
+/// data file format:
+///   fixed u64         timestamp base (MergeTimestamp) - rows are stored with
+///                      RowFlags::DELTA_TIMESTAMPS relative to this base
+///   rows              each preceded by its length, see RowData
+pub struct SsTable {
     schema: Arc<TableSchema>,
     index_mmap: Mmap,
     data_mmap: Mmap,
-    name_base: String,
+    /// for positioned reads, see read_by_pk
+    data_file: File,
+    block_cache: BlockCache,
+    timestamp_base: MergeTimestamp,
+    stats: SsTableStats,
+    filter: BloomFilter,
+    column_index: ColumnIndex,
+    key_cache: KeyCache,
+    name: SsTableName,
+    folder: PathBuf,
 }
 
 impl SsTable {
+    /// Writes rows to a single new SsTable. Its generation follows the highest one in the table's
+    ///  data folders, so this must not run concurrently with other writers of the same table -
+    ///  a Table writes through SsTableWriter with its SsTableGenerations.
     pub fn create<'a, RI>(config: &Arc<TableConfig>,
                           schema: &Arc<TableSchema>,
                           rows: RI)
                           -> HtResult<SsTable>
         where RI: Iterator<Item=RowData<'a>> {
-        let name_base = format!("{}-{}", schema.name, uuid::Uuid::new_v4().to_string());
-
-        let mut index_file = config.new_file(&name_base, "index", true)?;
-        let mut data_file = config.new_file(&name_base, "data", true)?;
+        let mut rows = rows.peekable();
+        let timestamp_base = rows.peek()
+            .map(|r| r.timestamp())
+            .unwrap_or(MergeTimestamp::from_ticks(0));
 
+        let generations = SsTableGenerations::seed(config, schema)?;
+        let mut files = SsTableFiles::new(config, schema, &generations, timestamp_base)?;
         for row in rows {
-            let pos = data_file.seek(SeekFrom::Current(0))?;
-            index_file.encode_fixed_u64(pos)?;
-
-            row.write_to(&mut data_file)?;
+            files.write_row(&row)?;
         }
+        files.finish(config, schema)
+    }
 
-        //TODO marker to handle crash during indexing robustly
-        //TODO hash to verify integrity
-        //TODO Bloom Filter
-        index_file.flush()?;
-        data_file.flush()?;
+    /// Copies an SsTable from another folder (e.g. one produced by another node or by an external
+    ///  tool) into one of the table's data folders, assigning it the table's next generation.
+    ///  The copy's files are removed if copying fails.
+    pub fn import(config: &Arc<TableConfig>, schema: &Arc<TableSchema>, generations: &SsTableGenerations, source: &Path, source_name: &SsTableName) -> HtResult<SsTable> {
+        let folder = config.select_data_folder(schema)?;
+        let (name, mut data_file) = SsTableName::allocate(config, schema, generations, &folder)?;
+
+        let mut copy = || -> HtResult<SsTable> {
+            std::io::copy(&mut File::open(source.join(source_name.file_name(SsTableComponent::Data)))?, &mut data_file)?;
+            data_file.sync_all()?;
+            for &component in SsTableComponent::ALL {
+                let source_file = source.join(source_name.file_name(component));
+                if component == SsTableComponent::Data || (component.is_optional() && !source_file.exists()) {
+                    continue;
+                }
+                std::fs::copy(source_file, folder.join(name.file_name(component)))?;
+                File::open(folder.join(name.file_name(component)))?.sync_all()?;
+            }
+            sync_folder(&folder)?;
+            SsTable::open(config, schema, &folder, &name)
+        };
 
-        SsTable::open(config, schema, &name_base)
+        copy().inspect_err(|_| {
+            for &component in SsTableComponent::ALL {
+                let _ = std::fs::remove_file(folder.join(name.file_name(component)));
+            }
+        })
     }
 
-    pub fn open(config: &Arc<TableConfig>, schema: &Arc<TableSchema>, name_base: &str) -> HtResult<SsTable> {
-        let index_file = config.new_file(&name_base, "index", false)?;
-        let data_file = config.new_file(&name_base, "data", false)?;
+    /// Opens an existing SsTable, folder being the table's data folder containing the SsTable.
+    ///  See TableConfig::preload_index and TableConfig::lock_index for warming the index.
+    pub fn open(config: &Arc<TableConfig>, schema: &Arc<TableSchema>, folder: &Path, name: &SsTableName) -> HtResult<SsTable> {
+        let index_file = config.new_file(folder, &name.file_name(SsTableComponent::Index), false)?;
+        let data_file = config.new_file(folder, &name.file_name(SsTableComponent::Data), false)?;
         let index_mmap = unsafe { MmapOptions::new().map(&index_file) }?;
         let data_mmap = unsafe { MmapOptions::new().map(&data_file) }?;
 
-        Ok(SsTable { schema: schema.clone(), index_mmap, data_mmap, name_base: name_base.to_string() })
+        let mut stats_buf = Vec::new();
+        config.new_file(folder, &name.file_name(SsTableComponent::Stats), false)?.read_to_end(&mut stats_buf)?;
+        let stats = SsTableStats::read_from(&stats_buf)?;
+
+        let filter = match SsTable::read_optional_component(config, folder, name, SsTableComponent::Filter)? {
+            Some(buf) => BloomFilter::read_from(&buf)?,
+            None => BloomFilter::match_all(),
+        };
+
+        let column_index = match SsTable::open_optional_component(config, folder, name, SsTableComponent::ColumnIndex)? {
+            Some(file) => ColumnIndex::open(&file)?,
+            None => ColumnIndex::empty(),
+        };
+
+        if data_mmap.len() < size_of::<u64>() {
+            return Err(HtError::misc("data file is shorter than its header"));
+        }
+        let timestamp_base = MergeTimestamp::from_ticks(data_mmap.decode_fixed_u64(&mut 0));
+
+        if config.lock_index {
+            lock_mmap(&index_mmap, name);
+        }
+        else if config.preload_index {
+            prefault_mmap(&index_mmap);
+        }
+
+        let result = SsTable { schema: schema.clone(), index_mmap, data_mmap, data_file, block_cache: BlockCache::new(config.block_cache_size), timestamp_base, stats, filter, column_index, key_cache: KeyCache::new(config.key_cache_size), name: name.clone(), folder: folder.to_path_buf() };
+        result.advise(config.read_advice)?;
+        Ok(result)
+    }
+
+    /// an optional component's file, or None if the file is missing
+    fn open_optional_component(config: &Arc<TableConfig>, folder: &Path, name: &SsTableName, component: SsTableComponent) -> HtResult<Option<File>> {
+        match config.new_file(folder, &name.file_name(component), false) {
+            Ok(file) => Ok(Some(file)),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// the contents of an optional component's file, or None if the file is missing or empty
+    fn read_optional_component(config: &Arc<TableConfig>, folder: &Path, name: &SsTableName, component: SsTableComponent) -> HtResult<Option<Vec<u8>>> {
+        let mut buf = Vec::new();
+        match SsTable::open_optional_component(config, folder, name, component)? {
+            Some(mut file) => file.read_to_end(&mut buf)?,
+            None => return Ok(None),
+        };
+        Ok(Some(buf).filter(|buf| !buf.is_empty()))
+    }
+
+    /// Passes an access pattern hint for this SsTable's index and data files to the OS. The
+    ///  hint applies until the next call, except for WillNeed and DontNeed which have a one-time
+    ///  effect.
+    pub fn advise(&self, advice: AccessAdvice) -> HtResult<()> {
+        advise_mmap(&self.index_mmap, advice)?;
+        advise_mmap(&self.data_mmap, advice)?;
+
+        #[cfg(target_os = "linux")]
+        {
+            if advice == AccessAdvice::DontNeed {
+                // madvise only unmaps the pages, dropping them from the page cache requires fadvise
+                for component in &[SsTableComponent::Index, SsTableComponent::Data] {
+                    let file = File::open(self.folder.join(self.name.file_name(*component)))?;
+                    let rc = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+                    if rc != 0 {
+                        return Err(std::io::Error::from_raw_os_error(rc).into());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> &SsTableStats {
+        &self.stats
+    }
+
+    pub fn name(&self) -> &SsTableName {
+        &self.name
+    }
+
+    pub fn filter(&self) -> &BloomFilter {
+        &self.filter
+    }
+
+    pub fn column_index(&self) -> &ColumnIndex {
+        &self.column_index
+    }
+
+    /// The rows whose value of an indexed column equals the given value, in primary key order,
+    ///  or None if the column was not indexed when the SsTable was written
+    pub fn find_by_column_value(&self, col_id: ColumnId, value: &ColumnValue) -> HtResult<Option<Vec<RowData<'_>>>> {
+        match self.column_index.lookup(col_id, value) {
+            None => Ok(None),
+            Some(offsets) => Ok(Some(offsets.iter()
+                .map(|&offs| self.data_at(offs))
+                .collect::<HtResult<Vec<_>>>()?)),
+        }
+    }
+
+    /// the cache of this SsTable's recent point reads
+    pub fn key_cache(&self) -> &KeyCache {
+        &self.key_cache
+    }
+
+    /// false if the SsTable definitely holds no rows of the partition with the given token
+    pub fn may_contain_partition(&self, partition_token: u64) -> bool {
+        self.filter.may_contain(partition_token)
+    }
+
+    /// the table's data folder containing this SsTable's files
+    pub fn folder(&self) -> &Path {
+        &self.folder
+    }
+
+    /// size of the data file in bytes
+    pub fn data_size(&self) -> u64 {
+        self.data_mmap.len() as u64
+    }
+
+    /// iterates over all rows in primary key order
+    pub fn rows(&self) -> SsTableRowIter {
+        SsTableRowIter { ss_table: self, idx: 0, quarantine: None }
+    }
+
+    /// Same as rows, but rows that fail RowData::validate, or whose index entry points outside
+    ///  the data file, are skipped and added to the quarantine. SsTables have no checksums, so
+    ///  corruption that leaves a row well-formed (e.g. a changed value) is not detected.
+    pub fn validated_rows<'a>(&'a self, quarantine: &'a Quarantine) -> SsTableRowIter<'a> {
+        SsTableRowIter { ss_table: self, idx: 0, quarantine: Some(quarantine) }
+    }
+
+    /// Removes this SsTable's files. The SsTable's memory maps remain valid until it is
+    ///  dropped.
+    pub fn delete_files(&self) -> HtResult<()> {
+        for &component in SsTableComponent::ALL {
+            match std::fs::remove_file(self.folder.join(self.name.file_name(component))) {
+                Err(ref e) if e.kind() == ErrorKind::NotFound && component.is_optional() => {},
+                other => other?,
+            }
+        }
+        Ok(())
     }
 
     pub fn find_by_full_pk(&self, pks: &RowData<'_>) -> HtResult<Option<RowData>> {
+        self.find_by_pk(&pks.encode_pk())
+    }
+
+    /// same as find_by_full_pk, searching the index by the encoded primary key
+    pub fn find_by_pk(&self, pk: &PrimaryKey) -> HtResult<Option<RowData<'_>>> {
+        match self.find_offs_by_pk(pk, None)? {
+            None => Ok(None),
+            Some(offs) => Ok(Some(self.data_at(offs)?)),
+        }
+    }
+
+    /// Same as find_by_pk, but each row the index search visits is validated (see
+    ///  validated_rows). A corrupt row is added to the quarantine, and since the search can not
+    ///  continue past it, the key is treated as missing from this SsTable.
+    pub fn find_validated_by_pk(&self, pk: &PrimaryKey, quarantine: &Quarantine) -> HtResult<Option<RowData<'_>>> {
+        match self.find_offs_by_pk(pk, Some(quarantine))? {
+            None => Ok(None),
+            // the row may come from the key cache rather than the search
+            Some(offs) => Ok(self.validated_data_at(offs, quarantine)),
+        }
+    }
+
+    /// Same as find_by_pk - or find_validated_by_pk if a quarantine is given - but the rows on
+    ///  the search path are read with positioned reads through the block cache rather than
+    ///  through the data file's memory map, see IoBackend::Pread.
+    pub fn read_by_pk(&self, pk: &PrimaryKey, quarantine: Option<&Quarantine>) -> HtResult<Option<DetachedRowData>> {
+        if !self.may_contain_partition(pk.partition_token()) {
+            return Ok(None);
+        }
+
+        if let Some(offs) = self.cached_offs(pk) {
+            let mut buf = Vec::new();
+            let row = self.pread_data_at(offs, &mut buf);
+            let row = match quarantine {
+                Some(quarantine) => self.quarantine_invalid(offs, row, quarantine),
+                None => Some(row?),
+            };
+            return Ok(row.map(|row| row.detach()));
+        }
+
+        // the search reads the row it finds, so that row is returned rather than read again
+        let mut found = None;
+        let result = self.search_index_by(quarantine, true, |row| {
+            let result = row.try_compare_to_pk(pk)?;
+            if result == Ordering::Equal {
+                found = Some(row.detach());
+            }
+            Ok(result)
+        })?;
+        match result {
+            Some(Ok(idx)) => {
+                self.cache_offs(pk, self.index_slice()[idx]);
+                Ok(found)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Same as find_by_full_pk, but the returned RowHandle keeps the SsTable alive, so it can be
+    ///  held after the SsTable was replaced by compaction.
+    pub fn find_handle_by_full_pk(ss_table: &Arc<SsTable>, pks: &RowData<'_>) -> HtResult<Option<RowHandle>> {
+        match ss_table.find_offs_by_pk(&pks.encode_pk(), None)? {
+            None => Ok(None),
+            Some(offs) => {
+                let (offs, len) = ss_table.row_bounds(offs)?;
+                Ok(Some(RowHandle { ss_table: ss_table.clone(), offs, len }))
+            }
+        }
+    }
+
+    /// the offset of a row in the data file, see search_index_by for the quarantine
+    fn find_offs_by_pk(&self, pk: &PrimaryKey, quarantine: Option<&Quarantine>) -> HtResult<Option<u64>> {
+        if !self.may_contain_partition(pk.partition_token()) {
+            return Ok(None);
+        }
+        if let Some(offs) = self.cached_offs(pk) {
+            return Ok(Some(offs));
+        }
+
+        let result = match self.search_index_by(quarantine, false, |row| row.try_compare_to_pk(pk))? {
+            Some(Ok(idx)) => Some(self.index_slice()[idx]),
+            _ => None,
+        };
+        if let Some(offs) = result {
+            self.cache_offs(pk, offs);
+        }
+        Ok(result)
+    }
+
+    /// the key cache's offset for a primary key, without counting a miss if the cache is disabled
+    fn cached_offs(&self, pk: &PrimaryKey) -> Option<u64> {
+        match self.key_cache.is_enabled() {
+            true => self.key_cache.get(pk.bytes()),
+            false => None,
+        }
+    }
+
+    fn cache_offs(&self, pk: &PrimaryKey, offs: u64) {
+        if self.key_cache.is_enabled() {
+            self.key_cache.put(pk.bytes().clone(), offs);
+        }
+    }
+
+    /// binary search in the index, with the semantics of slice::binary_search
+    fn search_index(&self, pks: &RowData<'_>) -> HtResult<Result<usize, usize>> {
+        Ok(self.search_index_by(None, false, |row| row.try_compare_by_pk(pks))?
+            .expect("searches without a quarantine always complete"))
+    }
+
+    /// Binary search in the index, cmp comparing a row to the key that is searched for. A
+    ///  corrupt row on the search path is an error, or - if a quarantine is given - it is added
+    ///  to the quarantine and the search returns None, since it can not tell which way to go.
+    ///  With pread, rows are read with positioned reads rather than through the memory map, all
+    ///  into the same buffer.
+    fn search_index_by<F>(&self, quarantine: Option<&Quarantine>, pread: bool, mut cmp: F) -> HtResult<Option<Result<usize, usize>>> where F: FnMut(&RowData) -> HtResult<Ordering> {
         let mut err = None;
+        let mut quarantined = false;
+        let mut buf = Vec::new();
 
-        let result = self.index_slice().binary_search_by(|offs| {
-            match self.data_at(*offs) {
-                _ if err.is_some() => Ordering::Equal,
-                Ok(row) => row.compare_by_pk(pks),
-                Err(e) => {
-                    err = Some(e);
-                    Ordering::Equal
-                }
+        let result = self.index_slice().binary_search_by(|&offs| {
+            if err.is_some() || quarantined {
+                return Ordering::Equal;
             }
+            let row = match pread {
+                true => self.pread_data_at(offs, &mut buf),
+                false => self.data_at(offs),
+            };
+            let row = match quarantine {
+                Some(quarantine) => match self.quarantine_invalid(offs, row, quarantine) {
+                    Some(row) => row,
+                    None => {
+                        quarantined = true;
+                        return Ordering::Equal;
+                    }
+                },
+                None => match row {
+                    Ok(row) => row,
+                    Err(e) => {
+                        err = Some(e);
+                        return Ordering::Equal;
+                    }
+                },
+            };
+            cmp(&row).unwrap_or_else(|e| {
+                err = Some(e);
+                Ordering::Equal
+            })
         });
 
-        match (result, err) {
-            (_, Some(e)) => Err(e),
-            (Err(_), _) => Ok(None),
-            (Ok(idx), _) => {
-                let offs = self.index_slice()[idx];
-                Ok(Some(self.data_at(offs)?))
-            }
+        match err {
+            Some(e) => Err(e),
+            None if quarantined => Ok(None),
+            None => Ok(Some(result)),
         }
     }
 
@@ -84,17 +465,481 @@ impl SsTable {
         unsafe { from_raw_parts(ptr, len) }
     }
 
-    fn data_at(&self, offs: u64) -> HtResult<RowData> {
-        let mut offs = offs as usize;
-        let len = self.data_mmap.decode_varint_usize(&mut offs);
-        Ok(RowData::from_view(&self.schema, &self.data_mmap[offs..offs+len]))
+    /// Checks the SsTable's files for consistency: index entries point to rows inside the data
+    ///  file, rows are well-formed and in strictly ascending primary key order, and the row
+    ///  count matches the stats. This reads the entire SsTable. Corruption is reported as an
+    ///  error rather than a panic.
+    pub fn verify(&self) -> HtResult<()> {
+        if !self.index_mmap.len().is_multiple_of(size_of::<u64>()) {
+            return Err(HtError::misc("index file length is not a multiple of the entry size"));
+        }
+
+        let mut prev: Option<RowData> = None;
+        for &offs in self.index_slice() {
+            let row = self.data_at(offs)?;
+            row.validate()?;
+            if let Some(prev) = &prev {
+                if prev.try_compare_by_pk(&row)? != Ordering::Less {
+                    return Err(HtError::misc("rows are not in ascending primary key order"));
+                }
+            }
+            prev = Some(row);
+        }
+
+        if self.index_slice().len() as u64 != self.stats.row_count {
+            return Err(HtError::misc("row count does not match the stats"));
+        }
+        Ok(())
+    }
+
+    /// the offset and length of the row data at an index entry's offset, i.e. after the row's
+    ///  length, failing if they lie outside the data file
+    fn row_bounds(&self, offs: u64) -> HtResult<(usize, usize)> {
+        self.row_bounds_from(offs, self.data_mmap.get(offs as usize..).unwrap_or(&[]))
+    }
+
+    /// same as row_bounds, decoding the row's length from header, i.e. the data file's bytes
+    ///  starting at offs
+    fn row_bounds_from(&self, offs: u64, header: &[u8]) -> HtResult<(usize, usize)> {
+        let outside = || HtError::misc("index entry points outside the data file's rows");
+        if offs < size_of::<u64>() as u64 {
+            return Err(outside());
+        }
+
+        let mut header_len = 0;
+        let len = header.try_decode_varint_usize(&mut header_len).ok_or_else(outside)?;
+        let start = offs as usize + header_len;
+        match start.checked_add(len) {
+            Some(end) if end <= self.data_mmap.len() => Ok((start, len)),
+            _ => Err(HtError::misc("row extends beyond the end of the data file")),
+        }
+    }
+
+    fn data_at(&self, offs: u64) -> HtResult<RowData<'_>> {
+        let (offs, len) = self.row_bounds(offs)?;
+        Ok(RowData::from_view_with_base(&self.schema, &self.data_mmap[offs..offs+len], self.timestamp_base))
+    }
+
+    /// same as data_at, but reading the row into buf with positioned reads rather than through
+    ///  the memory map
+    fn pread_data_at<'a>(&'a self, offs: u64, buf: &'a mut Vec<u8>) -> HtResult<RowData<'a>> {
+        // a varint holding a u64 has at most 10 bytes
+        let mut header = [0u8; 10];
+        let header_len = self.data_mmap.len().saturating_sub(offs as usize).min(header.len());
+        self.read_data_at(&mut header[..header_len], offs)?;
+
+        let (start, len) = self.row_bounds_from(offs, &header[..header_len])?;
+        buf.resize(len, 0);
+        self.read_data_at(buf, start as u64)?;
+        Ok(RowData::from_view_with_base(&self.schema, buf, self.timestamp_base))
+    }
+
+    /// reads from the data file through the block cache, see IoBackend::Pread
+    fn read_data_at(&self, buf: &mut [u8], offs: u64) -> HtResult<()> {
+        if !self.block_cache.is_enabled() {
+            return self.read_file_at(buf, offs);
+        }
+        self.block_cache.read(buf, offs, self.data_mmap.len() as u64, |block, offs| self.read_file_at(block, offs))
+    }
+
+    #[cfg(unix)]
+    fn read_file_at(&self, buf: &mut [u8], offs: u64) -> HtResult<()> {
+        use std::os::unix::fs::FileExt;
+        self.data_file.read_exact_at(buf, offs)?;
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn read_file_at(&self, buf: &mut [u8], offs: u64) -> HtResult<()> {
+        let offs = offs as usize;
+        buf.copy_from_slice(&self.data_mmap[offs..offs + buf.len()]);
+        Ok(())
+    }
+
+    /// the row at an index entry's offset if it passes RowData::validate, adding it to the
+    ///  quarantine otherwise
+    fn validated_data_at(&self, offs: u64, quarantine: &Quarantine) -> Option<RowData<'_>> {
+        self.quarantine_invalid(offs, self.data_at(offs), quarantine)
+    }
+
+    /// the row if it was read successfully and passes RowData::validate, adding the index
+    ///  entry's offset to the quarantine otherwise
+    fn quarantine_invalid<'a>(&self, offs: u64, row: HtResult<RowData<'a>>, quarantine: &Quarantine) -> Option<RowData<'a>> {
+        let row = row.and_then(|row| {
+            row.validate()?;
+            Ok(row)
+        });
+        match row {
+            Ok(row) => Some(row),
+            Err(e) => {
+                quarantine.add(&self.name, offs, &e);
+                None
+            }
+        }
+    }
+}
+
+/// A row read from an SsTable. It holds a reference to the SsTable, keeping the SsTable's memory
+///  maps valid for as long as the handle exists, even if the SsTable's files were deleted in the
+///  meantime.
+pub struct RowHandle {
+    ss_table: Arc<SsTable>,
+    offs: usize,
+    len: usize,
+}
+
+impl RowHandle {
+    pub fn row_data_view(&self) -> RowData<'_> {
+        RowData::from_view_with_base(&self.ss_table.schema, &self.ss_table.data_mmap[self.offs..self.offs+self.len], self.ss_table.timestamp_base)
+    }
+
+    /// copies the row's data, releasing the SsTable when the handle is dropped
+    pub fn detach(&self) -> DetachedRowData {
+        self.row_data_view().detach()
+    }
+
+    pub fn ss_table(&self) -> &Arc<SsTable> {
+        &self.ss_table
+    }
+}
+
+/// The files an SsTable consists of
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SsTableComponent {
+    Index,
+    Data,
+    Stats,
+    Filter,
+    ColumnIndex,
+}
+
+impl SsTableComponent {
+    pub const ALL: &'static [SsTableComponent] = &[SsTableComponent::Index, SsTableComponent::Data, SsTableComponent::Stats, SsTableComponent::Filter, SsTableComponent::ColumnIndex];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SsTableComponent::Index => "index",
+            SsTableComponent::Data => "data",
+            SsTableComponent::Stats => "stats",
+            SsTableComponent::Filter => "filter",
+            SsTableComponent::ColumnIndex => "columnindex",
+        }
+    }
+
+    /// Optional components may be missing, e.g. in SsTables written before they existed. An
+    ///  SsTable whose file for an optional component is missing or empty is read without it,
+    ///  see SsTable::open.
+    pub fn is_optional(&self) -> bool {
+        matches!(self, SsTableComponent::Filter | SsTableComponent::ColumnIndex)
+    }
+
+    pub fn parse(name: &str) -> Option<SsTableComponent> {
+        SsTableComponent::ALL.iter()
+            .find(|c| c.name() == name)
+            .copied()
+    }
+}
+
+/// Identifies an SsTable on disk. Each of its components is stored in a file named
+///  `<table>-<generation>-<component>`. Generations are assigned in ascending order as SsTables
+///  are created, so they reflect the order of creation.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct SsTableName {
+    pub table: String,
+    pub generation: u64,
+}
+
+impl SsTableName {
+    pub fn new(table: &str, generation: u64) -> SsTableName {
+        SsTableName { table: table.to_string(), generation }
+    }
+
+    pub fn file_name(&self, component: SsTableComponent) -> String {
+        format!("{}-{}-{}", self.table, self.generation, component.name())
+    }
+
+    /// Parses a component's file name. Table names may contain '-', so the name is parsed from
+    ///  the end.
+    pub fn parse(file_name: &str) -> Option<(SsTableName, SsTableComponent)> {
+        let mut parts = file_name.rsplitn(3, '-');
+
+        let component = SsTableComponent::parse(parts.next()?)?;
+        let generation = parts.next()?.parse::<u64>().ok()?;
+        let table = parts.next()?;
+
+        Some((SsTableName::new(table, generation), component))
+    }
+
+    /// All SsTables in a table's data folders together with the folder containing them, ordered
+    ///  by generation. This includes SsTables with missing components.
+    pub fn discover(config: &TableConfig, schema: &TableSchema) -> HtResult<Vec<(SsTableName, PathBuf)>> {
+        let mut result = Vec::new();
+        for folder in config.table_data_folders(schema) {
+            for file_name in config.file_names(&folder)? {
+                match SsTableName::parse(&file_name) {
+                    Some((name, _)) if name.table == schema.name => result.push((name, folder.clone())),
+                    _ => {}
+                }
+            }
+        }
+
+        result.sort_by(|a, b| a.0.generation.cmp(&b.0.generation).then_with(|| a.1.cmp(&b.1)));
+        result.dedup();
+        Ok(result)
+    }
+
+    /// The SsTables of a table in a given folder (e.g. a snapshot), ordered by generation. This
+    ///  includes SsTables with missing components.
+    pub fn in_folder(config: &TableConfig, schema: &TableSchema, folder: &Path) -> HtResult<Vec<SsTableName>> {
+        let mut result = config.file_names(folder)?.iter()
+            .filter_map(|file_name| SsTableName::parse(file_name))
+            .map(|(name, _)| name)
+            .filter(|name| name.table == schema.name)
+            .collect::<Vec<_>>();
+        result.sort_by_key(|name| name.generation);
+        result.dedup();
+        Ok(result)
+    }
+
+    /// Assigns the table's next generation and creates its data file in a given folder. The
+    ///  file is created exclusively, skipping generations whose files exist although they were
+    ///  not known when the counter was seeded (e.g. files copied into the folder).
+    pub fn allocate(config: &TableConfig, schema: &TableSchema, generations: &SsTableGenerations, folder: &Path) -> HtResult<(SsTableName, File)> {
+        loop {
+            let name = SsTableName::new(&schema.name, generations.next());
+            match config.create_new_file(folder, &name.file_name(SsTableComponent::Data)) {
+                Ok(file) => return Ok((name, file)),
+                Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Hands out the generations of a table's new SsTables. Generations identify SsTables across
+///  all of a table's data folders, so they come from a single counter per table rather than
+///  from the folder an SsTable is written to - writers in different folders (e.g. parallel
+///  compaction threads) never get the same generation.
+#[derive(Default)]
+pub struct SsTableGenerations {
+    last: AtomicU64,
+}
+
+impl SsTableGenerations {
+    /// starts after the highest generation in any of the table's data folders
+    pub fn seed(config: &TableConfig, schema: &TableSchema) -> HtResult<SsTableGenerations> {
+        let last = SsTableName::discover(config, schema)?
+            .last()
+            .map(|(name, _)| name.generation)
+            .unwrap_or(0);
+        Ok(SsTableGenerations { last: AtomicU64::new(last) })
+    }
+
+    fn next(&self) -> u64 {
+        self.last.fetch_add(1, AtomicOrdering::SeqCst) + 1
+    }
+}
+
+impl Display for SsTableName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.table, self.generation)
+    }
+}
+
+pub struct SsTableRowIter<'a> {
+    ss_table: &'a SsTable,
+    idx: usize,
+    /// if present, rows are validated, see SsTable::validated_rows
+    quarantine: Option<&'a Quarantine>,
+}
+
+impl <'a> Iterator for SsTableRowIter<'a> {
+    type Item = HtResult<RowData<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.ss_table.index_slice();
+        while self.idx < index.len() {
+            let offs = index[self.idx];
+            self.idx += 1;
+
+            match self.quarantine {
+                Some(quarantine) => match self.ss_table.validated_data_at(offs, quarantine) {
+                    Some(row) => return Some(Ok(row)),
+                    None => continue,
+                },
+                None => return Some(self.ss_table.data_at(offs)),
+            }
+        }
+        None
+    }
+}
+
+impl <'a> SeekableRows<'a> for SsTableRowIter<'a> {
+    fn seek(&mut self, pks: &RowData) -> HtResult<()> {
+        self.idx = match self.ss_table.search_index(pks)? {
+            Ok(idx) => idx,
+            Err(idx) => idx,
+        };
+        Ok(())
+    }
+}
+
+/// The files of a single SsTable while it is being written
+struct SsTableFiles {
+    name: SsTableName,
+    folder: PathBuf,
+    timestamp_base: MergeTimestamp,
+    index_file: File,
+    data_file: File,
+    data_len: u64,
+    stats: StatsCollector,
+    column_index: ColumnIndexCollector,
+    /// one token per partition for the BloomFilter, None if the table has no filters (see
+    ///  TableConfig::bloom_fp_chance)
+    partition_tokens: Option<Vec<u64>>,
+}
+
+impl SsTableFiles {
+    fn new(config: &Arc<TableConfig>, schema: &Arc<TableSchema>, generations: &SsTableGenerations, timestamp_base: MergeTimestamp) -> HtResult<SsTableFiles> {
+        let folder = config.select_data_folder(schema)?;
+        let (name, mut data_file) = SsTableName::allocate(config, schema, generations, &folder)?;
+        let index_file = config.new_file(&folder, &name.file_name(SsTableComponent::Index), true)?;
+
+        data_file.encode(timestamp_base)?;
+        let data_len = size_of::<MergeTimestamp>() as u64;
+
+        let column_index = ColumnIndexCollector::new(&config.indexed_columns,
+                                                     folder.join(format!("{}.spill", name.file_name(SsTableComponent::ColumnIndex))),
+                                                     ColumnIndexCollector::DEFAULT_MAX_BUFFERED_ENTRIES);
+        let partition_tokens = config.bloom_fp_chance.map(|_| Vec::new());
+        Ok(SsTableFiles { name, folder, timestamp_base, index_file, data_file, data_len, stats: StatsCollector::new(config.large_partition_warn_threshold), column_index, partition_tokens })
+    }
+
+    fn write_row(&mut self, row: &RowData) -> HtResult<()> {
+        self.index_file.encode_fixed_u64(self.data_len)?;
+
+        let buf = row.encode_with_timestamp_base(self.timestamp_base);
+        let stored = RowData::from_view_with_base(&row.schema, &buf, self.timestamp_base);
+        stored.write_to(&mut self.data_file)?;
+        let stored_len = stored.stored_len() as u64;
+
+        self.stats.add_row(row, stored_len);
+        self.column_index.add_row(row, self.data_len)?;
+        self.data_len += stored_len;
+
+        // rows arrive in primary key order, so a partition's rows are consecutive
+        if let Some(tokens) = &mut self.partition_tokens {
+            let token = row.partition_token();
+            if tokens.last() != Some(&token) {
+                tokens.push(token);
+            }
+        }
+        Ok(())
+    }
+
+    /// Completes the SsTable's files, syncing all of them and the folder, so that the SsTable
+    ///  can be listed in the manifest once this returns.
+    fn finish(self, config: &Arc<TableConfig>, schema: &Arc<TableSchema>) -> HtResult<SsTable> {
+        //TODO marker to handle crash during indexing robustly
+        //TODO hash to verify integrity
+        self.index_file.sync_all()?;
+        self.data_file.sync_all()?;
+
+        let mut stats_file = config.new_file(&self.folder, &self.name.file_name(SsTableComponent::Stats), true)?;
+        self.stats.finish().write_to(&mut stats_file)?;
+        stats_file.sync_all()?;
+
+        // the column index is optional, so there is none without indexed columns
+        if !config.indexed_columns.is_empty() {
+            let mut column_index_file = BufWriter::new(config.new_file(&self.folder, &self.name.file_name(SsTableComponent::ColumnIndex), true)?);
+            self.column_index.write_to(&mut column_index_file)?;
+            column_index_file.flush()?;
+            column_index_file.get_ref().sync_all()?;
+        }
+
+        // the filter is sized for the number of partitions, which is only known once all rows
+        //  were written. Without the filter file, the SsTable is read without a filter.
+        if let (Some(fp_chance), Some(tokens)) = (config.bloom_fp_chance, &self.partition_tokens) {
+            let mut filter = BloomFilter::with_capacity(tokens.len() as u64, fp_chance);
+            for &token in tokens {
+                filter.add(token);
+            }
+            let mut filter_file = config.new_file(&self.folder, &self.name.file_name(SsTableComponent::Filter), true)?;
+            filter.write_to(&mut filter_file)?;
+            filter_file.sync_all()?;
+        }
+
+        sync_folder(&self.folder)?;
+        SsTable::open(config, schema, &self.folder, &self.name)
+    }
+}
+
+/// Writes a (potentially large) sorted sequence of rows to SsTables, starting a new SsTable
+///  whenever the current one's data file reaches TableConfig::max_sstable_data_size. This is
+///  e.g. for compaction where the merged output should not end up in a single huge file.
+///
+/// Splitting happens at row boundaries, so the rows of a partition can end up in several
+///  (adjacent) SsTables.
+pub struct SsTableWriter {
+    config: Arc<TableConfig>,
+    schema: Arc<TableSchema>,
+    generations: Arc<SsTableGenerations>,
+    current: Option<SsTableFiles>,
+    created: Vec<SsTable>,
+}
+
+impl SsTableWriter {
+    pub fn new(config: &Arc<TableConfig>, schema: &Arc<TableSchema>, generations: &Arc<SsTableGenerations>) -> SsTableWriter {
+        SsTableWriter {
+            config: config.clone(),
+            schema: schema.clone(),
+            generations: generations.clone(),
+            current: None,
+            created: Vec::new(),
+        }
+    }
+
+    /// rows must be written in primary key order
+    pub fn write_row(&mut self, row: &RowData) -> HtResult<()> {
+        if self.current.is_none() {
+            self.current = Some(SsTableFiles::new(&self.config, &self.schema, &self.generations, row.timestamp())?);
+        }
+
+        let files = self.current.as_mut().unwrap();
+        files.write_row(row)?;
+
+        if files.data_len >= self.config.max_sstable_data_size {
+            self.roll()?;
+        }
+        Ok(())
+    }
+
+    fn roll(&mut self) -> HtResult<()> {
+        if let Some(files) = self.current.take() {
+            self.created.push(files.finish(&self.config, &self.schema)?);
+        }
+        Ok(())
+    }
+
+    /// Returns the SsTables that were created, in primary key order. This can be empty if no rows
+    ///  were written.
+    pub fn finish(mut self) -> HtResult<Vec<SsTable>> {
+        self.roll()?;
+        Ok(self.created)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::sstable::SsTable;
+    use std::sync::Arc;
+
+    use crate::bloom::BloomFilter;
+    use crate::config::{AccessAdvice, TableConfig};
+    use crate::quarantine::Quarantine;
+    use crate::sstable::{SsTable, SsTableComponent, SsTableGenerations, SsTableName, SsTableWriter};
+    use crate::table::{ColumnValue, DetachedRowData, PrimaryKey};
     use crate::testutils::{SimpleTableTestSetup, test_table_config};
+    use crate::time::{HtClock, MergeTimestamp};
 
     #[test]
     pub fn test_simple() {
@@ -124,20 +969,319 @@ mod test {
             assert!(ss_table.find_by_full_pk(&setup.pk_row(4,).row_data_view()).unwrap().is_none());
             assert!(ss_table.find_by_full_pk(&setup.pk_row(6).row_data_view()).unwrap().is_none());
             assert!(ss_table.find_by_full_pk(&setup.pk_row(8).row_data_view()).unwrap().is_none());
+
+            let pk = PrimaryKey::new(&setup.schema, &[ColumnValue::BigInt(5)]).unwrap();
+            assert_eq!(setup.value(&ss_table.find_by_pk(&pk).unwrap().unwrap()), "c");
+            let pk = PrimaryKey::new(&setup.schema, &[ColumnValue::BigInt(6)]).unwrap();
+            assert!(ss_table.find_by_pk(&pk).unwrap().is_none());
+
+            // positioned reads find the same rows, the second time through the key cache
+            for pk in (0..9).chain(0..9) {
+                let pk = setup.pk_row(pk).row_data_view().encode_pk();
+                assert_eq!(ss_table.read_by_pk(&pk, None).unwrap().map(|row| row.row_data_view().describe()),
+                           ss_table.find_by_pk(&pk).unwrap().map(|row| row.describe()));
+            }
+            assert_eq!(ss_table.block_cache.len(), 1);
         }
 
-        let rows = vec!(
+        let rows = [
             setup.full_row(1, Some("a"), None),
             setup.full_row(3, Some("b"), None),
             setup.full_row(5, Some("c"), None),
             setup.full_row(7, Some("d"), None),
-        );
+        ];
 
         let it = rows.iter().map(|r| r.row_data_view());
         let ss_table = SsTable::create(&config, &setup.schema, it).unwrap();
         check(&setup, &ss_table);
+        // nothing is indexed, so there is no column index file
+        assert!(!ss_table.folder().join(ss_table.name().file_name(SsTableComponent::ColumnIndex)).exists());
 
-        let ss_table = SsTable::open(&config, &setup.schema, &ss_table.name_base).unwrap();
+        let ss_table = SsTable::open(&config, &setup.schema, ss_table.folder(), ss_table.name()).unwrap();
         check(&setup, &ss_table);
     }
+
+    #[test]
+    pub fn test_writer_splits_by_size() {
+        let config = Arc::new(TableConfig {
+            max_sstable_data_size: 100,
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+        let setup = SimpleTableTestSetup::new();
+
+        let rows = (0..20)
+            .map(|pk| setup.full_row(pk, Some("some text to fill up the data file"), None))
+            .collect::<Vec<_>>();
+
+        let generations = Arc::new(SsTableGenerations::seed(&config, &setup.schema).unwrap());
+        let mut writer = SsTableWriter::new(&config, &setup.schema, &generations);
+        for row in &rows {
+            writer.write_row(&row.row_data_view()).unwrap();
+        }
+        let ss_tables = writer.finish().unwrap();
+
+        assert!(ss_tables.len() > 1);
+        for ss_table in &ss_tables {
+            assert!(ss_table.data_mmap.len() < 2 * 100);
+        }
+
+        for pk in 0..20 {
+            let found = ss_tables.iter()
+                .filter_map(|t| t.find_by_full_pk(&setup.pk_row(pk).row_data_view()).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(found.len(), 1);
+            assert_eq!(setup.pk(&found[0]), pk);
+        }
+    }
+
+    #[test]
+    pub fn test_writer_empty() {
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+
+        let writer = SsTableWriter::new(&config, &setup.schema, &Arc::new(SsTableGenerations::default()));
+        assert!(writer.finish().unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn test_stats() {
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+
+        let rows = vec!(
+            setup.full_row(1, Some("a"), None),
+            setup.full_row(3, None, None),
+        );
+        setup.clock.set(MergeTimestamp::from_ticks(99999));
+        let rows = rows.into_iter()
+            .chain(vec!(setup.full_row(5, Some("c"), Some(17))))
+            .collect::<Vec<_>>();
+
+        let it = rows.iter().map(|r| r.row_data_view());
+        let ss_table = SsTable::create(&config, &setup.schema, it).unwrap();
+
+        fn check(rows: &[DetachedRowData], ss_table: &SsTable) {
+            let stats = ss_table.stats();
+            assert_eq!(stats.row_count, 3);
+            assert_eq!(stats.tombstone_count, 3);
+            assert_eq!(stats.min_timestamp, MergeTimestamp::from_ticks(12345));
+            assert_eq!(stats.max_timestamp, MergeTimestamp::from_ticks(99999));
+            assert_eq!(stats.min_partition_key, rows[0].row_data_view().partition_key_bytes());
+            assert_eq!(stats.max_partition_key, rows[2].row_data_view().partition_key_bytes());
+            assert_eq!(stats.partition_count_estimate(), 3);
+            assert_eq!(stats.partition_rows.count(), 3);
+            assert_eq!(stats.partition_rows.max(), 1);
+            assert_eq!(stats.large_partition_count, 0);
+
+            let tokens = rows.iter().map(|r| r.row_data_view().partition_token()).collect::<Vec<_>>();
+            assert_eq!(stats.min_token, *tokens.iter().min().unwrap());
+            assert_eq!(stats.max_token, *tokens.iter().max().unwrap());
+            assert!(stats.overlaps_tokens(&(tokens[0]..=tokens[0])));
+            assert!(!stats.overlaps_tokens(&(0..=stats.min_token - 1)));
+        }
+
+        check(&rows, &ss_table);
+        let ss_table = SsTable::open(&config, &setup.schema, ss_table.folder(), ss_table.name()).unwrap();
+        check(&rows, &ss_table);
+    }
+
+    #[test]
+    pub fn test_filter() {
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+        let rows = (0..1000).map(|pk| setup.full_row(pk, Some("a"), None)).collect::<Vec<_>>();
+        let ss_table = SsTable::create(&config, &setup.schema, rows.iter().map(|r| r.row_data_view())).unwrap();
+
+        // sized from the estimated partition count
+        let expected = BloomFilter::with_capacity(ss_table.stats().partition_count_estimate(), config.bloom_fp_chance.unwrap());
+        assert_eq!(ss_table.filter().size_bytes(), expected.size_bytes());
+        assert!(rows.iter().all(|r| ss_table.may_contain_partition(r.row_data_view().partition_token())));
+
+        // SsTables without a filter file are read without a filter
+        let filter_file = ss_table.folder().join(ss_table.name().file_name(SsTableComponent::Filter));
+        std::fs::remove_file(&filter_file).unwrap();
+        let ss_table = SsTable::open(&config, &setup.schema, ss_table.folder(), ss_table.name()).unwrap();
+        assert_eq!(ss_table.filter(), &BloomFilter::match_all());
+        assert_eq!(setup.value(&ss_table.find_by_full_pk(&setup.pk_row(7).row_data_view()).unwrap().unwrap()), "a");
+
+        std::fs::write(&filter_file, b"").unwrap();
+        assert!(SsTable::open(&config, &setup.schema, ss_table.folder(), ss_table.name()).is_ok());
+        std::fs::write(&filter_file, b"abc").unwrap();
+        assert!(SsTable::open(&config, &setup.schema, ss_table.folder(), ss_table.name()).is_err());
+
+        std::fs::remove_file(&filter_file).unwrap();
+        ss_table.delete_files().unwrap();
+    }
+
+    #[test]
+    pub fn test_stats_large_partitions() {
+        let config = Arc::new(TableConfig {
+            large_partition_warn_threshold: 50,
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+        let setup = SimpleTableTestSetup::new();
+
+        let rows = [
+            setup.full_row(1, Some("a"), None),
+            setup.full_row(2, Some("a text that makes the partition exceed the threshold"), None),
+            setup.full_row(3, Some("b"), None),
+        ];
+
+        let it = rows.iter().map(|r| r.row_data_view());
+        let ss_table = SsTable::create(&config, &setup.schema, it).unwrap();
+
+        let stats = ss_table.stats();
+        assert_eq!(stats.large_partition_count, 1);
+        assert_eq!(stats.partition_bytes.count(), 3);
+        assert!(stats.partition_bytes.max() > 50);
+        assert!(stats.partition_bytes.percentile(0.5) < 50);
+    }
+
+    #[test]
+    pub fn test_sstable_name() {
+        let name = SsTableName::new("my-table", 17);
+        assert_eq!(name.file_name(SsTableComponent::Data), "my-table-17-data");
+        assert_eq!(name.to_string(), "my-table-17");
+
+        assert_eq!(SsTableName::parse("my-table-17-data"), Some((name.clone(), SsTableComponent::Data)));
+        assert_eq!(SsTableName::parse("my-table-17-index"), Some((name.clone(), SsTableComponent::Index)));
+        assert_eq!(SsTableName::parse("t-0-stats"), Some((SsTableName::new("t", 0), SsTableComponent::Stats)));
+
+        assert_eq!(SsTableName::parse("my-table-17-xyz"), None);
+        assert_eq!(SsTableName::parse("my-table-x-data"), None);
+        assert_eq!(SsTableName::parse("17-data"), None);
+        assert_eq!(SsTableName::parse(""), None);
+    }
+
+    #[test]
+    pub fn test_generations() {
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+
+        let rows = [setup.full_row(1, Some("a"), None)];
+        let t1 = SsTable::create(&config, &setup.schema, rows.iter().map(|r| r.row_data_view())).unwrap();
+        let t2 = SsTable::create(&config, &setup.schema, rows.iter().map(|r| r.row_data_view())).unwrap();
+        assert!(t1.name().generation < t2.name().generation);
+
+        let discovered = SsTableName::discover(&config, &setup.schema).unwrap();
+        assert!(discovered.contains(&(t1.name().clone(), t1.folder().to_path_buf())));
+        assert!(discovered.contains(&(t2.name().clone(), t2.folder().to_path_buf())));
+        assert_eq!(&discovered.last().unwrap().0, t2.name());
+    }
+
+    #[test]
+    pub fn test_row_handle() {
+        let config = test_table_config();
+        let setup = SimpleTableTestSetup::new();
+
+        let rows = [setup.full_row(1, Some("a"), None), setup.full_row(2, Some("b"), None)];
+        let ss_table = Arc::new(SsTable::create(&config, &setup.schema, rows.iter().map(|r| r.row_data_view())).unwrap());
+
+        assert!(SsTable::find_handle_by_full_pk(&ss_table, &setup.pk_row(3).row_data_view()).unwrap().is_none());
+        let handle = SsTable::find_handle_by_full_pk(&ss_table, &setup.pk_row(2).row_data_view()).unwrap().unwrap();
+
+        // the handle outlives both the table's reference to the SsTable and its files
+        ss_table.delete_files().unwrap();
+        drop(ss_table);
+
+        assert_eq!(setup.value(&handle.row_data_view()), "b");
+        assert_eq!(setup.value(&handle.detach().row_data_view()), "b");
+    }
+
+    #[test]
+    pub fn test_advise() {
+        let config = Arc::new(TableConfig {
+            read_advice: AccessAdvice::Random,
+            ..TableConfig::new(test_table_config().base_folder.clone())
+        });
+        let setup = SimpleTableTestSetup::new();
+
+        let rows = [setup.full_row(1, Some("a"), None), setup.full_row(2, Some("b"), None)];
+        let ss_table = SsTable::create(&config, &setup.schema, rows.iter().map(|r| r.row_data_view())).unwrap();
+
+        for &advice in &[AccessAdvice::Sequential, AccessAdvice::WillNeed, AccessAdvice::DontNeed, AccessAdvice::Normal] {
+            ss_table.advise(advice).unwrap();
+            assert_eq!(setup.value(&ss_table.find_by_full_pk(&setup.pk_row(2).row_data_view()).unwrap().unwrap()), "b");
+        }
+    }
+
+    #[test]
+    pub fn test_preload_index() {
+        let setup = SimpleTableTestSetup::new();
+        let rows = (0..1000)
+            .map(|pk| setup.full_row(pk, Some("a"), None))
+            .collect::<Vec<_>>();
+
+        for &(preload_index, lock_index) in &[(true, false), (false, true)] {
+            let config = Arc::new(TableConfig {
+                preload_index,
+                lock_index,
+                ..TableConfig::new(test_table_config().base_folder.clone())
+            });
+            let ss_table = SsTable::create(&config, &setup.schema, rows.iter().map(|r| r.row_data_view())).unwrap();
+            assert_eq!(setup.pk(&ss_table.find_by_full_pk(&setup.pk_row(999).row_data_view()).unwrap().unwrap()), 999);
+        }
+    }
+
+    #[test]
+    pub fn test_key_cache() {
+        let setup = SimpleTableTestSetup::new();
+        let rows = [setup.full_row(1, Some("a"), None), setup.full_row(2, Some("b"), None)];
+
+        for &size in &[0, 1] {
+            let config = Arc::new(TableConfig {
+                key_cache_size: size,
+                ..TableConfig::new(test_table_config().base_folder.clone())
+            });
+            let ss_table = SsTable::create(&config, &setup.schema, rows.iter().map(|r| r.row_data_view())).unwrap();
+
+            for _ in 0..3 {
+                assert_eq!(setup.value(&ss_table.find_by_full_pk(&setup.pk_row(2).row_data_view()).unwrap().unwrap()), "b");
+            }
+            assert_eq!(setup.value(&ss_table.find_by_full_pk(&setup.pk_row(1).row_data_view()).unwrap().unwrap()), "a");
+
+            let stats = ss_table.key_cache().stats();
+            match size {
+                0 => assert_eq!((stats.hits, stats.misses), (0, 0)),
+                _ => assert_eq!((stats.hits, stats.misses), (2, 2)),
+            }
+            assert_eq!(ss_table.key_cache().len(), size);
+        }
+    }
+
+    #[test]
+    pub fn test_garbled_row() {
+        let setup = SimpleTableTestSetup::new();
+        let rows = (0..4).map(|pk| setup.full_row(pk, Some("abc"), None)).collect::<Vec<_>>();
+        let config = test_table_config();
+        let ss_table = SsTable::create(&config, &setup.schema, rows.iter().map(|r| r.row_data_view())).unwrap();
+
+        let offs = ss_table.index_slice()[1] as usize;
+        let path = ss_table.folder().join(ss_table.name().file_name(SsTableComponent::Data));
+        let mut data = std::fs::read(&path).unwrap();
+        let len = data[offs] as usize;
+        for b in &mut data[offs+1 .. offs+1+len] {
+            *b = 0xff;
+        }
+        std::fs::write(&path, &data).unwrap();
+        let ss_table = SsTable::open(&config, &setup.schema, ss_table.folder(), ss_table.name()).unwrap();
+
+        let pk = setup.pk_row(1).row_data_view().encode_pk();
+        assert!(ss_table.find_by_full_pk(&setup.pk_row(1).row_data_view()).is_err());
+        assert!(ss_table.read_by_pk(&pk, None).is_err());
+
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let quarantine = Quarantine::new(&clock);
+        assert!(ss_table.find_validated_by_pk(&pk, &quarantine).unwrap().is_none());
+        assert_eq!(quarantine.count(), 1);
+
+        let quarantine = Quarantine::new(&clock);
+        assert!(ss_table.read_by_pk(&pk, Some(&quarantine)).unwrap().is_none());
+        assert_eq!(quarantine.count(), 1);
+
+        let quarantine = Quarantine::new(&clock);
+        let pks = ss_table.validated_rows(&quarantine).map(|row| setup.pk(&row.unwrap())).collect::<Vec<_>>();
+        assert_eq!(pks, vec!(0, 2, 3));
+        assert_eq!(quarantine.count(), 1);
+    }
 }
@@ -0,0 +1,391 @@
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+use log::warn;
+
+use crate::hll::HyperLogLog;
+use crate::prelude::*;
+use crate::primitives::*;
+use crate::table::{partition_token, RowColumnIter, RowData};
+use crate::time::{MergeTimestamp, TtlTimestamp};
+
+/// Statistics about an SsTable's contents. They are collected while the SsTable is written and
+///  stored in a separate 'stats' file, serving as input for compaction heuristics and for
+///  operator visibility.
+///
+/// stats format:
+///   fixed u64         row count
+///   fixed u64         tombstone count
+///   fixed u64         min timestamp
+///   fixed u64         max timestamp
+///   varint<usize>     length of min partition key, followed by its raw bytes
+///   varint<usize>     length of max partition key, followed by its raw bytes
+///   HLL registers     sketch of the SsTable's partition keys
+///   histogram         partition sizes in bytes
+///   histogram         partition sizes in rows
+///   fixed u64         number of partitions exceeding the 'large partition' threshold
+///   fixed u32         max expiry (TtlTimestamp), u32::MAX if there is data that never expires
+///   fixed u64         min partition token
+///   fixed u64         max partition token
+///   fixed u32         max touch expiry (TtlTimestamp), 0 if there are no touches. This is
+///                      missing in SsTables written before touches existed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SsTableStats {
+    pub row_count: u64,
+    /// NULL column values (i.e. cells that were explicitly deleted) and row tombstones
+    pub tombstone_count: u64,
+    /// min / max of all row and column timestamps; these are meaningless for an empty SsTable
+    pub min_timestamp: MergeTimestamp,
+    pub max_timestamp: MergeTimestamp,
+    /// partition keys as returned by RowData::partition_key_bytes()
+    pub min_partition_key: Vec<u8>,
+    pub max_partition_key: Vec<u8>,
+    pub partitions: HyperLogLog,
+    pub partition_bytes: Histogram,
+    pub partition_rows: Histogram,
+    pub large_partition_count: u64,
+    /// The point in time when all of the SsTable's data is expired, or None if some of the data
+    ///  never expires. Tombstones count as expiring at the time they were written.
+    pub max_expiry: Option<TtlTimestamp>,
+    /// min / max of the partitions' tokens (see RowData::partition_token); these are meaningless
+    ///  for an empty SsTable
+    pub min_token: u64,
+    pub max_token: u64,
+    /// The latest expiry of the SsTable's touches (see RowTouch), or None if it has none. A
+    ///  touch can extend the expiry of columns in other SsTables, so compaction must not
+    ///  discard their expired columns while it may apply to them.
+    pub max_touch_expiry: Option<TtlTimestamp>,
+}
+
+impl SsTableStats {
+    pub fn new() -> SsTableStats {
+        SsTableStats {
+            row_count: 0,
+            tombstone_count: 0,
+            min_timestamp: MergeTimestamp::from_ticks(u64::MAX),
+            max_timestamp: MergeTimestamp::from_ticks(0),
+            min_partition_key: Vec::new(),
+            max_partition_key: Vec::new(),
+            partitions: HyperLogLog::new(),
+            partition_bytes: Histogram::new(),
+            partition_rows: Histogram::new(),
+            large_partition_count: 0,
+            max_expiry: Some(TtlTimestamp::new(0)),
+            min_token: u64::MAX,
+            max_token: 0,
+            max_touch_expiry: None,
+        }
+    }
+
+    /// estimated number of distinct partitions in the SsTable
+    pub fn partition_count_estimate(&self) -> u64 {
+        self.partitions.estimate()
+    }
+
+    /// true if the SsTable may contain partitions with tokens in the given range
+    pub fn overlaps_tokens(&self, tokens: &RangeInclusive<u64>) -> bool {
+        self.row_count > 0 && self.min_token <= *tokens.end() && self.max_token >= *tokens.start()
+    }
+
+    pub fn write_to<W>(&self, w: &mut W) -> std::io::Result<()> where W: Write {
+        w.encode_fixed_u64(self.row_count)?;
+        w.encode_fixed_u64(self.tombstone_count)?;
+        w.encode_fixed_u64(self.min_timestamp.ticks)?;
+        w.encode_fixed_u64(self.max_timestamp.ticks)?;
+        w.encode_varint_usize(self.min_partition_key.len())?;
+        w.write_all(&self.min_partition_key)?;
+        w.encode_varint_usize(self.max_partition_key.len())?;
+        w.write_all(&self.max_partition_key)?;
+        self.partitions.write_to(w)?;
+        self.partition_bytes.write_to(w)?;
+        self.partition_rows.write_to(w)?;
+        w.encode_fixed_u64(self.large_partition_count)?;
+        w.encode_fixed_u32(self.max_expiry.map(|e| e.epoch_seconds).unwrap_or(u32::MAX))?;
+        w.encode_fixed_u64(self.min_token)?;
+        w.encode_fixed_u64(self.max_token)?;
+        w.encode_fixed_u32(self.max_touch_expiry.map(|e| e.epoch_seconds).unwrap_or(0))
+    }
+
+    /// reads stats written by write_to, failing if they are truncated or malformed
+    pub fn read_from(buf: &[u8]) -> HtResult<SsTableStats> {
+        let corrupt = || HtError::misc("corrupt stats file");
+        let mut offs = 0;
+
+        let row_count = buf.try_decode_fixed_u64(&mut offs).ok_or_else(corrupt)?;
+        let tombstone_count = buf.try_decode_fixed_u64(&mut offs).ok_or_else(corrupt)?;
+        let min_timestamp = MergeTimestamp::from_ticks(buf.try_decode_fixed_u64(&mut offs).ok_or_else(corrupt)?);
+        let max_timestamp = MergeTimestamp::from_ticks(buf.try_decode_fixed_u64(&mut offs).ok_or_else(corrupt)?);
+        let min_partition_key = SsTableStats::read_bytes(buf, &mut offs).ok_or_else(corrupt)?;
+        let max_partition_key = SsTableStats::read_bytes(buf, &mut offs).ok_or_else(corrupt)?;
+        let partitions = HyperLogLog::read_from(buf, &mut offs)?;
+        let partition_bytes = Histogram::read_from(buf, &mut offs)?;
+        let partition_rows = Histogram::read_from(buf, &mut offs)?;
+        let large_partition_count = buf.try_decode_fixed_u64(&mut offs).ok_or_else(corrupt)?;
+        let max_expiry = match buf.try_decode_fixed_u32(&mut offs).ok_or_else(corrupt)? {
+            u32::MAX => None,
+            epoch_seconds => Some(TtlTimestamp::new(epoch_seconds)),
+        };
+        let min_token = buf.try_decode_fixed_u64(&mut offs).ok_or_else(corrupt)?;
+        let max_token = buf.try_decode_fixed_u64(&mut offs).ok_or_else(corrupt)?;
+        let max_touch_expiry = match offs < buf.len() {
+            true => Some(buf.try_decode_fixed_u32(&mut offs).ok_or_else(corrupt)?).filter(|&e| e > 0).map(TtlTimestamp::new),
+            false => None,
+        };
+
+        Ok(SsTableStats {
+            row_count,
+            tombstone_count,
+            min_timestamp,
+            max_timestamp,
+            min_partition_key,
+            max_partition_key,
+            partitions,
+            partition_bytes,
+            partition_rows,
+            large_partition_count,
+            max_expiry,
+            min_token,
+            max_token,
+            max_touch_expiry,
+        })
+    }
+
+    fn read_bytes(buf: &[u8], offs: &mut usize) -> Option<Vec<u8>> {
+        let len = buf.try_decode_varint_usize(offs)?;
+        Some(buf.try_decode_slice(offs, len)?.to_vec())
+    }
+}
+
+/// Collects SsTableStats while an SsTable is written. This relies on rows being added in
+///  primary key order, so that all rows of a partition are added consecutively.
+pub struct StatsCollector {
+    stats: SsTableStats,
+    large_partition_threshold: u64,
+    cur_partition_bytes: u64,
+    cur_partition_rows: u64,
+}
+
+impl StatsCollector {
+    pub fn new(large_partition_threshold: u64) -> StatsCollector {
+        StatsCollector {
+            stats: SsTableStats::new(),
+            large_partition_threshold,
+            cur_partition_bytes: 0,
+            cur_partition_rows: 0,
+        }
+    }
+
+    /// encoded_len is the number of bytes the row occupies in the data file
+    pub fn add_row(&mut self, row: &RowData, encoded_len: u64) {
+        let partition_key = row.partition_key_bytes();
+
+        if self.stats.row_count == 0 {
+            self.add_partition(&partition_key);
+            self.stats.min_partition_key = partition_key.clone();
+            self.stats.max_partition_key = partition_key;
+        }
+        else if partition_key != self.stats.max_partition_key {
+            self.finish_partition();
+            self.add_partition(&partition_key);
+            self.stats.max_partition_key = partition_key;
+        }
+
+        self.cur_partition_bytes += encoded_len;
+        self.cur_partition_rows += 1;
+
+        self.stats.row_count += 1;
+        self.add_timestamp(row.timestamp());
+        if row.row_tombstone().is_some() {
+            self.stats.tombstone_count += 1;
+        }
+        if let Some(touch) = row.touch() {
+            self.add_timestamp(touch.timestamp);
+            self.stats.max_expiry = self.stats.max_expiry.map(|e| e.max(touch.expiry));
+            self.stats.max_touch_expiry = self.stats.max_touch_expiry.max(Some(touch.expiry));
+        }
+
+        for col in RowColumnIter::new(row) {
+            self.add_timestamp(col.timestamp);
+            if col.value.is_none() {
+                self.stats.tombstone_count += 1;
+            }
+
+            if row.schema.is_primary_key(col.col_id) {
+                continue;
+            }
+
+            let expiry = match (&col.value, col.expiry) {
+                (_, Some(expiry)) => Some(expiry),
+                (None, None) => Some(col.timestamp.as_ttl_timestamp()),
+                (Some(_), None) => None,
+            };
+            self.stats.max_expiry = match (self.stats.max_expiry, expiry) {
+                (Some(a), Some(b)) => Some(a.max(b)),
+                _ => None,
+            };
+        }
+    }
+
+    fn add_partition(&mut self, partition_key: &[u8]) {
+        let token = partition_token(partition_key);
+        self.stats.partitions.add_hash(token);
+        self.stats.min_token = self.stats.min_token.min(token);
+        self.stats.max_token = self.stats.max_token.max(token);
+    }
+
+    fn add_timestamp(&mut self, timestamp: MergeTimestamp) {
+        self.stats.min_timestamp = self.stats.min_timestamp.min(timestamp);
+        self.stats.max_timestamp = self.stats.max_timestamp.max(timestamp);
+    }
+
+    fn finish_partition(&mut self) {
+        self.stats.partition_bytes.add(self.cur_partition_bytes);
+        self.stats.partition_rows.add(self.cur_partition_rows);
+
+        if self.cur_partition_bytes > self.large_partition_threshold {
+            warn!("large partition {:?}: {} bytes in {} rows (threshold is {} bytes)",
+                  self.stats.max_partition_key,
+                  self.cur_partition_bytes,
+                  self.cur_partition_rows,
+                  self.large_partition_threshold);
+            self.stats.large_partition_count += 1;
+        }
+
+        self.cur_partition_bytes = 0;
+        self.cur_partition_rows = 0;
+    }
+
+    pub fn finish(mut self) -> SsTableStats {
+        if self.cur_partition_rows > 0 {
+            self.finish_partition();
+        }
+        self.stats
+    }
+}
+
+/// A histogram with exponential buckets: bucket 0 holds the value 0, bucket n (n >= 1) holds
+///  values from 2^(n-1) to 2^n - 1.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+}
+
+impl Histogram {
+    const NUM_BUCKETS: usize = 65;
+
+    pub fn new() -> Histogram {
+        Histogram {
+            buckets: vec!(0; Histogram::NUM_BUCKETS),
+        }
+    }
+
+    fn bucket_index(value: u64) -> usize {
+        (64 - value.leading_zeros()) as usize
+    }
+
+    /// the largest value that falls into a given bucket
+    fn bucket_upper_bound(idx: usize) -> u64 {
+        match idx {
+            0 => 0,
+            64 => u64::MAX,
+            _ => (1u64 << idx) - 1,
+        }
+    }
+
+    pub fn add(&mut self, value: u64) {
+        self.buckets[Histogram::bucket_index(value)] += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        for (b, o) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *b += *o;
+        }
+    }
+
+    /// Returns an upper bound for the given percentile (0.0 to 1.0), i.e. the upper bound of
+    ///  the bucket containing the percentile. Returns 0 for an empty histogram.
+    pub fn percentile(&self, p: f64) -> u64 {
+        let count = self.count();
+        if count == 0 {
+            return 0;
+        }
+
+        let target = ((count as f64 * p).ceil() as u64).max(1);
+        let mut acc = 0;
+        for (idx, &n) in self.buckets.iter().enumerate() {
+            acc += n;
+            if acc >= target {
+                return Histogram::bucket_upper_bound(idx);
+            }
+        }
+        u64::MAX
+    }
+
+    /// upper bound of the largest value in the histogram, or 0 for an empty histogram
+    pub fn max(&self) -> u64 {
+        match self.buckets.iter().rposition(|&n| n > 0) {
+            Some(idx) => Histogram::bucket_upper_bound(idx),
+            None => 0,
+        }
+    }
+
+    pub fn write_to<W>(&self, w: &mut W) -> std::io::Result<()> where W: Write {
+        for &n in &self.buckets {
+            w.encode_varint_u64(n)?;
+        }
+        Ok(())
+    }
+
+    /// fails if the buffer is truncated, or if the buckets' total count overflows
+    pub fn read_from(buf: &[u8], offs: &mut usize) -> HtResult<Histogram> {
+        let buckets = (0..Histogram::NUM_BUCKETS)
+            .map(|_| buf.try_decode_varint_u64(offs))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| HtError::misc("truncated histogram"))?;
+        if buckets.iter().try_fold(0u64, |acc, &n| acc.checked_add(n)).is_none() {
+            return Err(HtError::misc("corrupt histogram"));
+        }
+        Ok(Histogram { buckets })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::stats::Histogram;
+
+    #[test]
+    pub fn test_histogram() {
+        let mut histogram = Histogram::new();
+        assert_eq!(histogram.count(), 0);
+        assert_eq!(histogram.percentile(0.5), 0);
+        assert_eq!(histogram.max(), 0);
+
+        for v in 1..=100 {
+            histogram.add(v);
+        }
+        histogram.add(10_000);
+
+        assert_eq!(histogram.count(), 101);
+        assert_eq!(histogram.percentile(0.0), 1);
+        assert_eq!(histogram.percentile(0.5), 63);
+        assert_eq!(histogram.percentile(0.99), 127);
+        assert_eq!(histogram.percentile(1.0), 16383);
+        assert_eq!(histogram.max(), 16383);
+
+        let mut buf = Vec::new();
+        histogram.write_to(&mut buf).unwrap();
+        let mut offs = 0;
+        assert_eq!(Histogram::read_from(&buf, &mut offs).unwrap(), histogram);
+        assert_eq!(offs, buf.len());
+
+        let mut merged = Histogram::new();
+        merged.add(0);
+        merged.merge(&histogram);
+        assert_eq!(merged.count(), 102);
+        assert_eq!(merged.percentile(0.0), 0);
+    }
+}
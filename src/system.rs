@@ -0,0 +1,199 @@
+//! System tables: in-memory tables describing a node's tables, so that tooling can introspect
+//!  a node through the same API it uses for reading user data. There is no catalog of tables
+//!  yet, so the tables to describe are passed to SystemTables::refresh.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::config::{StorageMode, TableConfig};
+use crate::engine::Table;
+use crate::prelude::*;
+use crate::rowbuilder::RowBuilder;
+use crate::table::{Collation, ColumnId, ColumnSchema, ColumnType, ColumnValue, DetachedRowData, PrimaryKeySpec, TableSchema};
+use crate::time::HtClock;
+
+pub struct SystemTables {
+    /// a row per column: table (partition key), column_id (cluster key), name, type, kind
+    ///  (primary key spec) and collation
+    pub schema: Table,
+    /// a row per TableConfig setting: table (partition key), name (cluster key) and value
+    pub settings: Table,
+    /// a row per live SsTable: table (partition key), generation (cluster key), folder,
+    ///  data_size, row_count, tombstone_count, min_timestamp and max_timestamp (ticks)
+    pub sstables: Table,
+    clock: Arc<dyn HtClock>,
+}
+
+impl SystemTables {
+    pub fn new(clock: &Arc<dyn HtClock>) -> HtResult<SystemTables> {
+        let config = Arc::new(TableConfig {
+            storage_mode: StorageMode::InMemory { max_size: None, evict: false },
+            ..TableConfig::new(PathBuf::new())
+        });
+
+        let schema = SystemTables::table_schema("system_schema", ColumnType::Int, &[
+            ("name", ColumnType::Text), ("type", ColumnType::Text), ("kind", ColumnType::Text), ("collation", ColumnType::Text),
+        ]);
+        let settings = SystemTables::table_schema("system_settings", ColumnType::Text, &[
+            ("value", ColumnType::Text),
+        ]);
+        let sstables = SystemTables::table_schema("system_sstables", ColumnType::BigInt, &[
+            ("folder", ColumnType::Text), ("data_size", ColumnType::BigInt), ("row_count", ColumnType::BigInt),
+            ("tombstone_count", ColumnType::BigInt), ("min_timestamp", ColumnType::BigInt), ("max_timestamp", ColumnType::BigInt),
+        ]);
+
+        Ok(SystemTables {
+            schema: Table::new(&config, &schema, clock)?,
+            settings: Table::new(&config, &settings, clock)?,
+            sstables: Table::new(&config, &sstables, clock)?,
+            clock: clock.clone(),
+        })
+    }
+
+    /// a schema with the table name as partition key, a cluster key of the given type, and
+    ///  regular columns
+    fn table_schema(name: &str, cluster_key_type: ColumnType, regular: &[(&str, ColumnType)]) -> Arc<TableSchema> {
+        let column = |col_id: usize, name: &str, tpe: ColumnType, pk_spec: PrimaryKeySpec| ColumnSchema {
+            col_id: ColumnId(col_id as u8),
+            name: name.to_string(),
+            tpe,
+            pk_spec,
+            collation: Collation::Binary,
+        };
+
+        let cluster_key_name = match cluster_key_type {
+            ColumnType::Int => "column_id",
+            ColumnType::BigInt => "generation",
+            _ => "name",
+        };
+        let mut columns = vec!(
+            column(0, "table", ColumnType::Text, PrimaryKeySpec::PartitionKey),
+            column(1, cluster_key_name, cluster_key_type, PrimaryKeySpec::ClusterKey(true)),
+        );
+        for (idx, (name, tpe)) in regular.iter().enumerate() {
+            columns.push(column(idx + 2, name, tpe.clone(), PrimaryKeySpec::Regular));
+        }
+        Arc::new(TableSchema::new(name, &Uuid::nil(), columns))
+    }
+
+    /// Replaces the system tables' contents with descriptions of the given tables. Rows for
+    ///  tables, columns and SsTables that no longer exist are deleted.
+    pub fn refresh(&self, tables: &[&Table]) -> HtResult<()> {
+        let mut schema_rows = Vec::new();
+        let mut settings_rows = Vec::new();
+        let mut sstable_rows = Vec::new();
+
+        for table in tables {
+            let name = table.schema().name.as_str();
+
+            for col in &table.schema().columns {
+                let tpe = format!("{:?}", col.tpe);
+                let kind = format!("{:?}", col.pk_spec);
+                let collation = format!("{:?}", col.collation);
+                schema_rows.push(self.row(&self.schema, &[
+                    ColumnValue::Text(name), ColumnValue::Int(col.col_id.0 as i32), ColumnValue::Text(&col.name),
+                    ColumnValue::Text(&tpe), ColumnValue::Text(&kind), ColumnValue::Text(&collation),
+                ])?);
+            }
+
+            for (setting, value) in table.config().settings() {
+                settings_rows.push(self.row(&self.settings, &[
+                    ColumnValue::Text(name), ColumnValue::Text(setting), ColumnValue::Text(&value),
+                ])?);
+            }
+
+            for ss_table in table.ss_tables() {
+                let folder = ss_table.folder().to_string_lossy().to_string();
+                let stats = ss_table.stats();
+                sstable_rows.push(self.row(&self.sstables, &[
+                    ColumnValue::Text(name), ColumnValue::BigInt(ss_table.name().generation as i64), ColumnValue::Text(&folder),
+                    ColumnValue::BigInt(ss_table.data_size() as i64), ColumnValue::BigInt(stats.row_count as i64),
+                    ColumnValue::BigInt(stats.tombstone_count as i64), ColumnValue::BigInt(stats.min_timestamp.ticks as i64),
+                    ColumnValue::BigInt(stats.max_timestamp.ticks as i64),
+                ])?);
+            }
+        }
+
+        self.schema.replace_contents(schema_rows)?;
+        self.settings.replace_contents(settings_rows)?;
+        self.sstables.replace_contents(sstable_rows)
+    }
+
+    /// a row with the given values for the table's columns, in column order
+    fn row(&self, table: &Table, values: &[ColumnValue]) -> HtResult<DetachedRowData> {
+        let mut builder = RowBuilder::new(table.schema(), self.clock.now());
+        for (col, value) in table.schema().columns.iter().zip(values) {
+            builder = builder.set(col.col_id, *value);
+        }
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::engine::Table;
+    use crate::slice::ClusteringBounds;
+    use crate::system::SystemTables;
+    use crate::table::{ColumnData, ColumnId, ColumnValue, DetachedRowData};
+    use crate::testutils::{SimpleTableTestSetup, test_table_config};
+    use crate::time::{HtClock, MergeTimestamp};
+
+    #[test]
+    pub fn test_system_tables() {
+        let setup = SimpleTableTestSetup::new();
+        let clock: Arc<dyn HtClock> = setup.clock.clone();
+        let table = Table::new(&test_table_config(), &setup.schema, &clock).unwrap();
+        for pk in 0..2 {
+            table.put(setup.full_row(pk, Some("a"), None)).unwrap();
+            table.flush().unwrap();
+        }
+
+        let system = SystemTables::new(&clock).unwrap();
+        system.refresh(&[&table]).unwrap();
+
+        let partition = |system_table: &Table| {
+            let key = DetachedRowData::assemble(system_table.schema(), &vec!(
+                ColumnData::new(ColumnId(0), clock.now(), None, Some(ColumnValue::Text("test_table"))),
+            )).unwrap();
+            system_table.get_partition(&key, &ClusteringBounds::all()).unwrap()
+        };
+        let text = |row: &DetachedRowData, col_id: u8| match row.row_data_view().read_col_by_id(ColumnId(col_id)).unwrap().value {
+            Some(ColumnValue::Text(s)) => s.to_string(),
+            other => panic!("not a text: {:?}", other),
+        };
+
+        let columns = partition(&system.schema);
+        assert_eq!(columns.iter().map(|row| text(row, 2)).collect::<Vec<_>>(), vec!("pk", "text", "int"));
+        assert_eq!(text(&columns[0], 3), "BigInt");
+        assert_eq!(text(&columns[0], 4), "PartitionKey");
+
+        let settings = partition(&system.settings);
+        let gc_grace = settings.iter().find(|row| text(row, 1) == "gc_grace_seconds").unwrap();
+        assert_eq!(text(gc_grace, 2), table.config().gc_grace_seconds.to_string());
+
+        assert_eq!(partition(&system.sstables).len(), 2);
+
+        // SsTables that were compacted away disappear on the next refresh
+        table.compact_all().unwrap();
+        setup.clock.set(MergeTimestamp::from_ticks(99999));
+        system.refresh(&[&table]).unwrap();
+        let sstables = partition(&system.sstables);
+        assert_eq!(sstables.len(), 1);
+        assert_eq!(sstables[0].row_data_view().read_col_by_id(ColumnId(1)).unwrap().value,
+                   Some(ColumnValue::BigInt(table.ss_tables()[0].name().generation as i64)));
+
+        system.refresh(&[]).unwrap();
+        assert!(partition(&system.schema).is_empty());
+
+        // refreshes leave no tombstones behind
+        for system_table in [&system.schema, &system.settings, &system.sstables] {
+            assert!(system_table.view().mem_table.read().unwrap().is_empty());
+        }
+
+        table.drop_table().unwrap();
+    }
+}
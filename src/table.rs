@@ -1,9 +1,12 @@
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
 use std::io::Write;
 use std::mem::size_of;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use bytes::Bytes;
 use uuid::Uuid;
 
 use crate::prelude::*;
@@ -21,6 +24,11 @@ impl <W> Encode<ColumnId> for W where W: Write {
         self.encode_u8(v.0)
     }
 }
+impl Put<ColumnId> for Vec<u8> {
+    fn put(&mut self, v: ColumnId) {
+        self.put_u8(v.0)
+    }
+}
 impl Decode<ColumnId> for &[u8] {
     fn decode(&self, offs: &mut usize) -> ColumnId {
         ColumnId(self.decode_u8(offs))
@@ -33,6 +41,51 @@ pub enum ColumnType {
     Int,
     BigInt,
     Text,
+    /// days since Jan 1 1970
+    Date,
+    /// nanoseconds since midnight
+    Time,
+    /// Months, days and nanoseconds. These can not be converted into each other (months and
+    ///  days differ in length), so durations have no meaningful order and can not be part of
+    ///  the primary key.
+    Duration,
+    /// an arbitrarily large integer, see Varint
+    Varint,
+}
+
+impl ColumnType {
+    pub fn matches(&self, value: &ColumnValue) -> bool {
+        value.tpe().as_ref() == Some(self)
+    }
+
+    /// the value kind that is stored for values of this type, see ColumnFlags
+    pub fn kind(&self) -> u8 {
+        match self {
+            ColumnType::Boolean => 0,
+            ColumnType::Int => 1,
+            ColumnType::BigInt => 2,
+            ColumnType::Text => 3,
+            ColumnType::Date => 4,
+            ColumnType::Time => 5,
+            ColumnType::Duration => 6,
+            ColumnType::Varint => 7,
+        }
+    }
+
+    /// the type for a stored value kind, None for a reserved kind
+    pub fn of_kind(kind: u8) -> Option<ColumnType> {
+        match kind {
+            0 => Some(ColumnType::Boolean),
+            1 => Some(ColumnType::Int),
+            2 => Some(ColumnType::BigInt),
+            3 => Some(ColumnType::Text),
+            4 => Some(ColumnType::Date),
+            5 => Some(ColumnType::Time),
+            6 => Some(ColumnType::Duration),
+            7 => Some(ColumnType::Varint),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -41,16 +94,62 @@ pub struct ColumnSchema {
     pub name: String,
     pub tpe: ColumnType,
     pub pk_spec: PrimaryKeySpec,
+    /// the order of values in a Text primary key column, ignored for other columns
+    pub collation: Collation,
 }
 
 impl ColumnSchema {
-    fn is_primary_key(&self) -> bool {
+    pub fn is_primary_key(&self) -> bool {
         match self.pk_spec {
             PrimaryKeySpec::PartitionKey => true,
             PrimaryKeySpec::ClusterKey(_) => true,
             PrimaryKeySpec::Regular => false,
         }
     }
+
+    /// compares two of this column's values in the type's order, honoring the collation of
+    ///  Text primary key columns but not descending cluster key columns
+    pub fn compare_values(&self, v1: &ColumnValue, v2: &ColumnValue) -> Ordering {
+        if self.is_primary_key() {
+            self.collation.compare(v1, v2)
+        }
+        else {
+            v1.cmp(v2)
+        }
+    }
+
+    /// compares two of this primary key column's values in clustering order, i.e. honoring
+    ///  the column's collation and descending cluster key columns
+    pub fn compare_pk_values(&self, v1: &ColumnValue, v2: &ColumnValue) -> Ordering {
+        let cmp = self.collation.compare(v1, v2);
+        match self.pk_spec {
+            PrimaryKeySpec::ClusterKey(false) => cmp.reverse(),
+            _ => cmp,
+        }
+    }
+}
+
+/// The order of Text values in primary key columns. A collation affects only the order of rows,
+///  not their identity: keys that differ only in case are different rows for all collations.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Collation {
+    /// UTF-8 byte order
+    Binary,
+    /// by lower case characters, with binary order breaking ties, i.e. "a" < "B" < "b"
+    CaseInsensitive,
+}
+
+impl Collation {
+    pub fn compare(&self, v1: &ColumnValue, v2: &ColumnValue) -> Ordering {
+        match (self, v1, v2) {
+            (Collation::CaseInsensitive, ColumnValue::Text(s1), ColumnValue::Text(s2)) => {
+                s1.chars().flat_map(char::to_lowercase)
+                    .cmp(s2.chars().flat_map(char::to_lowercase))
+                    .then_with(|| s1.cmp(s2))
+            }
+            _ => v1.cmp(v2),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -90,6 +189,53 @@ impl TableSchema {
             None => Err(HtError::misc("column not found")),
         }
     }
+
+    pub fn column_by_name(&self, name: &str) -> HtResult<&ColumnSchema> {
+        match self.columns.iter().find(|c| c.name == name) {
+            Some(c) => Ok(c),
+            None => Err(HtError::misc("column not found")),
+        }
+    }
+
+    /// A schema without columns, for walking rows without knowing their table, e.g. in tools
+    ///  that dump or check SsTables. Column values are self-describing (see ColumnFlags), and
+    ///  RowColumnIter::including_unknown yields all columns with their ids.
+    pub fn unknown() -> TableSchema {
+        TableSchema::new("", &Uuid::nil(), Vec::new())
+    }
+
+    pub fn is_primary_key(&self, col_id: ColumnId) -> bool {
+        self.pk_columns.iter().any(|c| c.col_id == col_id)
+    }
+
+    /// the number of cluster key columns
+    pub fn cluster_key_len(&self) -> usize {
+        self.pk_columns.iter().filter(|c| c.pk_spec != PrimaryKeySpec::PartitionKey).count()
+    }
+
+    /// compares partition keys as returned by RowData::partition_key_bytes() in row order
+    pub fn compare_partition_keys(&self, key1: &[u8], key2: &[u8]) -> Ordering {
+        let mut offs1 = 0;
+        let mut offs2 = 0;
+        for col in self.pk_columns.iter().take_while(|c| c.pk_spec == PrimaryKeySpec::PartitionKey) {
+            let v1 = decode_column_value(&key1, &col.tpe, &mut offs1);
+            let v2 = decode_column_value(&key2, &col.tpe, &mut offs2);
+            let cmp = col.compare_pk_values(&v1, &v2);
+            if cmp != Ordering::Equal {
+                return cmp;
+            }
+        }
+        Ordering::Equal
+    }
+
+    /// checks that the schema can be used for a table: primary key columns must have a type
+    ///  with a total order
+    pub fn validate(&self) -> HtResult<()> {
+        if self.pk_columns.iter().any(|c| c.tpe == ColumnType::Duration) {
+            return Err(HtError::misc("Duration columns can not be part of the primary key"));
+        }
+        Ok(())
+    }
 }
 
 
@@ -111,11 +257,17 @@ impl TableSchema {
 ///                      reference this timestamp
 ///                      (ColumnFlags::COLUMN_TIMESTAMP), saving storage in the frequent case that
 ///                      several columns in a row share the same timestamp.
+///                     If RowFlags::DELTA_TIMESTAMPS is set, this is a signed varint holding the
+///                      difference to a 'timestamp base' that is stored outside the row (e.g. in
+///                      an SsTable's header).
 ///   opt fixed u32     optional (if TTL row flag is set) row TtlTimestamp. We treat empty rows
 ///                      as non-existent, so there is no inherent concept of 'row TTL', but for
 ///                      the frequent case that several / all columns in a row share the same TTL,
 ///                      the row can store a TTL that can then be referenced from columns
 ///                      (ColumnFlags::ROW_EXPIRY)
+///   opt fixed u64     touch timestamp (MergeTimestamp, never delta encoded) - only present if
+///                      RowFlags::ROW_TOUCH is set, see RowTouch
+///   opt fixed u32     touch expiry (TtlTimestamp) - only present if RowFlags::ROW_TOUCH is set
 ///   varint 64         bitset for col_ids of columns present in this row
 ///
 ///   columns:
@@ -123,20 +275,28 @@ impl TableSchema {
 ///     u8              ColumnFlags
 ///     opt fixed u64   column timestamp - only present if column flags indicate that this column's
 ///                      timestamp differs from the row timestamp, otherwise the row's timestamp
-///                      is used as this column's timestamp
+///                      is used as this column's timestamp. If RowFlags::DELTA_TIMESTAMPS is set,
+///                      this is a signed varint holding the difference to the row timestamp.
 ///     opt fixed u32   column TTL - only present if ColumnFlags::COLUMN_EXPIRY and *not*
 ///                      ColumnFlags::ROW_EXPIRY
 ///     opt value       format depends on column type; only if 'is null' column flag is not set
 pub struct RowData<'a> {
     pub schema: Arc<TableSchema>,
     pub buf: &'a [u8],
+    /// the base for rows with RowFlags::DELTA_TIMESTAMPS
+    pub timestamp_base: MergeTimestamp,
 }
 
 impl<'a> RowData<'a> {
     pub fn from_view<'b>(schema: &Arc<TableSchema>, buf: &'b [u8]) -> RowData<'b> {
+        RowData::from_view_with_base(schema, buf, MergeTimestamp::from_ticks(0))
+    }
+
+    pub fn from_view_with_base<'b>(schema: &Arc<TableSchema>, buf: &'b [u8], timestamp_base: MergeTimestamp) -> RowData<'b> {
         RowData {
             schema: schema.clone(),
             buf,
+            timestamp_base,
         }
     }
 
@@ -144,17 +304,88 @@ impl<'a> RowData<'a> {
         self.schema.as_ref()
     }
 
-    /// checks that the buffer is well-formed and fits in with the schema
+    /// the size of the row's encoded data in bytes, see DetachedRowData::encoded_len
+    pub fn encoded_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// the number of bytes write_to writes, i.e. the row's size in an SsTable's data file
+    pub fn stored_len(&self) -> usize {
+        varint_len(self.buf.len() as u64) + self.buf.len()
+    }
+
+    /// Checks that the buffer is well-formed and fits in with the schema: it starts with the
+    ///  values of (a prefix of) the primary key columns, including the full partition key, and
+    ///  each column is decoded completely. Values of columns in the schema must have the
+    ///  column's type, other columns are decoded by the value kind in their flags. The checks use
+    ///  checked decoding, so this returns an error rather than panicking for a garbled buffer,
+    ///  and a row that passes can be read with the regular decoders (e.g. RowData::columns).
     pub fn validate(&self) -> HtResult<()> {
-        //TODO partition key first, then cluster key, then the rest
-        //TODO all columns values have the right type
-        //TODO full partition key present
-        //TODO no surplus bytes at the end
-        //TODO valid row flags
+        let malformed = || HtError::misc("malformed row");
+        let flags = self.flags();
+        let mut offs = self.try_offs_start_column_data()?;
+
+        let mut num_pk_columns = 0;
+        let mut has_regular_columns = false;
+        while offs < self.buf.len() {
+            let col_id = ColumnId(self.buf[offs]);
+            let col_flags = ColumnFlags(*self.buf.get(offs + 1).ok_or_else(malformed)?);
+            offs += 2;
+
+            // columns the schema does not know (e.g. from a newer schema version) are only
+            //  checked structurally, based on the value kind in their flags
+            if let (false, Ok(col_schema)) = (col_flags.is_null(), self.schema.column(col_id)) {
+                if col_flags.value_kind().as_ref() != Some(&col_schema.tpe) {
+                    return Err(HtError::misc("column value does not match the column type"));
+                }
+            }
+
+            let has_expiry = !matches!(col_flags.expiry(), ColumnExpiryKind::NoExpiry);
+            if self.schema.is_primary_key(col_id) && (col_flags.has_col_timestamp() || has_expiry) {
+                return Err(HtError::misc("primary key columns must not have a timestamp or expiry"));
+            }
+            if self.schema.is_primary_key(col_id) {
+                let expected = self.schema.pk_columns.get(num_pk_columns).map(|c| c.col_id);
+                if has_regular_columns || expected != Some(col_id) || col_flags.is_null() {
+                    return Err(HtError::misc("primary key columns must come first, in schema order and with values"));
+                }
+                num_pk_columns += 1;
+            }
+            else {
+                has_regular_columns = true;
+            }
+            if let ColumnExpiryKind::RowExpiry = col_flags.expiry() {
+                if !flags.has_row_expiry() {
+                    return Err(HtError::misc("column refers to a row expiry that the row does not have"));
+                }
+            }
 
-        //TODO full cluster key is present (if flag is set) or only leading columns and no regular columns
-        //TODO ... and not null
+            let has_timestamp = match (col_flags.has_col_timestamp(), flags.has_delta_timestamps()) {
+                (true, false) => self.buf.try_decode_fixed_u64(&mut offs).is_some(),
+                (true, true) => self.buf.try_decode_varint_i64(&mut offs).is_some(),
+                (false, _) => true,
+            };
+            if !has_timestamp {
+                return Err(malformed());
+            }
+            if let ColumnExpiryKind::ColumnExpiry = col_flags.expiry() {
+                self.buf.try_decode_fixed_u32(&mut offs).ok_or_else(malformed)?;
+            }
+            if !col_flags.is_null() {
+                try_decode_column_value_of_kind(self.buf, col_flags.kind(), &mut offs)
+                    .ok_or_else(malformed)?
+                    .validate()?;
+            }
+        }
 
+        let num_partition_key_columns = self.schema.pk_columns.iter()
+            .take_while(|c| c.pk_spec == PrimaryKeySpec::PartitionKey)
+            .count();
+        if num_pk_columns < num_partition_key_columns {
+            return Err(HtError::misc("the partition key is incomplete"));
+        }
+
+        //TODO valid row flags
         Ok(())
     }
 
@@ -170,13 +401,71 @@ impl<'a> RowData<'a> {
         self.buf.decode(&mut 0)
     }
 
+    /// the timestamp up to which the row deletes all data for its primary key, if it is a row
+    ///  tombstone (see DetachedRowData::row_tombstone)
+    pub fn row_tombstone(&self) -> Option<MergeTimestamp> {
+        if self.flags().is_row_tombstone() {
+            Some(self.timestamp())
+        }
+        else {
+            None
+        }
+    }
+
+    /// the row's touch, if it refreshes the expiry of older columns (see RowTouch)
+    pub fn touch(&self) -> Option<RowTouch> {
+        if self.flags().is_touch() {
+            let mut offs = self.offs_start_touch();
+            let timestamp = MergeTimestamp::from_ticks(self.buf.decode_fixed_u64(&mut offs));
+            let expiry = self.buf.decode(&mut offs);
+            Some(RowTouch { timestamp, expiry })
+        }
+        else {
+            None
+        }
+    }
+
+    /// true for a touch without columns to apply it to, i.e. a row that reads should skip
+    pub fn is_touch_only(&self) -> bool {
+        self.flags().is_touch() && !self.flags().is_row_tombstone() && self.columns().all(|c| self.schema.is_primary_key(c.col_id))
+    }
+
+    /// the number of NULL columns, plus one for a row tombstone
+    pub fn tombstone_count(&self) -> u64 {
+        let null_columns = self.columns().filter(|c| c.value.is_none()).count() as u64;
+        null_columns + if self.flags().is_row_tombstone() { 1 } else { 0 }
+    }
+
+    /// true for a row tombstone without newer columns, i.e. a row that reads should skip
+    pub fn is_deleted(&self) -> bool {
+        self.flags().is_row_tombstone() && self.columns().all(|c| self.schema.is_primary_key(c.col_id))
+    }
+
     pub fn timestamp(&self) -> MergeTimestamp {
-        self.buf.decode(&mut 1)
+        if self.flags().has_delta_timestamps() {
+            let delta = self.buf.decode_varint_i64(&mut 1);
+            MergeTimestamp::from_ticks(self.timestamp_base.ticks.wrapping_add(delta as u64))
+        }
+        else {
+            self.buf.decode(&mut 1)
+        }
+    }
+
+    /// the offset of the data following the row timestamp
+    fn offs_after_timestamp(&self) -> usize {
+        let mut offs = 1;
+        if self.flags().has_delta_timestamps() {
+            self.buf.decode_varint_i64(&mut offs);
+        }
+        else {
+            offs += size_of::<MergeTimestamp>();
+        }
+        offs
     }
 
     pub fn expiry(&self) -> Option<TtlTimestamp> {
         if self.flags().has_row_expiry() {
-            let mut offs = 1 + size_of::<u64>();
+            let mut offs = self.offs_after_timestamp();
             Some(self.buf.decode(&mut offs))
         }
         else {
@@ -200,9 +489,10 @@ impl<'a> RowData<'a> {
         let col_id = self.buf.decode(offs);
         let col_flags: ColumnFlags = self.buf.decode(offs);
 
-        let timestamp = match col_flags.has_col_timestamp() {
-            true => MergeTimestamp::from_ticks(self.buf.decode_fixed_u64(offs)),
-            false => row_timestamp,
+        let timestamp = match (col_flags.has_col_timestamp(), self.flags().has_delta_timestamps()) {
+            (true, false) => MergeTimestamp::from_ticks(self.buf.decode_fixed_u64(offs)),
+            (true, true) => MergeTimestamp::from_ticks(row_timestamp.ticks.wrapping_add(self.buf.decode_varint_i64(offs) as u64)),
+            (false, _) => row_timestamp,
         };
 
         use ColumnExpiryKind::*;
@@ -215,71 +505,266 @@ impl<'a> RowData<'a> {
         let mut col_data = None;
 
         if !col_flags.is_null() {
-            col_data = Some(match self.schema.column(col_id).unwrap().tpe { //TODO error handling?
-                ColumnType::Boolean => ColumnValue::Boolean(self.buf.decode_bool(offs)),
-                ColumnType::Int => ColumnValue::Int(self.buf.decode_varint_i32(offs)),
-                ColumnType::BigInt => ColumnValue::BigInt(self.buf.decode_varint_i64(offs)),
-                ColumnType::Text => ColumnValue::Text(self.buf.decode_utf8(offs)),
-            });
+            col_data = Some(decode_column_value_of_kind(&self.buf, col_flags.kind(), offs));
         }
         ColumnData::new (col_id, timestamp, expiry, col_data)
     }
 
-    fn offs_start_column_data(&self) -> usize {
+    /// Advances offs past a column without decoding its value. The column flags hold the
+    ///  value's kind, so this needs no schema lookup, and each part of the column is either
+    ///  fixed size, length prefixed or a varint.
+    fn skip_col(&self, offs: &mut usize) {
+        let _col_id: ColumnId = self.buf.decode(offs);
+        let col_flags: ColumnFlags = self.buf.decode(offs);
+
+        match (col_flags.has_col_timestamp(), self.flags().has_delta_timestamps()) {
+            (true, false) => *offs += size_of::<MergeTimestamp>(),
+            (true, true) => self.buf.skip_varint(offs),
+            (false, _) => {}
+        }
+        if let ColumnExpiryKind::ColumnExpiry = col_flags.expiry() {
+            *offs += size_of::<TtlTimestamp>();
+        }
+
+        if !col_flags.is_null() {
+            match col_flags.value_kind() {
+                Some(ColumnType::Boolean) => *offs += 1,
+                Some(ColumnType::Int) | Some(ColumnType::BigInt) | Some(ColumnType::Date) | Some(ColumnType::Time) => self.buf.skip_varint(offs),
+                Some(ColumnType::Duration) => {
+                    for _ in 0..3 {
+                        self.buf.skip_varint(offs);
+                    }
+                }
+                Some(ColumnType::Varint) => {
+                    let header = self.buf.decode_varint_usize(offs);
+                    *offs += header >> 1;
+                }
+                // values of reserved kinds are length prefixed, see ColumnFlags
+                Some(ColumnType::Text) | None => {
+                    let len = self.buf.decode_varint_usize(offs);
+                    *offs += len;
+                }
+            }
+        }
+    }
+
+    /// the offset of the touch, or of the column data if the row has no touch
+    fn offs_start_touch(&self) -> usize {
         let row_flags = RowFlags(self.buf[0]);
-        let mut offs = 1 + size_of::<MergeTimestamp>();
+        let mut offs = self.offs_after_timestamp();
 
         if row_flags.has_row_expiry() {
-            self.buf.decode_varint_u32(&mut offs);
+            offs += size_of::<TtlTimestamp>();
         }
 
         offs
     }
 
+    /// same as offs_start_column_data, but failing instead of panicking if the row's header is
+    ///  truncated
+    fn try_offs_start_column_data(&self) -> HtResult<usize> {
+        let malformed = || HtError::misc("malformed row header");
+
+        let flags = RowFlags(*self.buf.first().ok_or_else(malformed)?);
+        let mut offs = 1;
+        let has_timestamp = match flags.has_delta_timestamps() {
+            true => self.buf.try_decode_varint_i64(&mut offs).is_some(),
+            false => self.buf.try_decode_fixed_u64(&mut offs).is_some(),
+        };
+        if !has_timestamp {
+            return Err(malformed());
+        }
+        if flags.has_row_expiry() {
+            self.buf.try_decode_fixed_u32(&mut offs).ok_or_else(malformed)?;
+        }
+        if flags.is_touch() {
+            self.buf.try_decode_fixed_u64(&mut offs).ok_or_else(malformed)?;
+            self.buf.try_decode_fixed_u32(&mut offs).ok_or_else(malformed)?;
+        }
+        Ok(offs)
+    }
+
+    fn offs_start_column_data(&self) -> usize {
+        let mut offs = self.offs_start_touch();
+        if self.flags().is_touch() {
+            offs += size_of::<MergeTimestamp>() + size_of::<TtlTimestamp>();
+        }
+        offs
+    }
+
+    /// Compares two rows in primary key order. This panics if a row's primary key is malformed,
+    ///  see try_compare_by_pk for rows that may be corrupt.
     pub fn compare_by_pk(&self, other: &RowData) -> Ordering {
-        let mut offs_self = self.offs_start_column_data();
-        let mut offs_other = other.offs_start_column_data();
-
-        for col_meta in &self.schema.columns {
-            let desc = match col_meta.pk_spec {
-                PrimaryKeySpec::PartitionKey => false,
-                PrimaryKeySpec::ClusterKey(asc) => !asc,
-                PrimaryKeySpec::Regular => return Ordering::Equal
+        self.try_compare_by_pk(other).expect("malformed primary key")
+    }
+
+    /// same as compare_by_pk, but failing instead of panicking if a row's primary key is
+    ///  malformed, e.g. for searching the rows of an SsTable
+    pub fn try_compare_by_pk(&self, other: &RowData) -> HtResult<Ordering> {
+        let mut offs_self = self.try_offs_start_column_data()?;
+        let mut offs_other = other.try_offs_start_column_data()?;
+
+        for col_meta in &self.schema.pk_columns {
+            let cmp = match (self.read_pk_value(col_meta, &mut offs_self)?, other.read_pk_value(col_meta, &mut offs_other)?) {
+                (Some(v1), Some(v2)) => col_meta.compare_pk_values(&v1, &v2),
+                // a prefix of the primary key sorts before all keys starting with it
+                (None, None) => return Ok(Ordering::Equal),
+                (None, Some(_)) => return Ok(Ordering::Less),
+                (Some(_), None) => return Ok(Ordering::Greater),
             };
+            if cmp != Ordering::Equal {
+                return Ok(cmp);
+            }
+        }
 
-            //TODO special handling for primary key columns: never store TTL or timestamp
+        Ok(Ordering::Equal)
+    }
 
-            //TODO optimization: "read_col_value" to avoid having to pass in timestamps
-            let col_self = self.read_col(self.timestamp(), self.expiry(), &mut offs_self);
-            let col_other = other.read_col(other.timestamp(), other.expiry(), &mut offs_other);
+    /// Compares the row's primary key to a PrimaryKey in primary key order, like compare_by_pk
+    ///  does for two rows. This decodes the key in lockstep with the row, so it does not allocate.
+    pub fn compare_to_pk(&self, pk: &PrimaryKey) -> Ordering {
+        self.try_compare_to_pk(pk).expect("malformed primary key")
+    }
 
-            assert!(col_meta.col_id == col_self.col_id);
-            assert!(col_meta.col_id == col_other.col_id);
+    /// same as compare_to_pk, but failing instead of panicking if the row's primary key is
+    ///  malformed
+    pub fn try_compare_to_pk(&self, pk: &PrimaryKey) -> HtResult<Ordering> {
+        let mut offs_self = self.try_offs_start_column_data()?;
+        let mut offs_pk = 0;
 
-            let cmp = match (&col_self.value, &col_other.value) {
-                (Some(v1), Some(v2)) => v1.cmp(v2),
-                _ => panic!("primary key columns must not be null")
+        for col_meta in &self.schema.pk_columns {
+            let cmp = match self.read_pk_value(col_meta, &mut offs_self)? {
+                Some(v) => col_meta.compare_pk_values(&v, &decode_column_value(&pk.buf, &col_meta.tpe, &mut offs_pk)),
+                None => return Ok(Ordering::Less),
             };
-
-            match cmp {
-                Ordering::Equal => {}
-                _ if desc => return cmp.reverse(),
-                _ => return cmp
+            if cmp != Ordering::Equal {
+                return Ok(cmp);
             }
         }
 
-        Ordering::Equal
+        Ok(Ordering::Equal)
+    }
+
+    /// Reads a primary key column's value. Primary key columns have no timestamp or expiry of
+    ///  their own (see validate), so this skips only the column id and flags. Decoding is
+    ///  checked, so a malformed value is an error rather than a panic.
+    ///
+    /// Returns None if the row has no value for the column, i.e. if it contains only a prefix of
+    ///  the primary key (e.g. for seeking to the start of a partition).
+    fn read_pk_value(&self, col_meta: &ColumnSchema, offs: &mut usize) -> HtResult<Option<ColumnValue<'_>>> {
+        if *offs >= self.buf.len() {
+            return Ok(None);
+        }
+        let col_id = ColumnId(self.buf[*offs]);
+        if col_id != col_meta.col_id {
+            return Ok(None);
+        }
+
+        let malformed = || HtError::misc("malformed primary key column");
+        let col_flags = ColumnFlags(*self.buf.get(*offs + 1).ok_or_else(malformed)?);
+        if col_flags.is_null() {
+            return Err(HtError::misc("primary key columns must not be null"));
+        }
+
+        let mut value_offs = *offs + 2;
+        let result = try_decode_column_value(self.buf, &col_meta.tpe, &mut value_offs).ok_or_else(malformed)?;
+        *offs = value_offs;
+        Ok(Some(result))
+    }
+
+    /// a hash of the partition key bytes, identifying the partition e.g. for locking
+    pub fn partition_token(&self) -> u64 {
+        partition_token(&self.partition_key_bytes())
+    }
+
+    /// The values of the partition key columns in their raw encoding, i.e. without column flags,
+    ///  timestamps etc. Rows of the same partition have identical partition key bytes.
+    pub fn partition_key_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+
+        let mut offs = self.offs_start_column_data();
+        for col_meta in self.schema.pk_columns.iter().take_while(|c| c.pk_spec == PrimaryKeySpec::PartitionKey) {
+            let col = self.read_col(self.timestamp(), self.expiry(), &mut offs);
+            assert!(col_meta.col_id == col.col_id);
+
+            let value = col.value.expect("partition key columns must not be null");
+            DetachedRowData::encode_column_value(&mut result, &value);
+        }
+        result
+    }
+
+    /// the row's primary key on its own, see PrimaryKey
+    pub fn encode_pk(&self) -> PrimaryKey {
+        let mut buf = Vec::new();
+        for col in self.columns().take(self.schema.pk_columns.len()) {
+            let value = col.value.expect("primary key columns must not be null");
+            DetachedRowData::encode_column_value(&mut buf, &value);
+        }
+        PrimaryKey { schema: self.schema.clone(), buf: Bytes::from(buf) }
     }
 
     pub fn columns(&'a self) -> RowColumnIter<'a> {
-        RowColumnIter { row: &self, offs: 0 }
+        RowColumnIter::new(self)
+    }
+
+    /// a copy of the row with only its primary key columns and the given columns
+    pub fn project(&self, col_ids: &[ColumnId]) -> DetachedRowData {
+        let col_ids = self.schema.pk_columns.iter()
+            .map(|c| c.col_id)
+            .chain(col_ids.iter().cloned())
+            .collect::<Vec<_>>();
+        DetachedRowData::assemble_unchecked(&self.schema, &self.columns().projected(&col_ids).collect(), self.row_tombstone(), self.touch())
+    }
+
+    /// Renders the row's columns with their names, values, timestamps and expiry, e.g. for log
+    ///  messages and test failures:
+    ///   {pk: BigInt(1) @2021-03-04T05:06:07.089Z#c0@ctx12/t5, text: null @2021-03-04T05:06:07.090Z#c0@ctx12/t5 ttl 99999}
+    ///  Columns that are not in the schema are shown with their ids, e.g. '#7'.
+    pub fn describe(&self) -> String {
+        let columns = self.columns().including_unknown()
+            .map(|col| {
+                let name = self.schema.column(col.col_id)
+                    .map(|c| c.name.clone())
+                    .unwrap_or_else(|_| format!("#{}", col.col_id.0));
+                let value = match col.value {
+                    None => "null".to_string(),
+                    Some(v) => format!("{:?}", v),
+                };
+                match col.expiry {
+                    None => format!("{}: {} @{}", name, value, col.timestamp),
+                    Some(expiry) => format!("{}: {} @{} ttl {}", name, value, col.timestamp, expiry.epoch_seconds),
+                }
+            })
+            .collect::<Vec<_>>();
+        format!("{{{}}}", columns.join(", "))
+    }
+
+    /// Creates an owned copy of this row's data. Delta encoded timestamps are resolved, since
+    ///  a DetachedRowData has no timestamp base.
+    pub fn detach(&self) -> DetachedRowData {
+        if self.flags().has_delta_timestamps() {
+            return DetachedRowData::assemble_unchecked(&self.schema, &self.columns().including_unknown().collect(), self.row_tombstone(), self.touch());
+        }
+
+        DetachedRowData {
+            schema: self.schema.clone(),
+            buf: Bytes::copy_from_slice(self.buf),
+        }
+    }
+
+    /// Encodes this row with RowFlags::DELTA_TIMESTAMPS relative to a given timestamp base,
+    ///  e.g. for storing it in an SsTable. Timestamps in an SsTable are typically close to each
+    ///  other, so this saves several bytes per timestamp.
+    pub fn encode_with_timestamp_base(&self, timestamp_base: MergeTimestamp) -> Vec<u8> {
+        DetachedRowData::encode(&self.schema, &self.columns().including_unknown().collect(), Some(timestamp_base), self.row_tombstone(), self.touch())
     }
 
     pub fn merge(&self, other: &RowData) -> DetachedRowData {
         assert_eq!(self.schema, other.schema);
 
-        let self_columns = &mut self.columns();
-        let other_columns = &mut other.columns();
+        // columns the schema does not know, e.g. from a newer schema version, are kept
+        let self_columns = &mut self.columns().including_unknown();
+        let other_columns = &mut other.columns().including_unknown();
 
         let mut cur_self = self_columns.next();
         let mut cur_other = other_columns.next();
@@ -328,44 +813,117 @@ impl<'a> RowData<'a> {
             }
         }
 
-        DetachedRowData::assemble(
+        // a row tombstone deletes the other row's older columns, and the newer row tombstone
+        //  takes precedence
+        let row_tombstone = self.row_tombstone().max(other.row_tombstone());
+        if let Some(tombstone) = row_tombstone {
+            columns.retain(|c| self.schema.is_primary_key(c.col_id) || c.timestamp > tombstone);
+        }
+
+        // the newer touch takes precedence, and it is kept so that it applies to columns in
+        //  rows that are merged later. A newer row tombstone deletes everything it applies to.
+        let touch = self.touch().max(other.touch())
+            .filter(|touch| row_tombstone.map(|tombstone| touch.timestamp > tombstone).unwrap_or(true));
+        if let Some(touch) = touch {
+            for col in columns.iter_mut() {
+                if !self.schema.is_primary_key(col.col_id) {
+                    touch.apply(col);
+                }
+            }
+        }
+
+        DetachedRowData::assemble_unchecked(
             &self.schema.clone(),
-            &columns
+            &columns,
+            row_tombstone,
+            touch,
         )
     }
 }
 
+/// A source of rows in primary key order that can be repositioned, e.g. for skip scans
+pub trait SeekableRows<'a>: Iterator<Item=HtResult<RowData<'a>>> {
+    /// positions the source so that the next row is the first one with a primary key greater
+    ///  than or equal to that of pks; pks is a row containing (at least) the full primary key
+    fn seek(&mut self, pks: &RowData) -> HtResult<()>;
+}
+
+/// Iterates over a row's columns, starting after the row header. Row timestamp and expiry are
+///  decoded once rather than per column. Columns that are not in the row's schema are skipped.
 pub struct RowColumnIter<'a> {
     row: &'a RowData<'a>,
+    row_timestamp: MergeTimestamp,
+    row_expiry: Option<TtlTimestamp>,
     offs: usize,
+    /// if set, other columns are skipped without decoding them
+    col_ids: Option<&'a [ColumnId]>,
+    include_unknown: bool,
 }
 
 impl <'a> RowColumnIter<'a> {
     pub fn new(row: &'a RowData<'a>) -> RowColumnIter<'a> {
-        let offs = row.offs_start_column_data();
         RowColumnIter {
             row,
-            offs
+            row_timestamp: row.timestamp(),
+            row_expiry: row.expiry(),
+            offs: row.offs_start_column_data(),
+            col_ids: None,
+            include_unknown: false,
         }
     }
+
+    /// yields columns that are not in the row's schema rather than skipping them
+    pub fn including_unknown(mut self) -> RowColumnIter<'a> {
+        self.include_unknown = true;
+        self
+    }
+
+    /// pairs each column with its schema
+    pub fn with_schema(self) -> impl Iterator<Item=(&'a ColumnSchema, ColumnData<'a>)> {
+        let schema = &self.row.schema;
+        self.map(move |col| (schema.column(col.col_id).expect("column not in schema"), col))
+    }
+
+    /// Yields only the given columns. The other columns are skipped without decoding their
+    ///  values, which is cheaper for reads of a few columns of a wide row.
+    pub fn projected(mut self, col_ids: &'a [ColumnId]) -> RowColumnIter<'a> {
+        self.col_ids = Some(col_ids);
+        self
+    }
+
+    /// the values of the given columns, see projected
+    pub fn values_only(self, col_ids: &'a [ColumnId]) -> impl Iterator<Item=(ColumnId, Option<ColumnValue<'a>>)> {
+        self.projected(col_ids).map(|col| (col.col_id, col.value))
+    }
 }
 
 impl <'a> Iterator for RowColumnIter<'a> {
     type Item = ColumnData<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.offs >= self.row.buf.len() {
-            None
-        }
-        else {
-            Some(self.row.read_col(self.row.timestamp(), self.row.expiry(), &mut self.offs))
+        while self.offs < self.row.buf.len() {
+            let col_id: ColumnId = self.row.buf.decode(&mut self.offs.clone());
+            let skip = match self.col_ids {
+                Some(col_ids) => !col_ids.contains(&col_id),
+                // columns the schema does not know, e.g. from a newer schema version
+                None => !self.include_unknown && self.row.schema.column(col_id).is_err(),
+            };
+            if skip {
+                self.row.skip_col(&mut self.offs);
+                continue;
+            }
+            return Some(self.row.read_col(self.row_timestamp, self.row_expiry, &mut self.offs));
         }
+        None
     }
 }
 
+/// An owned row. Its buffer is reference counted, so clones share the row's data instead of
+///  copying it, e.g. when the same row is held in a MemTable and handed to a listener.
+#[derive(Clone)]
 pub struct DetachedRowData {
     schema: Arc<TableSchema>,
-    buf: Vec<u8>,
+    buf: Bytes,
 }
 
 ordered!(DetachedRowData);
@@ -375,21 +933,21 @@ impl DetachedRowData {
         a.row_data_view().compare_by_pk(&b.row_data_view())
     }
 
-    fn most_frequent_timestamp(columns: &Vec<ColumnData>) -> MergeTimestamp {
-        //TODO how to handle 'no columns'?
-        assert!(columns.len() > 0);
-
+    /// ties are broken by picking the latest timestamp, so that encoding is deterministic
+    fn most_frequent_timestamp(columns: &[&ColumnData]) -> Option<MergeTimestamp> {
         let mut timestamp_counter = HashMap::new();
         columns.iter().for_each(|c| {
             let count: u32 = *timestamp_counter.get(&c.timestamp).unwrap_or(&0);
             timestamp_counter.insert(c.timestamp, count + 1);
         });
 
-        let max = timestamp_counter.iter().max_by_key(|e| e.1);
-        *max.unwrap().0
+        timestamp_counter.iter()
+            .max_by_key(|e| (e.1, e.0))
+            .map(|e| *e.0)
     }
 
-    fn most_frequent_expiry(columns: &Vec<ColumnData>) -> Option<TtlTimestamp> {
+    /// ties are broken like in most_frequent_timestamp
+    fn most_frequent_expiry(columns: &[&ColumnData]) -> Option<TtlTimestamp> {
 
         let mut timestamp_counter = HashMap::new();
         columns.iter().for_each(|c| {
@@ -403,76 +961,494 @@ impl DetachedRowData {
         });
 
         timestamp_counter.iter()
-            .max_by_key(|e| e.1)
+            .max_by_key(|e| (e.1, e.0))
             .map(|e|*e.0)
     }
 
-    fn encode_column(buf: &mut Vec<u8>, col: &ColumnData, row_timestamp: MergeTimestamp, row_expiry: Option<TtlTimestamp>) {
-        buf.encode(col.col_id).expect("error writing Vec<u8>"); //TODO unchecked variant for Vec<u8>?
+    fn encode_column(buf: &mut Vec<u8>, col: &ColumnData, row_timestamp: MergeTimestamp, row_expiry: Option<TtlTimestamp>, delta_timestamps: bool) {
+        buf.put(col.col_id);
 
         let col_flags = ColumnFlags::new(
-            col.value.is_none(),
+            col.value.as_ref(),
             col.timestamp != row_timestamp,
             col.expiry.is_some() && col.expiry != row_expiry,
             col.expiry.is_some() && col.expiry == row_expiry,
         );
 
-        buf.encode(col_flags).expect("error writing Vec<u8>");
+        buf.put(col_flags);
 
         if col.timestamp != row_timestamp {
-            buf.encode(col.timestamp).expect("error writing Vec<u8>");
+            if delta_timestamps {
+                buf.put_varint_i64(col.timestamp.ticks.wrapping_sub(row_timestamp.ticks) as i64);
+            }
+            else {
+                buf.put(col.timestamp);
+            }
         }
 
+        if let (Some(expiry), true) = (col.expiry, col.expiry != row_expiry) {
+            buf.put(expiry);
+        }
 
-        match col.value {
-            None => {}
-            Some(ColumnValue::Boolean(v)) => buf.encode_bool(v).expect("error writing Vec<u8>"),
-            Some(ColumnValue::Int(v)) => buf.encode_varint_i32(v).expect("error writing Vec<u8>"),
-            Some(ColumnValue::BigInt(v)) => buf.encode_varint_i64(v).expect("error writing Vec<u8>"),
-            Some(ColumnValue::Text(v)) => buf.encode_utf8(v).expect("error writing Vec<u8>"),
+        if let Some(value) = &col.value {
+            DetachedRowData::encode_column_value(buf, value);
         }
     }
 
-    pub fn assemble(schema: &Arc<TableSchema>, columns: &Vec<ColumnData>) -> DetachedRowData {
-        let row_timestamp = DetachedRowData::most_frequent_timestamp(columns);
-        let row_expiry = DetachedRowData::most_frequent_expiry(columns);
+    pub fn encode_column_value(buf: &mut Vec<u8>, value: &ColumnValue) {
+        match *value {
+            ColumnValue::Boolean(v) => buf.put_bool(v),
+            ColumnValue::Int(v) => buf.put_varint_i32(v),
+            ColumnValue::BigInt(v) => buf.put_varint_i64(v),
+            ColumnValue::Text(v) => buf.put_utf8(v),
+            ColumnValue::Date(v) => buf.put_varint_u32(v),
+            ColumnValue::Time(v) => buf.put_varint_u64(v),
+            ColumnValue::Duration { months, days, nanos } => {
+                buf.put_varint_i32(months);
+                buf.put_varint_i32(days);
+                buf.put_varint_i64(nanos);
+            }
+            ColumnValue::Varint(v) => {
+                buf.put_varint_usize((v.magnitude.len() << 1) | v.negative as usize);
+                buf.extend_from_slice(v.magnitude);
+            }
+            ColumnValue::Unknown { data, .. } => {
+                buf.put_varint_usize(data.len());
+                buf.extend_from_slice(data);
+            }
+        }
+    }
 
-        let row_flags = RowFlags::create(row_expiry.is_some());
+    /// Encodes columns as a row. A column with a value of None is stored as NULL, deleting the
+    ///  column's previous value when the row is written, while columns that are left out keep
+    ///  their values. See RowBuilder for a more explicit API.
+    ///
+    /// Primary key columns must come first, in schema order and with values. A row can have
+    ///  only a prefix of the primary key, e.g. for seeking. Regular columns are sorted by id.
+    ///  Values are not checked against the column types (see RowData::validate).
+    ///
+    /// The row timestamp is chosen based on the regular columns. A row without regular columns
+    ///  does not exist (see RowData), so it can only serve as a key, e.g. for lookups - writes
+    ///  reject it. Its timestamp is that of the primary key columns.
+    pub fn assemble(schema: &Arc<TableSchema>, columns: &Vec<ColumnData>) -> HtResult<DetachedRowData> {
+        let columns = DetachedRowData::ordered_columns(schema, columns)?;
+        Ok(DetachedRowData::assemble_unchecked(schema, &columns, None, None))
+    }
 
-        let mut buf = Vec::new();
-        buf.encode(row_flags).expect("error writing Vec<u8>");
+    /// A row deleting all data for a primary key that was written up to and including the
+    ///  given timestamp. Merging it with older data yields a row tombstone without regular
+    ///  columns, which reads skip (see RowData::is_deleted), and writes with a later timestamp
+    ///  are merged on top of it. Compaction discards row tombstones like NULL columns.
+    ///
+    /// pk_columns must hold all primary key columns; their timestamps are ignored.
+    pub fn row_tombstone(schema: &Arc<TableSchema>, pk_columns: &Vec<ColumnData>, timestamp: MergeTimestamp) -> HtResult<DetachedRowData> {
+        let columns = DetachedRowData::ordered_columns(schema, pk_columns)?;
+        if columns.len() != schema.pk_columns.len() || columns.iter().any(|c| !schema.is_primary_key(c.col_id)) {
+            return Err(HtError::misc("a row tombstone must have exactly the primary key columns"));
+        }
+        Ok(DetachedRowData::assemble_unchecked(schema, &columns, Some(timestamp), None))
+    }
 
-        let timestamp = DetachedRowData::most_frequent_timestamp(columns);
-        buf.encode(timestamp).expect("error writing Vec<u8>");
+    /// A row without values that refreshes the expiry of the columns that were written up to
+    ///  and including its timestamp, when it is merged with them (see RowTouch) - e.g. for
+    ///  extending the lifetime of a session without rewriting its data. Reads skip it if there
+    ///  are no such columns (see RowData::is_touch_only).
+    ///
+    /// pk_columns must hold all primary key columns; their timestamps are ignored.
+    pub fn touch(schema: &Arc<TableSchema>, pk_columns: &Vec<ColumnData>, timestamp: MergeTimestamp, expiry: TtlTimestamp) -> HtResult<DetachedRowData> {
+        let columns = DetachedRowData::ordered_columns(schema, pk_columns)?;
+        if columns.len() != schema.pk_columns.len() || columns.iter().any(|c| !schema.is_primary_key(c.col_id)) {
+            return Err(HtError::misc("a touch must have exactly the primary key columns"));
+        }
+        Ok(DetachedRowData::assemble_unchecked(schema, &columns, None, Some(RowTouch { timestamp, expiry })))
+    }
+
+    /// checks the order of primary key columns and sorts regular columns, see assemble
+    fn ordered_columns<'c>(schema: &TableSchema, columns: &Vec<ColumnData<'c>>) -> HtResult<Vec<ColumnData<'c>>> {
+        if columns.is_empty() {
+            return Err(HtError::misc("a row must have at least one column"));
+        }
+
+        let num_pk_columns = columns.iter()
+            .take_while(|c| schema.is_primary_key(c.col_id))
+            .count();
+        for (col, pk_col) in columns[..num_pk_columns].iter().zip(&schema.pk_columns) {
+            if col.col_id != pk_col.col_id {
+                return Err(HtError::misc("primary key columns must be in schema order"));
+            }
+            if col.value.is_none() {
+                return Err(HtError::misc("primary key columns must not be NULL"));
+            }
+        }
+        if num_pk_columns > schema.pk_columns.len() {
+            return Err(HtError::misc("primary key column is present more than once"));
+        }
+
+        let mut columns = columns.clone();
+        let regular_columns = &mut columns[num_pk_columns..];
+        if regular_columns.iter().any(|c| schema.is_primary_key(c.col_id)) {
+            return Err(HtError::misc("primary key columns must come before regular columns"));
+        }
+        regular_columns.sort_by_key(|c| c.col_id);
+        if regular_columns.windows(2).any(|w| w[0].col_id == w[1].col_id) {
+            return Err(HtError::misc("column is present more than once"));
+        }
+        Ok(columns)
+    }
+
+    /// assemble for columns that are known to be in order, e.g. because they were taken from
+    ///  existing rows, with the row tombstone timestamp (if any) of these rows
+    pub fn assemble_unchecked(schema: &Arc<TableSchema>, columns: &Vec<ColumnData>, row_tombstone: Option<MergeTimestamp>, touch: Option<RowTouch>) -> DetachedRowData {
+        DetachedRowData {
+            schema: schema.clone(),
+            buf: Bytes::from(DetachedRowData::encode(schema, columns, None, row_tombstone, touch)),
+        }
+    }
+
+    /// Encodes a row, with delta encoded timestamps if a timestamp base is passed in. Primary
+    ///  key columns are stored without a timestamp or expiry of their own, so they are
+    ///  decoded with the row timestamp and no expiry. The row timestamp is the row tombstone's
+    ///  timestamp for row tombstones, and the row timestamp and expiry are chosen based on the
+    ///  regular columns (if any) otherwise, falling back to the touch's timestamp.
+    fn encode(schema: &TableSchema, columns: &Vec<ColumnData>, timestamp_base: Option<MergeTimestamp>, row_tombstone: Option<MergeTimestamp>, touch: Option<RowTouch>) -> Vec<u8> {
+        let (row_timestamp, row_expiry) = DetachedRowData::row_timestamp_and_expiry(schema, columns, row_tombstone, touch);
+        let encoded_len = DetachedRowData::precomputed_len(schema, columns, timestamp_base, row_tombstone, touch);
+
+        let mut row_flags = RowFlags::create(row_expiry.is_some());
+        if timestamp_base.is_some() {
+            row_flags = row_flags.with_delta_timestamps();
+        }
+        if row_tombstone.is_some() {
+            row_flags = row_flags.with_row_tombstone();
+        }
+        if touch.is_some() {
+            row_flags = row_flags.with_touch();
+        }
+
+        let mut buf = Vec::with_capacity(encoded_len);
+        buf.put(row_flags);
+
+        match timestamp_base {
+            Some(base) => buf.put_varint_i64(row_timestamp.ticks.wrapping_sub(base.ticks) as i64),
+            None => buf.put(row_timestamp),
+        }
 
         match row_expiry {
-            Some(ttl) => buf.encode(ttl).expect("error writing Vec<u8>"),
+            Some(ttl) => buf.put(ttl),
             None => {}
         }
 
-        //TODO verify that pk columns go first and are in schema order
-        //TODO verify that pk columns can not be null - absent is ok for incomplete rows, but explicit values of null are not
+        if let Some(touch) = touch {
+            buf.put_fixed_u64(touch.timestamp.ticks);
+            buf.put(touch.expiry);
+        }
 
         for col in columns {
-            DetachedRowData::encode_column(&mut buf, col, row_timestamp, row_expiry);
+            if schema.is_primary_key(col.col_id) {
+                buf.put(col.col_id);
+                buf.put(ColumnFlags::new(col.value.as_ref(), false, false, false));
+                if let Some(value) = &col.value {
+                    DetachedRowData::encode_column_value(&mut buf, value);
+                }
+            }
+            else {
+                DetachedRowData::encode_column(&mut buf, col, row_timestamp, row_expiry, timestamp_base.is_some());
+            }
         }
 
+        debug_assert_eq!(buf.len(), encoded_len);
+        buf
+    }
+
+    /// the row timestamp and expiry encode chooses, see there
+    fn row_timestamp_and_expiry(schema: &TableSchema, columns: &[ColumnData], row_tombstone: Option<MergeTimestamp>, touch: Option<RowTouch>) -> (MergeTimestamp, Option<TtlTimestamp>) {
+        let regular_columns = columns.iter()
+            .filter(|c| !schema.is_primary_key(c.col_id))
+            .collect::<Vec<_>>();
+        let row_timestamp = row_tombstone
+            .or_else(|| DetachedRowData::most_frequent_timestamp(&regular_columns))
+            .or_else(|| touch.map(|touch| touch.timestamp))
+            .or_else(|| DetachedRowData::most_frequent_timestamp(&columns.iter().collect::<Vec<_>>()))
+            .unwrap_or(MergeTimestamp::from_ticks(0));
+        (row_timestamp, DetachedRowData::most_frequent_expiry(&regular_columns))
+    }
+
+    /// the number of bytes encode writes for a row, computed without encoding it so that the
+    ///  buffer can be allocated with its exact size
+    fn precomputed_len(schema: &TableSchema, columns: &[ColumnData], timestamp_base: Option<MergeTimestamp>, row_tombstone: Option<MergeTimestamp>, touch: Option<RowTouch>) -> usize {
+        let (row_timestamp, row_expiry) = DetachedRowData::row_timestamp_and_expiry(schema, columns, row_tombstone, touch);
+
+        let mut encoded_len = 1 + match timestamp_base {
+            Some(base) => varint_len_i64(row_timestamp.ticks.wrapping_sub(base.ticks) as i64),
+            None => size_of::<MergeTimestamp>(),
+        };
+        if row_expiry.is_some() {
+            encoded_len += size_of::<TtlTimestamp>();
+        }
+        if touch.is_some() {
+            encoded_len += size_of::<MergeTimestamp>() + size_of::<TtlTimestamp>();
+        }
+        for col in columns {
+            encoded_len += if schema.is_primary_key(col.col_id) {
+                2 + col.value.as_ref().map(DetachedRowData::encoded_value_len).unwrap_or(0)
+            }
+            else {
+                DetachedRowData::encoded_column_len(col, row_timestamp, row_expiry, timestamp_base.is_some())
+            };
+        }
+        encoded_len
+    }
+
+    /// the number of bytes encode_column writes for a column
+    fn encoded_column_len(col: &ColumnData, row_timestamp: MergeTimestamp, row_expiry: Option<TtlTimestamp>, delta_timestamps: bool) -> usize {
+        let mut result = 2;
+        if col.timestamp != row_timestamp {
+            result += if delta_timestamps {
+                varint_len_i64(col.timestamp.ticks.wrapping_sub(row_timestamp.ticks) as i64)
+            }
+            else {
+                size_of::<MergeTimestamp>()
+            };
+        }
+        if col.expiry.is_some() && col.expiry != row_expiry {
+            result += size_of::<TtlTimestamp>();
+        }
+        if let Some(value) = &col.value {
+            result += DetachedRowData::encoded_value_len(value);
+        }
+        result
+    }
+
+    /// the number of bytes encode_column_value writes for a value
+    pub fn encoded_value_len(value: &ColumnValue) -> usize {
+        match *value {
+            ColumnValue::Boolean(_) => 1,
+            ColumnValue::Int(v) => varint_len_i32(v),
+            ColumnValue::BigInt(v) => varint_len_i64(v),
+            ColumnValue::Text(v) => varint_len(v.len() as u64) + v.len(),
+            ColumnValue::Date(v) => varint_len(v as u64),
+            ColumnValue::Time(v) => varint_len(v),
+            ColumnValue::Duration { months, days, nanos } => varint_len_i32(months) + varint_len_i32(days) + varint_len_i64(nanos),
+            ColumnValue::Varint(v) => varint_len(((v.magnitude.len() << 1) | v.negative as usize) as u64) + v.magnitude.len(),
+            ColumnValue::Unknown { data, .. } => varint_len(data.len() as u64) + data.len(),
+        }
+    }
+
+    /// the size of the row's encoded data in bytes, e.g. for MemTable size accounting
+    pub fn encoded_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// wraps a buffer holding a row's encoded data, e.g. as returned by bytes()
+    pub fn from_bytes(schema: &Arc<TableSchema>, buf: Bytes) -> DetachedRowData {
         DetachedRowData {
             schema: schema.clone(),
             buf,
         }
     }
 
+    /// the row's encoded data, shared rather than copied
+    pub fn bytes(&self) -> &Bytes {
+        &self.buf
+    }
+
     pub fn row_data_view(&self) -> RowData {
         RowData::from_view(&self.schema, &self.buf)
     }
 }
 
+/// the token of a partition, given its RowData::partition_key_bytes()
+pub fn partition_token(partition_key: &[u8]) -> u64 {
+    seahash::hash(partition_key)
+}
+
+/// like decode_column_value_of_kind, but None instead of a panic if the value is malformed
+pub fn try_decode_column_value_of_kind<'b>(buf: &'b [u8], kind: u8, offs: &mut usize) -> Option<ColumnValue<'b>> {
+    match ColumnType::of_kind(kind) {
+        Some(tpe) => try_decode_column_value(buf, &tpe, offs),
+        None => {
+            let len = buf.try_decode_varint_usize(offs)?;
+            let end = offs.checked_add(len)?;
+            let data = buf.get(*offs..end)?;
+            *offs = end;
+            Some(ColumnValue::Unknown { kind, data })
+        }
+    }
+}
+
+/// like decode_column_value, but None instead of a panic if the value extends beyond the end of
+///  the buffer or is malformed (e.g. invalid UTF-8), for reading rows that may be corrupt
+pub fn try_decode_column_value<'b>(buf: &'b [u8], tpe: &ColumnType, offs: &mut usize) -> Option<ColumnValue<'b>> {
+    // borrows from buf rather than from a reference to it, unlike DecodePrimitives::try_decode_slice
+    let slice = |offs: &mut usize, len: usize| -> Option<&'b [u8]> {
+        let end = offs.checked_add(len)?;
+        let result = buf.get(*offs..end)?;
+        *offs = end;
+        Some(result)
+    };
+
+    Some(match tpe {
+        ColumnType::Boolean => ColumnValue::Boolean(slice(offs, 1)?[0] != 0),
+        ColumnType::Int => ColumnValue::Int(buf.try_decode_varint_i32(offs)?),
+        ColumnType::BigInt => ColumnValue::BigInt(buf.try_decode_varint_i64(offs)?),
+        ColumnType::Text => {
+            let len = buf.try_decode_varint_usize(offs)?;
+            ColumnValue::Text(std::str::from_utf8(slice(offs, len)?).ok()?)
+        }
+        ColumnType::Date => ColumnValue::Date(buf.try_decode_varint_u32(offs)?),
+        ColumnType::Time => ColumnValue::Time(buf.try_decode_varint_u64(offs)?),
+        ColumnType::Duration => ColumnValue::Duration {
+            months: buf.try_decode_varint_i32(offs)?,
+            days: buf.try_decode_varint_i32(offs)?,
+            nanos: buf.try_decode_varint_i64(offs)?,
+        },
+        ColumnType::Varint => {
+            let header = buf.try_decode_varint_usize(offs)?;
+            ColumnValue::Varint(Varint {
+                negative: header & 1 != 0,
+                magnitude: slice(offs, header >> 1)?,
+            })
+        }
+    })
+}
+
+/// decodes a value of a stored value kind (see ColumnFlags), including the reserved kinds
+pub fn decode_column_value_of_kind<'b, D>(buf: &'b D, kind: u8, offs: &mut usize) -> ColumnValue<'b> where D: DecodePrimitives {
+    match ColumnType::of_kind(kind) {
+        Some(tpe) => decode_column_value(buf, &tpe, offs),
+        None => {
+            let len = buf.decode_varint_usize(offs);
+            ColumnValue::Unknown { kind, data: buf.decode_slice(offs, len) }
+        }
+    }
+}
+
+pub fn decode_column_value<'b, D>(buf: &'b D, tpe: &ColumnType, offs: &mut usize) -> ColumnValue<'b> where D: DecodePrimitives {
+    match tpe {
+        ColumnType::Boolean => ColumnValue::Boolean(buf.decode_bool(offs)),
+        ColumnType::Int => ColumnValue::Int(buf.decode_varint_i32(offs)),
+        ColumnType::BigInt => ColumnValue::BigInt(buf.decode_varint_i64(offs)),
+        ColumnType::Text => ColumnValue::Text(buf.decode_utf8(offs)),
+        ColumnType::Date => ColumnValue::Date(buf.decode_varint_u32(offs)),
+        ColumnType::Time => ColumnValue::Time(buf.decode_varint_u64(offs)),
+        ColumnType::Duration => ColumnValue::Duration {
+            months: buf.decode_varint_i32(offs),
+            days: buf.decode_varint_i32(offs),
+            nanos: buf.decode_varint_i64(offs),
+        },
+        ColumnType::Varint => {
+            let header = buf.decode_varint_usize(offs);
+            ColumnValue::Varint(Varint {
+                negative: header & 1 != 0,
+                magnitude: buf.decode_slice(offs, header >> 1),
+            })
+        }
+    }
+}
+
+/// A row's primary key on its own, encoded as the raw values of the primary key columns in
+///  schema order, i.e. without flags, timestamps or expiry. This identifies a row e.g. for
+///  lookups, without building a row that carries nothing but the primary key.
+///
+/// PrimaryKeys are ordered like rows, see RowData::compare_by_pk.
+#[derive(Clone)]
+pub struct PrimaryKey {
+    schema: Arc<TableSchema>,
+    buf: Bytes,
+}
+
+ordered!(PrimaryKey);
+
+impl PrimaryKey {
+    /// values holds the values of all primary key columns in schema order
+    pub fn new(schema: &Arc<TableSchema>, values: &[ColumnValue]) -> HtResult<PrimaryKey> {
+        if values.len() != schema.pk_columns.len() {
+            return Err(HtError::misc("number of values does not match the primary key"));
+        }
+
+        let mut buf = Vec::new();
+        for (col, value) in schema.pk_columns.iter().zip(values) {
+            if !col.tpe.matches(value) {
+                return Err(HtError::misc("primary key value does not match the column type"));
+            }
+            DetachedRowData::encode_column_value(&mut buf, value);
+        }
+        Ok(PrimaryKey { schema: schema.clone(), buf: Bytes::from(buf) })
+    }
+
+    pub fn values(&self) -> Vec<ColumnValue<'_>> {
+        let mut offs = 0;
+        self.schema.pk_columns.iter()
+            .map(|col| decode_column_value(&self.buf, &col.tpe, &mut offs))
+            .collect()
+    }
+
+    /// the encoded primary key
+    pub fn bytes(&self) -> &Bytes {
+        &self.buf
+    }
+
+    /// the partition token of the key's partition, see RowData::partition_token
+    pub fn partition_token(&self) -> u64 {
+        // partition key columns come first, and they are encoded like in RowData::partition_key_bytes
+        let mut offs = 0;
+        for col in self.schema.pk_columns.iter().take_while(|col| col.pk_spec == PrimaryKeySpec::PartitionKey) {
+            decode_column_value(&self.buf, &col.tpe, &mut offs);
+        }
+        partition_token(&self.buf[..offs])
+    }
+
+    /// A row containing only the primary key columns, for lookups in structures that are
+    ///  keyed by rows (e.g. MemTables). Its timestamps are meaningless.
+    pub fn lookup_row(&self) -> DetachedRowData {
+        let columns = self.schema.pk_columns.iter()
+            .zip(self.values())
+            .map(|(col, value)| ColumnData::new(col.col_id, MergeTimestamp::from_ticks(0), None, Some(value)))
+            .collect();
+        DetachedRowData::assemble_unchecked(&self.schema, &columns, None, None)
+    }
+
+    fn compare(a: &PrimaryKey, b: &PrimaryKey) -> Ordering {
+        for ((col, v1), v2) in a.schema.pk_columns.iter().zip(a.values()).zip(b.values()) {
+            match col.compare_pk_values(&v1, &v2) {
+                Ordering::Equal => {}
+                cmp => return cmp,
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl Debug for PrimaryKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.values())
+    }
+}
+
+impl Debug for RowData<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.describe())
+    }
+}
+
+impl Debug for DetachedRowData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.row_data_view().describe())
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct RowFlags (u8);
 
 impl RowFlags {
     const ROW_EXPIRY: u8 = 1;
+    /// row and column timestamps are stored as varint deltas, see RowData
+    const DELTA_TIMESTAMPS: u8 = 2;
+    /// The row deletes all of its columns up to and including the row timestamp, i.e. all
+    ///  data for its primary key that was written before. The row's own columns are newer.
+    const ROW_TOMBSTONE: u8 = 4;
+    /// the row refreshes the expiry of older columns, see RowTouch
+    const ROW_TOUCH: u8 = 8;
 
     pub fn create(has_row_expiry: bool) -> RowFlags {
         let mut flags = 0;
@@ -483,9 +1459,56 @@ impl RowFlags {
         RowFlags ( flags )
     }
 
+    pub fn with_delta_timestamps(self) -> RowFlags {
+        RowFlags(self.0 | RowFlags::DELTA_TIMESTAMPS)
+    }
+
+    pub fn with_row_tombstone(self) -> RowFlags {
+        RowFlags(self.0 | RowFlags::ROW_TOMBSTONE)
+    }
+
+    pub fn with_touch(self) -> RowFlags {
+        RowFlags(self.0 | RowFlags::ROW_TOUCH)
+    }
+
     pub fn has_row_expiry(&self) -> bool {
         self.0 & RowFlags::ROW_EXPIRY != 0
     }
+
+    pub fn has_delta_timestamps(&self) -> bool {
+        self.0 & RowFlags::DELTA_TIMESTAMPS != 0
+    }
+
+    pub fn is_row_tombstone(&self) -> bool {
+        self.0 & RowFlags::ROW_TOMBSTONE != 0
+    }
+
+    pub fn is_touch(&self) -> bool {
+        self.0 & RowFlags::ROW_TOUCH != 0
+    }
+}
+
+/// Refreshes the expiry of a row's columns without rewriting their values (see
+///  DetachedRowData::touch): when rows are merged, each column with a value that was written up
+///  to and including the touch's timestamp gets the touch's expiry. Columns that had already
+///  expired at that time are left alone rather than resurrected. The touch is kept in the
+///  merged row, so it also applies to columns in other SsTables that are merged later.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct RowTouch {
+    pub timestamp: MergeTimestamp,
+    pub expiry: TtlTimestamp,
+}
+
+impl RowTouch {
+    pub fn apply(&self, col: &mut ColumnData) {
+        let live = match col.expiry {
+            Some(expiry) => expiry >= self.timestamp.as_ttl_timestamp(),
+            None => true,
+        };
+        if col.value.is_some() && col.timestamp <= self.timestamp && live {
+            col.expiry = Some(self.expiry);
+        }
+    }
 }
 
 impl <W> Encode<RowFlags> for W where W: Write {
@@ -493,6 +1516,11 @@ impl <W> Encode<RowFlags> for W where W: Write {
         self.encode_u8(v.0)
     }
 }
+impl Put<RowFlags> for Vec<u8> {
+    fn put(&mut self, v: RowFlags) {
+        self.put_u8(v.0)
+    }
+}
 impl Decode<RowFlags> for &[u8] {
     fn decode(&self, offs: &mut usize) -> RowFlags {
         RowFlags(self.decode_u8(offs))
@@ -513,18 +1541,26 @@ impl ColumnFlags {
     /// the column has an expiry which is the 'row expiry'. This flag is mutually exclusive with
     ///  COLUMN_EXPIRY, and it requires RowFlags::ROW_EXPIRY to be set.
     const ROW_EXPIRY: u8 = 8;
+    /// Bits 4 to 7 hold the kind of the column's value (see ColumnType), so that a column can
+    ///  be skipped or decoded without looking up its type in the schema. This allows reading
+    ///  rows with columns the schema does not know, e.g. rows written with a newer schema.
+    ///  Kinds 8 to 15 are reserved for new types. Their values are stored with a varint length
+    ///  prefix, so that they can be skipped and copied (see ColumnValue::Unknown) by versions
+    ///  that do not know them.
+    const VALUE_KIND_SHIFT: u8 = 4;
+    const VALUE_KIND_MASK: u8 = 0xf0;
 
     #[inline]
     fn new(
-        is_null: bool,
+        value: Option<&ColumnValue>,
         has_timestamp: bool,
         has_col_expiry: bool,
         has_row_expiry: bool) -> ColumnFlags
     {
-        let mut flags = 0;
-        if is_null {
-            flags |= ColumnFlags::NULL_VALUE;
-        }
+        let mut flags = match value {
+            None => ColumnFlags::NULL_VALUE,
+            Some(v) => v.kind() << ColumnFlags::VALUE_KIND_SHIFT,
+        };
         if has_timestamp {
             flags |= ColumnFlags::COLUMN_TIMESTAMP;
         }
@@ -544,6 +1580,15 @@ impl ColumnFlags {
     pub fn has_col_timestamp(&self) -> bool {
         self.0 & ColumnFlags::COLUMN_TIMESTAMP != 0
     }
+    /// The type of the column's value, None for a reserved kind (e.g. a type added by a later
+    ///  version). This is meaningless for NULL values.
+    pub fn value_kind(&self) -> Option<ColumnType> {
+        ColumnType::of_kind(self.kind())
+    }
+    /// the raw kind of the column's value, see value_kind
+    pub fn kind(&self) -> u8 {
+        (self.0 & ColumnFlags::VALUE_KIND_MASK) >> ColumnFlags::VALUE_KIND_SHIFT
+    }
     pub fn expiry(&self) -> ColumnExpiryKind {
         let row_expiry = self.0 & ColumnFlags::ROW_EXPIRY != 0;
         let col_expiry = self.0 & ColumnFlags::COLUMN_EXPIRY != 0;
@@ -569,6 +1614,11 @@ impl <W> Encode<ColumnFlags> for W where W: Write {
         self.encode_u8(value.0)
     }
 }
+impl Put<ColumnFlags> for Vec<u8> {
+    fn put(&mut self, value: ColumnFlags) {
+        self.put_u8(value.0)
+    }
+}
 impl Decode<ColumnFlags> for &[u8] {
     fn decode(&self, offs: &mut usize) -> ColumnFlags {
         ColumnFlags(self.decode_u8(offs))
@@ -577,7 +1627,7 @@ impl Decode<ColumnFlags> for &[u8] {
 
 /// This is the logical representation of a column's data in a row. It holds a similar but
 ///  different data structure from a RowData's raw buffer, resolving some storage optimizations
-#[derive(Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct ColumnData<'a> {
     pub col_id: ColumnId,
     pub timestamp: MergeTimestamp,
@@ -612,19 +1662,154 @@ pub enum ColumnValue<'a> {
     Int(i32),
     BigInt(i64),
     Text(&'a str),
+    Date(u32),
+    Time(u64),
+    /// see ColumnType::Duration - the derived order is arbitrary
+    Duration { months: i32, days: i32, nanos: i64 },
+    Varint(Varint<'a>),
+    /// A value of a reserved kind (8 to 15, see ColumnFlags), e.g. of a type added by a later
+    ///  version. It is kept as its raw data, so that copying or compacting a row preserves it.
+    Unknown { kind: u8, data: &'a [u8] },
+}
+
+impl ColumnValue<'_> {
+    pub const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+    /// the Date of a point in time, i.e. its day in UTC
+    pub fn date(time: SystemTime) -> HtResult<ColumnValue<'static>> {
+        let days = time.duration_since(UNIX_EPOCH)
+            .map_err(|_| HtError::misc("dates before 1970 are not supported"))?
+            .as_secs() / (24 * 60 * 60);
+        if days > u32::MAX as u64 {
+            return Err(HtError::misc("date out of range"));
+        }
+        Ok(ColumnValue::Date(days as u32))
+    }
+
+    /// a Time value for the time since midnight, which must be less than a day
+    pub fn time(since_midnight: Duration) -> HtResult<ColumnValue<'static>> {
+        let value = ColumnValue::Time(since_midnight.as_nanos().min(u64::MAX as u128) as u64);
+        value.validate()?;
+        Ok(value)
+    }
+
+    /// the start of a Date value's day, None for other values
+    pub fn as_system_time(&self) -> Option<SystemTime> {
+        match *self {
+            ColumnValue::Date(days) => Some(UNIX_EPOCH + Duration::from_secs(days as u64 * 24 * 60 * 60)),
+            _ => None,
+        }
+    }
+
+    /// the time since midnight of a Time value, None for other values
+    pub fn as_duration(&self) -> Option<Duration> {
+        match *self {
+            ColumnValue::Time(nanos) => Some(Duration::from_nanos(nanos)),
+            _ => None,
+        }
+    }
+
+    /// the value's type, None for a value of a reserved kind
+    pub fn tpe(&self) -> Option<ColumnType> {
+        match self {
+            ColumnValue::Boolean(_) => Some(ColumnType::Boolean),
+            ColumnValue::Int(_) => Some(ColumnType::Int),
+            ColumnValue::BigInt(_) => Some(ColumnType::BigInt),
+            ColumnValue::Text(_) => Some(ColumnType::Text),
+            ColumnValue::Date(_) => Some(ColumnType::Date),
+            ColumnValue::Time(_) => Some(ColumnType::Time),
+            ColumnValue::Duration { .. } => Some(ColumnType::Duration),
+            ColumnValue::Varint(_) => Some(ColumnType::Varint),
+            ColumnValue::Unknown { .. } => None,
+        }
+    }
+
+    /// the value kind that is stored for the value, see ColumnFlags
+    pub fn kind(&self) -> u8 {
+        match (self, self.tpe()) {
+            (ColumnValue::Unknown { kind, .. }, _) => *kind,
+            (_, tpe) => tpe.expect("only values of reserved kinds have no type").kind(),
+        }
+    }
+
+    /// checks constraints beyond the value's type, i.e. that a Time is less than a day
+    pub fn validate(&self) -> HtResult<()> {
+        match *self {
+            ColumnValue::Time(nanos) if nanos >= ColumnValue::NANOS_PER_DAY => Err(HtError::misc("time of day must be less than 24 hours")),
+            ColumnValue::Varint(v) if v.magnitude.first() == Some(&0) => Err(HtError::misc("varint magnitude has leading zeros")),
+            ColumnValue::Varint(v) if v.negative && v.magnitude.is_empty() => Err(HtError::misc("varint zero must not be negative")),
+            _ => Ok(()),
+        }
+    }
+}
+
+
+/// An arbitrarily large integer in sign-magnitude representation, with the magnitude's bytes in
+///  big endian order. The representation is unique: the magnitude has no leading zero bytes,
+///  and zero has an empty magnitude and is not negative.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Varint<'a> {
+    pub negative: bool,
+    pub magnitude: &'a [u8],
+}
+
+impl <'a> Varint<'a> {
+    /// strips leading zeros from the magnitude
+    pub fn new(negative: bool, magnitude: &'a [u8]) -> Varint<'a> {
+        let leading_zeros = magnitude.iter().take_while(|&&b| b == 0).count();
+        let magnitude = &magnitude[leading_zeros..];
+        Varint { negative: negative && !magnitude.is_empty(), magnitude }
+    }
+
+    /// the value if it fits into an i128
+    pub fn as_i128(&self) -> Option<i128> {
+        if self.magnitude.len() > 16 {
+            return None;
+        }
+        let magnitude = self.magnitude.iter().fold(0u128, |acc, &b| (acc << 8) | b as u128);
+        match self.negative {
+            false if magnitude <= i128::MAX as u128 => Some(magnitude as i128),
+            true if magnitude <= i128::MAX as u128 + 1 => Some((magnitude as i128).wrapping_neg()),
+            _ => None,
+        }
+    }
+
+    fn compare_magnitude(a: &[u8], b: &[u8]) -> Ordering {
+        // there are no leading zeros, so longer magnitudes are larger
+        a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+    }
 }
 
+impl Ord for Varint<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, false) => Varint::compare_magnitude(self.magnitude, other.magnitude),
+            (true, true) => Varint::compare_magnitude(other.magnitude, self.magnitude),
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for Varint<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
 
 #[cfg(test)]
 mod test {
     use std::cmp::Ordering;
     use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
 
     use uuid::Uuid;
 
     use crate::primitives::DecodePrimitives;
-    use crate::table::{ColumnData, ColumnSchema, ColumnType, ColumnValue, DetachedRowData, PrimaryKeySpec, RowFlags, TableSchema, ColumnId};
-    use crate::time::{ManualClock, MergeTimestamp, HtClock};
+    use crate::simulation::SimRng;
+    use crate::table::{Collation, ColumnData, ColumnFlags, ColumnSchema, ColumnType, ColumnValue, DetachedRowData, PrimaryKey, PrimaryKeySpec, RowData, RowFlags, RowTouch, TableSchema, ColumnId, Varint};
+    use crate::testutils::{arbitrary_columns, arbitrary_schema, arbitrary_texts};
+    use crate::time::{ManualClock, MergeTimestamp, HtClock, TtlTimestamp};
 
     fn table_schema() -> TableSchema {
         TableSchema::new(
@@ -636,24 +1821,28 @@ mod test {
                     name: "part_key".to_string(),
                     tpe: ColumnType::BigInt,
                     pk_spec: PrimaryKeySpec::PartitionKey,
+                    collation: Collation::Binary,
                 },
                 ColumnSchema {
                     col_id: ColumnId(33),
                     name: "cl_key_1".to_string(),
                     tpe: ColumnType::Int,
                     pk_spec: PrimaryKeySpec::ClusterKey(false),
+                    collation: Collation::Binary,
                 },
                 ColumnSchema {
                     col_id: ColumnId(22),
                     name: "cl_key_2".to_string(),
                     tpe: ColumnType::Text,
                     pk_spec: PrimaryKeySpec::ClusterKey(true),
+                    collation: Collation::Binary,
                 },
                 ColumnSchema {
                     col_id: ColumnId(11),
                     name: "regular".to_string(),
                     tpe: ColumnType::Boolean,
                     pk_spec: PrimaryKeySpec::Regular,
+                    collation: Collation::Binary,
                 },
             ))
     }
@@ -728,7 +1917,7 @@ mod test {
         let row = DetachedRowData::assemble(
             &Arc::new(table_schema),
             &columns,
-        );
+        ).unwrap();
 
 
         let row_data = row.row_data_view();
@@ -763,6 +1952,13 @@ mod test {
         // assert_eq!(col.flags, ColumnFlags::new(false, false, false, false));
         assert_eq!(col.col_id, ColumnId(11));
         assert_eq!(col.value, Some(ColumnValue::Boolean(true)));
+
+        // clones and re-wrapped buffers share the row's data
+        let cloned = row.clone();
+        assert_eq!(cloned.bytes().as_ptr(), row.bytes().as_ptr());
+        let rewrapped = DetachedRowData::from_bytes(&row.schema, row.bytes().clone());
+        assert_eq!(rewrapped.bytes().as_ptr(), row.bytes().as_ptr());
+        assert_eq!(rewrapped, row);
     }
 
     #[test]
@@ -772,7 +1968,7 @@ mod test {
         let clock = ManualClock::new(MergeTimestamp::from_ticks(123456789));
 
         let row = DetachedRowData::assemble(&Arc::new(table_schema),
-                                            &vec!(col4_data(clock.now(), None)));
+                                            &vec!(col4_data(clock.now(), None))).unwrap();
 
         let row_data = row.row_data_view();
 
@@ -781,6 +1977,364 @@ mod test {
         assert_eq!(col.value, None);
     }
 
+    #[test]
+    pub fn test_row_column_iter() {
+        let schema = Arc::new(table_schema());
+        let ts = MergeTimestamp::from_ticks(100);
+        let row = DetachedRowData::assemble(&schema, &vec!(
+            col1_data(ts, 12345),
+            col2_data(ts, -7),
+            ColumnData { expiry: Some(TtlTimestamp::new(999)), ..col3_data(MergeTimestamp::from_ticks(99), "yo") },
+            col4_data(ts, Some(true)),
+        )).unwrap();
+        let view = row.row_data_view();
+
+        let names = view.columns().with_schema().map(|(c, _)| c.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!("part_key", "cl_key_1", "cl_key_2", "regular"));
+
+        let wanted = [ColumnId(33), ColumnId(11)];
+        assert_eq!(view.columns().values_only(&wanted).collect::<Vec<_>>(),
+                   vec!((ColumnId(33), Some(ColumnValue::Int(-7))), (ColumnId(11), Some(ColumnValue::Boolean(true)))));
+        assert_eq!(view.columns().values_only(&[ColumnId(22)]).collect::<Vec<_>>(),
+                   vec!((ColumnId(22), Some(ColumnValue::Text("yo")))));
+        assert_eq!(view.columns().values_only(&[]).count(), 0);
+
+        // skipping works with delta encoded timestamps as well
+        let base = MergeTimestamp::from_ticks(50);
+        let buf = view.encode_with_timestamp_base(base);
+        let delta_row = RowData::from_view_with_base(&schema, &buf, base);
+        assert_eq!(delta_row.columns().values_only(&wanted).collect::<Vec<_>>(),
+                   vec!((ColumnId(33), Some(ColumnValue::Int(-7))), (ColumnId(11), Some(ColumnValue::Boolean(true)))));
+    }
+
+    #[test]
+    pub fn test_project() {
+        let schema = Arc::new(table_schema());
+        let ts = MergeTimestamp::from_ticks(100);
+        let row = DetachedRowData::assemble(&schema, &vec!(
+            col1_data(ts, 12345),
+            col2_data(ts, -7),
+            col3_data(ts, "yo"),
+            col4_data(ts, Some(true)),
+        )).unwrap();
+
+        assert_eq!(row.row_data_view().project(&[ColumnId(11)]).bytes(), row.bytes());
+
+        let pk_only = DetachedRowData::assemble(&schema, &vec!(col1_data(ts, 12345), col2_data(ts, -7), col3_data(ts, "yo"))).unwrap();
+        assert_eq!(row.row_data_view().project(&[]).bytes(), pk_only.bytes());
+    }
+
+    #[test]
+    pub fn test_assemble_column_order() {
+        let schema = Arc::new(table_schema());
+        let ts = MergeTimestamp::from_ticks(100);
+        let regular = |col_id| ColumnData::new(ColumnId(col_id), ts, None, Some(ColumnValue::Int(col_id as i32)));
+
+        // regular columns are sorted, and rows can have a prefix of the primary key
+        let row = DetachedRowData::assemble(&schema, &vec!(col1_data(ts, 1), col2_data(ts, 2), col3_data(ts, "a"), regular(50), regular(11), regular(40))).unwrap();
+        assert_eq!(row.row_data_view().columns().including_unknown().map(|c| c.col_id.0).collect::<Vec<_>>(), vec!(0, 33, 22, 11, 40, 50));
+        assert!(DetachedRowData::assemble(&schema, &vec!(col1_data(ts, 1), col2_data(ts, 2))).is_ok());
+
+        assert!(DetachedRowData::assemble(&schema, &vec!(col1_data(ts, 1), col3_data(ts, "a"), col2_data(ts, 2))).is_err());
+        assert!(DetachedRowData::assemble(&schema, &vec!(col1_data(ts, 1), regular(11), col2_data(ts, 2))).is_err());
+        assert!(DetachedRowData::assemble(&schema, &vec!(col1_data(ts, 1), ColumnData::new(ColumnId(33), ts, None, None))).is_err());
+        assert!(DetachedRowData::assemble(&schema, &vec!(col1_data(ts, 1), col2_data(ts, 2), col3_data(ts, "a"), regular(11), regular(11))).is_err());
+    }
+
+    #[test]
+    pub fn test_row_tombstone() {
+        let schema = Arc::new(table_schema());
+        let ts = |ticks| MergeTimestamp::from_ticks(ticks);
+        let pk_columns = vec!(col1_data(ts(0), 1), col2_data(ts(0), 2), col3_data(ts(0), "a"));
+
+        assert!(DetachedRowData::assemble(&schema, &vec!()).is_err());
+        assert!(DetachedRowData::row_tombstone(&schema, &pk_columns[..2].to_vec(), ts(100)).is_err());
+        assert!(DetachedRowData::row_tombstone(&schema, &vec!(col1_data(ts(0), 1), col2_data(ts(0), 2), col3_data(ts(0), "a"), col4_data(ts(0), None)), ts(100)).is_err());
+
+        let tombstone = DetachedRowData::row_tombstone(&schema, &pk_columns, ts(100)).unwrap();
+        let tombstone = tombstone.row_data_view();
+        assert_eq!(tombstone.row_tombstone(), Some(ts(100)));
+        assert!(tombstone.is_deleted());
+        assert!(tombstone.validate().is_ok());
+
+        // a PK-only row without the flag is a marker for the row's presence
+        let pk_only = DetachedRowData::assemble(&schema, &pk_columns).unwrap();
+        assert_eq!(pk_only.row_data_view().row_tombstone(), None);
+        assert!(!pk_only.row_data_view().is_deleted());
+
+        let row = |ticks| DetachedRowData::assemble(&schema, &vec!(col1_data(ts(0), 1), col2_data(ts(0), 2), col3_data(ts(0), "a"), col4_data(ts(ticks), Some(true)))).unwrap();
+
+        // older columns are deleted regardless of the merge order, newer ones are kept
+        for merged in [tombstone.merge(&row(99).row_data_view()), row(99).row_data_view().merge(&tombstone)] {
+            assert!(merged.row_data_view().is_deleted());
+            assert_eq!(merged.row_data_view().row_tombstone(), Some(ts(100)));
+        }
+        let merged = row(101).row_data_view().merge(&tombstone);
+        let merged = merged.row_data_view();
+        assert!(!merged.is_deleted());
+        assert_eq!(merged.row_tombstone(), Some(ts(100)));
+        assert_eq!(merged.read_col_by_id(ColumnId(11)).unwrap().value, Some(ColumnValue::Boolean(true)));
+
+        // the newer tombstone wins, and the flag survives delta encoding
+        let newer = DetachedRowData::row_tombstone(&schema, &pk_columns, ts(200)).unwrap();
+        let merged = merged.merge(&newer.row_data_view());
+        assert!(merged.row_data_view().is_deleted());
+        let buf = merged.row_data_view().encode_with_timestamp_base(ts(50));
+        let delta_row = RowData::from_view_with_base(&schema, &buf, ts(50));
+        assert_eq!(delta_row.row_tombstone(), Some(ts(200)));
+        assert_eq!(delta_row.detach().row_data_view().row_tombstone(), Some(ts(200)));
+    }
+
+    #[test]
+    pub fn test_touch() {
+        let schema = Arc::new(table_schema());
+        let ts = |millis| MergeTimestamp::new(millis, 0, 1, 0);
+        let pk_columns = vec!(col1_data(ts(0), 1), col2_data(ts(0), 2), col3_data(ts(0), "a"));
+
+        assert!(DetachedRowData::touch(&schema, &pk_columns[..2].to_vec(), ts(2000), TtlTimestamp::new(100)).is_err());
+
+        let touch = DetachedRowData::touch(&schema, &pk_columns, ts(2000), TtlTimestamp::new(100)).unwrap();
+        let touch = touch.row_data_view();
+        assert_eq!(touch.touch(), Some(RowTouch { timestamp: ts(2000), expiry: TtlTimestamp::new(100) }));
+        assert_eq!(touch.timestamp(), ts(2000));
+        assert!(touch.is_touch_only());
+        assert!(!touch.is_deleted());
+        assert!(touch.validate().is_ok());
+        assert_eq!(touch.encode_pk(), DetachedRowData::assemble(&schema, &pk_columns).unwrap().row_data_view().encode_pk());
+
+        let row = |millis, expiry: Option<u32>, value: Option<bool>| {
+            let mut col = col4_data(ts(millis), value);
+            col.expiry = expiry.map(TtlTimestamp::new);
+            let mut columns = pk_columns.clone();
+            columns.push(col);
+            DetachedRowData::assemble(&schema, &columns).unwrap()
+        };
+        let expiry_after_touch = |row: DetachedRowData| {
+            let merged = [touch.merge(&row.row_data_view()), row.row_data_view().merge(&touch)];
+            assert_eq!(merged[0].row_data_view().buf, merged[1].row_data_view().buf);
+            let merged = merged[0].row_data_view();
+            assert!(!merged.is_touch_only());
+            assert_eq!(merged.touch(), touch.touch());
+            merged.read_col_by_id(ColumnId(11)).unwrap().expiry
+        };
+
+        // older columns with a value get the touch's expiry, regardless of the merge order
+        assert_eq!(expiry_after_touch(row(1000, Some(5), Some(true))), Some(TtlTimestamp::new(100)));
+        assert_eq!(expiry_after_touch(row(1000, None, Some(true))), Some(TtlTimestamp::new(100)));
+        assert_eq!(expiry_after_touch(row(1000, Some(500), Some(true))), Some(TtlTimestamp::new(100)));
+        // newer columns, columns that had expired and NULL columns are left alone
+        assert_eq!(expiry_after_touch(row(3000, None, Some(true))), None);
+        assert_eq!(expiry_after_touch(row(1000, Some(1), Some(true))), Some(TtlTimestamp::new(1)));
+        assert_eq!(expiry_after_touch(row(1000, None, None)), None);
+
+        // the newer touch wins, and the touch survives delta encoding
+        let newer = DetachedRowData::touch(&schema, &pk_columns, ts(3000), TtlTimestamp::new(50)).unwrap();
+        let merged = touch.merge(&newer.row_data_view()).row_data_view().merge(&row(1000, None, Some(true)).row_data_view());
+        let buf = merged.row_data_view().encode_with_timestamp_base(ts(500));
+        let delta_row = RowData::from_view_with_base(&schema, &buf, ts(500));
+        assert_eq!(delta_row.touch(), Some(RowTouch { timestamp: ts(3000), expiry: TtlTimestamp::new(50) }));
+        assert_eq!(delta_row.read_col_by_id(ColumnId(11)).unwrap().expiry, Some(TtlTimestamp::new(50)));
+        assert_eq!(delta_row.detach().row_data_view().buf, merged.row_data_view().buf);
+
+        // a newer row tombstone removes the touch
+        let tombstone = DetachedRowData::row_tombstone(&schema, &pk_columns, ts(2500)).unwrap();
+        let merged = touch.merge(&tombstone.row_data_view());
+        assert_eq!(merged.row_data_view().touch(), None);
+        assert!(merged.row_data_view().is_deleted());
+    }
+
+    #[test]
+    pub fn test_primary_key() {
+        let schema = Arc::new(table_schema());
+        let ts = MergeTimestamp::from_ticks(100);
+        let row = DetachedRowData::assemble(&schema, &vec!(col1_data(ts, 12345), col2_data(ts, -7), col3_data(ts, "yo"), col4_data(ts, Some(true)))).unwrap();
+
+        let pk = PrimaryKey::new(&schema, &[ColumnValue::BigInt(12345), ColumnValue::Int(-7), ColumnValue::Text("yo")]);
+        assert!(PrimaryKey::new(&schema, &[ColumnValue::BigInt(12345)]).is_err());
+        assert!(PrimaryKey::new(&schema, &[ColumnValue::BigInt(12345), ColumnValue::BigInt(-7), ColumnValue::Text("yo")]).is_err());
+        let pk = pk.unwrap();
+
+        assert_eq!(row.row_data_view().encode_pk(), pk);
+        assert_eq!(pk.bytes(), row.row_data_view().encode_pk().bytes());
+        assert_eq!(pk.values(), vec!(ColumnValue::BigInt(12345), ColumnValue::Int(-7), ColumnValue::Text("yo")));
+        assert!(pk.bytes().len() < row.bytes().len());
+        assert_eq!(pk.lookup_row().row_data_view().compare_by_pk(&row.row_data_view()), Ordering::Equal);
+
+        // ordering is consistent with rows, including descending cluster keys
+        let pk2 = PrimaryKey::new(&schema, &[ColumnValue::BigInt(12345), ColumnValue::Int(-8), ColumnValue::Text("a")]).unwrap();
+        assert_eq!(pk.cmp(&pk2), Ordering::Less);
+        assert_eq!(pk.lookup_row().row_data_view().compare_by_pk(&pk2.lookup_row().row_data_view()), Ordering::Less);
+        let pk3 = PrimaryKey::new(&schema, &[ColumnValue::BigInt(12346), ColumnValue::Int(-8), ColumnValue::Text("a")]).unwrap();
+        assert_eq!(pk2.cmp(&pk3), Ordering::Less);
+
+        assert_eq!(row.row_data_view().compare_to_pk(&pk), Ordering::Equal);
+        assert_eq!(row.row_data_view().compare_to_pk(&pk2), Ordering::Less);
+        assert_eq!(row.row_data_view().compare_to_pk(&pk3), Ordering::Less);
+        assert_eq!(pk3.lookup_row().row_data_view().compare_to_pk(&pk), Ordering::Greater);
+        assert_eq!(pk.partition_token(), row.row_data_view().partition_token());
+        assert_ne!(pk.partition_token(), pk3.partition_token());
+    }
+
+    #[test]
+    pub fn test_partition_key_bytes() {
+        let schema = Arc::new(table_schema());
+        let ts = MergeTimestamp::from_ticks(100);
+        let row = DetachedRowData::assemble(&schema, &vec!(col1_data(ts, 12345), col2_data(ts, -7), col3_data(ts, "yo"), col4_data(ts, Some(true)))).unwrap();
+
+        // the schema's column order does not matter, only that of the primary key columns
+        let mut columns = table_schema().columns;
+        columns.rotate_right(1);
+        let reordered = Arc::new(TableSchema::new("my_table", &schema.table_id, columns));
+        let reordered_row = DetachedRowData::assemble(&reordered, &vec!(col1_data(ts, 12345), col2_data(ts, -7), col3_data(ts, "yo"), col4_data(ts, Some(true)))).unwrap();
+
+        let mut expected = Vec::new();
+        DetachedRowData::encode_column_value(&mut expected, &ColumnValue::BigInt(12345));
+        assert_eq!(row.row_data_view().partition_key_bytes(), expected);
+        assert_eq!(reordered_row.row_data_view().partition_key_bytes(), expected);
+    }
+
+    #[test]
+    pub fn test_validate() {
+        let schema = Arc::new(table_schema());
+        let row = DetachedRowData::assemble(&schema, &vec!(
+            col1_data(MergeTimestamp::from_ticks(100), 12345),
+            col4_data(MergeTimestamp::from_ticks(101), None),
+        )).unwrap();
+        assert!(row.row_data_view().validate().is_ok());
+
+        // a primary key column with a timestamp of its own
+        let mut buf = row.bytes().to_vec();
+        let offs = row.row_data_view().offs_start_column_data();
+        buf[offs + 1] |= ColumnFlags::COLUMN_TIMESTAMP;
+        assert!(RowData::from_view(&schema, &buf).validate().is_err());
+
+        // a primary key column that is not part of the schema
+        let mut buf = row.bytes().to_vec();
+        buf[offs] = 63;
+        assert!(RowData::from_view(&schema, &buf).validate().is_err());
+
+        // regular columns that are not part of the schema are checked structurally
+        let ts = MergeTimestamp::from_ticks(100);
+        let unknown = DetachedRowData::assemble(&schema, &vec!(
+            col1_data(ts, 12345),
+            ColumnData::new(ColumnId(44), ts, None, Some(ColumnValue::Unknown { kind: 12, data: b"new type" })),
+        )).unwrap();
+        assert!(unknown.row_data_view().validate().is_ok());
+        let buf = unknown.bytes().to_vec();
+        assert!(RowData::from_view(&schema, &buf[..buf.len() - 1]).validate().is_err());
+
+        // a reserved value kind
+        let mut buf = row.bytes().to_vec();
+        buf[offs + 1] |= 0x80;
+        assert_eq!(ColumnFlags(buf[offs + 1]).value_kind(), None);
+        assert!(RowData::from_view(&schema, &buf).validate().is_err());
+
+        // malformed rows are reported as errors rather than panicking
+        let buf = row.bytes().to_vec();
+        assert!(RowData::from_view(&schema, &buf[..buf.len() - 1]).validate().is_err());
+        let buf = vec!(0xffu8; row.bytes().len());
+        assert!(RowData::from_view(&schema, &buf).validate().is_err());
+        assert!(RowData::from_view(&schema, &buf).try_compare_by_pk(&row.row_data_view()).is_err());
+    }
+
+    #[test]
+    pub fn test_unknown_columns() {
+        let schema = Arc::new(table_schema());
+
+        // a newer version of the schema with an additional column
+        let mut columns = table_schema().columns;
+        columns.push(ColumnSchema { col_id: ColumnId(44), name: "added".to_string(), tpe: ColumnType::Text, pk_spec: PrimaryKeySpec::Regular, collation: Collation::Binary });
+        let new_schema = Arc::new(TableSchema::new("my_table", &schema.table_id, columns));
+
+        let ts = MergeTimestamp::from_ticks(100);
+        let row = DetachedRowData::assemble(&new_schema, &vec!(
+            col1_data(ts, 1),
+            col2_data(ts, 2),
+            col3_data(ts, "a"),
+            col4_data(ts, Some(true)),
+            ColumnData::new(ColumnId(44), ts, None, Some(ColumnValue::Text("added value"))),
+        )).unwrap();
+
+        let old_row = RowData::from_view(&schema, row.bytes());
+        assert_eq!(old_row.columns().collect::<Vec<_>>(), vec!(col1_data(ts, 1), col2_data(ts, 2), col3_data(ts, "a"), col4_data(ts, Some(true))));
+        assert_eq!(old_row.read_col_by_id(ColumnId(44)).unwrap().value, Some(ColumnValue::Text("added value")));
+        assert!(old_row.validate().is_ok());
+        assert!(row.row_data_view().validate().is_ok());
+        assert_eq!(old_row.compare_by_pk(&row.row_data_view()), Ordering::Equal);
+
+        // column values have the kind of the schema's column type
+        let mut columns = table_schema().columns;
+        columns[3].tpe = ColumnType::Int;
+        let other_schema = Arc::new(TableSchema::new("my_table", &schema.table_id, columns));
+        let row = DetachedRowData::assemble(&other_schema, &vec!(col1_data(ts, 1), ColumnData::new(ColumnId(11), ts, None, Some(ColumnValue::Int(5))))).unwrap();
+        assert!(row.row_data_view().validate().is_ok());
+        assert!(RowData::from_view(&schema, row.bytes()).validate().is_err());
+    }
+
+    #[test]
+    pub fn test_reserved_value_kind() {
+        let schema = Arc::new(table_schema());
+        let ts = MergeTimestamp::from_ticks(100);
+        let unknown = ColumnData::new(ColumnId(44), ts, None, Some(ColumnValue::Unknown { kind: 12, data: b"new type" }));
+        let row = DetachedRowData::assemble(&schema, &vec!(col1_data(ts, 1), col4_data(ts, Some(true)), unknown)).unwrap();
+        let view = row.row_data_view();
+
+        let flags = ColumnFlags(row.bytes()[row.bytes().len() - 10]);
+        assert_eq!((flags.kind(), flags.value_kind()), (12, None));
+
+        // the column is skipped by its length prefix, and decoded as its raw data
+        assert_eq!(view.columns().collect::<Vec<_>>(), vec!(col1_data(ts, 1), col4_data(ts, Some(true))));
+        assert_eq!(view.read_col_by_id(ColumnId(44)), Some(unknown));
+
+        // copies and merges keep it
+        let base = MergeTimestamp::from_ticks(50);
+        let buf = view.encode_with_timestamp_base(base);
+        let detached = RowData::from_view_with_base(&schema, &buf, base).detach();
+        assert_eq!(detached.row_data_view().read_col_by_id(ColumnId(44)), Some(unknown));
+        let newer = DetachedRowData::assemble(&schema, &vec!(col1_data(ts, 1), col4_data(MergeTimestamp::from_ticks(200), Some(false)))).unwrap();
+        let merged = view.merge(&newer.row_data_view());
+        assert_eq!(merged.row_data_view().read_col_by_id(ColumnId(44)), Some(unknown));
+        assert_eq!(merged.row_data_view().read_col_by_id(ColumnId(11)).unwrap().value, Some(ColumnValue::Boolean(false)));
+    }
+
+    #[test]
+    pub fn test_unknown_schema() {
+        let schema = Arc::new(table_schema());
+        let ts = MergeTimestamp::from_ticks(100);
+        let columns = vec!(
+            col1_data(ts, 1),
+            col2_data(ts, 2),
+            col3_data(ts, "a"),
+            ColumnData { expiry: Some(TtlTimestamp::new(999)), ..col4_data(MergeTimestamp::from_ticks(101), None) },
+        );
+        let row = DetachedRowData::assemble(&schema, &columns).unwrap();
+        let buf = row.row_data_view().encode_with_timestamp_base(MergeTimestamp::from_ticks(50));
+
+        // rows can be walked without their schema
+        let unknown = Arc::new(TableSchema::unknown());
+        let raw = RowData::from_view_with_base(&unknown, &buf, MergeTimestamp::from_ticks(50));
+        assert_eq!(raw.columns().count(), 0);
+        assert!(raw.columns().including_unknown().eq(row.row_data_view().columns()));
+        let ts = MergeTimestamp::from_ticks(101);
+        assert_eq!(raw.describe(), format!("{{#0: BigInt(1) @{0}, #33: Int(2) @{0}, #22: Text(\"a\") @{0}, #11: null @{0} ttl 999}}", ts));
+    }
+
+    #[test]
+    pub fn test_describe() {
+        let schema = Arc::new(table_schema());
+        let row = DetachedRowData::assemble(&schema, &vec!(
+            col1_data(MergeTimestamp::from_ticks(100), 12345),
+            col2_data(MergeTimestamp::from_ticks(100), -7),
+            col3_data(MergeTimestamp::from_ticks(100), "yo"),
+            ColumnData { expiry: Some(TtlTimestamp::new(999)), ..col4_data(MergeTimestamp::from_ticks(101), None) },
+        )).unwrap();
+
+        // primary key columns show the row's timestamp
+        let expected = format!(r#"{{part_key: BigInt(12345) @{0}, cl_key_1: Int(-7) @{0}, cl_key_2: Text("yo") @{0}, regular: null @{0} ttl 999}}"#, MergeTimestamp::from_ticks(101));
+        assert_eq!(row.row_data_view().describe(), expected);
+        assert_eq!(format!("{:?}", row), expected);
+    }
+
     #[test]
     pub fn test_compare_by_pk() {
         fn row(v1: i64, v2: i32, v3: &'static str, v4: Option<bool>) -> DetachedRowData {
@@ -792,7 +2346,7 @@ mod test {
                 col2_data(clock.now(), v2),
                 col3_data(clock.now(), v3),
                 col4_data(clock.now(), v4)),
-            )
+            ).unwrap()
         }
 
         let row0 = row(100, 100, "hi", Some(true));
@@ -836,8 +2390,263 @@ mod test {
         assert_eq!(rd0.compare_by_pk(&rd_regular_different2), Ordering::Equal);
     }
 
+    #[test]
+    pub fn test_collation() {
+        let mut columns = table_schema().columns;
+        columns[2].collation = Collation::CaseInsensitive;
+        let table_schema = Arc::new(TableSchema::new("my_table", &Uuid::new_v4(), columns));
+
+        let clock = ManualClock::new(MergeTimestamp::from_ticks(123456789));
+        let row = |v3| DetachedRowData::assemble(&table_schema, &vec!(
+            col1_data(clock.now(), 1),
+            col2_data(clock.now(), 2),
+            col3_data(clock.now(), v3),
+        )).unwrap();
+
+        let cmp = |v1, v2| row(v1).row_data_view().compare_by_pk(&row(v2).row_data_view());
+        assert_eq!(cmp("a", "B"), Ordering::Less);
+        assert_eq!(cmp("B", "b"), Ordering::Less);
+        assert_eq!(cmp("b", "c"), Ordering::Less);
+        assert_eq!(cmp("Ab", "aa"), Ordering::Greater);
+        assert_eq!(cmp("b", "b"), Ordering::Equal);
+
+        let pk = |v3| PrimaryKey::new(&table_schema, &[ColumnValue::BigInt(1), ColumnValue::Int(2), ColumnValue::Text(v3)]).unwrap();
+        assert!(pk("a") < pk("B"));
+
+        assert_eq!(Collation::Binary.compare(&ColumnValue::Text("a"), &ColumnValue::Text("B")), Ordering::Greater);
+        assert_eq!(Collation::CaseInsensitive.compare(&ColumnValue::Int(2), &ColumnValue::Int(1)), Ordering::Greater);
+    }
+
+    #[test]
+    pub fn test_date_time() {
+        let day = Duration::from_secs(24 * 60 * 60);
+
+        let date = ColumnValue::date(UNIX_EPOCH + day * 3 + Duration::from_secs(5)).unwrap();
+        assert_eq!(date, ColumnValue::Date(3));
+        assert_eq!(date.as_system_time(), Some(UNIX_EPOCH + day * 3));
+        assert!(ColumnValue::date(UNIX_EPOCH - day).is_err());
+
+        let time = ColumnValue::time(Duration::from_millis(1500)).unwrap();
+        assert_eq!(time, ColumnValue::Time(1_500_000_000));
+        assert_eq!(time.as_duration(), Some(Duration::from_millis(1500)));
+        assert!(ColumnValue::time(day).is_err());
+        assert!(ColumnValue::Time(1) < ColumnValue::Time(2));
+
+        let table_schema = Arc::new(TableSchema::new("dates", &Uuid::new_v4(), vec!(
+            ColumnSchema { col_id: ColumnId(0), name: "day".to_string(), tpe: ColumnType::Date, pk_spec: PrimaryKeySpec::PartitionKey, collation: Collation::Binary },
+            ColumnSchema { col_id: ColumnId(1), name: "time".to_string(), tpe: ColumnType::Time, pk_spec: PrimaryKeySpec::ClusterKey(false), collation: Collation::Binary },
+        )));
+        let row = |time| DetachedRowData::assemble(&table_schema, &vec!(
+            ColumnData::new(ColumnId(0), MergeTimestamp::from_ticks(1), None, Some(date)),
+            ColumnData::new(ColumnId(1), MergeTimestamp::from_ticks(1), None, Some(ColumnValue::Time(time))),
+        )).unwrap();
+
+        let row1 = row(1_000);
+        assert_eq!(row1.row_data_view().read_col_by_id(ColumnId(1)).unwrap().value, Some(ColumnValue::Time(1_000)));
+        assert!(row1.row_data_view().validate().is_ok());
+        assert_eq!(row1.row_data_view().compare_by_pk(&row(2_000).row_data_view()), Ordering::Greater);
+        assert!(row(ColumnValue::NANOS_PER_DAY).row_data_view().validate().is_err());
+    }
+
+    #[test]
+    pub fn test_duration() {
+        let column = |col_id, tpe, pk_spec| ColumnSchema { col_id: ColumnId(col_id), name: format!("c{}", col_id), tpe, pk_spec, collation: Collation::Binary };
+
+        assert!(TableSchema::new("d", &Uuid::new_v4(), vec!(column(0, ColumnType::Duration, PrimaryKeySpec::PartitionKey))).validate().is_err());
+        assert!(TableSchema::new("d", &Uuid::new_v4(), vec!(
+            column(0, ColumnType::Int, PrimaryKeySpec::PartitionKey),
+            column(1, ColumnType::Duration, PrimaryKeySpec::ClusterKey(true)),
+        )).validate().is_err());
+
+        let table_schema = Arc::new(TableSchema::new("d", &Uuid::new_v4(), vec!(
+            column(0, ColumnType::Int, PrimaryKeySpec::PartitionKey),
+            column(1, ColumnType::Duration, PrimaryKeySpec::Regular),
+            column(2, ColumnType::Int, PrimaryKeySpec::Regular),
+        )));
+        assert!(table_schema.validate().is_ok());
+
+        let duration = ColumnValue::Duration { months: 1, days: -2, nanos: 3_000_000_000 };
+        let row = DetachedRowData::assemble(&table_schema, &vec!(
+            ColumnData::new(ColumnId(0), MergeTimestamp::from_ticks(1), None, Some(ColumnValue::Int(1))),
+            ColumnData::new(ColumnId(1), MergeTimestamp::from_ticks(1), None, Some(duration)),
+            ColumnData::new(ColumnId(2), MergeTimestamp::from_ticks(1), None, Some(ColumnValue::Int(5))),
+        )).unwrap();
+        let row = row.row_data_view();
+        assert!(row.validate().is_ok());
+        assert_eq!(row.read_col_by_id(ColumnId(1)).unwrap().value, Some(duration));
+        assert_eq!(row.read_col_by_id(ColumnId(2)).unwrap().value, Some(ColumnValue::Int(5)));
+    }
+
+    #[test]
+    pub fn test_varint() {
+        let v = |negative, magnitude: &'static [u8]| Varint::new(negative, magnitude);
+
+        assert_eq!(v(true, &[0, 0]), v(false, &[]));
+        assert_eq!(v(false, &[0, 1, 2]).magnitude, &[1, 2]);
+
+        let ordered = [v(true, &[1, 0, 0]), v(true, &[2, 0]), v(true, &[1, 255]), v(true, &[1]), v(false, &[]), v(false, &[1]), v(false, &[255]), v(false, &[1, 0])];
+        for i in 1..ordered.len() {
+            assert!(ordered[i-1] < ordered[i]);
+        }
+
+        assert_eq!(v(true, &[1, 0]).as_i128(), Some(-256));
+        assert_eq!(v(false, &[]).as_i128(), Some(0));
+        assert_eq!(v(true, &[128, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).as_i128(), Some(i128::MIN));
+        assert_eq!(v(false, &[128, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).as_i128(), None);
+        assert_eq!(v(false, &[1; 17]).as_i128(), None);
+
+        assert!(ColumnValue::Varint(Varint { negative: false, magnitude: &[0, 1] }).validate().is_err());
+        assert!(ColumnValue::Varint(Varint { negative: true, magnitude: &[] }).validate().is_err());
+
+        let table_schema = Arc::new(TableSchema::new("v", &Uuid::new_v4(), vec!(
+            ColumnSchema { col_id: ColumnId(0), name: "pk".to_string(), tpe: ColumnType::Varint, pk_spec: PrimaryKeySpec::PartitionKey, collation: Collation::Binary },
+            ColumnSchema { col_id: ColumnId(1), name: "ck".to_string(), tpe: ColumnType::Varint, pk_spec: PrimaryKeySpec::ClusterKey(true), collation: Collation::Binary },
+        )));
+        let row = |ck| DetachedRowData::assemble(&table_schema, &vec!(
+            ColumnData::new(ColumnId(0), MergeTimestamp::from_ticks(1), None, Some(ColumnValue::Varint(v(false, &[5; 20])))),
+            ColumnData::new(ColumnId(1), MergeTimestamp::from_ticks(1), None, Some(ColumnValue::Varint(ck))),
+        )).unwrap();
+        for i in 1..ordered.len() {
+            let (row1, row2) = (row(ordered[i-1]), row(ordered[i]));
+            assert_eq!(row1.row_data_view().compare_by_pk(&row2.row_data_view()), Ordering::Less);
+            assert_eq!(row2.row_data_view().read_col_by_id(ColumnId(1)).unwrap().value, Some(ColumnValue::Varint(ordered[i])));
+        }
+    }
+
     #[test]
     pub fn test_merge_rows() {
-        panic!("todo")
+        let table_schema = Arc::new(table_schema());
+
+        let t1 = MergeTimestamp::from_ticks(1000);
+        let t2 = MergeTimestamp::from_ticks(2000);
+
+        let row1 = DetachedRowData::assemble(&table_schema, &vec!(
+            col1_data(t1, 1),
+            col2_data(t1, 2),
+            col3_data(t1, "a"),
+            col4_data(t1, Some(true)),
+        )).unwrap();
+        let row2 = DetachedRowData::assemble(&table_schema, &vec!(
+            col1_data(t2, 1),
+            col2_data(t2, 2),
+            col3_data(t2, "a"),
+            col4_data(t2, Some(false)),
+        )).unwrap();
+        let row_pk_only = DetachedRowData::assemble(&table_schema, &vec!(
+            col1_data(t2, 1),
+            col2_data(t2, 2),
+            col3_data(t2, "a"),
+        )).unwrap();
+
+        // the newer column wins, regardless of merge order
+        for merged in [
+            row1.row_data_view().merge(&row2.row_data_view()),
+            row2.row_data_view().merge(&row1.row_data_view())] {
+            let merged = merged.row_data_view();
+            let col = merged.read_col_by_id(ColumnId(11)).unwrap();
+            assert_eq!(col.value, Some(ColumnValue::Boolean(false)));
+            assert_eq!(col.timestamp, t2);
+        }
+
+        // columns present in only one row are retained
+        let merged = row_pk_only.row_data_view().merge(&row1.row_data_view());
+        let merged = merged.row_data_view();
+        let col = merged.read_col_by_id(ColumnId(11)).unwrap();
+        assert_eq!(col.value, Some(ColumnValue::Boolean(true)));
+        assert_eq!(col.timestamp, t1);
+        assert_eq!(merged.compare_by_pk(&row1.row_data_view()), Ordering::Equal);
+    }
+
+    #[test]
+    pub fn test_delta_timestamps() {
+        let schema = Arc::new(table_schema());
+        let ts = MergeTimestamp::new(1_000_000, 5, 3, 0);
+        let col_ts = MergeTimestamp::new(999_990, 0, 3, 0);
+        let expiry = TtlTimestamp::new(12345);
+
+        let columns = vec!(
+            col1_data(ts, 12345),
+            col2_data(ts, 123),
+            col3_data(ts, "yo"),
+            ColumnData { expiry: Some(expiry), ..col4_data(col_ts, None) },
+        );
+        let row = DetachedRowData::assemble(&schema, &columns).unwrap();
+
+        let base = MergeTimestamp::new(999_000, 0, 0, 0);
+        let buf = row.row_data_view().encode_with_timestamp_base(base);
+        assert!(buf.len() < row.bytes().len());
+
+        let delta_row = RowData::from_view_with_base(&schema, &buf, base);
+        assert!(delta_row.flags().has_delta_timestamps());
+        assert_eq!(delta_row.timestamp(), col_ts);
+        assert!(delta_row.columns().eq(row.row_data_view().columns()));
+        assert_eq!(delta_row.read_col_by_id(ColumnId(11)), Some(ColumnData { expiry: Some(expiry), ..col4_data(col_ts, None) }));
+
+        // detaching resolves the deltas
+        let detached = delta_row.detach();
+        assert!(!detached.row_data_view().flags().has_delta_timestamps());
+        assert_eq!(detached.bytes(), row.bytes());
+    }
+
+    #[test]
+    pub fn test_encoded_len() {
+        for seed in 0..200 {
+            let mut rng = SimRng::new(seed);
+            let schema = arbitrary_schema(&mut rng);
+            let texts = arbitrary_texts(&mut rng, &schema);
+            let columns = arbitrary_columns(&mut rng, &schema, &texts);
+
+            let columns = DetachedRowData::ordered_columns(&schema, &columns).unwrap();
+            for timestamp_base in [None, Some(MergeTimestamp::from_ticks(rng.below(1_000_000)))] {
+                let encoded = DetachedRowData::encode(&schema, &columns, timestamp_base, None, None);
+                assert_eq!(DetachedRowData::precomputed_len(&schema, &columns, timestamp_base, None, None), encoded.len(), "seed {}", seed);
+
+                let view = RowData::from_view_with_base(&schema, &encoded, timestamp_base.unwrap_or(MergeTimestamp::from_ticks(0)));
+                let mut written = Vec::new();
+                view.write_to(&mut written).unwrap();
+                assert_eq!(view.stored_len(), written.len(), "seed {}", seed);
+            }
+
+            for value in columns.iter().filter_map(|c| c.value.as_ref()) {
+                let mut buf = Vec::new();
+                DetachedRowData::encode_column_value(&mut buf, value);
+                assert_eq!(DetachedRowData::encoded_value_len(value), buf.len(), "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_arbitrary_rows_round_trip() {
+        for seed in 0..500 {
+            let mut rng = SimRng::new(seed);
+            let schema = arbitrary_schema(&mut rng);
+            let texts = arbitrary_texts(&mut rng, &schema);
+            let columns = arbitrary_columns(&mut rng, &schema, &texts);
+
+            // encode -> decode
+            let row = DetachedRowData::assemble(&schema, &columns).unwrap();
+            let view = row.row_data_view();
+            let decoded = view.columns().collect::<Vec<_>>();
+
+            // primary key columns are stored without a timestamp or expiry of their own
+            let columns = columns.into_iter()
+                .map(|col| if schema.is_primary_key(col.col_id) {
+                    ColumnData::new(col.col_id, view.timestamp(), None, col.value)
+                } else {
+                    col
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(decoded, columns, "seed {}", seed);
+
+            // decode -> re-encode
+            assert_eq!(DetachedRowData::assemble(&schema, &decoded).unwrap().bytes(), row.bytes(), "seed {}", seed);
+
+            // delta timestamps
+            let base = MergeTimestamp::from_ticks(rng.next_u64());
+            let buf = row.row_data_view().encode_with_timestamp_base(base);
+            let delta_row = RowData::from_view_with_base(&schema, &buf, base);
+            assert_eq!(delta_row.columns().collect::<Vec<_>>(), columns, "seed {}", seed);
+            assert_eq!(delta_row.detach().bytes(), row.bytes(), "seed {}", seed);
+        }
     }
 }
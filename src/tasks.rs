@@ -0,0 +1,153 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::sstable::SsTableName;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TaskKind {
+    Flush,
+    Compaction,
+    /// rewriting a single SsTable, see Table::cleanup
+    Cleanup,
+    /// splitting a single SsTable by token range, see Table::split
+    Split,
+}
+
+/// A snapshot of a running flush or compaction, see BackgroundTasks
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackgroundTask {
+    pub kind: TaskKind,
+    pub table: String,
+    /// the SsTables a compaction reads, empty for flushes
+    pub inputs: Vec<SsTableName>,
+    pub bytes_processed: u64,
+    /// an estimate that is not necessarily reached exactly
+    pub bytes_total: u64,
+    pub elapsed: Duration,
+    /// the remaining time, extrapolated from the progress so far; None before there is any
+    pub eta: Option<Duration>,
+}
+
+/// A task's progress, updated by the thread(s) doing the work
+#[derive(Debug, Default)]
+pub struct TaskProgress {
+    processed: AtomicU64,
+    total: AtomicU64,
+}
+
+impl TaskProgress {
+    pub fn add_processed(&self, bytes: u64) {
+        self.processed.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn set_total(&self, bytes: u64) {
+        self.total.store(bytes, Ordering::Relaxed);
+    }
+}
+
+struct RunningTask {
+    kind: TaskKind,
+    table: String,
+    inputs: Vec<SsTableName>,
+    started: Instant,
+    progress: Arc<TaskProgress>,
+}
+
+/// A table's running flushes and compactions, so that operators can monitor long-running
+///  maintenance. There is no catalog of tables yet, see admin::background_tasks for
+///  collecting the tasks of several tables.
+#[derive(Default)]
+pub struct BackgroundTasks {
+    running: Mutex<Vec<RunningTask>>,
+}
+
+impl BackgroundTasks {
+    /// registers a task, which is removed when the returned guard is dropped
+    pub fn start(&self, kind: TaskKind, table: &str, inputs: Vec<SsTableName>) -> TaskGuard<'_> {
+        let progress = Arc::new(TaskProgress::default());
+        self.running.lock().unwrap().push(RunningTask {
+            kind,
+            table: table.to_string(),
+            inputs,
+            started: Instant::now(),
+            progress: progress.clone(),
+        });
+        TaskGuard { tasks: self, progress }
+    }
+
+    /// the running tasks, oldest first
+    pub fn snapshot(&self) -> Vec<BackgroundTask> {
+        self.running.lock().unwrap().iter()
+            .map(|task| {
+                let bytes_processed = task.progress.processed.load(Ordering::Relaxed);
+                let bytes_total = task.progress.total.load(Ordering::Relaxed);
+                let elapsed = task.started.elapsed();
+                let eta = if bytes_processed > 0 {
+                    let remaining = bytes_total.saturating_sub(bytes_processed);
+                    Some(elapsed.mul_f64(remaining as f64 / bytes_processed as f64))
+                }
+                else {
+                    None
+                };
+
+                BackgroundTask {
+                    kind: task.kind,
+                    table: task.table.clone(),
+                    inputs: task.inputs.clone(),
+                    bytes_processed,
+                    bytes_total,
+                    elapsed,
+                    eta,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Gives access to a running task's progress and unregisters the task when it is dropped
+pub struct TaskGuard<'a> {
+    tasks: &'a BackgroundTasks,
+    progress: Arc<TaskProgress>,
+}
+
+impl TaskGuard<'_> {
+    pub fn progress(&self) -> &TaskProgress {
+        &self.progress
+    }
+}
+
+impl Drop for TaskGuard<'_> {
+    fn drop(&mut self) {
+        self.tasks.running.lock().unwrap()
+            .retain(|task| !Arc::ptr_eq(&task.progress, &self.progress));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sstable::SsTableName;
+    use crate::tasks::{BackgroundTasks, TaskKind};
+
+    #[test]
+    pub fn test_background_tasks() {
+        let tasks = BackgroundTasks::default();
+
+        let flush = tasks.start(TaskKind::Flush, "t", Vec::new());
+        let compaction = tasks.start(TaskKind::Compaction, "t", vec!(SsTableName::new("t", 1)));
+        compaction.progress().set_total(1000);
+        compaction.progress().add_processed(250);
+
+        let snapshot = tasks.snapshot();
+        assert_eq!(snapshot.iter().map(|t| t.kind).collect::<Vec<_>>(), vec!(TaskKind::Flush, TaskKind::Compaction));
+        assert_eq!(snapshot[0].eta, None);
+        assert_eq!(snapshot[1].inputs, vec!(SsTableName::new("t", 1)));
+        assert_eq!((snapshot[1].bytes_processed, snapshot[1].bytes_total), (250, 1000));
+        assert!(snapshot[1].eta.unwrap() >= snapshot[1].elapsed * 2);
+
+        drop(flush);
+        assert_eq!(tasks.snapshot().iter().map(|t| t.kind).collect::<Vec<_>>(), vec!(TaskKind::Compaction));
+        drop(compaction);
+        assert!(tasks.snapshot().is_empty());
+    }
+}
@@ -1,11 +1,13 @@
 
 
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use crate::config::TableConfig;
 use std::path::PathBuf;
-use crate::table::{TableSchema, ColumnSchema, ColumnId, ColumnType, PrimaryKeySpec, DetachedRowData, ColumnData, ColumnValue, RowData};
+use crate::table::{TableSchema, ColumnSchema, Collation, ColumnId, ColumnType, PrimaryKeySpec, DetachedRowData, ColumnData, ColumnValue, RowData, Varint};
 use uuid::Uuid;
-use crate::time::{ManualClock, MergeTimestamp, HtClock};
+use crate::time::{ManualClock, MergeTimestamp, HtClock, TtlTimestamp};
+use crate::simulation::SimRng;
+use crate::slowlog::{SlowQuery, SlowQueryCallback};
 
 const TEST_DIR: &str = "__test__";
 
@@ -17,21 +19,25 @@ pub fn test_table_config() -> Arc<TableConfig> {
     }
 
     Arc::new(TableConfig {
-        base_folder
+        max_sstable_data_size: 1024 * 1024,
+        ..TableConfig::new(base_folder)
     })
 }
 
 
 pub struct SimpleTableTestSetup {
     pub schema: Arc<TableSchema>,
-    pub clock: ManualClock,
+    pub clock: Arc<ManualClock>,
 }
 
 impl SimpleTableTestSetup {
     pub fn new() -> SimpleTableTestSetup {
+        let schema = SimpleTableTestSetup::table_schema();
+        test_table_config().create_table_folder(&schema).unwrap();
+
         SimpleTableTestSetup {
-            schema: SimpleTableTestSetup::table_schema(),
-            clock: ManualClock::new(MergeTimestamp::from_ticks(12345)),
+            schema,
+            clock: Arc::new(ManualClock::new(MergeTimestamp::from_ticks(12345))),
         }
     }
 
@@ -41,19 +47,22 @@ impl SimpleTableTestSetup {
                 col_id: ColumnId(0),
                 name: "pk".to_string(),
                 tpe: ColumnType::BigInt,
-                pk_spec: PrimaryKeySpec::PartitionKey
+                pk_spec: PrimaryKeySpec::PartitionKey,
+                collation: Collation::Binary
             },
             ColumnSchema {
                 col_id: ColumnId(1),
                 name: "text".to_string(),
                 tpe: ColumnType::Text,
-                pk_spec: PrimaryKeySpec::Regular
+                pk_spec: PrimaryKeySpec::Regular,
+                collation: Collation::Binary
             },
             ColumnSchema {
                 col_id: ColumnId(2),
                 name: "int".to_string(),
                 tpe: ColumnType::Int,
-                pk_spec: PrimaryKeySpec::Regular
+                pk_spec: PrimaryKeySpec::Regular,
+                collation: Collation::Binary
             },
         )))
     }
@@ -63,9 +72,9 @@ impl SimpleTableTestSetup {
                                   &vec!(
                                       ColumnData::new (ColumnId(0),self.clock.now(),None,Some(ColumnValue::BigInt(pk))),
                                       ColumnData::new (ColumnId(1), self.clock.now(), None, text.map(|t| ColumnValue::Text(t))),
-                                      ColumnData::new (ColumnId(2), self.clock.now(), None, int.map(|i| ColumnValue::BigInt(i))),
+                                      ColumnData::new (ColumnId(2), self.clock.now(), None, int.map(|i| ColumnValue::Int(i as i32))),
                                   ),
-        )
+        ).unwrap()
     }
 
     pub fn partial_row(&self, pk: i64, text: Option<&'static str>) -> DetachedRowData {
@@ -74,12 +83,12 @@ impl SimpleTableTestSetup {
                                       ColumnData::new (ColumnId(0),self.clock.now(),None,Some(ColumnValue::BigInt(pk))),
                                       ColumnData::new (ColumnId(1), self.clock.now(), None, text.map(|t| ColumnValue::Text(t))),
                                   ),
-        )
+        ).unwrap()
     }
 
     pub fn pk_row(&self, pk: i64) -> DetachedRowData {
         DetachedRowData::assemble(&self.schema,
-                                  &vec!(ColumnData::new(ColumnId(0), self.clock.now(), None, Some(ColumnValue::BigInt(pk)))))
+                                  &vec!(ColumnData::new(ColumnId(0), self.clock.now(), None, Some(ColumnValue::BigInt(pk))))).unwrap()
     }
 
     pub fn pk(&self, row: &RowData) -> i64 {
@@ -96,4 +105,154 @@ impl SimpleTableTestSetup {
         }
     }
 
-}
\ No newline at end of file
+    pub fn int_value(&self, row: &RowData) -> i64 {
+        match row.read_col_by_id(ColumnId(2)).unwrap().value.unwrap() {
+            ColumnValue::Int(v) => v as i64,
+            _ => panic!("no int value")
+        }
+    }
+
+}
+
+
+/// A table with a partition key 'pk', cluster key columns 'ts' (ascending) and 'seq'
+///  (descending), and a regular column 'text'
+pub struct ClusteredTableTestSetup {
+    pub schema: Arc<TableSchema>,
+    pub clock: Arc<ManualClock>,
+}
+
+impl ClusteredTableTestSetup {
+    pub fn new() -> ClusteredTableTestSetup {
+        let schema = Arc::new(TableSchema::new("clustered_table", &Uuid::new_v4(), vec!(
+            ColumnSchema { col_id: ColumnId(0), name: "pk".to_string(), tpe: ColumnType::BigInt, pk_spec: PrimaryKeySpec::PartitionKey, collation: Collation::Binary },
+            ColumnSchema { col_id: ColumnId(1), name: "ts".to_string(), tpe: ColumnType::Int, pk_spec: PrimaryKeySpec::ClusterKey(true), collation: Collation::Binary },
+            ColumnSchema { col_id: ColumnId(2), name: "seq".to_string(), tpe: ColumnType::Int, pk_spec: PrimaryKeySpec::ClusterKey(false), collation: Collation::Binary },
+            ColumnSchema { col_id: ColumnId(3), name: "text".to_string(), tpe: ColumnType::Text, pk_spec: PrimaryKeySpec::Regular, collation: Collation::Binary },
+        )));
+        test_table_config().create_table_folder(&schema).unwrap();
+
+        ClusteredTableTestSetup {
+            schema,
+            clock: Arc::new(ManualClock::new(MergeTimestamp::from_ticks(12345))),
+        }
+    }
+
+    pub fn row(&self, pk: i64, ts: i32, seq: i32, text: Option<&'static str>) -> DetachedRowData {
+        DetachedRowData::assemble(&self.schema, &vec!(
+            ColumnData::new(ColumnId(0), self.clock.now(), None, Some(ColumnValue::BigInt(pk))),
+            ColumnData::new(ColumnId(1), self.clock.now(), None, Some(ColumnValue::Int(ts))),
+            ColumnData::new(ColumnId(2), self.clock.now(), None, Some(ColumnValue::Int(seq))),
+            ColumnData::new(ColumnId(3), self.clock.now(), None, text.map(ColumnValue::Text)),
+        )).unwrap()
+    }
+
+    /// a row containing only the partition key
+    pub fn partition_row(&self, pk: i64) -> DetachedRowData {
+        DetachedRowData::assemble(&self.schema, &vec!(
+            ColumnData::new(ColumnId(0), self.clock.now(), None, Some(ColumnValue::BigInt(pk))),
+        )).unwrap()
+    }
+
+    /// the row's (ts, seq)
+    pub fn cluster_key(&self, row: &RowData) -> (i32, i32) {
+        match (row.read_col_by_id(ColumnId(1)).unwrap().value, row.read_col_by_id(ColumnId(2)).unwrap().value) {
+            (Some(ColumnValue::Int(ts)), Some(ColumnValue::Int(seq))) => (ts, seq),
+            _ => panic!("no cluster key")
+        }
+    }
+}
+
+
+/// a SlowQueryCallback collecting the queries it is called with
+pub struct CollectSlowQueries {
+    pub queries: Arc<Mutex<Vec<SlowQuery>>>,
+}
+
+impl SlowQueryCallback for CollectSlowQueries {
+    fn on_slow_query(&self, query: &SlowQuery) {
+        self.queries.lock().unwrap().push(query.clone());
+    }
+}
+
+
+/// Generators of random schemas and rows for round trip tests of the row format. Text values
+///  are generated up front (see arbitrary_texts) since ColumnValue::Text borrows them.
+pub fn arbitrary_schema(rng: &mut SimRng) -> Arc<TableSchema> {
+    let num_partition_keys = 1 + rng.below(2);
+    let num_cluster_keys = rng.below(3);
+    let num_regular = rng.below(6);
+
+    let mut columns = Vec::new();
+    for idx in 0..num_partition_keys + num_cluster_keys + num_regular {
+        let pk_spec = if idx < num_partition_keys {
+            PrimaryKeySpec::PartitionKey
+        }
+        else if idx < num_partition_keys + num_cluster_keys {
+            PrimaryKeySpec::ClusterKey(rng.below(2) == 0)
+        }
+        else {
+            PrimaryKeySpec::Regular
+        };
+        let tpe = match rng.below(8) {
+            0 => ColumnType::Boolean,
+            1 => ColumnType::Int,
+            2 => ColumnType::BigInt,
+            3 => ColumnType::Date,
+            4 => ColumnType::Time,
+            // durations have no order, so they can not be part of the primary key
+            5 if pk_spec == PrimaryKeySpec::Regular => ColumnType::Duration,
+            6 => ColumnType::Varint,
+            _ => ColumnType::Text,
+        };
+        columns.push(ColumnSchema { col_id: ColumnId(idx as u8), name: format!("c{}", idx), tpe, pk_spec, collation: Collation::Binary });
+    }
+
+    Arc::new(TableSchema::new("arbitrary", &Uuid::new_v4(), columns))
+}
+
+/// one text per column of the schema, also used as the magnitude of Varint values
+pub fn arbitrary_texts(rng: &mut SimRng, schema: &TableSchema) -> Vec<String> {
+    const CHARS: &[char] = &['a', 'Z', '0', ' ', 'ä', '€', '𝄞', '\0'];
+    schema.columns.iter()
+        .map(|_| (0..rng.below(20)).map(|_| CHARS[rng.below(CHARS.len() as u64) as usize]).collect())
+        .collect()
+}
+
+/// A row with all primary key columns and a random subset of the regular columns, some of
+///  them null, with random timestamps and expiry.
+pub fn arbitrary_columns<'a>(rng: &mut SimRng, schema: &TableSchema, texts: &'a [String]) -> Vec<ColumnData<'a>> {
+    let timestamps = [rng.next_u64(), rng.next_u64() >> rng.below(64)];
+    let expiries = [None, Some(TtlTimestamp::new(rng.next_u64() as u32))];
+
+    let mut result = Vec::new();
+    for (idx, col) in schema.columns.iter().enumerate() {
+        if !col.is_primary_key() && rng.below(3) == 0 {
+            continue;
+        }
+
+        let value = if !col.is_primary_key() && rng.below(4) == 0 {
+            None
+        }
+        else {
+            Some(match col.tpe {
+                ColumnType::Boolean => ColumnValue::Boolean(rng.below(2) == 0),
+                ColumnType::Int => ColumnValue::Int(rng.next_u64() as i32 >> rng.below(32)),
+                ColumnType::BigInt => ColumnValue::BigInt(rng.next_u64() as i64 >> rng.below(64)),
+                ColumnType::Text => ColumnValue::Text(&texts[idx]),
+                ColumnType::Date => ColumnValue::Date(rng.next_u64() as u32 >> rng.below(32)),
+                ColumnType::Time => ColumnValue::Time(rng.below(ColumnValue::NANOS_PER_DAY)),
+                ColumnType::Duration => ColumnValue::Duration {
+                    months: rng.next_u64() as i32 >> rng.below(32),
+                    days: rng.next_u64() as i32 >> rng.below(32),
+                    nanos: rng.next_u64() as i64 >> rng.below(64),
+                },
+                ColumnType::Varint => ColumnValue::Varint(Varint::new(rng.below(2) == 0, texts[idx].as_bytes())),
+            })
+        };
+        let timestamp = MergeTimestamp::from_ticks(timestamps[rng.below(2) as usize]);
+        let expiry = expiries[rng.below(2) as usize];
+        result.push(ColumnData::new(col.col_id, timestamp, expiry, value));
+    }
+    result
+}
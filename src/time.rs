@@ -1,6 +1,9 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 use std::sync::Mutex;
 use std::time::{Duration, SystemTime};
 use std::io::Write;
+use crate::prelude::*;
 use crate::primitives::*;
 
 /// MergeTimestamp is a globally unique value that is pretty much ordered by wall clock time (but
@@ -59,7 +62,7 @@ impl MergeTimestamp {
         MergeTimestamp { ticks }
     }
 
-    fn epoch_millis(&self) -> u64 {
+    pub fn epoch_millis(&self) -> u64 {
         self.ticks >> 23
     }
     fn counter_part(&self) -> u64 {
@@ -77,6 +80,103 @@ impl MergeTimestamp {
             + Duration::from_millis(HT_EPOCH_MILLIS)
             + Duration::from_millis(self.epoch_millis())
     }
+
+    /// the TtlTimestamp for the (full) second of this merge timestamp
+    pub fn as_ttl_timestamp(&self) -> TtlTimestamp {
+        TtlTimestamp::new((self.epoch_millis() / 1000) as u32)
+    }
+
+    /// the smallest merge timestamp that lies the given number of seconds before this one
+    pub fn minus_seconds(&self, seconds: u64) -> MergeTimestamp {
+        MergeTimestamp::new(self.epoch_millis().saturating_sub(seconds * 1000), 0, 0, 0)
+    }
+}
+
+const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// (year, month, day) of a day counted from 1970-01-01, see
+///  http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// the inverse of civil_from_days, for dates from 1970 onwards
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Renders all four parts of a MergeTimestamp, the time as UTC with millisecond precision,
+///  e.g. '2024-03-01T12:00:00.123Z#c17@ctx42/t3' for counter 17, unique context 42 and time
+///  travel part 3. FromStr parses this format.
+impl Display for MergeTimestamp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let unix_millis = HT_EPOCH_MILLIS + self.epoch_millis();
+        let (year, month, day) = civil_from_days(unix_millis / MILLIS_PER_DAY);
+        let millis_of_day = unix_millis % MILLIS_PER_DAY;
+        write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z#c{}@ctx{}/t{}",
+               year, month, day,
+               millis_of_day / 3_600_000, millis_of_day / 60_000 % 60, millis_of_day / 1000 % 60, millis_of_day % 1000,
+               self.counter_part(), self.unique_context(), self.time_travel_part())
+    }
+}
+
+impl FromStr for MergeTimestamp {
+    type Err = HtError;
+
+    fn from_str(s: &str) -> HtResult<MergeTimestamp> {
+        let invalid = || HtError::misc("invalid merge timestamp, expected e.g. '2024-03-01T12:00:00.123Z#c17@ctx42/t3'");
+        let number = |s: &str, max: u64| -> HtResult<u64> {
+            match s.parse::<u64>() {
+                Ok(n) if n <= max && s.bytes().all(|b| b.is_ascii_digit()) => Ok(n),
+                _ => Err(invalid()),
+            }
+        };
+
+        let (time, parts) = s.split_once('#').ok_or_else(invalid)?;
+        let b = time.as_bytes();
+        if b.len() != 24 || b[4] != b'-' || b[7] != b'-' || b[10] != b'T' || b[13] != b':' || b[16] != b':' || b[19] != b'.' || b[23] != b'Z' {
+            return Err(invalid());
+        }
+        let year = number(&time[0..4], 9999)?;
+        let month = number(&time[5..7], 12)?;
+        let day = number(&time[8..10], 31)?;
+        if year < 1970 || month == 0 || day == 0 || civil_from_days(days_from_civil(year, month, day)) != (year, month, day) {
+            return Err(invalid());
+        }
+        let unix_millis = days_from_civil(year, month, day) * MILLIS_PER_DAY
+            + number(&time[11..13], 23)? * 3_600_000
+            + number(&time[14..16], 59)? * 60_000
+            + number(&time[17..19], 59)? * 1000
+            + number(&time[20..23], 999)?;
+        if unix_millis < HT_EPOCH_MILLIS || unix_millis - HT_EPOCH_MILLIS >= 1 << 41 {
+            return Err(HtError::misc("merge timestamps must lie between 2020 and 2089"));
+        }
+
+        let parts = parts.strip_prefix('c').ok_or_else(invalid)?;
+        let (counter_part, parts) = parts.split_once("@ctx").ok_or_else(invalid)?;
+        let (unique_context, time_travel_part) = parts.split_once("/t").ok_or_else(invalid)?;
+
+        Ok(MergeTimestamp::new(
+            unix_millis - HT_EPOCH_MILLIS,
+            number(counter_part, 1023)?,
+            number(unique_context, 1023)?,
+            number(time_travel_part, 7)?,
+        ))
+    }
 }
 
 impl <W> Encode<MergeTimestamp> for W where W: Write {
@@ -84,6 +184,11 @@ impl <W> Encode<MergeTimestamp> for W where W: Write {
         self.encode_fixed_u64(v.ticks)
     }
 }
+impl Put<MergeTimestamp> for Vec<u8> {
+    fn put(&mut self, v: MergeTimestamp) {
+        self.put_fixed_u64(v.ticks)
+    }
+}
 impl Decode<MergeTimestamp> for &[u8] {
     fn decode(&self, offs: &mut usize) -> MergeTimestamp {
         MergeTimestamp::from_ticks(self.decode_fixed_u64(offs))
@@ -97,10 +202,22 @@ pub struct TtlTimestamp {
 }
 
 impl TtlTimestamp {
+    /// The latest expiry that can be stored, early in 2156. u32::MAX is reserved for data that
+    ///  never expires in SsTableStats.
+    pub const MAX: TtlTimestamp = TtlTimestamp { epoch_seconds: u32::MAX - 1 };
+
     pub fn new(epoch_seconds: u32) -> TtlTimestamp {
         TtlTimestamp { epoch_seconds }
     }
 
+    /// The expiry ttl_seconds after a point in time given in seconds since HT epoch. Expiries
+    ///  beyond MAX saturate rather than wrapping around, so they can not turn into expiries in
+    ///  the past; Table::put rejects TTLs exceeding TableConfig::max_ttl_seconds before that.
+    pub fn after(epoch_seconds: u64, ttl_seconds: u32) -> TtlTimestamp {
+        let epoch_seconds = (epoch_seconds + ttl_seconds as u64).min(TtlTimestamp::MAX.epoch_seconds as u64);
+        TtlTimestamp::new(epoch_seconds as u32)
+    }
+
     pub fn as_system_time(&self) -> SystemTime {
         SystemTime::UNIX_EPOCH
             + Duration::from_secs(HT_EPOCH_SECONDS)
@@ -113,13 +230,18 @@ impl <W> Encode<TtlTimestamp> for W where W: Write {
         self.encode_fixed_u32(v.epoch_seconds)
     }
 }
+impl Put<TtlTimestamp> for Vec<u8> {
+    fn put(&mut self, v: TtlTimestamp) {
+        self.put_fixed_u32(v.epoch_seconds)
+    }
+}
 impl Decode<TtlTimestamp> for &[u8] {
     fn decode(&self, offs: &mut usize) -> TtlTimestamp {
         TtlTimestamp::new(self.decode_fixed_u32(offs))
     }
 }
 
-pub trait HtClock {
+pub trait HtClock: Send + Sync {
     fn now(&self) -> MergeTimestamp;
     fn ttl_timestamp(&self, ttl_seconds: u32) -> TtlTimestamp;
 }
@@ -131,7 +253,7 @@ struct WallClockCounter {
     time_travel_counter: u64,
 }
 
-pub trait TimeTravelCallback {
+pub trait TimeTravelCallback: Send + Sync {
     fn on_time_travel(&self, cur_millis: u64, prev_millis: u64, new_time_travel_counter: u8);
 }
 
@@ -173,7 +295,7 @@ impl WallClock {
         WallClock::new(unique_context, time_travel_counter, Box::new(NoTimeTravelCallback {}))
     }
 
-    fn ht_epoch_millis() -> u64 {
+    pub fn ht_epoch_millis() -> u64 {
         let unix_millis = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .expect("'now()' appears to be before 1970-01-01")
@@ -218,8 +340,7 @@ impl HtClock for WallClock {
     }
 
     fn ttl_timestamp(&self, ttl_seconds: u32) -> TtlTimestamp {
-        let epoch_seconds = WallClock::ht_epoch_millis() / 1000;
-        TtlTimestamp::new(epoch_seconds as u32 + ttl_seconds)
+        TtlTimestamp::after(WallClock::ht_epoch_millis() / 1000, ttl_seconds)
     }
 }
 
@@ -248,8 +369,7 @@ impl HtClock for ManualClock {
     }
 
     fn ttl_timestamp(&self, ttl_seconds: u32) -> TtlTimestamp {
-        let epoch_seconds = self.now().epoch_millis() / 1000;
-        TtlTimestamp::new(epoch_seconds as u32 + ttl_seconds)
+        TtlTimestamp::after(self.now().epoch_millis() / 1000, ttl_seconds)
     }
 }
 
@@ -258,7 +378,52 @@ impl HtClock for ManualClock {
 mod test {
     use std::time::{Duration, SystemTime};
 
-    use crate::time::{HT_EPOCH_MILLIS, HtClock, ManualClock, MergeTimestamp, WallClock};
+    use crate::time::{HT_EPOCH_MILLIS, HtClock, ManualClock, MergeTimestamp, TtlTimestamp, WallClock};
+
+    #[test]
+    pub fn test_ttl_timestamp_saturates() {
+        assert_eq!(TtlTimestamp::after(100, 20), TtlTimestamp::new(120));
+        assert_eq!(TtlTimestamp::after(u32::MAX as u64 - 10, 20), TtlTimestamp::MAX);
+        assert_eq!(TtlTimestamp::after(u32::MAX as u64 - 10, u32::MAX), TtlTimestamp::MAX);
+
+        let clock = ManualClock::new(MergeTimestamp::new(1_000_000, 0, 0, 0));
+        assert_eq!(clock.ttl_timestamp(60), TtlTimestamp::new(1060));
+        assert_eq!(clock.ttl_timestamp(u32::MAX), TtlTimestamp::MAX);
+        assert_eq!(WallClock::new_without_callback(0, 0).ttl_timestamp(u32::MAX), TtlTimestamp::MAX);
+    }
+
+    #[test]
+    pub fn test_merge_timestamp_format() {
+        assert_eq!(MergeTimestamp::new(0, 0, 0, 0).to_string(), "2020-01-01T00:00:00.000Z#c0@ctx0/t0");
+
+        let s = "2024-03-01T12:00:00.123Z#c17@ctx42/t3";
+        let ts = s.parse::<MergeTimestamp>().unwrap();
+        assert_eq!(ts.to_string(), s);
+        assert_eq!((ts.counter_part(), ts.unique_context(), ts.time_travel_part()), (17, 42, 3));
+        assert_eq!(ts.as_system_time().duration_since(SystemTime::UNIX_EPOCH).unwrap(), Duration::from_millis(1_709_294_400_123));
+
+        for s in &["2024-02-29T23:59:59.999Z#c1023@ctx1023/t7", "2080-12-31T00:00:00.000Z#c0@ctx1/t0"] {
+            assert_eq!(s.parse::<MergeTimestamp>().unwrap().to_string(), *s);
+        }
+        let now = WallClock::new_without_callback(5, 1).now();
+        assert_eq!(now.to_string().parse::<MergeTimestamp>().unwrap(), now);
+
+        for s in &[
+            "",
+            "2024-03-01T12:00:00.123Z",
+            "2023-02-29T12:00:00.123Z#c17@ctx42/t3",
+            "2024-13-01T12:00:00.123Z#c17@ctx42/t3",
+            "2024-03-01T24:00:00.123Z#c17@ctx42/t3",
+            "2019-12-31T23:59:59.999Z#c0@ctx0/t0",
+            "2099-12-31T00:00:00.000Z#c0@ctx1/t0",
+            "2024-03-01T12:00:00.123Z#c17@ctx1024/t3",
+            "2024-03-01T12:00:00.123Z#c17@ctx42/t8",
+            "2024-03-01T12:00:00.123Z#c+7@ctx42/t3",
+            "2024-03-01 12:00:00.123Z#c17@ctx42/t3",
+        ] {
+            assert!(s.parse::<MergeTimestamp>().is_err(), "{}", s);
+        }
+    }
 
     #[test]
     pub fn test_wallclock_time() {
@@ -1,6 +1,5 @@
-use crate::table::{ColumnValue, TableSchema, RowData, ColumnType};
+use crate::table::{decode_column_value, TableSchema, RowData};
 use crate::time::MergeTimestamp;
-use crate::primitives::*;
 
 use std::sync::Arc;
 use std::cmp::Ordering;
@@ -64,13 +63,21 @@ impl TombStoneFlags {
     }
 }
 
+/// The values of the partition key and of some leading cluster key columns, encoded without
+///  column flags (see DetachedRowData::encode_column_value)
 pub struct PartialClusterKey<'a> {
     schema: Arc<TableSchema>,
     buf: &'a [u8],
 }
 
 impl <'a> PartialClusterKey<'a> {
-    pub fn compare_to(&self, row: &'a RowData) -> Ordering {
+    pub fn new(schema: &Arc<TableSchema>, buf: &'a [u8]) -> PartialClusterKey<'a> {
+        PartialClusterKey { schema: schema.clone(), buf }
+    }
+
+    /// Compares the key to the row's primary key in clustering order, i.e. honoring descending
+    ///  cluster key columns. A row that starts with the key is Equal.
+    pub fn compare_to(&self, row: &RowData) -> Ordering {
         assert_eq!(*self.schema, *row.schema);
 
         let mut offs = 0usize;
@@ -81,19 +88,14 @@ impl <'a> PartialClusterKey<'a> {
                 break;
             }
 
-            let col = match col_schema.tpe {
-                ColumnType::Boolean => ColumnValue::Boolean(self.buf.decode_bool(&mut offs)),
-                ColumnType::Int => ColumnValue::Int(self.buf.decode_varint_i32(&mut offs)),
-                ColumnType::BigInt => ColumnValue::BigInt(self.buf.decode_varint_i64(&mut offs)),
-                ColumnType::Text => ColumnValue::Text(self.buf.decode_utf8(&mut offs)),
-            };
+            let col = decode_column_value(&self.buf, &col_schema.tpe, &mut offs);
 
             let row_col = iter.next().expect("row has incomplete cluster key")
                 .value.expect("cluster key is null in row");
 
-            let cmp = col.cmp(&row_col);
-            if cmp != Ordering::Equal {
-                return cmp;
+            match col_schema.compare_pk_values(&col, &row_col) {
+                Ordering::Equal => {}
+                cmp => return cmp,
             }
         }
 